@@ -0,0 +1,15 @@
+struct Input {
+    [[vk::location(0)]] float3 Color : COLOR0;
+};
+
+struct Output {
+    [[vk::location(0)]] float4 Color : COLOR0;
+};
+
+Output main(Input input) {
+    Output output = (Output)0;
+
+    output.Color = float4(input.Color, 1.0);
+
+    return output;
+}
\ No newline at end of file
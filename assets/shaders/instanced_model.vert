@@ -0,0 +1,36 @@
+struct Input {
+    [[vk::location(0)]] float3 Position : POSITION0;
+    [[vk::location(1)]] float3 Color : COLOR0;
+    [[vk::location(2)]] float2 TexCoord : TEXCOORD0;
+    [[vk::location(3)]] float4 Instance0 : INSTANCE0;
+    [[vk::location(4)]] float4 Instance1 : INSTANCE1;
+    [[vk::location(5)]] float4 Instance2 : INSTANCE2;
+    [[vk::location(6)]] float4 Instance3 : INSTANCE3;
+};
+
+struct UBO {
+    float4x4 Model;
+    float4x4 View;
+    float4x4 Projection;
+};
+
+cbuffer ubo: register(b0, space0) { UBO ubo; }
+
+struct Output {
+    float4 Position: SV_POSITION;
+    [[vk::location(0)]] float3 Color : COLOR0;
+    [[vk::location(1)]] float2 TexCoord : TEXCOORD0;
+};
+
+Output main(Input input) {
+    Output output = (Output)0;
+
+    // Instance transform is uploaded as columns
+    float4x4 instance = transpose(float4x4(input.Instance0, input.Instance1, input.Instance2, input.Instance3));
+    float4 world = mul(instance, mul(ubo.Model, float4(input.Position.xyz, 1.0)));
+    output.Position = mul(ubo.Projection, mul(ubo.View, world));
+    output.Color = input.Color;
+    output.TexCoord = input.TexCoord;
+
+    return output;
+}
@@ -0,0 +1,19 @@
+struct Input {
+    [[vk::location(0)]] float2 TexCoord : TEXCOORD0;
+    [[vk::location(1)]] float4 Color : COLOR0;
+};
+
+Texture2D fontAtlas : register(t0);
+SamplerState fontSampler : register(s0);
+
+struct Output {
+    [[vk::location(0)]] float4 Color : COLOR0;
+};
+
+Output main(Input input) {
+    Output output = (Output)0;
+
+    output.Color = fontAtlas.Sample(fontSampler, input.TexCoord) * input.Color;
+
+    return output;
+}
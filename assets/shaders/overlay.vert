@@ -0,0 +1,22 @@
+struct Input {
+    [[vk::location(0)]] float2 Position : POSITION0;
+    [[vk::location(1)]] float2 TexCoord : TEXCOORD0;
+    [[vk::location(2)]] float4 Color : COLOR0;
+};
+
+struct Output {
+    float4 Position : SV_POSITION;
+    [[vk::location(0)]] float2 TexCoord : TEXCOORD0;
+    [[vk::location(1)]] float4 Color : COLOR0;
+};
+
+// Positions are converted from pixels when the overlay is flushed
+Output main(Input input) {
+    Output output = (Output)0;
+
+    output.Position = float4(input.Position, 0.0, 1.0);
+    output.TexCoord = input.TexCoord;
+    output.Color = input.Color;
+
+    return output;
+}
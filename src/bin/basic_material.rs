@@ -69,6 +69,9 @@ fn main() -> Result<()> {
             ty: QueueType::Graphics,
             count: 1,
         }],
+        debug: Default::default(),
+        validation: Default::default(),
+        device_selection: Default::default(),
     };
 
     let (
@@ -126,7 +129,7 @@ fn main() -> Result<()> {
     })?;
 
     let texture_image = texture.bind_result(|_| {
-        ImageFile::new("assets/textures/viking_room.png")?.upload_to_gpu(&loader, features, &pool)
+        ImageFile::new("assets/textures/viking_room.png")?.upload_to_gpu(&loader, features, &pool, Default::default())
     })?;
 
     let effect =
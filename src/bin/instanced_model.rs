@@ -0,0 +1,403 @@
+use anyhow::{anyhow, Result};
+use silt::loader::{LoaderCreateInfo, LoaderHandles};
+use silt::material::{MaterialSkeleton, MaterialSystemBuilder, ShaderOptions};
+use silt::model::{Instance, Model, Vertex, MVP};
+#[cfg(feature = "overlay")]
+use silt::overlay::{DebugOverlay, FpsCounter, OverlayDraw};
+use silt::prelude::*;
+use silt::properties::{DeviceFeatures, DeviceFeaturesRequest, ProvidedFeatures};
+use silt::resources::{
+    get_depth_format, get_surface_format, AttachmentDescription, AttachmentType, BindableResource,
+    Buffer, Image, ImageFile,
+};
+use silt::resources::{ResourceDescription, UniformBuffer};
+use silt::swapchain::{Swapchain, SwapchainCreateInfo};
+use silt::sync::{
+    CommandPool, FrameContext, FrameOutcome, FrameScheduler, QueueRequest, QueueType, RecordList,
+    Recordable,
+};
+use silt::{compile, id, resources};
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::ControlFlow;
+
+const FOREST_SIZE: u32 = 32;
+
+struct Forest {
+    model: Model,
+    instances: Buffer,
+    instance_count: u32,
+}
+
+impl Forest {
+    fn new(loader: &Loader, features: ProvidedFeatures, pool: &CommandPool) -> Result<Self> {
+        let model = Model::load(loader, features, pool, "assets/models/viking_room.obj")?;
+
+        let instances = (0..FOREST_SIZE * FOREST_SIZE)
+            .map(|idx| Instance {
+                transform: glam::Mat4::from_translation(glam::vec3(
+                    (idx % FOREST_SIZE) as f32 * 2.5,
+                    (idx / FOREST_SIZE) as f32 * 2.5,
+                    0.,
+                )),
+            })
+            .collect::<Vec<_>>();
+
+        let instance_buffer = Buffer::upload_to_gpu(
+            loader,
+            pool,
+            &instances,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            id!("Forest Instances"),
+        )?;
+
+        Ok(Self {
+            model,
+            instances: instance_buffer,
+            instance_count: instances.len() as u32,
+        })
+    }
+}
+
+impl Recordable for Forest {
+    fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, _frame: &FrameContext) {
+        Instance::bind(loader, command_buffer, &self.instances);
+        self.model
+            .record_instanced(loader, command_buffer, self.instance_count, 0);
+    }
+}
+
+impl Destructible for Forest {
+    fn destroy(self, loader: &Loader) {
+        self.model.destroy(loader);
+        self.instances.destroy(loader);
+    }
+}
+
+/// Depth buffer and a framebuffer per swapchain image, rendering straight into the swapchain
+/// images. Rebuilt whenever the swapchain is recreated
+struct Targets {
+    depth: Image,
+    framebuffers: Vec<vk::Framebuffer>,
+}
+
+impl Targets {
+    fn new(
+        loader: &Loader,
+        render_pass: vk::RenderPass,
+        depth: &AttachmentDescription,
+        swapchain: &Swapchain,
+    ) -> Result<Self> {
+        let extent = swapchain.render_extent;
+        let depth = Image::new_attachment(loader, depth, extent)?;
+
+        let framebuffers = swapchain
+            .frames()
+            .map(|(_, frame)| {
+                let attachments = [frame.view, depth.view];
+                let framebuffer_ci = vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass)
+                    .attachments(&attachments)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+
+                Ok(unsafe { loader.device.create_framebuffer(&framebuffer_ci, None)? })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            depth,
+            framebuffers,
+        })
+    }
+}
+
+impl Destructible for Targets {
+    fn destroy(self, loader: &Loader) {
+        self.framebuffers.destroy(loader);
+        self.depth.destroy(loader);
+    }
+}
+
+/// Looks over the whole forest from one corner, flipping y to match the vulkan viewport
+fn forest_mvp(extent: vk::Extent2D) -> MVP {
+    let center = FOREST_SIZE as f32 * 2.5 / 2.;
+    let mut proj = glam::Mat4::perspective_rh(
+        45f32.to_radians(),
+        extent.width as f32 / extent.height as f32,
+        0.1,
+        500.,
+    );
+    proj.y_axis.y *= -1.;
+
+    MVP {
+        model: glam::Mat4::IDENTITY,
+        view: glam::Mat4::look_at_rh(
+            glam::vec3(center, -center, center),
+            glam::vec3(center, center, 0.),
+            glam::Vec3::Z,
+        ),
+        proj,
+    }
+}
+
+fn main() -> Result<()> {
+    let loader_ci = LoaderCreateInfo {
+        width: 1920,
+        height: 1080,
+        title: "Instanced Model".into(),
+        device_features: DeviceFeaturesRequest {
+            required: DeviceFeatures::SAMPLER_ANISOTROPY,
+            prefered: DeviceFeatures::empty(),
+        },
+        queue_requests: vec![QueueRequest {
+            ty: QueueType::Graphics,
+            count: 1,
+        }],
+        debug: Default::default(),
+        validation: Default::default(),
+        device_selection: Default::default(),
+    };
+
+    let (loader, handles) = Loader::new(loader_ci)?;
+    let LoaderHandles {
+        surface, pdevice, ..
+    } = handles;
+    let features = ProvidedFeatures::new(&loader, pdevice);
+    let pool = CommandPool::new(
+        &loader,
+        &handles.queues[0],
+        vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+    )?;
+
+    let surface_format = get_surface_format(&loader, surface, pdevice).format;
+    let depth_format = get_depth_format(&loader.instance, pdevice)
+        .ok_or_else(|| anyhow!("no supported depth format"))?;
+
+    let mut materials = MaterialSystemBuilder::new(&loader);
+
+    let vertex = ResourceDescription::vertex_input::<(Vertex, Instance)>(id!("Instanced Vertex"));
+    let mvp =
+        ResourceDescription::uniform::<MVP>(id!("MVP Uniform"), 0, vk::DescriptorFrequency::Global);
+    let texture = ResourceDescription::sampled_image(
+        id!("Texture Image"),
+        1,
+        vk::DescriptorFrequency::Global,
+    );
+    let color = ResourceDescription::attachment(
+        id!("Swapchain Color"),
+        AttachmentType::Color,
+        surface_format,
+        vk::SampleCountFlags::TYPE_1,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+    );
+    let depth = ResourceDescription::attachment(
+        id!("Forest Depth"),
+        AttachmentType::DepthStencil,
+        depth_format,
+        vk::SampleCountFlags::TYPE_1,
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    );
+
+    let vertex_shader = materials.add_shader(
+        id!("Instanced MVP Vertex Pass"),
+        compile!(
+            "../../assets/shaders/instanced_model.vert",
+            ShaderOptions::HLSL
+        )?,
+        resources!(vertex, mvp),
+    )?;
+
+    let fragment_shader = materials.add_shader(
+        id!("Unlit Texture Pass"),
+        compile!(
+            "../../assets/shaders/model_loading.frag",
+            ShaderOptions::HLSL
+        )?,
+        resources!(texture, color, depth),
+    )?;
+
+    let mvp_buffer = mvp.bind_result(|description| {
+        UniformBuffer::new(
+            &loader,
+            description,
+            Default::default(),
+            Some(description.id().clone()),
+        )
+    })?;
+
+    let texture_image = texture.bind_result(|_| {
+        ImageFile::new("assets/textures/viking_room.png")?.upload_to_gpu(
+            &loader,
+            features,
+            &pool,
+            Default::default(),
+        )
+    })?;
+
+    let effect = materials.register_effect(
+        id!("Instanced Model Effect"),
+        [vertex_shader, fragment_shader],
+    )?;
+    materials.register_material(
+        id!("Instanced Model Material"),
+        MaterialSkeleton {
+            effects: vec![effect.clone()],
+        },
+    )?;
+
+    let mut materials = materials.build_static()?;
+    *materials.get_global_resources_mut() = vec![mvp_buffer.bind(), texture_image.bind()].into();
+
+    let render_pass = materials
+        .get_effect_pipeline(&loader, &effect)?
+        .render_pass
+        .ok_or_else(|| anyhow!("instanced model effect has no render pass"))?;
+    let clear_values = materials.get_effect_clear_values(&effect)?;
+    materials.write_global_sets(&loader)?;
+
+    #[cfg(feature = "overlay")]
+    let mut overlay = DebugOverlay::new(
+        &loader,
+        features,
+        &pool,
+        render_pass,
+        resources!(color, depth),
+        64,
+    )?;
+    #[cfg(feature = "overlay")]
+    let mut fps = FpsCounter::default();
+
+    // The effect's render pass draws into the swapchain images, so the swapchain doesn't need its own
+    let mut swapchain = unsafe {
+        Swapchain::new(
+            &loader,
+            surface,
+            pdevice,
+            vk::RenderPass::null(),
+            SwapchainCreateInfo {
+                width: 1920,
+                height: 1080,
+                manage_depth: false,
+                ..Default::default()
+            },
+        )?
+    };
+    let depth = depth.as_ref().clone().unwrap_attachment();
+    let mut targets = Targets::new(&loader, render_pass, &depth, &swapchain)?;
+    let mut scheduler = FrameScheduler::new(&loader, &pool)?;
+
+    let forest = Forest::new(&loader, features, &pool)?;
+
+    let mut draw = || -> Result<()> {
+        // The global set and uniform written have to belong to the frame the scheduler records
+        if materials.frame() != scheduler.frame() {
+            materials.advance_frame();
+        }
+
+        let context = materials.get_frame_context(&loader, &effect)?;
+        let mvp = forest_mvp(swapchain.render_extent);
+
+        #[cfg(feature = "overlay")]
+        {
+            fps.tick();
+            fps.draw(&mut overlay, 8., 8.);
+        }
+
+        let outcome = scheduler.draw_frame(&loader, &mut swapchain, |frame| {
+            mvp_buffer.resource.copy(frame.parity, mvp);
+
+            #[cfg(feature = "overlay")]
+            let overlay_draw = {
+                overlay.flush(&loader, frame.parity, frame.render_extent)?;
+                OverlayDraw {
+                    overlay: &overlay,
+                    parity: frame.parity,
+                }
+            };
+
+            let mut list = RecordList::new();
+            list.push(context, &forest);
+            // Drawn last, over the forest
+            #[cfg(feature = "overlay")]
+            list.push(overlay.frame_context(frame.parity), &overlay_draw);
+
+            let render_area = vk::Rect2D {
+                offset: vk::Offset2D::default(),
+                extent: frame.render_extent,
+            };
+            let begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(render_pass)
+                .framebuffer(targets.framebuffers[frame.image_index.as_raw() as usize])
+                .render_area(render_area)
+                .clear_values(&clear_values);
+            let viewport = vk::Viewport {
+                width: frame.render_extent.width as f32,
+                height: frame.render_extent.height as f32,
+                max_depth: 1.,
+                ..Default::default()
+            };
+
+            unsafe {
+                loader.device.cmd_begin_render_pass(
+                    frame.command_buffer,
+                    &begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                loader
+                    .device
+                    .cmd_set_viewport(frame.command_buffer, 0, &[viewport]);
+                loader
+                    .device
+                    .cmd_set_scissor(frame.command_buffer, 0, &[render_area]);
+            }
+
+            list.record(&loader, frame.command_buffer);
+            unsafe { loader.device.cmd_end_render_pass(frame.command_buffer) };
+            Ok(())
+        })?;
+
+        if outcome == FrameOutcome::Recreated {
+            loader.wait_idle()?;
+            let recreated = Targets::new(&loader, render_pass, &depth, &swapchain)?;
+            std::mem::replace(&mut targets, recreated).destroy(&loader);
+        }
+
+        Ok(())
+    };
+
+    let mut result = Ok(());
+    loader.context.run_return(|event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => *control_flow = ControlFlow::Exit,
+            Event::MainEventsCleared => {
+                result = draw();
+                if result.is_err() {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => (),
+        }
+    });
+
+    loader.wait_idle()?;
+    forest.destroy(&loader);
+    #[cfg(feature = "overlay")]
+    overlay.destroy(&loader);
+    targets.destroy(&loader);
+    scheduler.destroy(&loader);
+    unsafe { swapchain.cleanup(&loader)? };
+    materials.destroy(&loader);
+    mvp_buffer.resource.destroy(&loader);
+    texture_image.resource.destroy(&loader);
+    pool.destroy(&loader);
+    loader
+        .destroy(handles)
+        .map_err(|failed| anyhow!("failed to destroy the loader: {}", failed.2))?;
+
+    result
+}
@@ -394,6 +394,10 @@ impl<T> FromIterator<T> for SwapSet<T> {
     }
 }
 
+/// Selects the per frame in flight resources, such as command buffers, fences and descriptor sets.
+/// Not interchangeable with the swapchain image being rendered to, see [`crate::swapchain::ImageIndex`]
+pub type FrameIndex = Parity;
+
 #[derive(Clone, Copy, Hash, Debug, PartialEq, Eq, IsVariant)]
 pub enum Parity {
     Even,
@@ -401,6 +405,8 @@ pub enum Parity {
 }
 
 impl Parity {
+    pub const ELEMENTS: [Self; 2] = [Self::Even, Self::Odd];
+
     pub fn swap(&mut self) {
         *self = match self {
             Parity::Even => Parity::Odd,
@@ -458,6 +464,14 @@ impl<T> ParitySet<T> {
         self.into_iter()
     }
 
+    pub fn iter_tagged(&self) -> std::array::IntoIter<(Parity, &T), 2> {
+        [(Parity::Even, &self.even), (Parity::Odd, &self.odd)].into_iter()
+    }
+
+    pub fn enumerate_parity(self) -> std::array::IntoIter<(Parity, T), 2> {
+        [(Parity::Even, self.even), (Parity::Odd, self.odd)].into_iter()
+    }
+
     pub fn map<R>(self, f: impl Fn(T) -> R) -> ParitySet<R> {
         ParitySet {
             even: f(self.even),
@@ -622,6 +636,54 @@ impl<T> IntoIterator for RedundantSet<T> {
     }
 }
 
+/// A set whose redundancy can't be generalized with the sets before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedundancyConflict {
+    /// Position of the offending set in the input
+    pub index: usize,
+    pub found: Redundancy,
+    /// Redundancy generalized from the sets before `index`
+    pub expected: Redundancy,
+}
+
+impl std::fmt::Display for RedundancyConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "set at index {} is {:?}, incompatible with {:?}",
+            self.index, self.found, self.expected
+        )
+    }
+}
+
+/// Generalizes every redundancy, skipping over conflicting ones so all of them are reported
+fn generalize_all<I: IntoIterator<Item = Redundancy>>(
+    redundancies: I,
+) -> std::result::Result<Redundancy, Vec<RedundancyConflict>> {
+    let mut conflicts = vec![];
+    let mut redundancies = redundancies.into_iter().enumerate();
+    let first = match redundancies.next() {
+        Some((_, first)) => first,
+        None => return Ok(Redundancy::Single),
+    };
+
+    let redundancy = redundancies.fold(first, |acc, (index, next)| {
+        acc.generalize(next).unwrap_or_else(|| {
+            conflicts.push(RedundancyConflict {
+                index,
+                found: next,
+                expected: acc,
+            });
+            acc
+        })
+    });
+
+    match conflicts.is_empty() {
+        true => Ok(redundancy),
+        false => Err(conflicts),
+    }
+}
+
 /// Enable turning an iterator of redundant sets of T into a redundant set of iterators of T
 pub trait RedundancyTools: Iterator {
     type Underlying;
@@ -629,6 +691,9 @@ pub trait RedundancyTools: Iterator {
     type Err;
 
     fn merge_rsets(self) -> std::result::Result<RedundantSet<Self::Iter>, Self::Err>;
+
+    /// Preflight for [`RedundancyTools::merge_rsets`], returning the merged redundancy or every conflict
+    fn validate(self) -> std::result::Result<Redundancy, Vec<RedundancyConflict>>;
 }
 
 impl<T: Clone, I: Iterator<Item = RedundantSet<T>>> RedundancyTools for I {
@@ -636,10 +701,14 @@ impl<T: Clone, I: Iterator<Item = RedundantSet<T>>> RedundancyTools for I {
     type Iter = std::vec::IntoIter<T>;
     type Err = anyhow::Error;
 
+    fn validate(self) -> std::result::Result<Redundancy, Vec<RedundancyConflict>> {
+        generalize_all(self.map(|set| set.get_redundancy()))
+    }
+
     fn merge_rsets(self) -> Result<RedundantSet<Self::Iter>> {
         let (sets, redundancies, swap_lens): (Vec<_>, Vec<_>, Vec<_>) = self
             .map(|set| {
-                let redundancy = Some(set.get_redundancy());
+                let redundancy = set.get_redundancy();
                 let swap_len = match &set {
                     RedundantSet::Swapchain(s) => Some(s.len()),
                     _ => None,
@@ -649,11 +718,12 @@ impl<T: Clone, I: Iterator<Item = RedundantSet<T>>> RedundancyTools for I {
             })
             .multiunzip();
 
-        let redundancy = redundancies
-            .into_iter()
-            .reduce(|a, b| a.and_then(|a| a.generalize(b.unwrap())))
-            .unwrap_or_default()
-            .ok_or(anyhow!("Failed to generalize redundancy"))?;
+        let redundancy = generalize_all(redundancies).map_err(|conflicts| {
+            anyhow!(
+                "Failed to generalize redundancy: {}",
+                conflicts.iter().join(", ")
+            )
+        })?;
 
         let swap_len = if redundancy == Redundancy::Swapchain {
             swap_lens
@@ -752,6 +822,18 @@ mod tests {
         assert_eq!(manual, automatic);
     }
 
+    #[test]
+    fn parity_set_tagged_iteration() {
+        let set = ParitySet { even: 1, odd: 2 };
+
+        for (parity, value) in set.iter_tagged() {
+            assert_eq!(set.get(parity), value);
+        }
+
+        let tagged = set.enumerate_parity().collect_vec();
+        assert_eq!(tagged, vec![(Parity::Even, 1), (Parity::Odd, 2)]);
+    }
+
     #[test]
     fn upgrade_to_parity() {
         let single = RedundantSet::Single("test");
@@ -837,4 +919,32 @@ mod tests {
         assert_eq!(c.next(), Some("ferris"));
         assert_eq!(c.next(), None);
     }
+
+    #[test]
+    fn merge_conflict_reports_index() {
+        let sets = || {
+            [
+                RedundantSet::Single("foo"),
+                RedundantSet::Parity(ParitySet::from_single("bar")),
+                RedundantSet::Swapchain(vec!["rust"; 3].into()),
+                RedundantSet::Swapchain(vec!["crab"; 3].into()),
+            ]
+            .into_iter()
+        };
+
+        let err = sets().merge_rsets().unwrap_err().to_string();
+        assert!(err.contains("index 2"), "{}", err);
+
+        let conflicts = sets().validate().unwrap_err();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(
+            conflicts[0],
+            RedundancyConflict {
+                index: 2,
+                found: Redundancy::Swapchain,
+                expected: Redundancy::Parity,
+            }
+        );
+        assert_eq!(conflicts[1].index, 3);
+    }
 }
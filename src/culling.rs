@@ -0,0 +1,299 @@
+use crate::collections::{Parity, ParitySet};
+use crate::error::{Result, SiltError};
+use crate::resources::{Buffer, BufferCreateInfo};
+use crate::sync::{CommandPool, FrameContext, QueryPool, Recordable};
+use crate::{id, prelude::*};
+
+/// Axis aligned bounding box in model space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self {
+            min: glam::Vec3::ZERO,
+            max: glam::Vec3::ZERO,
+        }
+    }
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = glam::Vec3>) -> Option<Self> {
+        points.into_iter().fold(None, |acc, point| {
+            Some(match acc {
+                Some(Aabb { min, max }) => Aabb {
+                    min: min.min(point),
+                    max: max.max(point),
+                },
+                None => Aabb {
+                    min: point,
+                    max: point,
+                },
+            })
+        })
+    }
+
+    pub fn center(&self) -> glam::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> glam::Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+}
+
+/// Six clip planes in the form `ax + by + cz + d >= 0`, ordered
+/// left, right, bottom, top, near, far
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the clip planes from a combined `proj * view (* model)` matrix,
+    /// assuming vulkan's `0..1` clip space depth range
+    pub fn from_view_proj(view_proj: glam::Mat4) -> Self {
+        let row = |idx| view_proj.row(idx);
+
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| plane / plane.truncate().length());
+
+        Self { planes }
+    }
+
+    pub fn intersects_aabb(&self, min: glam::Vec3, max: glam::Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            // Vertex of the box furthest along the plane normal
+            let positive = glam::vec3(
+                if plane.x >= 0. { max.x } else { min.x },
+                if plane.y >= 0. { max.y } else { min.y },
+                if plane.z >= 0. { max.z } else { min.z },
+            );
+
+            plane.truncate().dot(positive) + plane.w >= 0.
+        })
+    }
+
+    pub fn contains(&self, aabb: &Aabb) -> bool {
+        self.intersects_aabb(aabb.min, aabb.max)
+    }
+}
+
+/// Skips drawing objects hidden behind others, decided by occlusion queries wrapped around draws
+/// of their bounding volumes. Each frame in flight has its own query pool and results buffer, so
+/// a frame's results are read once its fence has signaled, and visibility lags the queries by the
+/// number of frames in flight. Objects stay visible until a query has reported them hidden
+///
+/// ```ignore
+/// // After waiting on the frame's fence, and outside the render pass
+/// culler.resolve(&loader, &pool, parity)?;
+/// culler.cmd_reset(&loader, command_buffer, parity);
+///
+/// // In the render pass, visible objects first so they occlude the proxies
+/// for (index, object) in objects.iter().enumerate() {
+///     list.push(frame.clone(), &CulledDraw { culler: &culler, index, inner: object });
+/// }
+/// // Then the bounding volumes of every object, with a PipelineState::occlusion_proxy pipeline
+/// for (index, object) in objects.iter().enumerate() {
+///     proxies.push(proxy_frame.clone(), &OcclusionQuery { culler: &culler, parity, index, proxy: &object.bounds });
+/// }
+///
+/// // After the render pass
+/// culler.cmd_copy_results(&loader, command_buffer, parity);
+/// ```
+pub struct OcclusionCuller {
+    queries: ParitySet<QueryPool>,
+    /// Sample count and availability of every query, copied by [`OcclusionCuller::cmd_copy_results`]
+    results: ParitySet<Buffer>,
+    visible: Vec<bool>,
+}
+
+impl Destructible for OcclusionCuller {
+    fn destroy(self, loader: &Loader) {
+        self.queries.into_iter().destroy(loader);
+        self.results.into_iter().destroy(loader);
+    }
+}
+
+impl OcclusionCuller {
+    /// Object indices range over `0..capacity`
+    pub fn new(loader: &Loader, capacity: u32) -> Result<Self> {
+        if capacity == 0 {
+            return Err(SiltError::InvalidUsage("occlusion culler capacity must be non zero".into()));
+        }
+
+        let even = QueryPool::occlusion(loader, capacity)?;
+        let odd = match QueryPool::occlusion(loader, capacity) {
+            Ok(odd) => odd,
+            Err(err) => {
+                even.destroy(loader);
+                return Err(err);
+            }
+        };
+        let queries = ParitySet::new(even, odd);
+
+        let results_info = BufferCreateInfo::builder()
+            .size(capacity as u64 * std::mem::size_of::<[u64; 2]>() as u64)
+            .name(id!("Occlusion Results"))
+            .transfer_dst()
+            .location(vk::MemoryLocation::GpuToCpu)
+            .build()?;
+
+        // Zeroed results read as unavailable, so resolving a frame before its first copy keeps
+        // every object visible instead of reading uninitialized memory
+        let zeroed = vec![[0u64; 2]; capacity as usize];
+        let mut results = vec![];
+        for _ in Parity::ELEMENTS {
+            let buffer = Buffer::new(loader, results_info.clone()).and_then(|buffer| {
+                match buffer.copy_data(loader, None, &zeroed) {
+                    Ok(()) => Ok(buffer),
+                    Err(err) => {
+                        buffer.destroy(loader);
+                        Err(err)
+                    }
+                }
+            });
+
+            match buffer {
+                Ok(buffer) => results.push(buffer),
+                Err(err) => {
+                    results.destroy(loader);
+                    queries.into_iter().destroy(loader);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(Self {
+            queries,
+            results: results.into(),
+            visible: vec![true; capacity as usize],
+        })
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.visible.len() as u32
+    }
+
+    /// Whether object `index` passed its latest query, or hasn't been queried yet
+    pub fn is_visible(&self, index: usize) -> bool {
+        self.visible.get(index).copied().unwrap_or(true)
+    }
+
+    /// Updates visibility from the queries of the frame `parity`, which must have finished
+    /// executing. Objects left unqueried that frame keep their visibility
+    pub fn resolve(&mut self, loader: &Loader, pool: &CommandPool, parity: Parity) -> Result<()> {
+        let results = self.results.get(parity).read_back::<[u64; 2]>(loader, pool)?;
+
+        for (visible, [samples, available]) in self.visible.iter_mut().zip(results) {
+            if available != 0 {
+                *visible = samples != 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resets the queries of the frame `parity`. Record outside of a render pass, before any query
+    pub fn cmd_reset(&self, loader: &Loader, command_buffer: vk::CommandBuffer, parity: Parity) {
+        self.queries.get(parity).cmd_reset(loader, command_buffer);
+    }
+
+    /// Records `proxy`, usually an object's bounding box, inside the query of object `index`.
+    /// The proxy pipeline should be bound, see [`crate::pipeline::PipelineState::occlusion_proxy`]
+    pub fn cmd_query(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        parity: Parity,
+        index: u32,
+        frame: &FrameContext,
+        proxy: &dyn Recordable,
+    ) {
+        debug_assert!(index < self.capacity());
+        let queries = self.queries.get(parity);
+        queries.cmd_begin_query(loader, command_buffer, index, false);
+        proxy.record(loader, command_buffer, frame);
+        queries.cmd_end_query(loader, command_buffer, index);
+    }
+
+    /// Copies the results of the frame `parity` for [`OcclusionCuller::resolve`]. Record outside
+    /// of a render pass, after every query. Queries not yet available are copied as unavailable
+    pub fn cmd_copy_results(&self, loader: &Loader, command_buffer: vk::CommandBuffer, parity: Parity) {
+        let queries = self.queries.get(parity);
+        let stride = std::mem::size_of::<[u64; 2]>() as u64;
+
+        unsafe {
+            loader.device.cmd_copy_query_pool_results(
+                command_buffer,
+                queries.pool,
+                0,
+                queries.count,
+                self.results.get(parity).buffer,
+                0,
+                stride,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            );
+
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ);
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                std::slice::from_ref(&barrier),
+                &[],
+                &[],
+            );
+        }
+    }
+}
+
+/// Records `proxy` inside the occlusion query of object `index`, see [`OcclusionCuller::cmd_query`]
+pub struct OcclusionQuery<'a> {
+    pub culler: &'a OcclusionCuller,
+    pub parity: Parity,
+    pub index: u32,
+    pub proxy: &'a dyn Recordable,
+}
+
+impl Recordable for OcclusionQuery<'_> {
+    fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, frame: &FrameContext) {
+        self.culler
+            .cmd_query(loader, command_buffer, self.parity, self.index, frame, self.proxy);
+    }
+}
+
+/// Records `inner` only while object `index` is visible
+pub struct CulledDraw<'a> {
+    pub culler: &'a OcclusionCuller,
+    pub index: usize,
+    pub inner: &'a dyn Recordable,
+}
+
+impl Recordable for CulledDraw<'_> {
+    fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, frame: &FrameContext) {
+        if self.culler.is_visible(self.index) {
+            self.inner.record(loader, command_buffer, frame);
+        }
+    }
+}
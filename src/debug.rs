@@ -0,0 +1,154 @@
+use memoffset::offset_of;
+use std::cell::Cell;
+
+use crate::collections::{Parity, ParitySet};
+use crate::culling::Aabb;
+use crate::error::{Result, SiltError};
+use crate::resources::{Buffer, BufferCreateInfo, VertexInput};
+use crate::{id, prelude::*};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DebugVertex {
+    pub pos: glam::Vec3,
+    pub color: glam::Vec3,
+}
+
+impl VertexInput for DebugVertex {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()]
+    }
+
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(offset_of!(DebugVertex, pos) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(offset_of!(DebugVertex, color) as u32)
+                .build(),
+        ]
+    }
+}
+
+/// Immediate mode line drawing for debug visualization. Lines are queued each frame,
+/// uploaded to a host visible buffer per parity, and drawn with an effect built with
+/// [`crate::pipeline::PipelineState::lines`] and the bundled `debug_lines` shaders
+#[derive(Debug)]
+pub struct DebugLines {
+    vertices: Vec<DebugVertex>,
+    buffers: ParitySet<Buffer>,
+    counts: ParitySet<Cell<u32>>,
+    capacity: usize,
+}
+
+impl Destructible for DebugLines {
+    fn destroy(self, loader: &Loader) {
+        self.buffers.into_iter().destroy(loader);
+    }
+}
+
+impl DebugLines {
+    /// `capacity` is the maximum number of lines drawn in a single frame
+    pub fn new(loader: &Loader, capacity: usize) -> Result<Self> {
+        let create_info = BufferCreateInfo {
+            size: (capacity * 2 * std::mem::size_of::<DebugVertex>()) as u64,
+            name: id!("Debug Lines"),
+            usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+            location: vk::MemoryLocation::CpuToGpu,
+            ..Default::default()
+        };
+
+        Ok(Self {
+            vertices: Vec::with_capacity(capacity * 2),
+            buffers: ParitySet::new(
+                Buffer::new(loader, create_info.clone())?,
+                Buffer::new(loader, create_info)?,
+            ),
+            counts: ParitySet::from_fn(Default::default),
+            capacity,
+        })
+    }
+
+    pub fn line(&mut self, from: glam::Vec3, to: glam::Vec3, color: glam::Vec3) {
+        self.vertices.extend([
+            DebugVertex { pos: from, color },
+            DebugVertex { pos: to, color },
+        ]);
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: glam::Vec3) {
+        let corner = |idx: usize| {
+            glam::vec3(
+                if idx & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if idx & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if idx & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            )
+        };
+
+        // Each edge connects two corners differing in a single axis
+        for idx in 0..8 {
+            for axis in [1, 2, 4] {
+                if idx & axis == 0 {
+                    self.line(corner(idx), corner(idx | axis), color);
+                }
+            }
+        }
+    }
+
+    /// Small axis aligned cross, for marking points
+    pub fn marker(&mut self, pos: glam::Vec3, size: f32, color: glam::Vec3) {
+        for axis in [glam::Vec3::X, glam::Vec3::Y, glam::Vec3::Z] {
+            self.line(pos - axis * size * 0.5, pos + axis * size * 0.5, color);
+        }
+    }
+
+    /// Uploads the queued lines to the buffer for `parity` and clears the queue.
+    /// The buffer must not be in use by any pending command buffers
+    pub fn flush(&mut self, loader: &Loader, parity: Parity) -> Result<()> {
+        if self.vertices.len() > self.capacity * 2 {
+            let count = self.vertices.len() / 2;
+            self.vertices.clear();
+
+            return Err(SiltError::InvalidUsage(format!(
+                "{} debug lines exceeds capacity of {}",
+                count, self.capacity
+            )));
+        }
+
+        self.buffers
+            .get(parity)
+            .copy_data(loader, None, &self.vertices)?;
+        self.counts.get(parity).set(self.vertices.len() as u32);
+        self.vertices.clear();
+
+        Ok(())
+    }
+
+    /// Draws the lines last flushed for `parity`, the debug line pipeline must already be bound
+    pub fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, parity: Parity) {
+        let count = self.counts.get(parity).get();
+        if count == 0 {
+            return;
+        }
+
+        unsafe {
+            loader.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[self.buffers.get(parity).buffer],
+                &[0],
+            );
+            loader.device.cmd_draw(command_buffer, count, 1, 0, 0);
+        }
+    }
+}
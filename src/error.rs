@@ -0,0 +1,100 @@
+use derive_more::{Display, From};
+use gpu_allocator::AllocationError;
+
+use crate::prelude::*;
+use crate::properties::DeviceFeatures;
+
+#[derive(Debug, Display, From)]
+pub enum SiltError {
+    #[display(fmt = "device selection failed: {}", _0)]
+    #[from(ignore)]
+    DeviceSelection(String),
+    #[display(fmt = "allocation failed: {}", _0)]
+    AllocationFailed(AllocationError),
+    /// The descriptor pool ran out of space, allocating from a fresh pool may succeed
+    #[display(fmt = "descriptor pool exhausted")]
+    DescriptorExhausted,
+    #[display(fmt = "unsupported format: {}", _0)]
+    #[from(ignore)]
+    UnsupportedFormat(String),
+    #[display(fmt = "shader compilation failed: {}", _0)]
+    #[from(ignore)]
+    ShaderCompilation(String),
+    #[display(fmt = "identifier {} already exists", _0)]
+    #[from(ignore)]
+    DuplicateIdentifier(Identifier),
+    #[display(fmt = "identifier {} does not exist", _0)]
+    #[from(ignore)]
+    MissingIdentifier(Identifier),
+    /// An identifier was looked up or registered as a different type than it holds, see [`Registry`]
+    #[display(fmt = "identifier {} holds a {}, not a {}", id, found, expected)]
+    #[from(ignore)]
+    MismatchedIdentifier {
+        id: Identifier,
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[display(fmt = "device features {:?} are not enabled", _0)]
+    #[from(ignore)]
+    MissingFeatures(DeviceFeatures),
+    #[display(fmt = "invalid usage: {}", _0)]
+    #[from(ignore)]
+    InvalidUsage(String),
+    /// The device was lost, e.g. to a driver reset. Everything created from it has to be
+    /// recreated, see [`Loader::check`]
+    #[display(fmt = "device lost")]
+    DeviceLost,
+    /// Never `ERROR_DEVICE_LOST` when converted with `From`, which is [`SiltError::DeviceLost`]
+    #[display(fmt = "vulkan error: {}", _0)]
+    #[from(ignore)]
+    Vk(vk::Result),
+    #[display(fmt = "window error: {}", _0)]
+    Window(winit::error::OsError),
+    #[display(fmt = "image error: {}", _0)]
+    Image(image::ImageError),
+    #[display(fmt = "io error: {}", _0)]
+    Io(std::io::Error),
+    #[display(fmt = "{}", _0)]
+    #[from(ignore)]
+    Other(anyhow::Error),
+}
+
+impl std::error::Error for SiltError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SiltError::AllocationFailed(err) => Some(err),
+            SiltError::Vk(err) => Some(err),
+            SiltError::Window(err) => Some(err),
+            SiltError::Image(err) => Some(err),
+            SiltError::Io(err) => Some(err),
+            SiltError::Other(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Splits `ERROR_DEVICE_LOST` off into [`SiltError::DeviceLost`], so `?` on any vulkan call
+/// can be checked with [`SiltError::is_device_lost`]
+impl From<vk::Result> for SiltError {
+    fn from(err: vk::Result) -> Self {
+        match err {
+            vk::Result::ERROR_DEVICE_LOST => SiltError::DeviceLost,
+            err => SiltError::Vk(err),
+        }
+    }
+}
+
+/// Recovers the original error if it was a `SiltError` passed through `anyhow`
+impl From<anyhow::Error> for SiltError {
+    fn from(err: anyhow::Error) -> Self {
+        err.downcast().unwrap_or_else(SiltError::Other)
+    }
+}
+
+impl SiltError {
+    pub fn is_device_lost(&self) -> bool {
+        matches!(self, SiltError::DeviceLost)
+    }
+}
+
+pub type Result<T, E = SiltError> = std::result::Result<T, E>;
@@ -10,4 +10,10 @@ pub mod pipeline;
 pub mod properties;
 pub mod material;
 pub mod resources;
-pub mod collections;
\ No newline at end of file
+pub mod collections;
+pub mod error;
+pub mod culling;
+pub mod model;
+pub mod debug;
+#[cfg(feature = "overlay")]
+pub mod overlay;
@@ -1,12 +1,16 @@
 use std::borrow::Cow;
-use std::ffi::CStr;
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
+use std::sync::Arc;
 
 use crate::prelude::*;
+use ash::extensions::ext::{ExtendedDynamicState, MeshShader};
+use ash::extensions::khr::{BufferDeviceAddress, PresentWait};
 use crate::properties::{DeviceFeaturesRequest, DeviceFeatures};
-use crate::resources::get_depth_format_prime_cache;
+use crate::resources::{get_depth_format_prime_cache, TextureQuality};
 use crate::sync::get_device_queues;
-use crate::sync::{QueueHandle, QueueRequest, QueueType};
-use anyhow::{anyhow, Result};
+use crate::sync::{GpuUploadHandle, QueueHandle, QueueRequest, QueueType};
+use crate::error::{Result, SiltError};
 use itertools::Itertools;
 use raw_window_handle::HasRawDisplayHandle;
 use raw_window_handle::HasRawWindowHandle;
@@ -20,6 +24,109 @@ pub struct LoaderCreateInfo {
     pub title: String,
     pub device_features: DeviceFeaturesRequest,
     pub queue_requests: Vec<QueueRequest>,
+    pub debug: DebugConfig,
+    pub validation: ValidationConfig,
+    pub device_selection: DeviceSelection,
+}
+
+/// Which physical device [`Loader::new`] uses. An explicitly chosen device must still meet
+/// the required features, queues and surface support, otherwise creation fails listing the
+/// available devices
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DeviceSelection {
+    /// First suitable device, preferring discrete over virtual over integrated GPUs
+    #[default]
+    Automatic,
+    /// Index into `vkEnumeratePhysicalDevices`
+    Index(usize),
+    /// Device whose name contains this, ignoring case
+    Name(String),
+    /// Like [`DeviceSelection::Automatic`] but ranking integrated GPUs first, e.g. to save power
+    PreferIntegrated,
+}
+
+impl DeviceSelection {
+    fn rank(&self, device_type: vk::PhysicalDeviceType) -> u32 {
+        match (self, device_type) {
+            (Self::PreferIntegrated, vk::PhysicalDeviceType::INTEGRATED_GPU) => 0,
+            (Self::PreferIntegrated, vk::PhysicalDeviceType::DISCRETE_GPU) => 1,
+            (_, vk::PhysicalDeviceType::DISCRETE_GPU) => 0,
+            (_, vk::PhysicalDeviceType::INTEGRATED_GPU) => 2,
+            (_, vk::PhysicalDeviceType::VIRTUAL_GPU) => 1,
+            (_, vk::PhysicalDeviceType::CPU) => 3,
+            _ => 4,
+        }
+    }
+
+    fn matches(&self, index: usize, info: &PhysicalDeviceInfo) -> bool {
+        match self {
+            Self::Index(selected) => *selected == index,
+            Self::Name(name) => info.name().to_lowercase().contains(&name.to_lowercase()),
+            Self::Automatic | Self::PreferIntegrated => true,
+        }
+    }
+
+    fn is_explicit(&self) -> bool {
+        matches!(self, Self::Index(_) | Self::Name(_))
+    }
+}
+
+/// Whether to enable `VK_LAYER_KHRONOS_validation`. If the layer isn't installed
+/// a warning is logged and the instance is created without it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationConfig {
+    Disabled,
+    Enabled,
+    EnabledWithGpuAssisted,
+    EnabledWithSync,
+}
+
+impl Default for ValidationConfig {
+    /// Enabled in debug builds only
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Self::Enabled
+        } else {
+            Self::Disabled
+        }
+    }
+}
+
+impl ValidationConfig {
+    fn features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        match self {
+            Self::EnabledWithGpuAssisted => vec![
+                vk::ValidationFeatureEnableEXT::GPU_ASSISTED,
+                vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT,
+            ],
+            Self::EnabledWithSync => vec![vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION],
+            _ => vec![],
+        }
+    }
+}
+
+/// Controls which validation messages reach the `log` crate
+#[derive(Debug, Clone, Copy)]
+pub struct DebugConfig {
+    pub severities: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub types: vk::DebugUtilsMessageTypeFlagsEXT,
+    /// Panics on validation errors, aborting the process since the panic can't unwind out of
+    /// the callback. Meant for tests, so errors fail loudly
+    pub panic_on_error: bool,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            severities: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            types: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            panic_on_error: false,
+        }
+    }
 }
 
 pub struct Loader {
@@ -30,8 +137,25 @@ pub struct Loader {
     pub debug: DebugUtils,
     pub surface: Surface,
     pub device: Device,
-    pub allocator: Allocator,
+    /// Shared with every [`GpuUploadHandle`], see [`Loader::upload_handle`]
+    pub allocator: Arc<Allocator>,
     pub swapchain: Swapchain,
+    /// Loaded if [`DeviceFeatures::BUFFER_DEVICE_ADDRESS`] is enabled
+    pub buffer_device_address: Option<BufferDeviceAddress>,
+    /// Loaded if [`DeviceFeatures::MESH_SHADER`] is enabled, see [`crate::pipeline::cmd_draw_mesh_tasks`]
+    pub mesh_shader: Option<MeshShader>,
+    /// Loaded if [`DeviceFeatures::EXTENDED_DYNAMIC_STATE`] is enabled, see [`crate::pipeline::cmd_set_cull_mode`]
+    pub extended_dynamic_state: Option<ExtendedDynamicState>,
+    /// Loaded if [`DeviceFeatures::PRESENT_WAIT`] is enabled, see [`crate::swapchain::Swapchain::wait_present`]
+    pub present_wait: Option<PresentWait>,
+    /// Features actually enabled on the device, a subset of the requested features
+    pub features: DeviceFeatures,
+    api_version: u32,
+    pdevice: vk::PhysicalDevice,
+    device_info: DeviceInfo,
+    instance_extensions: Vec<CString>,
+    device_extensions: Vec<CString>,
+    texture_quality: Cell<TextureQuality>,
 }
 
 pub struct LoaderHandles {
@@ -46,21 +170,47 @@ impl Loader {
         unsafe {
             let (window, context) =
                 get_window(loader_ci.width, loader_ci.height, &loader_ci.title)?;
-            let (entry, instance) = get_instance(&window, &loader_ci.title)?;
-            let (debug, debug_handle) = get_debug_hooks(&entry, &instance)?;
+            let (entry, instance, instance_version, instance_extensions) =
+                get_instance(&window, &loader_ci.title, loader_ci.validation)?;
+            let (debug, debug_handle) = get_debug_hooks(&entry, &instance, loader_ci.debug)?;
             let (surface, surface_handle) = get_surface(&window, &entry, &instance)?;
-            let (pdevice_handle, device, queue_handles) = get_device(
+            let SelectedDevice {
+                pdevice: pdevice_handle,
+                device,
+                queues: queue_handles,
+                features,
+                info: device_info,
+                extensions: device_extensions,
+            } = get_device(
                 &instance,
+                instance_version,
+                &instance_extensions,
                 &surface,
                 surface_handle,
-                loader_ci.queue_requests,
-                loader_ci.device_features,
+                &loader_ci,
             )?;
-            let allocator = get_allocator(&instance, &device, pdevice_handle)?;
+            let allocator = get_allocator(&instance, &device, pdevice_handle, features)?;
             let swapchain = Swapchain::new(&instance, &device);
+            let buffer_device_address = features
+                .contains(DeviceFeatures::BUFFER_DEVICE_ADDRESS)
+                .then(|| BufferDeviceAddress::new(&instance, &device));
+            let mesh_shader = features
+                .contains(DeviceFeatures::MESH_SHADER)
+                .then(|| MeshShader::new(&instance, &device));
+            let extended_dynamic_state = features
+                .contains(DeviceFeatures::EXTENDED_DYNAMIC_STATE)
+                .then(|| ExtendedDynamicState::new(&instance, &device));
+            let present_wait = features
+                .contains(DeviceFeatures::PRESENT_WAIT)
+                .then(|| PresentWait::new(&instance, &device));
+            let api_version = instance_version.min(
+                instance
+                    .get_physical_device_properties(pdevice_handle)
+                    .api_version,
+            );
 
             get_depth_format_prime_cache(&instance, pdevice_handle)
-                .ok_or(anyhow!("could not find suitable depth format"))?;
+                .ok_or_else(|| SiltError::UnsupportedFormat("could not find suitable depth format".into()))?;
 
             Ok((
                 Self {
@@ -73,6 +223,17 @@ impl Loader {
                     device,
                     allocator,
                     swapchain,
+                    buffer_device_address,
+                    mesh_shader,
+                    extended_dynamic_state,
+                    present_wait,
+                    features,
+                    api_version,
+                    pdevice: pdevice_handle,
+                    device_info,
+                    instance_extensions,
+                    device_extensions,
+                    texture_quality: Default::default(),
                 },
                 LoaderHandles {
                     debug_messenger: debug_handle,
@@ -83,8 +244,146 @@ impl Loader {
             ))
         }
     }
+
+    /// Version usable by the application as `(major, minor, patch)`, the lower of
+    /// the version the instance requests and the version the device supports
+    pub fn api_version(&self) -> (u32, u32, u32) {
+        (
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            vk::api_version_patch(self.api_version),
+        )
+    }
+
+    /// The physical device picked when the loader was created
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    /// Everything the surface supports on the loader's device, e.g. to offer real choices in a
+    /// settings menu before (re)creating the swapchain
+    pub fn query_surface_support(&self, surface: vk::SurfaceKHR) -> Result<SurfaceSupport> {
+        unsafe {
+            Ok(SurfaceSupport {
+                formats: Self::check(
+                    self.surface
+                        .get_physical_device_surface_formats(self.pdevice, surface),
+                )?,
+                present_modes: Self::check(
+                    self.surface
+                        .get_physical_device_surface_present_modes(self.pdevice, surface),
+                )?,
+                capabilities: Self::check(
+                    self.surface
+                        .get_physical_device_surface_capabilities(self.pdevice, surface),
+                )?,
+            })
+        }
+    }
+
+    pub fn enabled_instance_extensions(&self) -> &[CString] {
+        &self.instance_extensions
+    }
+
+    pub fn enabled_device_extensions(&self) -> &[CString] {
+        &self.device_extensions
+    }
+
+    /// Quality applied to every sampler created from here on
+    pub fn texture_quality(&self) -> TextureQuality {
+        self.texture_quality.get()
+    }
+
+    /// Existing samplers keep their old quality until rebuilt, see
+    /// [`crate::resources::Resource::apply_texture_quality`]
+    pub fn set_texture_quality(&self, quality: TextureQuality) {
+        self.texture_quality.set(quality);
+    }
+
+    /// Shares the device and allocator with a background thread, which submits its transfers to
+    /// `queue`. See [`GpuUploadHandle`] for the synchronization contract
+    pub fn upload_handle(&self, queue: &QueueHandle) -> Result<GpuUploadHandle> {
+        GpuUploadHandle::new(self.device.clone(), self.allocator.clone(), queue)
+    }
+
+    pub fn wait_idle(&self) -> Result<()> {
+        Self::check(unsafe { self.device.device_wait_idle() })
+    }
+
+    /// Fails while a [`GpuUploadHandle`] is still alive, see [`Loader::destroy`]
+    pub fn can_destroy(&self) -> Result<()> {
+        if Arc::strong_count(&self.allocator) > 1 {
+            return Err(SiltError::InvalidUsage(
+                "upload handles must be dropped before destroying the loader".into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the device to go idle, then tears down the allocator, device, surface,
+    /// debug messenger and instance in that order. Every other object, including the
+    /// swapchain, has to be destroyed beforehand. Fails if a [`GpuUploadHandle`] is still alive,
+    /// handing the loader and its handles back untouched so the teardown can be retried
+    pub fn destroy(
+        self,
+        handles: LoaderHandles,
+    ) -> std::result::Result<(), Box<(Self, LoaderHandles, SiltError)>> {
+        if let Err(err) = self.can_destroy().and_then(|_| self.wait_idle()) {
+            return Err(Box::new((self, handles, err)));
+        }
+
+        let Self {
+            allocator,
+            device,
+            surface,
+            debug,
+            instance,
+            ..
+        } = self;
+
+        // Frees the allocator's memory blocks, which needs the device alive
+        drop(allocator);
+
+        unsafe {
+            device.destroy_device(None);
+            surface.destroy_surface(handles.surface, None);
+            debug.destroy_debug_utils_messenger(handles.debug_messenger, None);
+            instance.destroy_instance(None);
+        }
+
+        Ok(())
+    }
+
+    /// Converts the result of a device operation like `?` does, surfacing `ERROR_DEVICE_LOST`
+    /// as [`SiltError::DeviceLost`], and logs the lost device
+    pub fn check<T>(result: ash::prelude::VkResult<T>) -> Result<T> {
+        result.map_err(|err| {
+            if err == vk::Result::ERROR_DEVICE_LOST {
+                log::error!("vulkan device lost");
+            }
+            SiltError::from(err)
+        })
+    }
+
+    /// Whether viewports may have a negative height, core in 1.1 or through `VK_KHR_maintenance1`
+    pub fn supports_negative_viewport_height(&self) -> bool {
+        self.api_version >= vk::API_VERSION_1_1
+            || self.supports_extension(vk::KhrMaintenance1Fn::name())
+    }
+
+    /// Whether `name` was enabled on either the instance or the device
+    pub fn supports_extension(&self, name: &CStr) -> bool {
+        self.instance_extensions
+            .iter()
+            .chain(&self.device_extensions)
+            .any(|extension| extension.as_c_str() == name)
+    }
 }
 
+/// Highest version requested when creating the instance, lowered to what the loader supports
+const API_VERSION: u32 = vk::API_VERSION_1_1;
+
 unsafe fn get_window(width: u32, height: u32, title: &str) -> Result<(Window, Context)> {
     let context = Context::new();
 
@@ -96,25 +395,71 @@ unsafe fn get_window(width: u32, height: u32, title: &str) -> Result<(Window, Co
     Ok((window, context))
 }
 
-unsafe fn get_instance(window: &Window, title: &str) -> Result<(Entry, Instance)> {
+unsafe fn get_instance(
+    window: &Window,
+    title: &str,
+    validation: ValidationConfig,
+) -> Result<(Entry, Instance, u32, Vec<CString>)> {
     let entry = Entry::linked();
+    let api_version = entry
+        .try_enumerate_instance_version()?
+        .unwrap_or(vk::API_VERSION_1_0)
+        .min(API_VERSION);
+
+    let validation_layer = CStr::from_bytes_with_nul_unchecked(b"VK_LAYER_KHRONOS_validation\0");
+    let validation = match validation {
+        ValidationConfig::Disabled => validation,
+        _ if entry
+            .enumerate_instance_layer_properties()?
+            .iter()
+            .any(|layer| CStr::from_ptr(layer.layer_name.as_ptr()) == validation_layer) =>
+        {
+            validation
+        }
+        _ => {
+            log::warn!("validation requested but VK_LAYER_KHRONOS_validation is not installed, skipping");
+            ValidationConfig::Disabled
+        }
+    };
+
+    let layer_names = match validation {
+        ValidationConfig::Disabled => vec![],
+        _ => vec![validation_layer.as_ptr()],
+    };
 
-    let layer_names =
-        vec![CStr::from_bytes_with_nul_unchecked(b"VK_LAYER_KHRONOS_validation\0").as_ptr()];
+    let validation_features = validation.features();
+    let validation_extensions = if validation_features.is_empty() {
+        vec![]
+    } else {
+        vec![vk::ExtValidationFeaturesFn::name().as_ptr()]
+    };
 
     #[allow(unused_mut)]
     let mut portability_extensions: Vec<*const i8> = vec![];
     #[cfg(any(target_os = "macos", target_os = "ios"))]
     {
         portability_extensions.push(vk::KhrPortabilityEnumerationFn::name().as_ptr());
-        portability_extensions.push(vk::KhrGetPhysicalDeviceProperties2Fn::name().as_ptr());
     }
 
+    // Enabled when present, needed by device extensions backing some DeviceFeatures
+    let available_extensions = entry.enumerate_instance_extension_properties(None)?;
+    let optional_extensions = OPTIONAL_INSTANCE_EXTENSIONS
+        .iter()
+        .map(|name| name.as_ptr())
+        .filter(|&name| {
+            available_extensions
+                .iter()
+                .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == CStr::from_ptr(name))
+        })
+        .collect_vec();
+
     let extension_names = ash_window::enumerate_required_extensions(window.raw_display_handle())
         .unwrap()
         .iter()
         .chain(std::iter::once(&DebugUtils::name().as_ptr()))
         .chain(&portability_extensions)
+        .chain(&optional_extensions)
+        .chain(&validation_extensions)
         .map(|ptr| *ptr)
         .collect::<Vec<_>>();
 
@@ -131,7 +476,7 @@ unsafe fn get_instance(window: &Window, title: &str) -> Result<(Entry, Instance)
         .application_version(vk::make_api_version(0, 1, 0, 0))
         .engine_name(CStr::from_bytes_with_nul_unchecked(b"silt\0"))
         .engine_version(vk::make_api_version(0, 0, 1, 0))
-        .api_version(vk::make_api_version(0, 1, 0, 0));
+        .api_version(api_version);
 
     let instance_flags = if cfg!(any(target_os = "macos", target_os = "ios")) {
         vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
@@ -139,33 +484,45 @@ unsafe fn get_instance(window: &Window, title: &str) -> Result<(Entry, Instance)
         vk::InstanceCreateFlags::empty()
     };
 
-    let instance_create_info = vk::InstanceCreateInfo::builder()
+    let mut validation_features_ci =
+        vk::ValidationFeaturesEXT::builder().enabled_validation_features(&validation_features);
+
+    let mut instance_create_info = vk::InstanceCreateInfo::builder()
         .flags(instance_flags)
         .application_info(&app_info)
         .enabled_layer_names(&layer_names)
         .enabled_extension_names(&extension_names);
 
+    if !validation_features.is_empty() {
+        instance_create_info = instance_create_info.push_next(&mut validation_features_ci);
+    }
+
     let instance = entry.create_instance(&instance_create_info, None)?;
+    let extensions = extension_names
+        .iter()
+        .map(|&name| CStr::from_ptr(name).to_owned())
+        .collect();
 
-    Ok((entry, instance))
+    Ok((entry, instance, api_version, extensions))
 }
 
 unsafe fn get_debug_hooks(
     entry: &Entry,
     instance: &Instance,
+    config: DebugConfig,
 ) -> Result<(DebugUtils, vk::DebugUtilsMessengerEXT)> {
+    // The user data pointer is only used as a flag, so there's nothing to keep alive
+    let user_data = if config.panic_on_error {
+        PANIC_ON_ERROR as *mut std::os::raw::c_void
+    } else {
+        std::ptr::null_mut()
+    };
+
     let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-        .message_severity(
-            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-        )
-        .message_type(
-            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-        )
-        .pfn_user_callback(Some(vulkan_debug_callback));
+        .message_severity(config.severities)
+        .message_type(config.types)
+        .pfn_user_callback(Some(vulkan_debug_callback))
+        .user_data(user_data);
 
     let debug_utils_loader = DebugUtils::new(entry, instance);
     let debug_callback = debug_utils_loader.create_debug_utils_messenger(&debug_info, None)?;
@@ -191,6 +548,35 @@ unsafe fn get_surface(
     Ok((surface_loader, surface))
 }
 
+/// Formats, present modes and capabilities of a surface, see [`Loader::query_surface_support`]
+#[derive(Debug, Clone)]
+pub struct SurfaceSupport {
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    pub capabilities: vk::SurfaceCapabilitiesKHR,
+}
+
+impl SurfaceSupport {
+    pub fn supports_format(&self, format: vk::SurfaceFormatKHR) -> bool {
+        self.formats
+            .iter()
+            .any(|f| f.format == format.format && f.color_space == format.color_space)
+    }
+
+    pub fn supports_present_mode(&self, present_mode: vk::PresentModeKHR) -> bool {
+        self.present_modes.contains(&present_mode)
+    }
+
+    /// Distinct color spaces across every format
+    pub fn color_spaces(&self) -> Vec<vk::ColorSpaceKHR> {
+        self.formats
+            .iter()
+            .map(|format| format.color_space)
+            .unique()
+            .collect()
+    }
+}
+
 pub struct PhysicalDeviceInfo {
     pub pdevice: vk::PhysicalDevice,
     pub queues: Vec<vk::QueueFamilyProperties>,
@@ -198,20 +584,79 @@ pub struct PhysicalDeviceInfo {
     pub features: vk::PhysicalDeviceFeatures,
 }
 
+impl PhysicalDeviceInfo {
+    pub fn name(&self) -> String {
+        unsafe { CStr::from_ptr(self.properties.device_name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Description of the physical device the loader picked, see [`Loader::device_info`]
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    /// Encoded in a vendor specific way
+    pub driver_version: u32,
+    pub api_version: u32,
+    /// Everything the device supports, a superset of [`Loader::features`]
+    pub supported_features: DeviceFeatures,
+}
+
+impl DeviceInfo {
+    fn new(info: &PhysicalDeviceInfo, supported_features: DeviceFeatures) -> Self {
+        let properties = &info.properties;
+        Self {
+            name: info.name(),
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            api_version: properties.api_version,
+            supported_features,
+        }
+    }
+
+    pub fn is_discrete(&self) -> bool {
+        self.device_type == vk::PhysicalDeviceType::DISCRETE_GPU
+    }
+}
+
+/// Returned by [`get_device`], `features` and `extensions` being those actually enabled
+struct SelectedDevice {
+    pdevice: vk::PhysicalDevice,
+    device: Device,
+    queues: Vec<QueueHandle>,
+    features: DeviceFeatures,
+    info: DeviceInfo,
+    extensions: Vec<CString>,
+}
+
 unsafe fn get_device(
     instance: &Instance,
+    instance_version: u32,
+    instance_extensions: &[CString],
     surface_loader: &Surface,
     surface: vk::SurfaceKHR,
-    queue_requests: Vec<QueueRequest>,
-    device_features: DeviceFeaturesRequest,
-) -> Result<(vk::PhysicalDevice, Device, Vec<QueueHandle>)> {
+    loader_ci: &LoaderCreateInfo,
+) -> Result<SelectedDevice> {
+    let LoaderCreateInfo {
+        queue_requests,
+        device_features,
+        device_selection,
+        ..
+    } = loader_ci;
+
     if queue_requests.is_empty() {
-        return Err(anyhow!(
-            "no queues requested. you,,, you need queues to do things bestie"
+        return Err(SiltError::DeviceSelection(
+            "no queues requested. you,,, you need queues to do things bestie".into(),
         ));
     }
 
-    let (info, queues, enabled_features) = instance
+    let devices = instance
         .enumerate_physical_devices()?
         .into_iter()
         .map(|pdevice| {
@@ -226,17 +671,18 @@ unsafe fn get_device(
                 features,
             }
         })
-        .sorted_by_cached_key(|info| match info.properties.device_type {
-            vk::PhysicalDeviceType::DISCRETE_GPU => 0,
-            vk::PhysicalDeviceType::VIRTUAL_GPU => 1,
-            vk::PhysicalDeviceType::INTEGRATED_GPU => 2,
-            vk::PhysicalDeviceType::CPU => 3,
-            _ => 4,
-        })
+        .collect_vec();
+
+    let selected = devices
+        .iter()
+        .enumerate()
+        .filter(|(index, info)| device_selection.matches(*index, info))
+        .map(|(_, info)| info)
+        .sorted_by_cached_key(|info| device_selection.rank(info.properties.device_type))
         .filter_map(|info| {
             let (queues, failures) = queue_requests
                 .iter()
-                .map(|request| request.suitability(&info))
+                .map(|request| request.suitability(info))
                 .partition_result::<Vec<_>, Vec<_>, _, _>();
 
             if !failures.is_empty() {
@@ -246,14 +692,37 @@ unsafe fn get_device(
             Some((info, queues))
         })
         .filter_map(|(info, queues)| {
-            let supported_features: DeviceFeatures = info.features.into();
+            let mut supported_features: DeviceFeatures = info.features.into();
+            if supports_buffer_device_address(instance, instance_extensions, info.pdevice) {
+                supported_features |= DeviceFeatures::BUFFER_DEVICE_ADDRESS;
+            }
+            if supports_mesh_shader(instance, instance_version, info).is_some() {
+                supported_features |= DeviceFeatures::MESH_SHADER;
+            }
+            if supports_present_wait(instance, instance_version, info) {
+                supported_features |= DeviceFeatures::PRESENT_WAIT;
+            }
+            if supports_sampler_filter_minmax(
+                instance,
+                instance_version,
+                instance_extensions,
+                info.pdevice,
+            ) {
+                supported_features |= DeviceFeatures::SAMPLER_FILTER_MINMAX;
+            }
+            if supports_multiview(instance, instance_version, info) {
+                supported_features |= DeviceFeatures::MULTIVIEW;
+            }
+            if supports_extended_dynamic_state(instance, instance_version, info) {
+                supported_features |= DeviceFeatures::EXTENDED_DYNAMIC_STATE;
+            }
 
             if !supported_features.contains(device_features.required) {
                 return None;
             }
 
             let enabled_features =
-                (supported_features & (device_features.prefered | device_features.required)).into();
+                supported_features & (device_features.prefered | device_features.required);
 
             let graphics_family = queues
                 .iter()
@@ -267,47 +736,317 @@ unsafe fn get_device(
                 return None;
             }
 
-            Some((info, queues, enabled_features))
+            Some((info, queues, supported_features, enabled_features))
         })
-        .next()
-        .ok_or(anyhow!("could not find suitable device"))?;
+        .next();
 
-    let device_extensions_raw = [
+    let (info, queues, supported_features, enabled_features) = match selected {
+        Some(selected) => selected,
+        None => {
+            let available = devices
+                .iter()
+                .enumerate()
+                .map(|(index, info)| format!("{}: {}", index, info.name()))
+                .join(", ");
+
+            return Err(SiltError::DeviceSelection(match device_selection.is_explicit() {
+                true => format!(
+                    "selected device {:?} is missing or unsuitable, available devices are [{}]",
+                    device_selection, available
+                ),
+                false => format!("could not find suitable device among [{}]", available),
+            }));
+        }
+    };
+
+    let mut device_extensions_raw = vec![
         Swapchain::name().as_ptr(),
         #[cfg(any(target_os = "macos", target_os = "ios"))]
         vk::KhrPortabilitySubsetFn::name().as_ptr(),
     ];
 
-    let device_ci = vk::DeviceCreateInfo::builder()
-        .enabled_extension_names(&device_extensions_raw)
-        .enabled_features(&enabled_features);
+    // Enabled when present, needed for negative viewport heights on a 1.0 device
+    let available_extensions = instance
+        .enumerate_device_extension_properties(info.pdevice)
+        .unwrap_or_default();
+    device_extensions_raw.extend(
+        OPTIONAL_DEVICE_EXTENSIONS
+            .iter()
+            .filter(|&&name| {
+                available_extensions
+                    .iter()
+                    .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == name)
+            })
+            .map(|name| name.as_ptr()),
+    );
+
+    let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeaturesKHR::builder()
+        .buffer_device_address(true);
+
+    let vk_features: vk::PhysicalDeviceFeatures = enabled_features.into();
+    let mut device_ci = vk::DeviceCreateInfo::builder().enabled_features(&vk_features);
+
+    if enabled_features.contains(DeviceFeatures::BUFFER_DEVICE_ADDRESS) {
+        device_extensions_raw.extend(BUFFER_DEVICE_ADDRESS_EXTENSIONS.map(|name| name.as_ptr()));
+        device_ci = device_ci.push_next(&mut buffer_device_address);
+    }
+
+    let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::builder().mesh_shader(true);
+    if enabled_features.contains(DeviceFeatures::MESH_SHADER) {
+        let task_shader = supports_mesh_shader(instance, instance_version, info)
+            .map_or(false, |features| features.task_shader == vk::TRUE);
+        mesh_shader = mesh_shader.task_shader(task_shader);
+
+        device_extensions_raw.extend(MESH_SHADER_EXTENSIONS.map(|name| name.as_ptr()));
+        device_ci = device_ci.push_next(&mut mesh_shader);
+    }
+
+    let mut present_id = vk::PhysicalDevicePresentIdFeaturesKHR::builder().present_id(true);
+    let mut present_wait = vk::PhysicalDevicePresentWaitFeaturesKHR::builder().present_wait(true);
+    if enabled_features.contains(DeviceFeatures::PRESENT_WAIT) {
+        device_extensions_raw.extend(PRESENT_WAIT_EXTENSIONS.map(|name| name.as_ptr()));
+        device_ci = device_ci
+            .push_next(&mut present_id)
+            .push_next(&mut present_wait);
+    }
+
+    if enabled_features.contains(DeviceFeatures::SAMPLER_FILTER_MINMAX) {
+        device_extensions_raw.push(vk::ExtSamplerFilterMinmaxFn::name().as_ptr());
+    }
+
+    let mut multiview = vk::PhysicalDeviceMultiviewFeatures::builder().multiview(true);
+    if enabled_features.contains(DeviceFeatures::MULTIVIEW) {
+        device_ci = device_ci.push_next(&mut multiview);
+    }
+
+    let mut extended_dynamic_state = vk::PhysicalDeviceExtendedDynamicStateFeaturesEXT::builder()
+        .extended_dynamic_state(true);
+    if enabled_features.contains(DeviceFeatures::EXTENDED_DYNAMIC_STATE) {
+        device_extensions_raw.push(vk::ExtExtendedDynamicStateFn::name().as_ptr());
+        device_ci = device_ci.push_next(&mut extended_dynamic_state);
+    }
+
+    let device_ci = device_ci.enabled_extension_names(&device_extensions_raw);
 
     let (device, queue_handles) = get_device_queues(instance, queues, info.pdevice, device_ci)?;
+    let extensions = device_extensions_raw
+        .iter()
+        .map(|&name| CStr::from_ptr(name).to_owned())
+        .collect();
 
-    Ok((info.pdevice, device, queue_handles))
+    Ok(SelectedDevice {
+        pdevice: info.pdevice,
+        device,
+        queues: queue_handles,
+        features: enabled_features,
+        info: DeviceInfo::new(info, supported_features),
+        extensions,
+    })
+}
+
+/// Device extensions needed for buffer device addresses on a 1.0 instance, device groups
+/// providing the `DEVICE_ADDRESS` memory allocation flag
+const BUFFER_DEVICE_ADDRESS_EXTENSIONS: [&CStr; 2] =
+    [vk::KhrBufferDeviceAddressFn::name(), vk::KhrDeviceGroupFn::name()];
+
+/// Device extensions needed for mesh shaders on a 1.1 device, `VK_EXT_mesh_shader` requiring SPIR-V 1.4
+const MESH_SHADER_EXTENSIONS: [&CStr; 3] = [
+    vk::ExtMeshShaderFn::name(),
+    vk::KhrSpirv14Fn::name(),
+    vk::KhrShaderFloatControlsFn::name(),
+];
+
+const PRESENT_WAIT_EXTENSIONS: [&CStr; 2] =
+    [vk::KhrPresentIdFn::name(), vk::KhrPresentWaitFn::name()];
+
+const OPTIONAL_DEVICE_EXTENSIONS: [&CStr; 1] = [vk::KhrMaintenance1Fn::name()];
+
+const OPTIONAL_INSTANCE_EXTENSIONS: [&CStr; 2] = [
+    vk::KhrGetPhysicalDeviceProperties2Fn::name(),
+    vk::KhrDeviceGroupCreationFn::name(),
+];
+
+/// The extension guarantees the `bufferDeviceAddress` feature, so no feature query is needed
+unsafe fn supports_buffer_device_address(
+    instance: &Instance,
+    instance_extensions: &[CString],
+    pdevice: vk::PhysicalDevice,
+) -> bool {
+    let device_extensions = instance
+        .enumerate_device_extension_properties(pdevice)
+        .unwrap_or_default();
+
+    OPTIONAL_INSTANCE_EXTENSIONS
+        .iter()
+        .all(|&name| instance_extensions.iter().any(|ext| ext.as_c_str() == name))
+        && BUFFER_DEVICE_ADDRESS_EXTENSIONS.iter().all(|&name| {
+            device_extensions
+                .iter()
+                .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == name)
+        })
+}
+
+/// Mesh shader features of the device, if the extensions are present and it can mesh shade at all.
+/// Querying them needs `vkGetPhysicalDeviceFeatures2`, so both instance and device have to be 1.1
+unsafe fn supports_mesh_shader(
+    instance: &Instance,
+    instance_version: u32,
+    info: &PhysicalDeviceInfo,
+) -> Option<vk::PhysicalDeviceMeshShaderFeaturesEXT> {
+    if instance_version < vk::API_VERSION_1_1 || info.properties.api_version < vk::API_VERSION_1_1 {
+        return None;
+    }
+
+    let device_extensions = instance
+        .enumerate_device_extension_properties(info.pdevice)
+        .unwrap_or_default();
+
+    let extensions_present = MESH_SHADER_EXTENSIONS.iter().all(|&name| {
+        device_extensions
+            .iter()
+            .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == name)
+    });
+
+    if !extensions_present {
+        return None;
+    }
+
+    let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut mesh_shader);
+    instance.get_physical_device_features2(info.pdevice, &mut features);
+
+    let mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT {
+        p_next: std::ptr::null_mut(),
+        ..mesh_shader
+    };
+
+    (mesh_shader.mesh_shader == vk::TRUE).then_some(mesh_shader)
+}
+
+/// Core in 1.1, but only queryable through `vkGetPhysicalDeviceFeatures2`
+unsafe fn supports_multiview(
+    instance: &Instance,
+    instance_version: u32,
+    info: &PhysicalDeviceInfo,
+) -> bool {
+    if instance_version < vk::API_VERSION_1_1 || info.properties.api_version < vk::API_VERSION_1_1 {
+        return false;
+    }
+
+    let mut multiview = vk::PhysicalDeviceMultiviewFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut multiview);
+    instance.get_physical_device_features2(info.pdevice, &mut features);
+
+    multiview.multiview == vk::TRUE
+}
+
+/// Queried like [`supports_mesh_shader`], through `vkGetPhysicalDeviceFeatures2`
+unsafe fn supports_extended_dynamic_state(
+    instance: &Instance,
+    instance_version: u32,
+    info: &PhysicalDeviceInfo,
+) -> bool {
+    if instance_version < vk::API_VERSION_1_1 || info.properties.api_version < vk::API_VERSION_1_1 {
+        return false;
+    }
+
+    let extension_present = instance
+        .enumerate_device_extension_properties(info.pdevice)
+        .unwrap_or_default()
+        .iter()
+        .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == vk::ExtExtendedDynamicStateFn::name());
+
+    if !extension_present {
+        return false;
+    }
+
+    let mut extended_dynamic_state = vk::PhysicalDeviceExtendedDynamicStateFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut extended_dynamic_state);
+    instance.get_physical_device_features2(info.pdevice, &mut features);
+
+    extended_dynamic_state.extended_dynamic_state == vk::TRUE
+}
+
+/// Both present ids and present waits, the latter is useless without the former
+unsafe fn supports_present_wait(
+    instance: &Instance,
+    instance_version: u32,
+    info: &PhysicalDeviceInfo,
+) -> bool {
+    if instance_version < vk::API_VERSION_1_1 || info.properties.api_version < vk::API_VERSION_1_1 {
+        return false;
+    }
+
+    let device_extensions = instance
+        .enumerate_device_extension_properties(info.pdevice)
+        .unwrap_or_default();
+
+    let extensions_present = PRESENT_WAIT_EXTENSIONS.iter().all(|&name| {
+        device_extensions
+            .iter()
+            .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == name)
+    });
+
+    if !extensions_present {
+        return false;
+    }
+
+    let mut present_id = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+    let mut present_wait = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut present_id)
+        .push_next(&mut present_wait);
+    instance.get_physical_device_features2(info.pdevice, &mut features);
+
+    present_id.present_id == vk::TRUE && present_wait.present_wait == vk::TRUE
+}
+
+/// The extension has no feature struct, support for individual formats is reported through
+/// `SAMPLED_IMAGE_FILTER_MINMAX` instead, see [`crate::resources::supports_filter_minmax`].
+/// It depends on `VK_KHR_get_physical_device_properties2`, core in 1.1
+unsafe fn supports_sampler_filter_minmax(
+    instance: &Instance,
+    instance_version: u32,
+    instance_extensions: &[CString],
+    pdevice: vk::PhysicalDevice,
+) -> bool {
+    let properties2 = instance_version >= vk::API_VERSION_1_1
+        || instance_extensions
+            .iter()
+            .any(|ext| ext.as_c_str() == vk::KhrGetPhysicalDeviceProperties2Fn::name());
+
+    properties2
+        && instance
+            .enumerate_device_extension_properties(pdevice)
+            .unwrap_or_default()
+            .iter()
+            .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == vk::ExtSamplerFilterMinmaxFn::name())
 }
 
 unsafe fn get_allocator(
     instance: &Instance,
     device: &Device,
     pdevice: vk::PhysicalDevice,
-) -> Result<Allocator> {
+    features: DeviceFeatures,
+) -> Result<Arc<Allocator>> {
     let allocator_create_info = vk::AllocatorCreateInfo {
         physical_device: pdevice,
         device: device.clone(),
         instance: instance.clone(),
         debug_settings: Default::default(),
-        buffer_device_address: false,
+        buffer_device_address: features.contains(DeviceFeatures::BUFFER_DEVICE_ADDRESS),
     };
 
-    Allocator::new(&allocator_create_info)
+    Ok(Arc::new(Allocator::new(&allocator_create_info)?))
 }
 
+const PANIC_ON_ERROR: usize = 1;
+
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
     let message_id_number = callback_data.message_id_number;
@@ -324,9 +1063,22 @@ unsafe extern "system" fn vulkan_debug_callback(
         CStr::from_ptr(callback_data.p_message).to_string_lossy()
     };
 
-    println!(
-        "{message_severity:?}:\n{message_type:?} [{message_id_name} ({message_id_number})] : {message}\n",
+    let level = match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => log::Level::Error,
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => log::Level::Warn,
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => log::Level::Info,
+        _ => log::Level::Trace,
+    };
+
+    log::log!(
+        target: "silt::validation",
+        level,
+        "{message_type:?} [{message_id_name} ({message_id_number})] : {message}",
     );
 
+    if level == log::Level::Error && user_data as usize == PANIC_ON_ERROR {
+        panic!("vulkan validation error: [{message_id_name}] {message}");
+    }
+
     vk::FALSE
 }
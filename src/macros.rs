@@ -1,8 +1,12 @@
-use anyhow::{anyhow, Result};
+use crate::error::{Result, SiltError};
 use ash::util::read_spv;
 use once_cell::sync::Lazy;
-use shaderc::{CompileOptions, Compiler, ShaderKind};
+use itertools::Itertools;
+use shaderc::{CompileOptions, Compiler, IncludeType, ResolvedInclude, ShaderKind};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io::Cursor;
 use std::path::{Path, PathBuf};
 
@@ -11,75 +15,211 @@ use crate::prelude::ShaderCode;
 
 static SHADERC_COMPILER: Lazy<Compiler> = Lazy::new(|| Compiler::new().unwrap());
 
+/// Compiles a shader at runtime, taking the path, [`ShaderOptions`] and optionally a list of
+/// include directories. Literal paths are relative to the invoking file, like `include_str!`,
+/// other paths and include directories to the working directory. `#include "file"` searches
+/// the including file's directory first, `#include <file>` only the include directories.
+/// The entry point defaults to `main`, HLSL entry points such as `VSMain` are passed last as
+/// `entry = "VSMain"`, see [`crate::material::MaterialSystemBuilder::add_shader_entry`]
 #[macro_export]
 macro_rules! compile {
     ($path: literal, $options: expr) => {
-        $crate::macros::__get_shader_code($path, include_str!($path), ($options).into(), std::path::Path::new(file!()).parent().unwrap().into())
+        $crate::compile!($path, $options, [] as [&str; 0])
+    };
+    ($path: literal, $options: expr, entry = $entry: expr) => {
+        $crate::compile!($path, $options, [] as [&str; 0], entry = $entry)
+    };
+    ($path: literal, $options: expr, $includes: expr) => {
+        $crate::compile!($path, $options, $includes, entry = "main")
+    };
+    ($path: literal, $options: expr, $includes: expr, entry = $entry: expr) => {
+        $crate::macros::__get_shader_code(
+            $path,
+            include_str!($path),
+            ($options).into(),
+            $entry,
+            std::path::Path::new(file!()).parent().unwrap().into(),
+            &$crate::macros::__include_dirs(std::path::Path::new(file!()).parent().unwrap(), $includes),
+        )
     };
     ($path: expr, $options: expr) => {
+        $crate::compile!($path, $options, [] as [&str; 0])
+    };
+    ($path: expr, $options: expr, entry = $entry: expr) => {
+        $crate::compile!($path, $options, [] as [&str; 0], entry = $entry)
+    };
+    ($path: expr, $options: expr, $includes: expr) => {
+        $crate::compile!($path, $options, $includes, entry = "main")
+    };
+    ($path: expr, $options: expr, $includes: expr, entry = $entry: expr) => {
         match std::fs::read($path) {
             Ok(text) => {
-                $crate::macros::__get_shader_code($path, std::str::from_utf8(&text).unwrap(), ($options).into(), std::env::current_dir().unwrap())
+                let invocation_path = std::env::current_dir().unwrap();
+                let includes = $crate::macros::__include_dirs(&invocation_path, $includes);
+                $crate::macros::__get_shader_code($path, std::str::from_utf8(&text).unwrap(), ($options).into(), $entry, invocation_path, &includes)
             },
-            _ => Err(anyhow::anyhow!("failed to read shader code"))
+            Err(err) => Err($crate::error::SiltError::Io(err))
         }
     };
 }
 
+pub fn __include_dirs<P: AsRef<Path>>(
+    invocation_path: &Path,
+    includes: impl IntoIterator<Item = P>,
+) -> Vec<PathBuf> {
+    includes
+        .into_iter()
+        .map(|dir| invocation_path.join(dir))
+        .collect()
+}
+
 pub fn __get_shader_code(
     path: &str,
     text: &str,
     options: ShaderOptions,
+    entry_point: &str,
     invocation_path: PathBuf,
+    include_dirs: &[PathBuf],
 ) -> Result<crate::prelude::ShaderCode> {
     let cache_enabled = options.contains(ShaderOptions::CACHE) && cfg!(target_os = "linux");
-    let compile_options = if options.contains(ShaderOptions::HLSL) {
-        let mut options = CompileOptions::new().unwrap();
-        options.set_source_language(shaderc::SourceLanguage::HLSL);
-        Some(options)
-    } else {
-        None
-    };
+    let source_path = invocation_path.join(path);
+    let includes = RefCell::new(Vec::<PathBuf>::new());
+
+    let kind = get_kind(path)
+        .ok_or_else(|| SiltError::ShaderCompilation(format!("failed to determine shader type of {}", path)))?;
 
-    let kind = get_kind(path).ok_or(anyhow!("failed to determine shader type"))?;
+    let mut compile_options = CompileOptions::new().unwrap();
+    if options.contains(ShaderOptions::HLSL) {
+        compile_options.set_source_language(shaderc::SourceLanguage::HLSL);
+    }
+    // VK_EXT_mesh_shader needs SPIR-V 1.4, which shaderc only emits for a Vulkan 1.2 target
+    if matches!(kind, ShaderKind::Mesh | ShaderKind::Task) {
+        compile_options.set_target_env(shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_2 as u32);
+        compile_options.set_target_spirv(shaderc::SpirvVersion::V1_4);
+    }
+    compile_options.set_include_callback(|requested, ty, requesting, _| {
+        let include = resolve_include(requested, ty, Path::new(requesting), include_dirs)?;
+        includes.borrow_mut().push(PathBuf::from(&include.resolved_name));
+        Ok(include)
+    });
 
-    let flat_path = String::from(path).replace("/", "_");
+    // One file can hold several entry points, each is cached separately
+    let flat_path = String::from(path).replace("/", "_") + "_" + entry_point;
     let spirv_path = String::from("/tmp/silt_") + &flat_path + ".spirv";
     let copy_path = String::from("/tmp/silt_") + &flat_path;
+    let includes_path = String::from("/tmp/silt_") + &flat_path + ".includes";
 
     if cache_enabled {
         let spirv_file = fs::File::open(&spirv_path);
         let copy_text = fs::read_to_string(&copy_path);
+        let cached_includes = fs::read_to_string(&includes_path)
+            .ok()
+            .and_then(|text| unchanged_includes(&text));
 
-        match (spirv_file, copy_text) {
-            (Ok(ref mut spirv_file), Ok(copy_text)) if copy_text == text => {
+        match (spirv_file, copy_text, cached_includes) {
+            (Ok(ref mut spirv_file), Ok(copy_text), Some(includes)) if copy_text == text => {
                 let code = read_spv(spirv_file)?;
                 return Ok(ShaderCode {
-                    code, kind
+                    code, kind, includes
                 });
             }
             _ => (),
         }
     }
 
-    let spirv =
-        SHADERC_COMPILER.compile_into_spirv(text, kind, path, "main", compile_options.as_ref())?;
+    let spirv = SHADERC_COMPILER
+        .compile_into_spirv(text, kind, &source_path.to_string_lossy(), entry_point, Some(&compile_options))
+        .map_err(|err| SiltError::ShaderCompilation(err.to_string()))?;
     let code = read_spv(&mut Cursor::new(spirv.as_binary_u8()))?;
+    drop(compile_options);
+    let includes = includes.into_inner().into_iter().unique().collect_vec();
 
     if cache_enabled {
         fs::write(&spirv_path, spirv.as_binary_u8())?;
-        fs::copy(invocation_path.join(path), copy_path)?;
+        fs::copy(&source_path, copy_path)?;
+        fs::write(&includes_path, record_includes(&includes)?)?;
     }
 
-    Ok(ShaderCode { code, kind })
+    Ok(ShaderCode { code, kind, includes })
 }
 
-fn get_kind(path: &str) -> Option<shaderc::ShaderKind> {
+/// Relative includes are searched next to the including file first, then like standard
+/// includes through `include_dirs` in order
+fn resolve_include(
+    requested: &str,
+    ty: IncludeType,
+    requesting: &Path,
+    include_dirs: &[PathBuf],
+) -> std::result::Result<ResolvedInclude, String> {
+    let candidates = match ty {
+        IncludeType::Relative => requesting
+            .parent()
+            .into_iter()
+            .map(Path::to_path_buf)
+            .chain(include_dirs.iter().cloned())
+            .collect_vec(),
+        IncludeType::Standard => include_dirs.to_vec(),
+    };
+
+    candidates
+        .iter()
+        .map(|dir| dir.join(requested))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            format!(
+                "{} not found in [{}], included from {}",
+                requested,
+                candidates.iter().map(|dir| dir.display()).join(", "),
+                requesting.display()
+            )
+        })
+        .and_then(|path| {
+            let content = fs::read_to_string(&path)
+                .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+            Ok(ResolvedInclude {
+                resolved_name: path.to_string_lossy().into_owned(),
+                content,
+            })
+        })
+}
+
+fn hash_file(path: &Path) -> std::io::Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    fs::read(path)?.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+/// One `hash path` line per include, so the cache can tell when any of them changed
+fn record_includes(includes: &[PathBuf]) -> Result<String> {
+    includes
+        .iter()
+        .map(|path| Ok(format!("{:016x} {}\n", hash_file(path)?, path.display())))
+        .collect()
+}
+
+/// The includes recorded by [`record_includes`], or `None` if any changed or went missing
+fn unchanged_includes(recorded: &str) -> Option<Vec<PathBuf>> {
+    recorded
+        .lines()
+        .map(|line| {
+            let (hash, path) = line.split_once(' ')?;
+            let path = PathBuf::from(path);
+            (u64::from_str_radix(hash, 16).ok()? == hash_file(&path).ok()?).then_some(path)
+        })
+        .collect()
+}
+
+pub(crate) fn get_kind(path: &str) -> Option<shaderc::ShaderKind> {
     let extension = Path::new(path).extension()?.to_str()?;
     match extension {
         "vert" => Some(shaderc::ShaderKind::Vertex),
         "frag" => Some(shaderc::ShaderKind::Fragment),
         "comp" => Some(shaderc::ShaderKind::Compute),
+        "geom" => Some(shaderc::ShaderKind::Geometry),
+        "tesc" => Some(shaderc::ShaderKind::TessControl),
+        "tese" => Some(shaderc::ShaderKind::TessEvaluation),
+        "mesh" => Some(shaderc::ShaderKind::Mesh),
+        "task" => Some(shaderc::ShaderKind::Task),
         _ => None,
     }
 }
@@ -102,3 +242,97 @@ macro_rules! bindable {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory under the system temp dir, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("silt_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn write(&self, path: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn relative_includes_prefer_the_including_directory() {
+        let dir = TempDir::new("relative_include");
+        let shader = dir.write("shaders/main.frag", "");
+        let local = dir.write("shaders/common.hlsl", "local");
+        dir.write("include/common.hlsl", "include dir");
+
+        let include_dirs = [dir.0.join("include")];
+        let resolved =
+            resolve_include("common.hlsl", IncludeType::Relative, &shader, &include_dirs).unwrap();
+
+        assert_eq!(resolved.content, "local");
+        assert_eq!(PathBuf::from(resolved.resolved_name), local);
+    }
+
+    #[test]
+    fn relative_includes_fall_back_to_include_dirs() {
+        let dir = TempDir::new("relative_fallback");
+        let shader = dir.write("shaders/main.frag", "");
+        dir.write("first/other.hlsl", "first");
+        dir.write("second/lighting.hlsl", "second");
+
+        let include_dirs = [dir.0.join("first"), dir.0.join("second")];
+        let resolved =
+            resolve_include("lighting.hlsl", IncludeType::Relative, &shader, &include_dirs).unwrap();
+        assert_eq!(resolved.content, "second");
+
+        let missing = resolve_include("missing.hlsl", IncludeType::Relative, &shader, &include_dirs);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn standard_includes_skip_the_including_directory() {
+        let dir = TempDir::new("standard_include");
+        let shader = dir.write("shaders/main.frag", "");
+        dir.write("shaders/common.hlsl", "local");
+
+        let include_dirs = [dir.0.join("include")];
+        assert!(resolve_include("common.hlsl", IncludeType::Standard, &shader, &include_dirs).is_err());
+
+        dir.write("include/common.hlsl", "include dir");
+        let resolved =
+            resolve_include("common.hlsl", IncludeType::Standard, &shader, &include_dirs).unwrap();
+        assert_eq!(resolved.content, "include dir");
+    }
+
+    #[test]
+    fn recorded_includes_detect_changes() {
+        let dir = TempDir::new("recorded_includes");
+        let includes = vec![dir.write("a.hlsl", "a"), dir.write("b.hlsl", "b")];
+
+        let recorded = record_includes(&includes).unwrap();
+        assert_eq!(unchanged_includes(&recorded), Some(includes.clone()));
+        assert_eq!(unchanged_includes(""), Some(vec![]));
+
+        dir.write("b.hlsl", "changed");
+        assert_eq!(unchanged_includes(&recorded), None);
+
+        let recorded = record_includes(&includes).unwrap();
+        fs::remove_file(&includes[0]).unwrap();
+        assert_eq!(unchanged_includes(&recorded), None);
+        assert!(record_includes(&includes).is_err());
+    }
+}
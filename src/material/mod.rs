@@ -1,19 +1,34 @@
-use anyhow::{anyhow, Result};
+use crate::error::{Result, SiltError};
 use bitflags::bitflags;
 use derive_more::{Deref, From, Into};
 use itertools::Itertools;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ffi::CString,
+    hash::Hash,
+    rc::{Rc, Weak},
+};
 
 use crate::{
-    collections::{ParitySet, PartialFrequencySet},
-    pipeline::{build_pipeline, build_render_pass},
+    collections::{Parity, ParitySet, PartialFrequencySet},
+    pipeline::{
+        build_compute_pipeline, build_derived_pipeline, build_pipeline, build_render_pass, declared_entry_points, declared_output_locations,
+        declared_spec_constants,
+        BlendMode, DerivationProbe, PipelineDerivation, PipelineState, RasterState, SpecConstant, Specialization,
+    },
+    properties::DeviceFeatures,
     prelude::*,
     resources::{
-        write_global_descriptor_sets, DescriptorSets, Discriminant, Layouts, ResourceBinding,
-        ResourceDescription,
+        AttachmentDescription, AttachmentType, ClearValue, DescriptorCacheStats, DescriptorSetCache, DescriptorSets, DescriptorWriter, Discriminant, FrameSync, FrameUniform, Layouts,
+        ResourceBinding, ResourceDescription, TypedResourceDescription,
     },
+    sync::FrameContext,
 };
 
+mod pbr;
+pub use pbr::*;
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct ShaderOptions: u32 {
@@ -32,7 +47,13 @@ impl Into<ShaderOptions> for () {
 pub struct ShaderModule {
     pub module: vk::ShaderModule,
     pub stage_flags: vk::ShaderStageFlags,
+    /// Function the stage starts in, `main` unless registered with [`MaterialSystemBuilder::add_shader_entry`]
+    pub entry_point: CString,
     pub resources: Vec<Rc<ResourceDescription>>,
+    /// Specialization constant ids declared by the module
+    pub spec_constants: Vec<u32>,
+    /// Output locations of the entry point, for a fragment shader the color attachments it writes
+    pub output_locations: Vec<u32>,
 }
 
 impl Destructible for ShaderModule {
@@ -41,17 +62,138 @@ impl Destructible for ShaderModule {
     }
 }
 
+impl ShaderModule {
+    /// Creates the module for one entry point of `code`, taking the stage from the entry point.
+    /// `id` only names the shader in errors, see [`MaterialSystemBuilder::add_shader_entry`]
+    /// for registering it with a material system
+    pub fn new(
+        loader: &Loader,
+        id: &Identifier,
+        code: ShaderCode,
+        entry_point: &str,
+        resources: impl IntoIterator<Item = Rc<ResourceDescription>>,
+    ) -> Result<Self> {
+        let entry_points = declared_entry_points(&code.code)?;
+        let stage_flags = match entry_points.iter().find(|(_, name)| name == entry_point) {
+            Some((stage, _)) if !stage.is_empty() => *stage,
+            Some(_) => shader_kind_to_shader_stage_flags(code.kind),
+            None => {
+                return Err(SiltError::InvalidUsage(format!(
+                    "shader {} has no entry point {}, found {:?}",
+                    id,
+                    entry_point,
+                    entry_points.iter().map(|(_, name)| name).collect_vec()
+                )))
+            }
+        };
+        let spec_constants = declared_spec_constants(&code.code)?;
+        let output_locations = declared_output_locations(&code.code, entry_point)?;
+        let entry_point = CString::new(entry_point).map_err(|_| {
+            SiltError::InvalidUsage(format!("entry point {:?} contains a nul byte", entry_point))
+        })?;
+
+        let create_info = vk::ShaderModuleCreateInfo::builder().code(&code.code);
+
+        let module = unsafe { loader.device.create_shader_module(&create_info, None)? };
+
+        Ok(Self {
+            module,
+            stage_flags,
+            entry_point,
+            resources: resources.into_iter().collect(),
+            spec_constants,
+            output_locations,
+        })
+    }
+
+    /// Builds a specialization for this stage, erroring on ids the module doesn't declare
+    pub fn specialize(&self, map: &[(u32, SpecConstant)]) -> Result<Specialization> {
+        let specialization = Specialization {
+            stage: self.stage_flags,
+            constants: map.to_vec(),
+        };
+
+        self.validate(&specialization)?;
+        Ok(specialization)
+    }
+
+    fn validate(&self, specialization: &Specialization) -> Result<()> {
+        match specialization
+            .constants
+            .iter()
+            .find(|(id, _)| !self.spec_constants.contains(id))
+        {
+            Some((id, _)) => Err(SiltError::InvalidUsage(format!(
+                "{:?} shader does not declare specialization constant {}",
+                self.stage_flags, id
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ShaderEffect {
     resources: Vec<Rc<ResourceDescription>>,
     shaders: Vec<Identifier>,
+    state: PipelineState,
+    compute: bool,
 }
 
 impl ShaderEffect {
-    pub fn new<'a, I>(loader: &Loader, modules: I) -> Result<Self>
+    pub fn new<'a, I>(loader: &Loader, modules: I, state: PipelineState) -> Result<Self>
     where
         I: IntoIterator<Item = (Identifier, &'a ShaderModule)> + Clone + 'a,
     {
+        let stages = modules
+            .clone()
+            .into_iter()
+            .fold(vk::ShaderStageFlags::empty(), |acc, (_, s)| acc | s.stage_flags);
+
+        let tessellation_stages =
+            vk::ShaderStageFlags::TESSELLATION_CONTROL | vk::ShaderStageFlags::TESSELLATION_EVALUATION;
+        let required_features = if stages.contains(vk::ShaderStageFlags::GEOMETRY) {
+            DeviceFeatures::GEOMETRY_SHADER
+        } else {
+            DeviceFeatures::empty()
+        } | if stages.intersects(tessellation_stages) {
+            DeviceFeatures::TESSELLATION_SHADER
+        } else {
+            DeviceFeatures::empty()
+        } | state.raster.required_features();
+
+        if !loader.features.contains(required_features) {
+            return Err(SiltError::MissingFeatures(required_features - loader.features));
+        }
+
+        if stages.intersects(tessellation_stages) && !stages.contains(tessellation_stages) {
+            return Err(SiltError::InvalidUsage(
+                "tessellation requires both control and evaluation stages".into(),
+            ));
+        }
+
+        let compute = stages.contains(vk::ShaderStageFlags::COMPUTE);
+        if compute && modules.clone().into_iter().count() != 1 {
+            return Err(SiltError::InvalidUsage(
+                "compute effects take exactly one compute stage".into(),
+            ));
+        }
+
+        for specialization in &state.specializations {
+            let (_, module) = modules
+                .clone()
+                .into_iter()
+                .find(|(_, s)| s.stage_flags == specialization.stage)
+                .ok_or_else(|| {
+                    SiltError::InvalidUsage(format!(
+                        "specialization for missing {:?} stage",
+                        specialization.stage
+                    ))
+                })?;
+
+            module.validate(specialization)?;
+        }
+
         let resources = modules
             .clone()
             .into_iter()
@@ -60,7 +202,17 @@ impl ShaderEffect {
 
         let (shaders, _) = modules.clone().into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
 
-        Ok(Self { resources, shaders })
+        Ok(Self {
+            resources,
+            shaders,
+            state,
+            compute,
+        })
+    }
+
+    /// Whether the effect is a single compute stage, built without a render pass
+    pub fn is_compute(&self) -> bool {
+        self.compute
     }
 }
 
@@ -80,18 +232,67 @@ pub struct MaterialSystemBuilder<'a> {
     shaders: HashMap<Identifier, ShaderModule>,
     effects: HashMap<Identifier, ShaderEffect>,
     skeletons: HashMap<Identifier, MaterialSkeleton>,
+    registry: Registry,
 }
 
 #[derive(Debug, Clone)]
 pub struct PipelineData {
     pub local_sets: DescriptorSets,
     pub pipeline: vk::Pipeline,
-    pub render_pass: vk::RenderPass,
+    /// `None` for compute effects
+    pub render_pass: Option<vk::RenderPass>,
+    pub bind_point: vk::PipelineBindPoint,
+    /// Created as a [`PipelineDerivation::Base`], so variants may derive from it
+    pub derivation_base: bool,
 }
 
 impl Destructible for PipelineData {
     fn destroy(self, loader: &Loader) {
         self.local_sets.destroy(loader);
+        self.pipeline.destroy(loader);
+        self.render_pass.into_iter().destroy(loader);
+    }
+}
+
+impl PipelineData {
+    pub fn is_compute(&self) -> bool {
+        self.bind_point == vk::PipelineBindPoint::COMPUTE
+    }
+
+    /// Binds the pipeline at its bind point
+    pub fn record_bind(&self, loader: &Loader, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            loader
+                .device
+                .cmd_bind_pipeline(command_buffer, self.bind_point, self.pipeline)
+        }
+    }
+}
+
+/// Depth only variant of an effect, see [`MaterialSystem::get_depth_prepass`].
+/// Shares the effect's pipeline layout, so the effect's descriptor sets are bound with it
+#[derive(Debug, Clone)]
+pub struct DepthPrepass {
+    pub pipeline: vk::Pipeline,
+    pub render_pass: vk::RenderPass,
+    /// Depth of the effect's [`crate::pipeline::DepthConfig`], see [`DepthPrepass::clear_value`]
+    pub clear_depth: f32,
+}
+
+impl DepthPrepass {
+    /// To begin the render pass with
+    pub fn clear_value(&self) -> vk::ClearValue {
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: self.clear_depth,
+                stencil: 0,
+            },
+        }
+    }
+}
+
+impl Destructible for DepthPrepass {
+    fn destroy(self, loader: &Loader) {
         self.pipeline.destroy(loader);
         self.render_pass.destroy(loader);
     }
@@ -149,23 +350,113 @@ impl<'a, R: Default + IntoIterator<Item = ResourceBinding<'a>>> ResourceProvider
     }
 }
 
+/// Bindings keyed by e.g. an entity, of which only the keys in `selection` are provided.
+/// Selected keys without a binding are skipped, so despawned entities needn't be deselected
+///
+/// ```no_run
+/// # use silt::material::MaterialSystemBuilder;
+/// # use silt::prelude::*;
+/// # use silt::resources::{BindableResource, Resource, SampledImage};
+/// # fn example(
+/// #     builder: MaterialSystemBuilder,
+/// #     effect_id: &Identifier,
+/// #     texture: &Resource<SampledImage>,
+/// # ) -> silt::error::Result<()> {
+/// type Entity = u32;
+/// let (entity, despawned): (Entity, Entity) = (0, 1);
+///
+/// let mut materials = builder.build_mapped::<Entity>()?;
+/// let locals = materials.get_local_resources_mut(effect_id)?;
+/// locals.material.insert(entity, texture.bind());
+/// locals.material.select([entity, despawned]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MappedResourceProvider<'a, K> {
+    pub resources: HashMap<K, ResourceBinding<'a>>,
+    pub selection: Vec<K>,
+}
+
+impl<'a, K> Default for MappedResourceProvider<'a, K> {
+    fn default() -> Self {
+        Self {
+            resources: Default::default(),
+            selection: vec![],
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq> MappedResourceProvider<'a, K> {
+    pub fn insert(&mut self, key: K, binding: ResourceBinding<'a>) -> Option<ResourceBinding<'a>> {
+        self.resources.insert(key, binding)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<ResourceBinding<'a>> {
+        self.resources.remove(key)
+    }
+
+    pub fn select(&mut self, keys: impl IntoIterator<Item = K>) {
+        self.selection = keys.into_iter().collect();
+    }
+}
+
+pub struct MappedResources<'b, 'a, K> {
+    resources: &'b HashMap<K, ResourceBinding<'a>>,
+    selection: std::slice::Iter<'b, K>,
+}
+
+impl<'b, 'a, K: Hash + Eq> Iterator for MappedResources<'b, 'a, K> {
+    type Item = &'b ResourceBinding<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let resources = self.resources;
+        self.selection.find_map(|key| resources.get(key))
+    }
+}
+
+impl<'a, K: Hash + Eq> ResourceProvider<'a> for MappedResourceProvider<'a, K> {
+    type Resource<'b> = &'b ResourceBinding<'a> where Self: 'b;
+    type Iter<'b> = MappedResources<'b, 'a, K> where Self: 'b;
+
+    fn get_resources<'b>(&'b mut self) -> Self::Iter<'b> {
+        MappedResources {
+            resources: &self.resources,
+            selection: self.selection.iter(),
+        }
+    }
+}
+
 pub struct MaterialSystem<'a, R: ResourceProvider<'a>> {
     // Copy of material description stuff
     resources: HashMap<Identifier, ResourceDescription>,
     shaders: HashMap<Identifier, ShaderModule>,
     effects: HashMap<Identifier, ShaderEffect>,
     skeletons: HashMap<Identifier, MaterialSkeleton>,
+    registry: Registry,
 
     // Descriptors
     descriptor_pool: RefCell<DescriptorPool>,
     layouts: Layouts,
     global_sets: Option<ParitySet<ManagedDescriptorSet>>,
     pipelines: HashMap<Identifier, PipelineData>,
+    prepasses: HashMap<Identifier, DepthPrepass>,
+    variants: HashMap<Identifier, Vec<(RasterState, BlendMode, vk::Pipeline)>>,
+    derive_pipelines: bool,
+    /// Times variant creation until it is known whether `derive_pipelines` pays off
+    derivation_probe: Option<DerivationProbe>,
+    set_cache: DescriptorSetCache,
 
     // Resource References
     _phantom: std::marker::PhantomData<&'a Self>,
     global_resources: R,
     local_resources: HashMap<Identifier, PartialFrequencySet<R>>,
+    /// Generation last written for each (effect, resource), `None` being the global sets
+    written: HashMap<(Option<Identifier>, Identifier), u64>,
+
+    // Frame tracking
+    frame: Rc<Cell<Parity>>,
+    frame_uniforms: RefCell<Vec<Weak<dyn FrameSync>>>,
 }
 
 impl<'a, R: ResourceProvider<'a>> Destructible for MaterialSystem<'a, R> {
@@ -174,6 +465,13 @@ impl<'a, R: ResourceProvider<'a>> Destructible for MaterialSystem<'a, R> {
         self.descriptor_pool.into_inner().destroy(loader);
         self.global_sets.into_iter().flatten().destroy(loader);
         self.pipelines.into_values().destroy(loader);
+        self.prepasses.into_values().destroy(loader);
+        self.set_cache.destroy(loader);
+        self.variants
+            .into_values()
+            .flatten()
+            .map(|(_, _, pipeline)| pipeline)
+            .destroy(loader);
     }
 }
 
@@ -185,6 +483,7 @@ impl<'a> MaterialSystemBuilder<'a> {
             shaders: Default::default(),
             effects: Default::default(),
             skeletons: Default::default(),
+            registry: Default::default(),
         }
     }
 
@@ -192,39 +491,68 @@ impl<'a> MaterialSystemBuilder<'a> {
         self.resources.get(id)
     }
 
+    /// Custom resources, carried over to the built [`MaterialSystem`]
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
     pub fn add_shader(
         &mut self,
         id: Identifier,
         code: ShaderCode,
         resources: impl IntoIterator<Item = Rc<ResourceDescription>>,
     ) -> Result<Identifier> {
-        let stage_flags = shader_kind_to_shader_stage_flags(code.kind);
-
-        let create_info = vk::ShaderModuleCreateInfo::builder().code(&code.code);
-
-        let module = unsafe {
-            self.loader
-                .device
-                .create_shader_module(&create_info, None)?
-        };
+        self.add_shader_entry(id, code, "main", resources)
+    }
 
-        let shader = ShaderModule {
-            module,
-            stage_flags,
-            resources: resources.into_iter().collect(),
-        };
+    /// Registers one entry point of `code`, so a module declaring several, e.g. HLSL's
+    /// `VSMain` and `PSMain`, can be added once per stage. The stage is taken from the entry point.
+    /// Compile HLSL sources with the same entry point, e.g. `compile!(path, options, entry = "VSMain")`
+    pub fn add_shader_entry(
+        &mut self,
+        id: Identifier,
+        code: ShaderCode,
+        entry_point: &str,
+        resources: impl IntoIterator<Item = Rc<ResourceDescription>>,
+    ) -> Result<Identifier> {
+        let shader = ShaderModule::new(self.loader, &id, code, entry_point, resources)?;
 
         if self.shaders.insert(id.clone(), shader).is_some() {
-            return Err(anyhow!("Resource id {} already exists", id));
+            return Err(SiltError::DuplicateIdentifier(id));
         };
 
         Ok(id)
     }
 
+    /// Specializes a registered shader, pass the result in the [`PipelineState`] of an effect
+    pub fn specialize(
+        &self,
+        shader: &Identifier,
+        map: &[(u32, SpecConstant)],
+    ) -> Result<Specialization> {
+        self.shaders
+            .get(shader)
+            .ok_or_else(|| SiltError::MissingIdentifier(shader.clone()))?
+            .specialize(map)
+    }
+
     pub fn register_effect(
         &mut self,
         id: Identifier,
         identifiers: impl IntoIterator<Item = Identifier> + Clone,
+    ) -> Result<Identifier> {
+        self.register_effect_with_state(id, identifiers, Default::default())
+    }
+
+    pub fn register_effect_with_state(
+        &mut self,
+        id: Identifier,
+        identifiers: impl IntoIterator<Item = Identifier> + Clone,
+        state: PipelineState,
     ) -> Result<Identifier> {
         let modules = identifiers
             .clone()
@@ -233,16 +561,16 @@ impl<'a> MaterialSystemBuilder<'a> {
                 self.shaders
                     .get_key_value(&id)
                     .map(|(id, shader)| (id.clone(), shader))
-                    .ok_or_else(|| anyhow!("Identifier {} does not point to a valid shader", id))
+                    .ok_or_else(|| SiltError::MissingIdentifier(id))
             })
             .collect::<Result<Vec<_>>>()?;
 
         if self
             .effects
-            .insert(id.clone(), ShaderEffect::new(self.loader, modules)?)
+            .insert(id.clone(), ShaderEffect::new(self.loader, modules, state)?)
             .is_some()
         {
-            return Err(anyhow!("Effect {} already exists", id));
+            return Err(SiltError::DuplicateIdentifier(id));
         }
 
         Ok(id)
@@ -254,7 +582,7 @@ impl<'a> MaterialSystemBuilder<'a> {
         skeleton: MaterialSkeleton,
     ) -> Result<Identifier> {
         if self.skeletons.insert(id.clone(), skeleton).is_some() {
-            return Err(anyhow!("Skeleton {} already exists", id));
+            return Err(SiltError::DuplicateIdentifier(id));
         }
 
         Ok(id)
@@ -293,15 +621,25 @@ impl<'a> MaterialSystemBuilder<'a> {
             shaders: self.shaders,
             effects: self.effects,
             skeletons: self.skeletons,
+            registry: self.registry,
 
             descriptor_pool,
             layouts,
             global_sets,
             pipelines: Default::default(),
+            prepasses: Default::default(),
+            variants: Default::default(),
+            derive_pipelines: true,
+            derivation_probe: Some(Default::default()),
+            set_cache: Default::default(),
 
             _phantom: std::marker::PhantomData,
             global_resources: global_resource_provider,
             local_resources: Default::default(),
+            written: Default::default(),
+
+            frame: Rc::new(Cell::new(Parity::Even)),
+            frame_uniforms: Default::default(),
         })
     }
 
@@ -314,6 +652,27 @@ impl<'a> MaterialSystemBuilder<'a> {
     ) -> Result<MaterialSystem<'b, DynamicResourceProvider<'b, R>>> {
         self.build(Default::default())
     }
+
+    pub fn build_mapped<'b, K: Hash + Eq>(
+        self,
+    ) -> Result<MaterialSystem<'b, MappedResourceProvider<'b, K>>> {
+        self.build(Default::default())
+    }
+}
+
+/// Variants only derive from effect pipelines created as bases, which those created while
+/// derivation was turned off aren't, even if it has been turned on since
+fn variant_derivation(
+    derive_pipelines: bool,
+    probe: Option<&DerivationProbe>,
+    base: vk::Pipeline,
+    derivation_base: bool,
+) -> PipelineDerivation {
+    match (derive_pipelines && derivation_base, probe) {
+        (false, _) => PipelineDerivation::Independent,
+        (true, Some(probe)) => probe.next(base),
+        (true, None) => PipelineDerivation::Derived(base),
+    }
 }
 
 impl<'a, R: ResourceProvider<'a>> MaterialSystem<'a, R> {
@@ -325,6 +684,14 @@ impl<'a, R: ResourceProvider<'a>> MaterialSystem<'a, R> {
         &mut self.global_resources
     }
 
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
     pub fn try_get_local_resources(&self, id: &Identifier) -> Option<&PartialFrequencySet<R>> {
         self.local_resources.get(id)
     }
@@ -339,15 +706,134 @@ impl<'a, R: ResourceProvider<'a>> MaterialSystem<'a, R> {
                 .entry(id.clone())
                 .or_insert_with(|| Default::default()))
         } else {
-            Err(anyhow!("{} Not a valid shader effect", id))
+            Err(SiltError::MissingIdentifier(id.clone()))
         }
     }
 
+    pub fn frame(&self) -> Parity {
+        self.frame.get()
+    }
+
+    /// Moves on to the next frame, writing any pending values of [`FrameUniform`]s into
+    /// that frame's buffers. Call once per frame, after waiting on the new frame's fence
+    pub fn advance_frame(&self) -> Parity {
+        let mut frame = self.frame.get();
+        frame.swap();
+        self.frame.set(frame);
+
+        self.frame_uniforms.borrow_mut().retain(|uniform| match uniform.upgrade() {
+            Some(uniform) => {
+                uniform.sync(frame);
+                true
+            }
+            None => false,
+        });
+
+        frame
+    }
+
+    pub fn frame_uniform<T: Copy + 'static>(
+        &self,
+        loader: &Loader,
+        description: &TypedResourceDescription<T>,
+        value: T,
+        name: Option<Identifier>,
+    ) -> Result<FrameUniform<T>> {
+        let (uniform, sync) =
+            FrameUniform::new(loader, description, value, name, self.frame.clone())?;
+        self.frame_uniforms.borrow_mut().push(sync);
+
+        Ok(uniform)
+    }
+
+    /// Global set for the current frame, referencing that frame's uniform buffers
+    pub fn global_set(&self) -> Option<vk::DescriptorSet> {
+        self.global_sets
+            .as_ref()
+            .map(|sets| **sets.get(self.frame.get()))
+    }
+
+    /// Object frequency set of the effect `id` for the current frame, written by
+    /// [`MaterialSystem::write_local_sets`]. Bound per draw with a dynamic offset, see [`crate::resources::ObjectUniforms`]
+    pub fn object_set(&self, id: &Identifier) -> Option<vk::DescriptorSet> {
+        self.pipelines
+            .get(id)?
+            .local_sets
+            .sets
+            .get(vk::PartialDescriptorFrequency::Object)
+            .as_ref()
+            .map(|sets| **sets.get(self.frame.get()))
+    }
+
+    /// Forces every descriptor referencing the resource `id` to be rewritten on the next write
+    pub fn mark_dirty(&mut self, id: &Identifier) {
+        self.written.retain(|(_, resource), _| resource != id);
+        self.set_cache.invalidate(id);
+    }
+
+    /// Sets for the `frequency` bindings of the effect `id`, shared with every other lookup
+    /// binding the same resources, see [`DescriptorSetCache`]. Unlike the effect's own local
+    /// sets, these can differ per material, e.g. for per material textures
+    pub fn get_cached_sets<'r, I, T>(
+        &mut self,
+        loader: &Loader,
+        id: &Identifier,
+        frequency: vk::PartialDescriptorFrequency,
+        resources: I,
+    ) -> Result<ParitySet<vk::DescriptorSet>>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<ResourceBinding<'r>>,
+    {
+        let layout = self
+            .layouts
+            .get(id)
+            .ok_or_else(|| SiltError::MissingIdentifier(id.clone()))?;
+
+        if layout.descriptors.get(frequency.into()).is_none() {
+            return Err(SiltError::InvalidUsage(format!(
+                "effect {} has no descriptor set at frequency {:?}",
+                id, frequency
+            )));
+        }
+
+        self.set_cache.get_or_write(
+            loader,
+            &mut self.descriptor_pool.borrow_mut(),
+            layout,
+            frequency.into(),
+            resources,
+        )
+    }
+
+    pub fn descriptor_cache_stats(&self) -> DescriptorCacheStats {
+        self.set_cache.stats()
+    }
+
+    /// Frees cached sets retired by resource changes, call after waiting on every frame in flight
+    pub fn release_retired_sets(&mut self, loader: &Loader) {
+        self.set_cache.release_retired(loader);
+    }
+
+    /// Only writes resources that haven't been written yet, or whose generation changed
     pub fn write_global_sets(&mut self, loader: &Loader) -> Result<()> {
         if let Some(sets) = &self.global_sets {
             let unmanaged = sets.as_ref().map(|managed| **managed);
-            let resources = self.global_resources.get_resources();
-            write_global_descriptor_sets(loader, resources, unmanaged)?
+            let mut writer = DescriptorWriter::default();
+            let mut written = vec![];
+
+            for resource in self.global_resources.get_resources() {
+                let resource = resource.as_ref();
+                let key = (None, resource.description.id().clone());
+
+                if self.written.get(&key) != Some(&resource.generation) {
+                    resource.queue_global(&mut writer, unmanaged)?;
+                    written.push((key, resource.generation));
+                }
+            }
+
+            writer.submit(loader);
+            self.written.extend(written);
         }
 
         Ok(())
@@ -363,19 +849,35 @@ impl<'a, R: ResourceProvider<'a>> MaterialSystem<'a, R> {
             let resources = self
                 .local_resources
                 .get_mut(id)
-                .ok_or(anyhow!(
-                    "Local resource provider for effect {} has not yet been initialized",
-                    id
-                ))?
+                .ok_or_else(|| {
+                    SiltError::InvalidUsage(format!(
+                        "Local resource provider for effect {} has not yet been initialized",
+                        id
+                    ))
+                })?
                 .get_mut(frequency)
                 .get_resources();
-            pipeline
-                .local_sets
-                .write_descriptor_sets(loader, resources)?;
+
+            let sets = pipeline.local_sets.get_unmanaged_sets().into_frequency_set(None);
+            let mut writer = DescriptorWriter::default();
+            let mut written = vec![];
+
+            for resource in resources {
+                let resource = resource.as_ref();
+                let key = (Some(id.clone()), resource.description.id().clone());
+
+                if self.written.get(&key) != Some(&resource.generation) {
+                    resource.queue_partial(&mut writer, sets)?;
+                    written.push((key, resource.generation));
+                }
+            }
+
+            writer.submit(loader);
+            self.written.extend(written);
 
             Ok(())
         } else {
-            Err(anyhow!("Effect {} does not exist", id))
+            Err(SiltError::MissingIdentifier(id.clone()))
         }
     }
 
@@ -392,11 +894,248 @@ impl<'a, R: ResourceProvider<'a>> MaterialSystem<'a, R> {
         Ok(self.pipelines.get(id).unwrap())
     }
 
+    /// Pipeline, layout and current frame's global, pass and material sets of the effect `id`,
+    /// creating its pipeline if needed. The object set is left out, as it is bound per draw
+    pub fn get_frame_context(&mut self, loader: &Loader, id: &Identifier) -> Result<FrameContext> {
+        let frame = self.frame.get();
+        let global_set = self.global_set();
+
+        let pipeline = self.get_effect_pipeline(loader, id)?;
+        let (pipeline, local_sets) = (pipeline.pipeline, pipeline.local_sets.get_unmanaged_sets());
+        let local_sets = [
+            vk::PartialDescriptorFrequency::Pass,
+            vk::PartialDescriptorFrequency::Material,
+        ]
+        .into_iter()
+        .filter_map(|frequency| local_sets.get(frequency).map(|sets| *sets.get(frame)));
+
+        Ok(FrameContext {
+            pipeline,
+            layout: self.layouts.get(id).unwrap().pipeline,
+            descriptor_sets: global_set.into_iter().chain(local_sets).collect(),
+        })
+    }
+
+    /// Whether effect pipelines are created as bases for [`MaterialSystem::get_effect_pipeline_variant`].
+    /// By default the first variants alternate between derived and independent creation, see
+    /// [`DerivationProbe`], and derivation is turned off unless it turns out faster. Most desktop
+    /// drivers ignore derivation entirely. Setting it explicitly skips the measurement, and only
+    /// affects pipelines created afterwards
+    pub fn set_pipeline_derivatives(&mut self, enabled: bool) {
+        self.derive_pipelines = enabled;
+        self.derivation_probe = None;
+    }
+
+    /// Whether new effect pipelines are derivation bases, see [`MaterialSystem::set_pipeline_derivatives`]
+    pub fn pipeline_derivatives(&self) -> bool {
+        self.derive_pipelines
+    }
+
+    /// Pipeline for the effect `id` with its raster state and blend mode replaced, derived
+    /// from the effect pipeline and sharing its render pass and descriptor sets
+    pub fn get_effect_pipeline_variant(
+        &mut self,
+        loader: &Loader,
+        id: &Identifier,
+        raster: RasterState,
+        blend_mode: BlendMode,
+    ) -> Result<vk::Pipeline> {
+        let existing = self.variants.get(id).and_then(|variants| {
+            variants
+                .iter()
+                .find(|(r, b, _)| *r == raster && *b == blend_mode)
+                .map(|(_, _, pipeline)| *pipeline)
+        });
+
+        if let Some(pipeline) = existing {
+            return Ok(pipeline);
+        }
+
+        let base = self.get_effect_pipeline(loader, id)?;
+        let (base_pipeline, render_pass, derivation_base) = match base.render_pass {
+            Some(render_pass) => (base.pipeline, render_pass, base.derivation_base),
+            None => {
+                return Err(SiltError::InvalidUsage(format!(
+                    "compute effect {} has no raster state or blend mode to vary",
+                    id
+                )))
+            }
+        };
+        let derivation = variant_derivation(
+            self.derive_pipelines,
+            self.derivation_probe.as_ref(),
+            base_pipeline,
+            derivation_base,
+        );
+
+        let effect = self.effects.get(id).unwrap();
+        let state = PipelineState {
+            raster,
+            blend_modes: vec![blend_mode],
+            ..effect.state.clone()
+        };
+
+        let start = std::time::Instant::now();
+        let pipeline = build_derived_pipeline(
+            loader,
+            render_pass,
+            self.layouts.get(id).unwrap(),
+            effect.resources.iter().map(|resource| resource.as_ref()),
+            effect.shaders.iter().map(|id| self.shaders.get(id).unwrap()),
+            &state,
+            derivation,
+        )?;
+        let elapsed = start.elapsed();
+        log::debug!("created {:?} variant of {} in {:?}", derivation, id, elapsed);
+
+        if let Some(probe) = self.derivation_probe.as_mut() {
+            probe.record(derivation, elapsed);
+            if let Some(faster) = probe.verdict() {
+                log::info!(
+                    "pipeline derivatives are {} on this driver, {} them for new variants",
+                    if faster { "faster" } else { "not faster" },
+                    if faster { "keeping" } else { "disabling" },
+                );
+                self.derive_pipelines = faster;
+                self.derivation_probe = None;
+            }
+        }
+
+        self.variants
+            .entry(id.clone())
+            .or_default()
+            .push((raster, blend_mode, pipeline));
+
+        Ok(pipeline)
+    }
+
+    /// Depth only pipeline for the effect `id`, used to fill the depth buffer before the
+    /// shaded pass to cut overdraw. Reuses every stage but the fragment shader, and renders
+    /// into a pass holding just the effect's depth attachment, which the prepass always clears
+    /// to [`DepthPrepass::clear_value`] from `UNDEFINED`. The effect's own depth attachment should
+    /// then be described with [`crate::resources::ClearValue::Load`], so the shaded pass keeps
+    /// the prepass depth
+    pub fn get_depth_prepass(&mut self, loader: &Loader, id: &Identifier) -> Result<&DepthPrepass> {
+        if !self.prepasses.contains_key(id) {
+            let prepass = self.generate_depth_prepass(loader, id)?;
+            self.prepasses.insert(id.clone(), prepass);
+        }
+
+        Ok(self.prepasses.get(id).unwrap())
+    }
+
+    fn generate_depth_prepass(&self, loader: &Loader, id: &Identifier) -> Result<DepthPrepass> {
+        let effect = self
+            .effects
+            .get(id)
+            .ok_or_else(|| SiltError::MissingIdentifier(id.clone()))?;
+
+        if effect.is_compute() {
+            return Err(SiltError::InvalidUsage(format!(
+                "compute effect {} can't have a depth prepass",
+                id
+            )));
+        }
+
+        // Always cleared, whatever the effect's own pass does with its depth attachment
+        let depth = effect.state.depth.clear_value;
+        let resources = effect
+            .resources
+            .iter()
+            .filter_map(|resource| match resource.as_ref() {
+                ResourceDescription::Attachment(attachment)
+                    if attachment.ty == AttachmentType::DepthStencil =>
+                {
+                    Some(ResourceDescription::Attachment(AttachmentDescription {
+                        clear: ClearValue::Depth(depth),
+                        ..attachment.clone()
+                    }))
+                }
+                ResourceDescription::Attachment(_) => None,
+                resource => Some(resource.clone()),
+            })
+            .collect_vec();
+        let resources = resources.iter();
+
+        if !resources.clone().any(|resource| resource.is_attachment()) {
+            return Err(SiltError::InvalidUsage(format!(
+                "effect {} has no depth attachment for a depth prepass",
+                id
+            )));
+        }
+
+        if !effect.state.depth.write {
+            return Err(SiltError::InvalidUsage(format!(
+                "effect {} doesn't write depth, a depth prepass would be empty",
+                id
+            )));
+        }
+
+        let shaders = effect
+            .shaders
+            .iter()
+            .map(|id| self.shaders.get(id).unwrap())
+            .filter(|module| module.stage_flags != vk::ShaderStageFlags::FRAGMENT);
+
+        let state = PipelineState {
+            blend_modes: vec![],
+            specializations: effect
+                .state
+                .specializations
+                .iter()
+                .filter(|specialization| specialization.stage != vk::ShaderStageFlags::FRAGMENT)
+                .cloned()
+                .collect(),
+            ..effect.state.clone()
+        };
+
+        let layout = self.layouts.get(id).unwrap();
+        let render_pass = build_render_pass(loader, resources.clone(), state.multiview)?;
+        let pipeline = build_pipeline(loader, render_pass, layout, resources, shaders, &state)?;
+
+        Ok(DepthPrepass {
+            pipeline,
+            render_pass,
+            clear_depth: depth,
+        })
+    }
+
+    /// Clear values to begin the effect's render pass with, in attachment order.
+    /// Depth attachments left at their default clear take the depth of the effect's
+    /// [`crate::pipeline::DepthConfig`], explicit clears are kept as given
+    pub fn get_effect_clear_values(&self, id: &Identifier) -> Result<Vec<vk::ClearValue>> {
+        let effect = self
+            .effects
+            .get(id)
+            .ok_or_else(|| SiltError::MissingIdentifier(id.clone()))?;
+
+        let depth = effect.state.depth.clear_value;
+        let mut clear_values = vec![];
+        for resource in &effect.resources {
+            match resource.as_ref() {
+                ResourceDescription::Attachment(attachment)
+                    if attachment.ty == AttachmentType::DepthStencil
+                        && attachment.clear == attachment.ty.default_clear() =>
+                {
+                    clear_values.push(vk::ClearValue {
+                        depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+                    });
+                }
+                ResourceDescription::Attachment(attachment) => {
+                    clear_values.push(attachment.clear_value())
+                }
+                _ => (),
+            }
+        }
+
+        Ok(clear_values)
+    }
+
     fn generate_effect_pipeline(&self, loader: &Loader, id: &Identifier) -> Result<PipelineData> {
         let effect = self
             .effects
             .get(id)
-            .ok_or(anyhow!("Effect {} does not exist", id))?;
+            .ok_or_else(|| SiltError::MissingIdentifier(id.clone()))?;
         let resources = effect.resources.iter().map(|resource| resource.as_ref());
         let shaders = effect
             .shaders
@@ -407,13 +1146,41 @@ impl<'a, R: ResourceProvider<'a>> MaterialSystem<'a, R> {
         let local_sets =
             DescriptorSets::allocate(loader, &mut self.descriptor_pool.borrow_mut(), layout, None)?;
 
-        let render_pass = build_render_pass(loader, resources.clone())?;
-        let pipeline = build_pipeline(loader, render_pass, layout, resources, shaders)?;
+        if effect.is_compute() {
+            let shader = shaders.clone().next().unwrap();
+            let pipeline = build_compute_pipeline(loader, layout, shader, &effect.state)?;
+
+            return Ok(PipelineData {
+                local_sets,
+                pipeline,
+                render_pass: None,
+                bind_point: vk::PipelineBindPoint::COMPUTE,
+                derivation_base: false,
+            });
+        }
+
+        let derivation = match self.derive_pipelines {
+            true => PipelineDerivation::Base,
+            false => PipelineDerivation::Independent,
+        };
+
+        let render_pass = build_render_pass(loader, resources.clone(), effect.state.multiview)?;
+        let pipeline = build_derived_pipeline(
+            loader,
+            render_pass,
+            layout,
+            resources,
+            shaders,
+            &effect.state,
+            derivation,
+        )?;
 
         Ok(PipelineData {
             local_sets,
             pipeline,
-            render_pass,
+            render_pass: Some(render_pass),
+            bind_point: vk::PipelineBindPoint::GRAPHICS,
+            derivation_base: derivation == PipelineDerivation::Base,
         })
     }
 }
@@ -427,3 +1194,90 @@ pub enum Resource<'a> {
     Attachment(RedundantSet<&'a Image>),
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id;
+    use crate::resources::{BindableResource, Resource, Sampler};
+
+    fn sampler(description: &Rc<ResourceDescription>) -> Resource<Sampler> {
+        let sampler = Sampler {
+            sampler: vk::Sampler::null(),
+            config: Default::default(),
+            properties: Default::default(),
+            mips: 1,
+        };
+
+        Resource::new(sampler, description.clone())
+    }
+
+    #[test]
+    fn variants_only_derive_from_bases() {
+        let base = vk::Pipeline::null();
+        assert_eq!(
+            variant_derivation(true, None, base, true),
+            PipelineDerivation::Derived(base)
+        );
+
+        // Derivation turned on after the effect pipeline was created without it
+        assert_eq!(
+            variant_derivation(true, None, base, false),
+            PipelineDerivation::Independent
+        );
+        assert_eq!(
+            variant_derivation(true, Some(&DerivationProbe::default()), base, false),
+            PipelineDerivation::Independent
+        );
+        assert_eq!(
+            variant_derivation(false, None, base, true),
+            PipelineDerivation::Independent
+        );
+    }
+
+    #[test]
+    fn mapped_resources_skip_missing_keys() {
+        let description =
+            ResourceDescription::sampler(id!("Sampler"), 0, vk::DescriptorFrequency::Material);
+        let (first, second) = (sampler(&description), sampler(&description));
+
+        let mut provider = MappedResourceProvider::default();
+        provider.insert(1, first.bind());
+        provider.insert(3, second.bind());
+        provider.select([0, 1, 2, 3, 4]);
+
+        let generations = provider
+            .get_resources()
+            .map(|binding| binding.generation)
+            .collect_vec();
+        assert_eq!(
+            generations,
+            [first.generation.get(), second.generation.get()]
+        );
+    }
+
+    #[test]
+    fn mapped_resources_follow_selection_order() {
+        let description =
+            ResourceDescription::sampler(id!("Sampler"), 0, vk::DescriptorFrequency::Material);
+        let (first, second) = (sampler(&description), sampler(&description));
+
+        let mut provider = MappedResourceProvider::default();
+        provider.insert("first", first.bind());
+        provider.insert("second", second.bind());
+
+        provider.select(["second", "despawned", "first"]);
+        let generations = provider
+            .get_resources()
+            .map(|binding| binding.generation)
+            .collect_vec();
+        assert_eq!(
+            generations,
+            [second.generation.get(), first.generation.get()]
+        );
+
+        provider.remove(&"second");
+        provider.select(["second"]);
+        assert_eq!(provider.get_resources().count(), 0);
+    }
+}
@@ -0,0 +1,208 @@
+use std::rc::Rc;
+
+use crate::collections::Parity;
+use crate::error::Result;
+use crate::properties::ProvidedFeatures;
+use crate::resources::{
+    BindableResource, ColorSpace, ImageFile, Resource, ResourceBinding, ResourceDescription, SampledImage,
+    TypedResourceDescription, UniformBuffer,
+};
+use crate::sync::CommandPool;
+use crate::{id, prelude::*};
+
+/// Metallic-roughness factors, laid out to match a std140 uniform block:
+/// ```glsl
+/// layout(set = 2, binding = 0) uniform PbrFactors {
+///     vec4 base_color;
+///     vec4 emissive;
+///     float metallic;
+///     float roughness;
+///     float normal_scale;
+///     float occlusion_strength;
+/// };
+/// ```
+/// Defaults follow glTF's `pbrMetallicRoughness`, so a material without factors renders as authored
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PbrFactors {
+    pub base_color: glam::Vec4,
+    /// Linear rgb, w is unused
+    pub emissive: glam::Vec4,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+}
+
+impl Default for PbrFactors {
+    fn default() -> Self {
+        Self {
+            base_color: glam::Vec4::ONE,
+            emissive: glam::Vec4::ZERO,
+            metallic: 1.,
+            roughness: 1.,
+            normal_scale: 1.,
+            occlusion_strength: 1.,
+        }
+    }
+}
+
+/// Conventional bindings of a [`PbrMaterial`], all at [`vk::DescriptorFrequency::Material`]:
+/// the factors at 0, then base color, metallic-roughness, normal, occlusion and emissive
+/// textures at 1 through 5. Create once and pass [`PbrDescriptions::all`] to the shaders reading them
+#[derive(Debug, Clone)]
+pub struct PbrDescriptions {
+    pub factors: TypedResourceDescription<PbrFactors>,
+    pub base_color: Rc<ResourceDescription>,
+    pub metallic_roughness: Rc<ResourceDescription>,
+    pub normal: Rc<ResourceDescription>,
+    pub occlusion: Rc<ResourceDescription>,
+    pub emissive: Rc<ResourceDescription>,
+}
+
+impl Default for PbrDescriptions {
+    fn default() -> Self {
+        let frequency = vk::DescriptorFrequency::Material;
+        Self {
+            factors: ResourceDescription::uniform(id!("PBR Factors"), 0, frequency),
+            base_color: ResourceDescription::sampled_image(id!("PBR Base Color"), 1, frequency),
+            metallic_roughness: ResourceDescription::sampled_image(
+                id!("PBR Metallic Roughness"),
+                2,
+                frequency,
+            ),
+            normal: ResourceDescription::sampled_image(id!("PBR Normal"), 3, frequency),
+            occlusion: ResourceDescription::sampled_image(id!("PBR Occlusion"), 4, frequency),
+            emissive: ResourceDescription::sampled_image(id!("PBR Emissive"), 5, frequency),
+        }
+    }
+}
+
+impl PbrDescriptions {
+    pub fn all(&self) -> [Rc<ResourceDescription>; 6] {
+        [
+            self.factors.clone().into(),
+            self.base_color.clone(),
+            self.metallic_roughness.clone(),
+            self.normal.clone(),
+            self.occlusion.clone(),
+            self.emissive.clone(),
+        ]
+    }
+}
+
+/// Source images of a [`PbrMaterial`]. Missing slots are replaced by a single texel that leaves
+/// the factors unchanged, e.g. white for base color and a flat normal. Only base color and
+/// emissive are uploaded as sRGB, whatever the [`ImageFile::color_space`] of the others
+#[derive(Debug, Default)]
+pub struct PbrTextures {
+    pub base_color: Option<ImageFile>,
+    pub metallic_roughness: Option<ImageFile>,
+    pub normal: Option<ImageFile>,
+    pub occlusion: Option<ImageFile>,
+    pub emissive: Option<ImageFile>,
+}
+
+pub struct PbrMaterial {
+    pub factors: Resource<UniformBuffer<PbrFactors>>,
+    pub base_color: Resource<SampledImage>,
+    pub metallic_roughness: Resource<SampledImage>,
+    pub normal: Resource<SampledImage>,
+    pub occlusion: Resource<SampledImage>,
+    pub emissive: Resource<SampledImage>,
+}
+
+impl Destructible for PbrMaterial {
+    fn destroy(self, loader: &Loader) {
+        self.factors.resource.destroy(loader);
+        self.base_color.resource.destroy(loader);
+        self.metallic_roughness.resource.destroy(loader);
+        self.normal.resource.destroy(loader);
+        self.occlusion.resource.destroy(loader);
+        self.emissive.resource.destroy(loader);
+    }
+}
+
+impl PbrMaterial {
+    pub fn new(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        pool: &CommandPool,
+        descriptions: &PbrDescriptions,
+        factors: PbrFactors,
+        textures: PbrTextures,
+    ) -> Result<Self> {
+        let upload = |description: &Rc<ResourceDescription>,
+                      file: Option<ImageFile>,
+                      fallback,
+                      color_space| {
+            description.bind_result(|_| {
+                let file = file.unwrap_or_else(|| ImageFile::solid(fallback));
+                ImageFile { color_space, ..file }.upload_to_gpu(
+                    loader,
+                    features,
+                    pool,
+                    Default::default(),
+                )
+            })
+        };
+
+        let values = factors;
+        let factors = descriptions.factors.bind_result(|description| {
+            UniformBuffer::new(loader, description, values, Some(description.id().clone()))
+        })?;
+        factors.resource.copy(Parity::Even, values);
+        factors.resource.copy(Parity::Odd, values);
+
+        let mut images = vec![];
+        // Only base color and emissive hold color, the other maps are sampled as data
+        for (description, file, fallback, color_space) in [
+            (&descriptions.base_color, textures.base_color, [255; 4], ColorSpace::Srgb),
+            (&descriptions.metallic_roughness, textures.metallic_roughness, [255; 4], ColorSpace::Linear),
+            (&descriptions.normal, textures.normal, [128, 128, 255, 255], ColorSpace::Linear),
+            (&descriptions.occlusion, textures.occlusion, [255; 4], ColorSpace::Linear),
+            (&descriptions.emissive, textures.emissive, [255; 4], ColorSpace::Srgb),
+        ] {
+            match upload(description, file, fallback, color_space) {
+                Ok(image) => images.push(image),
+                Err(err) => {
+                    factors.resource.destroy(loader);
+                    images
+                        .into_iter()
+                        .for_each(|image| image.resource.destroy(loader));
+                    return Err(err);
+                }
+            }
+        }
+
+        let [base_color, metallic_roughness, normal, occlusion, emissive]: [_; 5] =
+            images.try_into().unwrap();
+
+        Ok(Self {
+            factors,
+            base_color,
+            metallic_roughness,
+            normal,
+            occlusion,
+            emissive,
+        })
+    }
+
+    /// Writes new factors into the buffer of `parity`, which must not be in flight
+    pub fn set_factors(&self, parity: Parity, factors: PbrFactors) {
+        self.factors.resource.copy(parity, factors);
+    }
+
+    /// Every binding of the material, to be placed in the material frequency of
+    /// [`super::MaterialSystem::get_local_resources_mut`]
+    pub fn bindings(&self) -> Vec<ResourceBinding<'_>> {
+        vec![
+            self.factors.bind(),
+            self.base_color.bind(),
+            self.metallic_roughness.bind(),
+            self.normal.bind(),
+            self.occlusion.bind(),
+            self.emissive.bind(),
+        ]
+    }
+}
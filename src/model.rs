@@ -0,0 +1,862 @@
+use anyhow::Result;
+use itertools::Itertools;
+use memoffset::offset_of;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::culling::{Aabb, Frustum};
+use crate::error::SiltError;
+use crate::properties::ProvidedFeatures;
+use crate::resources::{
+    BindingDescription, Buffer, DescriptorWriter, ImageFile, ResourceReference, SampledImage,
+    VertexInput,
+};
+use crate::sync::{CommandPool, FrameContext, Recordable};
+use crate::{id, prelude::*};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Vertex {
+    pub pos: glam::Vec3,
+    pub color: glam::Vec3,
+    pub uv: glam::Vec2,
+}
+
+impl VertexInput for Vertex {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()]
+    }
+
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(offset_of!(Vertex, pos) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32_SFLOAT)
+                .offset(offset_of!(Vertex, color) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(offset_of!(Vertex, uv) as u32)
+                .build(),
+        ]
+    }
+}
+
+/// Per instance model transform, read from binding 1 at locations 3 through 6.
+/// Pair with [`Vertex`] as `(Vertex, Instance)` when describing the vertex input
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Instance {
+    pub transform: glam::Mat4,
+}
+
+impl Instance {
+    pub const BINDING: u32 = 1;
+
+    pub fn bind(loader: &Loader, command_buffer: vk::CommandBuffer, instances: &Buffer) {
+        unsafe {
+            loader.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                Self::BINDING,
+                &[instances.buffer],
+                &[0],
+            )
+        };
+    }
+}
+
+impl VertexInput for Instance {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::builder()
+            .binding(Self::BINDING)
+            .stride(std::mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()]
+    }
+
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        // A mat4 takes up one location per column
+        (0..4)
+            .map(|column| {
+                vk::VertexInputAttributeDescription::builder()
+                    .binding(Self::BINDING)
+                    .location(3 + column)
+                    .format(vk::Format::R32G32B32A32_SFLOAT)
+                    .offset(column * std::mem::size_of::<glam::Vec4>() as u32)
+                    .build()
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MVP {
+    pub model: glam::Mat4,
+    pub view: glam::Mat4,
+    pub proj: glam::Mat4,
+}
+
+impl MVP {
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(self.proj * self.view * self.model)
+    }
+
+    /// Right handed perspective projection, `fov_y` in radians. Flips Y to match Vulkan's
+    /// downward clip space Y, depth maps `near..far` to `0..1`
+    pub fn set_perspective(&mut self, fov_y: f32, aspect: f32, near: f32, far: f32) {
+        self.proj = glam::Mat4::perspective_rh(fov_y, aspect, near, far);
+        self.proj.y_axis.y *= -1.;
+    }
+
+    /// [`MVP::set_perspective`] with depth mapping `near..far` to `1..0`, for pipelines using
+    /// [`crate::pipeline::DepthConfig::reverse_z`]
+    pub fn set_perspective_reverse_z(&mut self, fov_y: f32, aspect: f32, near: f32, far: f32) {
+        self.proj = perspective_rh_reverse_z(fov_y, aspect, near, far);
+    }
+
+    /// Right handed orthographic projection, with the same Y flip and depth range as [`MVP::set_perspective`]
+    pub fn set_orthographic(
+        &mut self,
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    ) {
+        self.proj = glam::Mat4::orthographic_rh(left, right, bottom, top, near, far);
+        self.proj.y_axis.y *= -1.;
+    }
+
+    pub fn look_at(&mut self, eye: glam::Vec3, target: glam::Vec3, up: glam::Vec3) {
+        self.view = glam::Mat4::look_at_rh(eye, target, up);
+    }
+}
+
+/// Right handed, Y flipped perspective projection mapping `near` to depth 1 and `far` to depth 0
+pub fn perspective_rh_reverse_z(fov_y: f32, aspect: f32, near: f32, far: f32) -> glam::Mat4 {
+    let mut proj = glam::Mat4::perspective_rh(fov_y, aspect, far, near);
+    proj.y_axis.y *= -1.;
+    proj
+}
+
+/// Subpixel camera offsets for temporal antialiasing, cycling through the Halton (2, 3) sequence.
+/// Apply to the projection each frame, then [`Jitter::advance`] once the frame is recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jitter {
+    pub frame: u32,
+    /// Offsets before the sequence repeats
+    pub period: u32,
+}
+
+impl Default for Jitter {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl Jitter {
+    pub fn new(period: u32) -> Self {
+        Self {
+            frame: 0,
+            period: period.max(1),
+        }
+    }
+
+    pub fn advance(&mut self) {
+        self.frame = (self.frame + 1) % self.period;
+    }
+
+    /// Offset of the current frame in pixels, within `-0.5..0.5`
+    pub fn offset(&self) -> glam::Vec2 {
+        // Index 0 of the sequence is the origin, skipped so every frame moves
+        let index = self.frame % self.period + 1;
+        glam::vec2(halton(index, 2), halton(index, 3)) - 0.5
+    }
+
+    /// `proj` shifted by the current offset on a target of `extent`. Works for any projection,
+    /// as the shift is applied in clip space
+    pub fn apply(&self, proj: glam::Mat4, extent: vk::Extent2D) -> glam::Mat4 {
+        let ndc = self.offset() * 2. / glam::vec2(extent.width as f32, extent.height as f32);
+        glam::Mat4::from_translation(ndc.extend(0.)) * proj
+    }
+}
+
+/// Element `index` of the van der Corput sequence in `base`, within `0..1`
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.;
+    let mut result = 0.;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
+
+/// Vertices and indices of several meshes packed into one buffer, all vertices
+/// first followed by all indices. Bound once with [`MeshBuffer::bind`], each
+/// [`Mesh`] then draws its own range
+#[derive(Debug, Clone)]
+pub struct MeshBuffer {
+    pub buffer: Buffer,
+    pub index_offset: vk::DeviceSize,
+}
+
+impl Destructible for MeshBuffer {
+    fn destroy(self, loader: &Loader) {
+        self.buffer.destroy(loader);
+    }
+}
+
+impl MeshBuffer {
+    /// Each mesh is given as its vertices, indices, and material index
+    pub fn new(
+        loader: &Loader,
+        pool: &CommandPool,
+        meshes: &[(&[Vertex], &[u32], usize)],
+        name: &Identifier,
+    ) -> Result<(Self, Vec<Mesh>)> {
+        let vertices = meshes
+            .iter()
+            .flat_map(|(vertices, _, _)| vertices.iter().copied())
+            .collect::<Vec<_>>();
+        let indices = meshes
+            .iter()
+            .flat_map(|(_, indices, _)| indices.iter().copied())
+            .collect::<Vec<_>>();
+
+        let mut first_index = 0;
+        let mut vertex_offset = 0;
+        let ranges = meshes
+            .iter()
+            .map(|&(vertices, indices, material)| {
+                let mesh = Mesh {
+                    first_index,
+                    index_count: indices.len() as u32,
+                    vertex_offset,
+                    material,
+                    aabb: Aabb::from_points(vertices.iter().map(|vertex| vertex.pos))
+                        .unwrap_or_default(),
+                };
+
+                first_index += indices.len() as u32;
+                vertex_offset += vertices.len() as i32;
+                mesh
+            })
+            .collect();
+
+        // Vertex is 4 byte aligned, so the index data following it is too
+        let index_offset = std::mem::size_of_val(&vertices[..]) as vk::DeviceSize;
+        let data = unsafe {
+            std::slice::from_raw_parts(vertices.as_ptr() as *const u8, index_offset as usize)
+        }
+        .iter()
+        .chain(unsafe {
+            std::slice::from_raw_parts(
+                indices.as_ptr() as *const u8,
+                std::mem::size_of_val(&indices[..]),
+            )
+        })
+        .copied()
+        .collect::<Vec<_>>();
+
+        let buffer = Buffer::upload_to_gpu(
+            loader,
+            pool,
+            &data,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER,
+            id!(format!("{} Mesh Buffer", name)),
+        )?;
+
+        Ok((
+            Self {
+                buffer,
+                index_offset,
+            },
+            ranges,
+        ))
+    }
+
+    pub fn bind(&self, loader: &Loader, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            loader
+                .device
+                .cmd_bind_vertex_buffers(command_buffer, 0, &[self.buffer.buffer], &[0]);
+            loader.device.cmd_bind_index_buffer(
+                command_buffer,
+                self.buffer.buffer,
+                self.index_offset,
+                vk::IndexType::UINT32,
+            );
+        }
+    }
+}
+
+/// Range of a [`MeshBuffer`] belonging to a single mesh
+#[derive(Debug, Clone, Copy)]
+pub struct Mesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    /// Index into [`Model::textures`]
+    pub material: usize,
+    pub aabb: Aabb,
+}
+
+impl Mesh {
+    /// Draws `instance_count` instances. The mesh buffer must already be bound with [`MeshBuffer::bind`],
+    /// and the instance buffer with [`Instance::bind`]
+    pub fn record_instanced(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        instance_count: u32,
+        first_instance: u32,
+    ) {
+        unsafe {
+            loader.device.cmd_draw_indexed(
+                command_buffer,
+                self.index_count,
+                instance_count,
+                self.first_index,
+                self.vertex_offset,
+                first_instance,
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub buffer: MeshBuffer,
+    pub meshes: Vec<Mesh>,
+    /// Diffuse texture of each material, the last being a white fallback for untextured meshes
+    pub textures: Vec<SampledImage>,
+    pub aabb: Aabb,
+    material_sets: Vec<ManagedDescriptorSet>,
+    /// Set once [`Recordable::record`] has warned about drawing without material sets
+    warned_untextured: Cell<bool>,
+}
+
+impl Destructible for Model {
+    fn destroy(self, loader: &Loader) {
+        self.buffer.destroy(loader);
+        self.textures.destroy(loader);
+        self.material_sets.destroy(loader);
+    }
+}
+
+impl Model {
+    /// Loads every shape in the obj, along with the diffuse textures of its `.mtl` materials.
+    /// Missing materials or textures fall back to white
+    pub fn load(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        pool: &CommandPool,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        Self::load_with(loader, features, pool, path, false)
+    }
+
+    /// Like [`Model::load`], but merges identical vertices with [`deduplicate_vertices`] and
+    /// reorders each mesh's triangles with [`optimize_vertex_cache`]
+    pub fn load_optimized(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        pool: &CommandPool,
+        path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        Self::load_with(loader, features, pool, path, true)
+    }
+
+    fn load_with(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        pool: &CommandPool,
+        path: impl AsRef<Path>,
+        optimize: bool,
+    ) -> Result<Self> {
+        let (models, materials) = tobj::load_obj(path.as_ref(), &tobj::GPU_LOAD_OPTIONS)?;
+        let materials = materials.unwrap_or_else(|err| {
+            log::warn!("failed to load materials for {:?}: {}", path.as_ref(), err);
+            vec![]
+        });
+
+        let directory = path.as_ref().parent().unwrap_or(Path::new(""));
+        let mut textures = vec![];
+        let material_textures = materials
+            .iter()
+            .map(|material| {
+                if material.diffuse_texture.is_empty() {
+                    return Ok(None);
+                }
+
+                let image = match ImageFile::new(directory.join(&material.diffuse_texture)) {
+                    Ok(image) => image,
+                    Err(err) => {
+                        log::warn!(
+                            "failed to load texture {} for material {}: {}",
+                            material.diffuse_texture,
+                            material.name,
+                            err
+                        );
+                        return Ok(None);
+                    }
+                };
+
+                textures.push(image.upload_to_gpu(loader, features, pool, Default::default())?);
+                Ok(Some(textures.len() - 1))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let fallback = textures.len();
+        textures.push(ImageFile::solid([255; 4]).upload_to_gpu(
+            loader,
+            features,
+            pool,
+            Default::default(),
+        )?);
+
+        let geometry = models
+            .iter()
+            .map(|model| {
+                let mesh = &model.mesh;
+                let vertices = mesh
+                    .positions
+                    .chunks_exact(3)
+                    .enumerate()
+                    .map(|(idx, pos)| Vertex {
+                        pos: glam::vec3(pos[0], pos[1], pos[2]),
+                        color: glam::Vec3::ONE,
+                        uv: mesh
+                            .texcoords
+                            .get(idx * 2..idx * 2 + 2)
+                            .map(|uv| glam::vec2(uv[0], 1. - uv[1]))
+                            .unwrap_or_default(),
+                    })
+                    .collect::<Vec<_>>();
+
+                if !optimize {
+                    return (vertices, mesh.indices.clone());
+                }
+
+                let (vertices, mut indices) = deduplicate_vertices(&vertices, &mesh.indices);
+                optimize_vertex_cache(&mut indices, vertices.len());
+                (vertices, indices)
+            })
+            .collect::<Vec<_>>();
+
+        let mesh_data = geometry
+            .iter()
+            .zip(&models)
+            .map(|((vertices, indices), model)| {
+                let material = model
+                    .mesh
+                    .material_id
+                    .and_then(|id| material_textures.get(id).copied().flatten())
+                    .unwrap_or(fallback);
+
+                (&vertices[..], &indices[..], material)
+            })
+            .collect::<Vec<_>>();
+
+        let name = path.as_ref().to_string_lossy();
+        let (buffer, meshes) = MeshBuffer::new(loader, pool, &mesh_data, &id!(name.as_ref()))?;
+
+        let aabb = meshes
+            .iter()
+            .map(|mesh| mesh.aabb)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or_default();
+
+        Ok(Self {
+            buffer,
+            meshes,
+            textures,
+            aabb,
+            material_sets: vec![],
+            warned_untextured: Cell::new(false),
+        })
+    }
+
+    /// Allocates a set per texture, written as a combined image sampler at `binding`.
+    /// Required before [`Model::record_textured`], and for [`Recordable::record`] to bind textures
+    pub fn allocate_material_sets(
+        &mut self,
+        loader: &Loader,
+        pool: &mut DescriptorPool,
+        layout: vk::DescriptorSetLayout,
+        binding: u32,
+    ) -> Result<()> {
+        let sets = pool.allocate(loader, &vec![layout; self.textures.len()])?;
+        let binding = BindingDescription {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            binding,
+            ..Default::default()
+        };
+
+        let mut writer = DescriptorWriter::default();
+        for (texture, set) in self.textures.iter().zip(&sets) {
+            writer.push(&ResourceReference::Image(texture), &binding, **set);
+        }
+        writer.submit(loader);
+
+        std::mem::replace(&mut self.material_sets, sets).destroy(loader);
+        Ok(())
+    }
+
+    /// Draws every mesh, binding its material's set at index `set` of `layout` whenever the material changes.
+    /// Fails without recording anything if [`Model::allocate_material_sets`] was never called
+    pub fn record_textured(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        set: u32,
+    ) -> Result<()> {
+        if self.material_sets.len() != self.textures.len() {
+            return Err(SiltError::InvalidUsage(
+                "material sets must be allocated before recording a textured model".to_string(),
+            )
+            .into());
+        }
+
+        self.buffer.bind(loader, command_buffer);
+        self.meshes.iter().fold(None, |bound, mesh| {
+            if bound != Some(mesh.material) {
+                unsafe {
+                    loader.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        layout,
+                        set,
+                        &[*self.material_sets[mesh.material]],
+                        &[],
+                    )
+                };
+            }
+
+            mesh.record_instanced(loader, command_buffer, 1, 0);
+            Some(mesh.material)
+        });
+        Ok(())
+    }
+
+    /// Records only the meshes whose bounding boxes intersect `frustum`.
+    /// The frustum should be built from the same matrices used to render the model
+    pub fn record_culled(&self, loader: &Loader, command_buffer: vk::CommandBuffer, frustum: &Frustum) {
+        self.buffer.bind(loader, command_buffer);
+        self.meshes
+            .iter()
+            .filter(|mesh| frustum.contains(&mesh.aabb))
+            .for_each(|mesh| mesh.record_instanced(loader, command_buffer, 1, 0));
+    }
+
+    /// Draws every mesh `instance_count` times, the instance buffer must already be bound with [`Instance::bind`]
+    pub fn record_instanced(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        instance_count: u32,
+        first_instance: u32,
+    ) {
+        self.buffer.bind(loader, command_buffer);
+        self.meshes.iter().for_each(|mesh| {
+            mesh.record_instanced(loader, command_buffer, instance_count, first_instance)
+        });
+    }
+}
+
+/// Material sets are bound at the index right after the frame's descriptor sets. Models without
+/// allocated material sets are drawn untextured, warning once
+impl Recordable for Model {
+    fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, frame: &FrameContext) {
+        let set = frame.descriptor_sets.len() as u32;
+        if let Err(err) = self.record_textured(loader, command_buffer, frame.layout, set) {
+            if !self.warned_untextured.replace(true) {
+                log::warn!("{}, drawing the model untextured", err);
+            }
+            self.record_instanced(loader, command_buffer, 1, 0);
+        }
+    }
+}
+
+/// Merges bit identical vertices, returning the unique vertices in order of first use and
+/// the indices rewritten to point at them
+pub fn deduplicate_vertices(vertices: &[Vertex], indices: &[u32]) -> (Vec<Vertex>, Vec<u32>) {
+    let key = |vertex: &Vertex| {
+        let Vertex { pos, color, uv } = vertex;
+        [pos.x, pos.y, pos.z, color.x, color.y, color.z, uv.x, uv.y].map(f32::to_bits)
+    };
+
+    let mut unique = vec![];
+    let mut remap = HashMap::new();
+    let indices = indices
+        .iter()
+        .map(|&index| {
+            let vertex = &vertices[index as usize];
+            *remap.entry(key(vertex)).or_insert_with(|| {
+                unique.push(*vertex);
+                unique.len() as u32 - 1
+            })
+        })
+        .collect();
+
+    (unique, indices)
+}
+
+const VERTEX_CACHE_SIZE: usize = 32;
+
+/// Tom Forsyth's linear-speed vertex cache score, favouring vertices used by the last
+/// triangle and vertices with few triangles left
+fn vertex_cache_score(cache_position: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.;
+    }
+
+    let cache = match cache_position {
+        Some(position) if position < 3 => 0.75,
+        Some(position) => {
+            (1. - (position - 3) as f32 / (VERTEX_CACHE_SIZE - 3) as f32).powf(1.5)
+        }
+        None => 0.,
+    };
+
+    cache + 2. * (remaining as f32).powf(-0.5)
+}
+
+/// Reorders triangles to improve post-transform vertex cache hits, using Tom Forsyth's
+/// algorithm. Every index must be below `vertex_count`
+pub fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) {
+    let triangles = indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]].map(|index| index as usize))
+        .collect::<Vec<_>>();
+
+    let mut remaining = vec![0u32; vertex_count];
+    let mut adjacency = vec![vec![]; vertex_count];
+    for (triangle, vertices) in triangles.iter().enumerate() {
+        for &vertex in vertices {
+            remaining[vertex] += 1;
+            adjacency[vertex].push(triangle);
+        }
+    }
+
+    let mut scores = remaining
+        .iter()
+        .map(|&remaining| vertex_cache_score(None, remaining))
+        .collect::<Vec<_>>();
+    let mut emitted = vec![false; triangles.len()];
+    // Every triangle before it has been emitted, so restarting is linear over the whole mesh
+    let mut cursor = 0;
+    let mut cache: Vec<usize> = vec![];
+    let mut order = Vec::with_capacity(indices.len());
+
+    let triangle_score =
+        |scores: &[f32], triangle: usize| triangles[triangle].iter().map(|&v| scores[v]).sum::<f32>();
+
+    for _ in 0..triangles.len() {
+        // Candidates touch the cache, restarting at the next unemitted triangle when it runs dry
+        let best = cache
+            .iter()
+            .flat_map(|&vertex| adjacency[vertex].iter().copied())
+            .max_by(|&a, &b| triangle_score(&scores, a).total_cmp(&triangle_score(&scores, b)))
+            .or_else(|| {
+                while cursor < triangles.len() && emitted[cursor] {
+                    cursor += 1;
+                }
+                (cursor < triangles.len()).then_some(cursor)
+            });
+
+        let Some(best) = best else { break };
+        emitted[best] = true;
+        let vertices = triangles[best];
+        order.extend(vertices.map(|vertex| vertex as u32));
+
+        for vertex in vertices {
+            remaining[vertex] -= 1;
+            adjacency[vertex].retain(|&triangle| triangle != best);
+        }
+
+        let fresh = vertices.into_iter().unique().collect::<Vec<_>>();
+        cache.retain(|vertex| !fresh.contains(vertex));
+        cache.splice(0..0, fresh);
+        let evicted = cache.split_off(cache.len().min(VERTEX_CACHE_SIZE));
+
+        for (position, &vertex) in cache.iter().enumerate() {
+            scores[vertex] = vertex_cache_score(Some(position), remaining[vertex]);
+        }
+        for vertex in evicted {
+            scores[vertex] = vertex_cache_score(None, remaining[vertex]);
+        }
+    }
+
+    indices[..order.len()].copy_from_slice(&order);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(mvp: &MVP, point: glam::Vec3) -> glam::Vec3 {
+        let clip = mvp.proj * mvp.view * mvp.model * point.extend(1.);
+        clip.truncate() / clip.w
+    }
+
+    #[test]
+    fn halton_sequence_matches_reference() {
+        assert_eq!([1, 2, 3, 4].map(|index| halton(index, 2)), [0.5, 0.25, 0.75, 0.125]);
+        assert!((halton(1, 3) - 1. / 3.).abs() < 1e-6);
+        assert!((halton(2, 3) - 2. / 3.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn jitter_shifts_projection_by_subpixel_offset() {
+        let mut jitter = Jitter::new(4);
+        let extent = vk::Extent2D {
+            width: 100,
+            height: 50,
+        };
+
+        let mut mvp = MVP {
+            model: glam::Mat4::IDENTITY,
+            view: glam::Mat4::IDENTITY,
+            ..Default::default()
+        };
+        mvp.set_perspective(std::f32::consts::FRAC_PI_2, 2., 1., 10.);
+        let point = glam::vec3(0.3, -0.2, -5.);
+        let base = project(&mvp, point);
+
+        let mut offsets = vec![];
+        for _ in 0..4 {
+            let offset = jitter.offset();
+            assert!(offset.abs().max_element() < 0.5, "{offset}");
+
+            let jittered = project(
+                &MVP {
+                    proj: jitter.apply(mvp.proj, extent),
+                    ..mvp
+                },
+                point,
+            );
+            let pixels = (jittered - base).truncate() * glam::vec2(50., 25.);
+            assert!((pixels - offset).length() < 1e-3, "{pixels} {offset}");
+
+            offsets.push(offset);
+            jitter.advance();
+        }
+
+        // Wraps around after a period
+        assert_eq!(jitter.offset(), offsets[0]);
+        assert!(offsets.iter().tuple_windows().all(|(a, b)| a != b));
+    }
+
+    #[test]
+    fn perspective_flips_y_for_vulkan() {
+        let mut mvp = MVP {
+            model: glam::Mat4::IDENTITY,
+            ..Default::default()
+        };
+        mvp.look_at(glam::Vec3::ZERO, glam::Vec3::NEG_Z, glam::Vec3::Y);
+        mvp.set_perspective(std::f32::consts::FRAC_PI_2, 1., 1., 10.);
+
+        // Top edge of the near plane lands at the top of the viewport, which is -1 in Vulkan
+        let top = project(&mvp, glam::vec3(0., 1., -1.));
+        assert!((top - glam::vec3(0., -1., 0.)).length() < 1e-5, "{top}");
+
+        let far = project(&mvp, glam::vec3(1., 0., -10.));
+        assert!((far - glam::vec3(0.1, 0., 1.)).length() < 1e-5, "{far}");
+    }
+
+    #[test]
+    fn reverse_z_maps_near_to_one() {
+        let mut mvp = MVP {
+            model: glam::Mat4::IDENTITY,
+            view: glam::Mat4::IDENTITY,
+            ..Default::default()
+        };
+        mvp.set_perspective_reverse_z(std::f32::consts::FRAC_PI_2, 1., 1., 10.);
+
+        let near = project(&mvp, glam::vec3(0., 1., -1.));
+        assert!((near - glam::vec3(0., -1., 1.)).length() < 1e-5, "{near}");
+
+        let far = project(&mvp, glam::vec3(0., 0., -10.));
+        assert!(far.z.abs() < 1e-5, "{far}");
+    }
+
+    fn quad() -> Vec<Vertex> {
+        [(0., 0.), (1., 0.), (1., 1.), (0., 0.), (1., 1.), (0., 1.)]
+            .map(|(x, y)| Vertex {
+                pos: glam::vec3(x, y, 0.),
+                color: glam::Vec3::ONE,
+                uv: glam::vec2(x, y),
+            })
+            .to_vec()
+    }
+
+    #[test]
+    fn deduplicate_merges_shared_corners() {
+        let vertices = quad();
+        let indices = (0..vertices.len() as u32).collect::<Vec<_>>();
+
+        let (unique, remapped) = deduplicate_vertices(&vertices, &indices);
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(unique.len(), 4);
+        assert_eq!(remapped, [0, 1, 2, 0, 2, 3]);
+
+        for (&before, &after) in indices.iter().zip(&remapped) {
+            assert_eq!(vertices[before as usize].pos, unique[after as usize].pos);
+        }
+    }
+
+    #[test]
+    fn vertex_cache_reorder_keeps_triangles() {
+        // Strip of quads, rotated to start halfway along
+        let mut indices = (0..8u32)
+            .flat_map(|quad| {
+                let (a, b, c, d) = (quad * 2, quad * 2 + 1, quad * 2 + 2, quad * 2 + 3);
+                [a, b, c, c, b, d]
+            })
+            .collect::<Vec<_>>();
+        let triangles = |indices: &[u32]| {
+            indices
+                .chunks_exact(3)
+                .map(|triangle| triangle.to_vec())
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        let before = triangles(&indices);
+        indices.rotate_left(24);
+
+        optimize_vertex_cache(&mut indices, 18);
+        assert_eq!(triangles(&indices), before);
+    }
+
+    #[test]
+    fn orthographic_flips_y_for_vulkan() {
+        let mut mvp = MVP {
+            model: glam::Mat4::IDENTITY,
+            view: glam::Mat4::IDENTITY,
+            ..Default::default()
+        };
+        mvp.set_orthographic(-2., 2., -1., 1., 0., 4.);
+
+        let corner = project(&mvp, glam::vec3(2., 1., -2.));
+        assert!((corner - glam::vec3(1., -1., 0.5)).length() < 1e-5, "{corner}");
+    }
+}
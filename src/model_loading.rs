@@ -1,4 +1,5 @@
 use crate::compile;
+use crate::error::{Result, SiltError};
 use crate::material::ShaderOptions;
 use crate::prelude::*;
 use crate::vk;
@@ -91,10 +92,48 @@ pub struct FrameData {
     pub uniform_buffer_mapping: RefCell<Align<UniformBufferObject>>,
     pub descriptor_set: vk::DescriptorSet,
     pub image_available: vk::Semaphore,
-    pub render_finished: vk::Semaphore,
     pub in_flight: vk::Fence,
 }
 
+/// A swapchain replaced by [`VulkanData::recreate_swapchain`], with the attachments, framebuffers
+/// and present semaphores created for it. Frames in flight may still use them, so they're kept
+/// until the new swapchain has presented and that frame's fence has signaled
+pub struct RetiredSwapchain {
+    pub swapchain_loader: Swapchain,
+    pub swapchain: vk::SwapchainKHR,
+    pub image_views: Vec<vk::ImageView>,
+    pub framebuffers: Vec<vk::Framebuffer>,
+    pub render_finished: Vec<vk::Semaphore>,
+    pub color: (vk::Image, vk::Allocation, vk::ImageView),
+    pub depth: (vk::Image, vk::Allocation, vk::ImageView),
+    /// Frame that first presented the new swapchain, `None` until it has
+    pub released_in: Option<usize>,
+}
+
+impl RetiredSwapchain {
+    unsafe fn destroy(self, device: &Device, allocator: &Allocator) {
+        for framebuffer in self.framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+
+        for image_view in self.image_views {
+            device.destroy_image_view(image_view, None);
+        }
+
+        for semaphore in self.render_finished {
+            device.destroy_semaphore(semaphore, None);
+        }
+
+        for (image, allocation, view) in [self.color, self.depth] {
+            device.destroy_image_view(view, None);
+            device.destroy_image(image, None);
+            allocator.free(allocation).unwrap();
+        }
+
+        self.swapchain_loader.destroy_swapchain(self.swapchain, None);
+    }
+}
+
 pub struct VulkanData {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
@@ -129,6 +168,10 @@ pub struct VulkanData {
 
     pub images: Vec<vk::Image>,
     pub image_views: Vec<vk::ImageView>,
+    /// Signaled by the submission rendering to each swapchain image and waited on by its present.
+    /// Per image rather than per frame in flight, as a present may still wait on the semaphore
+    /// after the frame that signaled it has been reused
+    pub render_finished: Vec<vk::Semaphore>,
 
     pub color_image: vk::Image,
     pub color_allocation: Option<vk::Allocation>,
@@ -161,6 +204,7 @@ pub struct VulkanData {
 
     pub frame_data: Vec<FrameData>,
     pub current_frame: usize,
+    pub retired: Vec<RetiredSwapchain>,
 }
 
 impl VulkanData {
@@ -185,12 +229,18 @@ impl VulkanData {
                 &instance,
                 &device,
                 &surface,
-                surface_capabilities,
-                surface_format,
-                present_mode,
-            );
+                SwapchainOptions {
+                    surface_capabilities,
+                    surface_format,
+                    present_mode,
+                    image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                    old_swapchain: vk::SwapchainKHR::null(),
+                },
+            )
+            .unwrap();
             let (images, image_views) =
                 get_image_views(&device, &swapchain_loader, &swapchain, surface_format);
+            let render_finished = get_render_finished_semaphores(&device, images.len());
             let (color_image, color_allocation, color_view) = get_color_resources(
                 &device,
                 &allocator,
@@ -263,7 +313,7 @@ impl VulkanData {
                     command_buffer,
                     (uniform_buffer, uniform_buffer_allocation, uniform_buffer_mapping),
                     descriptor_set,
-                    (image_available, render_finished, in_flight),
+                    (image_available, in_flight),
                 )| {
                     FrameData {
                         command_buffer,
@@ -272,7 +322,6 @@ impl VulkanData {
                         uniform_buffer_mapping: RefCell::new(uniform_buffer_mapping),
                         descriptor_set,
                         image_available,
-                        render_finished,
                         in_flight,
                     }
                 },
@@ -304,6 +353,7 @@ impl VulkanData {
                 image_extent,
                 images,
                 image_views,
+                render_finished,
                 color_image,
                 color_allocation: Some(color_allocation),
                 color_view,
@@ -328,6 +378,7 @@ impl VulkanData {
                 descriptor_pool,
                 frame_data,
                 current_frame: 0,
+                retired: vec![],
             }
         }
     }
@@ -422,32 +473,40 @@ impl VulkanData {
         self.image_extent.width as f32 / self.image_extent.height as f32
     }
 
-    unsafe fn draw_frame(&mut self, current_frame: usize, spin_angle: f32, zoom: f32) {
+    /// Out of date swapchains are recreated, any other failure, notably
+    /// [`SiltError::DeviceLost`], is returned to the caller
+    unsafe fn draw_frame(&mut self, current_frame: usize, spin_angle: f32, zoom: f32) -> Result<()> {
         let frame = &self.frame_data[current_frame];
 
-        self.device
-            .wait_for_fences(&[frame.in_flight], true, u64::MAX)
-            .unwrap();
+        Loader::check(self.device.wait_for_fences(&[frame.in_flight], true, u64::MAX))?;
+
+        // Every submission before the one this fence tracked has finished with the retired resources
+        let (released, retired) = std::mem::take(&mut self.retired)
+            .into_iter()
+            .partition::<Vec<_>, _>(|retired| retired.released_in == Some(current_frame));
+        self.retired = retired;
+        for retired in released {
+            retired.destroy(&self.device, &self.allocator);
+        }
 
-        let (image_index, swapchain_suboptimal) = self
-            .swapchain_loader
-            .as_ref()
-            .unwrap()
-            .acquire_next_image(
+        let (image_index, swapchain_suboptimal) = match Loader::check(
+            self.swapchain_loader.as_ref().unwrap().acquire_next_image(
                 self.swapchain.unwrap(),
                 u64::MAX,
                 frame.image_available,
                 vk::Fence::null(),
-            )
-            .unwrap_or((0, true));
+            ),
+        ) {
+            Err(SiltError::Vk(vk::Result::ERROR_OUT_OF_DATE_KHR)) => (0, true),
+            result => result?,
+        };
 
         if swapchain_suboptimal {
             println!("Swapchain OOD at Image Acquisition");
-            self.recreate_swapchain();
-            return;
+            return self.recreate_swapchain();
         }
 
-        self.device.reset_fences(&[frame.in_flight]).unwrap();
+        Loader::check(self.device.reset_fences(&[frame.in_flight]))?;
 
         self.device
             .reset_command_buffer(frame.command_buffer, vk::CommandBufferResetFlags::empty())
@@ -468,36 +527,42 @@ impl VulkanData {
             .borrow_mut()
             .copy_from_slice(&[ubo]);
 
-        self.device
-            .queue_submit(
-                self.queue,
-                std::slice::from_ref(
-                    &vk::SubmitInfo::builder()
-                        .wait_semaphores(&[frame.image_available])
-                        .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                        .command_buffers(&[frame.command_buffer])
-                        .signal_semaphores(&[frame.render_finished]),
-                ),
-                frame.in_flight,
-            )
-            .unwrap();
+        Loader::check(self.device.queue_submit(
+            self.queue,
+            std::slice::from_ref(
+                &vk::SubmitInfo::builder()
+                    .wait_semaphores(&[frame.image_available])
+                    .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+                    .command_buffers(&[frame.command_buffer])
+                    .signal_semaphores(&[self.render_finished[image_index as usize]]),
+            ),
+            frame.in_flight,
+        ))?;
 
         let present_info = vk::PresentInfoKHR::builder()
-            .wait_semaphores(std::slice::from_ref(&frame.render_finished))
+            .wait_semaphores(std::slice::from_ref(&self.render_finished[image_index as usize]))
             .swapchains(std::slice::from_ref(self.swapchain.as_ref().unwrap()))
             .image_indices(std::slice::from_ref(&image_index));
-        let swapchain_suboptimal = self
-            .swapchain_loader
-            .as_ref()
-            .unwrap()
-            .queue_present(self.queue, &present_info)
-            .unwrap_or(true);
+        let swapchain_suboptimal = match Loader::check(
+            self.swapchain_loader
+                .as_ref()
+                .unwrap()
+                .queue_present(self.queue, &present_info),
+        ) {
+            Err(SiltError::Vk(vk::Result::ERROR_OUT_OF_DATE_KHR)) => true,
+            result => result?,
+        };
+
+        for retired in self.retired.iter_mut().filter(|retired| retired.released_in.is_none()) {
+            retired.released_in = Some(current_frame);
+        }
 
         if swapchain_suboptimal {
             println!("Swapchain OOD at Queue Presentation");
-            self.recreate_swapchain();
-            return;
+            self.recreate_swapchain()?;
         }
+
+        Ok(())
     }
 
     pub fn run(mut self) {
@@ -541,38 +606,19 @@ impl VulkanData {
                         frame_count = 0;
                         old_time = new_time;
                     }
-                    self.draw_frame(self.current_frame, spin_angle, zoom);
+                    if let Err(err) = self.draw_frame(self.current_frame, spin_angle, zoom) {
+                        println!("Frame failed: {}", err);
+                        *control_flow = ControlFlow::Exit;
+                    }
                 },
                 _ => (),
             }
         });
     }
 
-    pub unsafe fn recreate_swapchain(&mut self) {
-        self.device.device_wait_idle().unwrap();
-
-        self.framebuffers.clear();
-        self.image_views.clear();
-        self.images.clear();
-        self.swapchain_loader
-            .as_ref()
-            .unwrap()
-            .destroy_swapchain(self.swapchain.unwrap(), None);
-        self.swapchain = None;
-        self.swapchain_loader = None;
-
-        self.device.destroy_image_view(self.color_view, None);
-        self.device.destroy_image(self.color_image, None);
-        self.allocator
-            .free(self.color_allocation.take().unwrap())
-            .unwrap();
-
-        self.device.destroy_image_view(self.depth_view, None);
-        self.device.destroy_image(self.depth_image, None);
-        self.allocator
-            .free(self.depth_allocation.take().unwrap())
-            .unwrap();
-
+    /// Frames in flight may still render to the old swapchain, so instead of waiting for the device
+    /// to idle its resources are retired, see [`RetiredSwapchain`]
+    pub unsafe fn recreate_swapchain(&mut self) -> Result<()> {
         while self.window.is_minimized().unwrap_or(false) {
             println!("Waiting for window visibility");
             std::thread::sleep(std::time::Duration::from_millis(50));
@@ -588,10 +634,27 @@ impl VulkanData {
             &self.instance,
             &self.device,
             &self.surface,
-            self.surface_capabilities,
-            self.surface_format,
-            self.present_mode,
-        );
+            SwapchainOptions {
+                surface_capabilities: self.surface_capabilities,
+                surface_format: self.surface_format,
+                present_mode: self.present_mode,
+                image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                old_swapchain: self.swapchain.unwrap(),
+            },
+        )?;
+
+        // The old swapchain is retired by the new one, and only destroyed once the new one presented
+        self.images.clear();
+        self.retired.push(RetiredSwapchain {
+            swapchain_loader: self.swapchain_loader.take().unwrap(),
+            swapchain: self.swapchain.take().unwrap(),
+            image_views: std::mem::take(&mut self.image_views),
+            framebuffers: std::mem::take(&mut self.framebuffers),
+            render_finished: std::mem::take(&mut self.render_finished),
+            color: (self.color_image, self.color_allocation.take().unwrap(), self.color_view),
+            depth: (self.depth_image, self.depth_allocation.take().unwrap(), self.depth_view),
+            released_in: None,
+        });
 
         let (images, image_views) = get_image_views(
             &self.device,
@@ -599,6 +662,7 @@ impl VulkanData {
             &swapchain,
             self.surface_format,
         );
+        let render_finished = get_render_finished_semaphores(&self.device, images.len());
 
         let (color_image, color_allocation, color_view) = get_color_resources(
             &self.device,
@@ -631,6 +695,7 @@ impl VulkanData {
         self.image_extent = image_extent;
         self.images = images;
         self.image_views = image_views;
+        self.render_finished = render_finished;
         self.framebuffers = framebuffers;
         self.color_image = color_image;
         self.color_allocation = Some(color_allocation);
@@ -638,6 +703,7 @@ impl VulkanData {
         self.depth_image = depth_image;
         self.depth_allocation = Some(depth_allocation);
         self.depth_view = depth_view;
+        Ok(())
     }
 }
 
@@ -943,16 +1009,41 @@ unsafe fn find_supported_format(
     })
 }
 
+/// Surface properties and image usage of the swapchain created by [`get_swapchain`]
+#[derive(Debug, Clone, Copy)]
+struct SwapchainOptions {
+    surface_capabilities: vk::SurfaceCapabilitiesKHR,
+    surface_format: vk::SurfaceFormatKHR,
+    present_mode: vk::PresentModeKHR,
+    /// Must be in the surface's `supported_usage_flags`
+    image_usage: vk::ImageUsageFlags,
+    /// Retired by the new swapchain, null when creating the first one
+    old_swapchain: vk::SwapchainKHR,
+}
+
 unsafe fn get_swapchain(
     width: u32,
     height: u32,
     instance: &Instance,
     device: &Device,
     surface: &vk::SurfaceKHR,
-    surface_capabilities: vk::SurfaceCapabilitiesKHR,
-    surface_format: vk::SurfaceFormatKHR,
-    present_mode: vk::PresentModeKHR,
-) -> (Swapchain, vk::SwapchainKHR, vk::Extent2D) {
+    options: SwapchainOptions,
+) -> Result<(Swapchain, vk::SwapchainKHR, vk::Extent2D)> {
+    let SwapchainOptions {
+        surface_capabilities,
+        surface_format,
+        present_mode,
+        image_usage,
+        old_swapchain,
+    } = options;
+
+    if !surface_capabilities.supported_usage_flags.contains(image_usage) {
+        return Err(SiltError::InvalidUsage(format!(
+            "swapchain usage {:?} unsupported by the surface, supported usage is {:?}",
+            image_usage, surface_capabilities.supported_usage_flags
+        )));
+    }
+
     let image_count = match surface_capabilities.max_image_count {
         0 => surface_capabilities.min_image_count + 1,
         max => (surface_capabilities.min_image_count + 1).min(max),
@@ -972,6 +1063,15 @@ unsafe fn get_swapchain(
         surface_capabilities.current_transform
     };
 
+    // Surfaces support at least one mode, fall back to the lowest one when opaque isn't among them
+    let supported_alpha = surface_capabilities.supported_composite_alpha;
+    let composite_alpha = if supported_alpha.contains(vk::CompositeAlphaFlagsKHR::OPAQUE) {
+        vk::CompositeAlphaFlagsKHR::OPAQUE
+    } else {
+        let raw = supported_alpha.as_raw();
+        vk::CompositeAlphaFlagsKHR::from_raw(raw & raw.wrapping_neg())
+    };
+
     let swapchain_loader = Swapchain::new(instance, device);
 
     let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
@@ -982,17 +1082,16 @@ unsafe fn get_swapchain(
         .image_format(surface_format.format)
         .present_mode(present_mode)
         .pre_transform(pre_transform)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(image_usage)
         .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-        .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+        .composite_alpha(composite_alpha)
         .clipped(true)
+        .old_swapchain(old_swapchain)
         .image_array_layers(1);
 
-    let swapchain = swapchain_loader
-        .create_swapchain(&swapchain_create_info, None)
-        .unwrap();
+    let swapchain = swapchain_loader.create_swapchain(&swapchain_create_info, None)?;
 
-    (swapchain_loader, swapchain, image_extent)
+    Ok((swapchain_loader, swapchain, image_extent))
 }
 
 unsafe fn get_image_views(
@@ -2122,24 +2221,28 @@ unsafe fn get_descriptor_sets(
     (descriptor_pool, descriptor_sets)
 }
 
-unsafe fn get_semaphores(
-    device: &Device,
-    frames_in_flight: u32,
-) -> Vec<(vk::Semaphore, vk::Semaphore, vk::Fence)> {
+unsafe fn get_semaphores(device: &Device, frames_in_flight: u32) -> Vec<(vk::Semaphore, vk::Fence)> {
     (0..frames_in_flight)
         .map(|_| {
             let image_available = device
                 .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
                 .unwrap();
-            let render_finished = device
-                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
-                .unwrap();
 
             let fence_create_info =
                 vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
             let in_flight = device.create_fence(&fence_create_info, None).unwrap();
 
-            (image_available, render_finished, in_flight)
+            (image_available, in_flight)
+        })
+        .collect()
+}
+
+unsafe fn get_render_finished_semaphores(device: &Device, image_count: usize) -> Vec<vk::Semaphore> {
+    (0..image_count)
+        .map(|_| {
+            device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                .unwrap()
         })
         .collect()
 }
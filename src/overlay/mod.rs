@@ -0,0 +1,390 @@
+use memoffset::offset_of;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::collections::{Parity, ParitySet};
+use crate::error::{Result, SiltError};
+use crate::material::{ShaderModule, ShaderOptions};
+use crate::pipeline::{build_pipeline, PipelineState};
+use crate::properties::ProvidedFeatures;
+use crate::resources::{
+    write_global_descriptor_sets, BindableResource, ImageFile, Layouts, Resource,
+    ResourceDescription, SampledImage, SamplerConfig, TransientBuffer, TransientBufferCreateInfo,
+    VertexInput,
+};
+use crate::sync::{CommandPool, FrameContext, Recordable};
+use crate::{compile, id, prelude::*};
+
+mod font;
+use font::GLYPHS;
+
+const GLYPH_SIZE: u32 = 8;
+const ATLAS_COLUMNS: u32 = 16;
+const FIRST_GLYPH: u8 = 0x20;
+/// Solid glyph sampled when drawing rects
+const SOLID_GLYPH: u8 = 0x7F;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OverlayVertex {
+    pub pos: glam::Vec2,
+    pub uv: glam::Vec2,
+    pub color: glam::Vec4,
+}
+
+impl VertexInput for OverlayVertex {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()]
+    }
+
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        vec![
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(offset_of!(OverlayVertex, pos) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(offset_of!(OverlayVertex, uv) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(offset_of!(OverlayVertex, color) as u32)
+                .build(),
+        ]
+    }
+}
+
+/// Immediate mode text and rect drawing for debug HUDs, e.g. an [`FpsCounter`].
+/// Positions are in pixels from the top left. Queue quads each frame, [`DebugOverlay::flush`]
+/// them, then push an [`OverlayDraw`] with [`DebugOverlay::frame_context`] last in the
+/// [`crate::sync::RecordList`] of the pass the overlay was created for
+#[derive(Debug)]
+pub struct DebugOverlay {
+    /// Glyphs are drawn at `8 * text_scale` pixels
+    pub text_scale: f32,
+    pub text_color: glam::Vec4,
+    vertices: Vec<OverlayVertex>,
+    buffer: TransientBuffer,
+    /// Buffer, offset and vertex count of the quads last flushed for each parity
+    draws: ParitySet<Cell<(vk::Buffer, vk::DeviceSize, u32)>>,
+    capacity: usize,
+    atlas: Resource<SampledImage>,
+    descriptor_pool: DescriptorPool,
+    sets: ParitySet<ManagedDescriptorSet>,
+    layouts: Layouts,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl Destructible for DebugOverlay {
+    fn destroy(self, loader: &Loader) {
+        self.buffer.destroy(loader);
+        self.atlas.resource.destroy(loader);
+        self.sets.into_iter().destroy(loader);
+        self.descriptor_pool.destroy(loader);
+        self.pipeline.destroy(loader);
+        self.layouts.destroy(loader);
+    }
+}
+
+impl DebugOverlay {
+    /// The pipeline is built for subpass 0 of `render_pass`, whose attachments are described by
+    /// `attachments`, e.g. those of the effect it's drawn over. There must be a single color
+    /// attachment. `capacity` is the maximum number of quads, glyphs and rects alike, drawn in a
+    /// single frame
+    pub fn new(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        pool: &CommandPool,
+        render_pass: vk::RenderPass,
+        attachments: impl IntoIterator<Item = Rc<ResourceDescription>>,
+        capacity: usize,
+    ) -> Result<Self> {
+        let id = id!("Debug Overlay");
+        let vertex = ResourceDescription::vertex_input::<OverlayVertex>(id!("Overlay Vertex"));
+        let atlas = ResourceDescription::sampled_image(
+            id!("Overlay Font Atlas"),
+            0,
+            vk::DescriptorFrequency::Global,
+        );
+        let attachments = attachments.into_iter().collect::<Vec<_>>();
+
+        let atlas = atlas.bind_result(|_| {
+            let mut atlas_file = ImageFile::from_pixels(font_atlas());
+            atlas_file.max_mips = 1;
+            atlas_file.upload_to_gpu(loader, features, pool, SamplerConfig::nearest())
+        })?;
+
+        let shaders = [
+            ShaderModule::new(
+                loader,
+                &id,
+                compile!("../../assets/shaders/overlay.vert", ShaderOptions::HLSL)?,
+                "main",
+                [vertex.clone()],
+            )?,
+            ShaderModule::new(
+                loader,
+                &id,
+                compile!("../../assets/shaders/overlay.frag", ShaderOptions::HLSL)?,
+                "main",
+                std::iter::once(atlas.description.clone()).chain(attachments.iter().cloned()),
+            )?,
+        ];
+
+        let layouts = Layouts::new(loader, shaders.iter().map(|shader| (&id, shader)))?;
+        let pipeline = build_pipeline(
+            loader,
+            render_pass,
+            &layouts[&id],
+            std::iter::once(vertex.clone()).chain(attachments.iter().cloned()),
+            &shaders,
+            &PipelineState::overlay(),
+        );
+        shaders.into_iter().destroy(loader);
+        let pipeline = pipeline?;
+
+        let global_layout = layouts
+            .global_layout
+            .ok_or_else(|| SiltError::InvalidUsage("overlay has no font atlas set".into()))?;
+        let mut descriptor_pool = DescriptorPool::new(loader)?;
+        let sets = descriptor_pool
+            .allocate(loader, &[global_layout, global_layout])?
+            .into_iter()
+            .collect::<ParitySet<_>>();
+        write_global_descriptor_sets(loader, [atlas.bind()], sets.ref_map(|set| **set))?;
+
+        let buffer = TransientBuffer::new(
+            loader,
+            TransientBufferCreateInfo {
+                size: (capacity * 6 * std::mem::size_of::<OverlayVertex>()) as u64,
+                name: id.clone(),
+                usage: vk::BufferUsageFlags::VERTEX_BUFFER,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Self {
+            text_scale: 1.,
+            text_color: glam::Vec4::ONE,
+            vertices: Vec::with_capacity(capacity * 6),
+            buffer,
+            draws: ParitySet::from_fn(Default::default),
+            capacity,
+            layout: layouts[&id].pipeline,
+            atlas,
+            descriptor_pool,
+            sets,
+            layouts,
+            pipeline,
+        })
+    }
+
+    /// Pipeline and font atlas set for the frame of `parity`
+    pub fn frame_context(&self, parity: Parity) -> FrameContext {
+        FrameContext {
+            pipeline: self.pipeline,
+            layout: self.layout,
+            descriptor_sets: vec![**self.sets.get(parity)],
+        }
+    }
+
+    /// Draws `text` with its top left corner at `(x, y)`, newlines start a new line
+    /// and characters outside of printable ascii are drawn as `?`
+    pub fn draw_text(&mut self, x: f32, y: f32, text: &str) {
+        let size = GLYPH_SIZE as f32 * self.text_scale;
+        let mut cursor = glam::vec2(x, y);
+
+        for c in text.chars() {
+            if c == '\n' {
+                cursor = glam::vec2(x, cursor.y + size);
+                continue;
+            }
+
+            let glyph = match c {
+                ' '..='~' => c as u8,
+                _ => b'?',
+            };
+
+            if glyph != b' ' {
+                let (uv_min, uv_max) = glyph_uvs(glyph);
+                self.quad(cursor, cursor + size, uv_min, uv_max, self.text_color);
+            }
+
+            cursor.x += size;
+        }
+    }
+
+    pub fn draw_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: glam::Vec4) {
+        // Sampling the middle of the solid glyph keeps nearest filtering inside it
+        let (uv_min, uv_max) = glyph_uvs(SOLID_GLYPH);
+        let uv = (uv_min + uv_max) * 0.5;
+        self.quad(
+            glam::vec2(x, y),
+            glam::vec2(x + width, y + height),
+            uv,
+            uv,
+            color,
+        );
+    }
+
+    fn quad(
+        &mut self,
+        min: glam::Vec2,
+        max: glam::Vec2,
+        uv_min: glam::Vec2,
+        uv_max: glam::Vec2,
+        color: glam::Vec4,
+    ) {
+        let vertex = |pos: glam::Vec2, uv: glam::Vec2| OverlayVertex { pos, uv, color };
+        let top_right = vertex(glam::vec2(max.x, min.y), glam::vec2(uv_max.x, uv_min.y));
+        let bottom_left = vertex(glam::vec2(min.x, max.y), glam::vec2(uv_min.x, uv_max.y));
+
+        self.vertices.extend([
+            vertex(min, uv_min),
+            top_right,
+            bottom_left,
+            bottom_left,
+            top_right,
+            vertex(max, uv_max),
+        ]);
+    }
+
+    /// Uploads the queued quads for a target of size `extent` to the transient buffer of
+    /// `parity` and clears the queue. Its last frame must no longer be in use by the device
+    pub fn flush(&mut self, loader: &Loader, parity: Parity, extent: vk::Extent2D) -> Result<()> {
+        self.buffer.reset(parity);
+        self.draws.get(parity).set(Default::default());
+
+        if self.vertices.len() > self.capacity * 6 {
+            let count = self.vertices.len() / 6;
+            self.vertices.clear();
+
+            return Err(SiltError::InvalidUsage(format!(
+                "{} overlay quads exceeds capacity of {}",
+                count, self.capacity
+            )));
+        }
+
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+
+        // Pixels from the top left to normalized device coordinates
+        let scale = 2. / glam::vec2(extent.width as f32, extent.height as f32);
+        for vertex in &mut self.vertices {
+            vertex.pos = vertex.pos * scale - 1.;
+        }
+
+        let (buffer, offset) = self.buffer.push(loader, parity, &self.vertices)?;
+        self.draws
+            .get(parity)
+            .set((buffer, offset, self.vertices.len() as u32));
+        self.vertices.clear();
+
+        Ok(())
+    }
+}
+
+/// Draws the quads last flushed for `parity`, recorded with [`DebugOverlay::frame_context`]
+#[derive(Debug, Clone, Copy)]
+pub struct OverlayDraw<'a> {
+    pub overlay: &'a DebugOverlay,
+    pub parity: Parity,
+}
+
+impl Recordable for OverlayDraw<'_> {
+    fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, _frame: &FrameContext) {
+        let (buffer, offset, count) = self.overlay.draws.get(self.parity).get();
+        if count == 0 {
+            return;
+        }
+
+        unsafe {
+            loader
+                .device
+                .cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[offset]);
+            loader.device.cmd_draw(command_buffer, count, 1, 0, 0);
+        }
+    }
+}
+
+/// Frames per second, averaged over windows of [`FpsCounter::WINDOW`]
+#[derive(Debug, Clone)]
+pub struct FpsCounter {
+    window_start: Instant,
+    frames: u32,
+    fps: f32,
+}
+
+impl Default for FpsCounter {
+    fn default() -> Self {
+        Self {
+            window_start: Instant::now(),
+            frames: 0,
+            fps: 0.,
+        }
+    }
+}
+
+impl FpsCounter {
+    pub const WINDOW: Duration = Duration::from_secs(1);
+
+    /// Counts a frame, call once per frame. Returns the rate of the last full window
+    pub fn tick(&mut self) -> f32 {
+        self.frames += 1;
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= Self::WINDOW {
+            self.fps = self.frames as f32 / elapsed.as_secs_f32();
+            self.frames = 0;
+            self.window_start = Instant::now();
+        }
+
+        self.fps
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    /// Draws the current rate with its top left corner at `(x, y)`
+    pub fn draw(&self, overlay: &mut DebugOverlay, x: f32, y: f32) {
+        overlay.draw_text(x, y, &format!("{:.0} fps", self.fps));
+    }
+}
+
+fn glyph_uvs(glyph: u8) -> (glam::Vec2, glam::Vec2) {
+    let idx = (glyph - FIRST_GLYPH) as u32;
+    let rows = GLYPHS.len() as u32 / ATLAS_COLUMNS;
+    let size = glam::vec2(1. / ATLAS_COLUMNS as f32, 1. / rows as f32);
+    let min = glam::vec2((idx % ATLAS_COLUMNS) as f32, (idx / ATLAS_COLUMNS) as f32) * size;
+
+    (min, min + size)
+}
+
+/// White glyphs on a transparent background, [`ATLAS_COLUMNS`] glyphs per row
+fn font_atlas() -> image::RgbaImage {
+    let rows = GLYPHS.len() as u32 / ATLAS_COLUMNS;
+
+    image::RgbaImage::from_fn(ATLAS_COLUMNS * GLYPH_SIZE, rows * GLYPH_SIZE, |x, y| {
+        let glyph = (y / GLYPH_SIZE) * ATLAS_COLUMNS + x / GLYPH_SIZE;
+        let row = GLYPHS[glyph as usize][(y % GLYPH_SIZE) as usize];
+
+        match row >> (x % GLYPH_SIZE) & 1 {
+            1 => image::Rgba([255; 4]),
+            _ => image::Rgba([255, 255, 255, 0]),
+        }
+    })
+}
@@ -8,4 +8,7 @@ mod render_context;
 pub use render_context::*;
 
 mod pipeline;
-pub use pipeline::*;
\ No newline at end of file
+pub use pipeline::*;
+
+mod specialization;
+pub use specialization::*;
\ No newline at end of file
@@ -1,20 +1,628 @@
 use anyhow::{Result, anyhow};
 use itertools::Itertools;
-use std::{ffi::CStr, ops::Deref};
+use std::ops::Deref;
+use ash::extensions::ext::ExtendedDynamicState;
 
-use super::{Shader, Shaders};
+use super::{Multiview, Shader, Shaders, Specialization};
 use crate::{
     material::ShaderModule,
     prelude::*,
-    properties::get_sample_counts,
+    properties::{get_sample_counts, DeviceFeatures},
     resources::{AttachmentType, ResourceDescription, VertexInputDescription, PipelineLayout},
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub clamp: f32,
+    pub slope_factor: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RasterState {
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub polygon_mode: vk::PolygonMode,
+    pub line_width: f32,
+    pub depth_bias: Option<DepthBias>,
+}
+
+impl Default for RasterState {
+    fn default() -> Self {
+        Self {
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::CLOCKWISE,
+            polygon_mode: vk::PolygonMode::FILL,
+            line_width: 1.,
+            depth_bias: None,
+        }
+    }
+}
+
+impl RasterState {
+    pub fn wireframe() -> Self {
+        Self {
+            cull_mode: vk::CullModeFlags::NONE,
+            polygon_mode: vk::PolygonMode::LINE,
+            ..Default::default()
+        }
+    }
+
+    pub fn required_features(&self) -> DeviceFeatures {
+        let mut features = DeviceFeatures::empty();
+
+        if self.polygon_mode != vk::PolygonMode::FILL {
+            features |= DeviceFeatures::FILL_MODE_NON_SOLID;
+        }
+
+        if self.line_width != 1. {
+            features |= DeviceFeatures::WIDE_LINES;
+        }
+
+        features
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    AlphaBlend,
+    Additive,
+    PremultipliedAlpha,
+    /// Leaves the attachment untouched, e.g. for occlusion query proxies
+    NoColorWrite,
+}
+
+impl BlendMode {
+    pub fn attachment_state(&self) -> vk::PipelineColorBlendAttachmentState {
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            BlendMode::Opaque => {
+                return vk::PipelineColorBlendAttachmentState::builder()
+                    .color_write_mask(vk::ColorComponentFlags::RGBA)
+                    .blend_enable(false)
+                    .build()
+            }
+            BlendMode::NoColorWrite => {
+                return vk::PipelineColorBlendAttachmentState::builder()
+                    .color_write_mask(vk::ColorComponentFlags::empty())
+                    .blend_enable(false)
+                    .build()
+            }
+            BlendMode::AlphaBlend => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Additive => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE,
+            ),
+            BlendMode::PremultipliedAlpha => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+        };
+
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .blend_enable(true)
+            .src_color_blend_factor(src_color)
+            .dst_color_blend_factor(dst_color)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(src_alpha)
+            .dst_alpha_blend_factor(dst_alpha)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build()
+    }
+}
+
+/// Depth test of an effect with a depth attachment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthConfig {
+    pub test: bool,
+    /// Only takes effect with `test` enabled, use [`vk::CompareOp::ALWAYS`] to write unconditionally
+    pub write: bool,
+    pub compare_op: vk::CompareOp,
+    /// Replaces the depth of attachments left at their default clear, an explicit
+    /// [`crate::resources::ClearValue`] wins, see [`crate::material::MaterialSystem::get_effect_clear_values`]
+    pub clear_value: f32,
+}
+
+impl Default for DepthConfig {
+    fn default() -> Self {
+        Self {
+            test: true,
+            write: true,
+            compare_op: vk::CompareOp::LESS,
+            clear_value: 1.,
+        }
+    }
+}
+
+impl DepthConfig {
+    /// Near at 1 and far at 0, spreading float precision evenly over distance.
+    /// Pair with [`crate::model::MVP::set_perspective_reverse_z`]
+    pub fn reverse_z() -> Self {
+        Self {
+            compare_op: vk::CompareOp::GREATER,
+            clear_value: 0.,
+            ..Default::default()
+        }
+    }
+
+    /// Draws over everything without touching depth, e.g. for editor gizmos and overlays
+    /// sharing the scene's render pass
+    pub fn disabled() -> Self {
+        Self {
+            test: false,
+            write: false,
+            ..Default::default()
+        }
+    }
+
+    /// Tests against depth written by an earlier pass without writing, e.g. after a depth prepass
+    pub fn read_only(self) -> Self {
+        Self {
+            write: false,
+            compare_op: match self.compare_op {
+                vk::CompareOp::LESS => vk::CompareOp::LESS_OR_EQUAL,
+                vk::CompareOp::GREATER => vk::CompareOp::GREATER_OR_EQUAL,
+                op => op,
+            },
+            ..self
+        }
+    }
+}
+
+/// Stencil test and ops per face. The reference value is dynamic, see [`cmd_set_stencil_reference`],
+/// so the `reference` of each op state is ignored
+#[derive(Debug, Clone, Copy)]
+pub struct StencilState {
+    pub front: vk::StencilOpState,
+    pub back: vk::StencilOpState,
+}
+
+impl PartialEq for StencilState {
+    fn eq(&self, other: &Self) -> bool {
+        let ops = |op: &vk::StencilOpState| {
+            (
+                op.fail_op,
+                op.pass_op,
+                op.depth_fail_op,
+                op.compare_op,
+                op.compare_mask,
+                op.write_mask,
+            )
+        };
+
+        ops(&self.front) == ops(&other.front) && ops(&self.back) == ops(&other.back)
+    }
+}
+
+impl StencilState {
+    /// Same ops for both faces
+    pub fn new(op: vk::StencilOpState) -> Self {
+        Self {
+            front: op,
+            back: op,
+        }
+    }
+
+    /// Always passes and writes the reference value, e.g. to mark an outlined object or portal
+    pub fn write() -> Self {
+        Self::new(vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::REPLACE,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            compare_mask: 0xff,
+            write_mask: 0xff,
+            reference: 0,
+        })
+    }
+
+    /// Passes where the stencil buffer holds the reference value, leaving it untouched
+    pub fn equal() -> Self {
+        Self::test(vk::CompareOp::EQUAL)
+    }
+
+    /// Passes where the stencil buffer differs from the reference value, e.g. outside an outlined object
+    pub fn not_equal() -> Self {
+        Self::test(vk::CompareOp::NOT_EQUAL)
+    }
+
+    fn test(compare_op: vk::CompareOp) -> Self {
+        Self::new(vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::KEEP,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op,
+            compare_mask: 0xff,
+            write_mask: 0,
+            reference: 0,
+        })
+    }
+}
+
+/// Sets the dynamic stencil reference of both faces for pipelines built with [`PipelineState::stencil`]
+pub fn cmd_set_stencil_reference(loader: &Loader, command_buffer: vk::CommandBuffer, reference: u32) {
+    unsafe {
+        loader.device.cmd_set_stencil_reference(
+            command_buffer,
+            vk::StencilFaceFlags::FRONT_AND_BACK,
+            reference,
+        )
+    }
+}
+
+/// How clip space y is mapped to the framebuffer. Vulkan points y down, opposite to OpenGL and glTF
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViewportConvention {
+    /// Plain viewport, the projection or shader flips y, e.g. [`crate::model::MVP::set_perspective`]
+    #[default]
+    FlipInShader,
+    /// Flips y with a negative viewport height, so OpenGL style projections render upright.
+    /// Requires Vulkan 1.1 or `VK_KHR_maintenance1`
+    NegativeHeight,
+}
+
+impl ViewportConvention {
+    pub fn validate(&self, loader: &Loader) -> Result<()> {
+        match self {
+            Self::NegativeHeight if !loader.supports_negative_viewport_height() => Err(anyhow!(
+                "negative viewport height requires Vulkan 1.1 or VK_KHR_maintenance1"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Viewport covering `extent` with depth range `[0, 1]`
+    pub fn viewport(&self, extent: vk::Extent2D) -> vk::Viewport {
+        let (width, height) = (extent.width as f32, extent.height as f32);
+
+        match self {
+            Self::FlipInShader => vk::Viewport {
+                x: 0.,
+                y: 0.,
+                width,
+                height,
+                min_depth: 0.,
+                max_depth: 1.,
+            },
+            Self::NegativeHeight => vk::Viewport {
+                x: 0.,
+                y: height,
+                width,
+                height: -height,
+                min_depth: 0.,
+                max_depth: 1.,
+            },
+        }
+    }
+}
+
+/// Sets the dynamic viewport to cover `extent`, flipped according to `convention`,
+/// which should have been checked with [`ViewportConvention::validate`]
+pub fn cmd_set_viewport(
+    loader: &Loader,
+    command_buffer: vk::CommandBuffer,
+    extent: vk::Extent2D,
+    convention: ViewportConvention,
+) {
+    let viewport = convention.viewport(extent);
+    unsafe {
+        loader
+            .device
+            .cmd_set_viewport(command_buffer, 0, std::slice::from_ref(&viewport))
+    }
+}
+
+/// States of `VK_EXT_extended_dynamic_state` that [`PipelineState::dynamic_states`] accepts
+const EXTENDED_DYNAMIC_STATES: [vk::DynamicState; 10] = [
+    vk::DynamicState::CULL_MODE,
+    vk::DynamicState::FRONT_FACE,
+    vk::DynamicState::PRIMITIVE_TOPOLOGY,
+    vk::DynamicState::VERTEX_INPUT_BINDING_STRIDE,
+    vk::DynamicState::DEPTH_TEST_ENABLE,
+    vk::DynamicState::DEPTH_WRITE_ENABLE,
+    vk::DynamicState::DEPTH_COMPARE_OP,
+    vk::DynamicState::DEPTH_BOUNDS_TEST_ENABLE,
+    vk::DynamicState::STENCIL_TEST_ENABLE,
+    vk::DynamicState::STENCIL_OP,
+];
+
+/// Core states that [`PipelineState::dynamic_states`] accepts
+const CORE_DYNAMIC_STATES: [vk::DynamicState; 9] = [
+    vk::DynamicState::VIEWPORT,
+    vk::DynamicState::SCISSOR,
+    vk::DynamicState::LINE_WIDTH,
+    vk::DynamicState::DEPTH_BIAS,
+    vk::DynamicState::BLEND_CONSTANTS,
+    vk::DynamicState::DEPTH_BOUNDS,
+    vk::DynamicState::STENCIL_COMPARE_MASK,
+    vk::DynamicState::STENCIL_WRITE_MASK,
+    vk::DynamicState::STENCIL_REFERENCE,
+];
+
+/// Viewport, scissor, the stencil reference when stencil testing and the effect's own states, deduplicated
+fn get_dynamic_states(loader: &Loader, state: &PipelineState) -> Result<Vec<vk::DynamicState>> {
+    for &dynamic_state in &state.dynamic_states {
+        if EXTENDED_DYNAMIC_STATES.contains(&dynamic_state) {
+            if !loader.features.contains(DeviceFeatures::EXTENDED_DYNAMIC_STATE) {
+                return Err(anyhow!(
+                    "dynamic state {:?} requires the EXTENDED_DYNAMIC_STATE device feature",
+                    dynamic_state
+                ));
+            }
+        } else if !CORE_DYNAMIC_STATES.contains(&dynamic_state) {
+            return Err(anyhow!("dynamic state {:?} is not supported", dynamic_state));
+        }
+    }
+
+    // The bias values are dynamic but enabling it isn't, without a static bias nothing is biased
+    if state.dynamic_states.contains(&vk::DynamicState::DEPTH_BIAS) && state.raster.depth_bias.is_none() {
+        return Err(anyhow!(
+            "dynamic depth bias needs a static depth bias in the raster state to be enabled"
+        ));
+    }
+
+    let stencil = state
+        .stencil
+        .is_some()
+        .then_some(vk::DynamicState::STENCIL_REFERENCE);
+
+    Ok([vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]
+        .into_iter()
+        .chain(stencil)
+        .chain(state.dynamic_states.iter().copied())
+        .unique()
+        .collect())
+}
+
+/// Sets the dynamic depth bias for pipelines with a `DEPTH_BIAS` dynamic state
+pub fn cmd_set_depth_bias(loader: &Loader, command_buffer: vk::CommandBuffer, bias: DepthBias) {
+    unsafe {
+        loader.device.cmd_set_depth_bias(
+            command_buffer,
+            bias.constant_factor,
+            bias.clamp,
+            bias.slope_factor,
+        )
+    }
+}
+
+fn extended_dynamic_state(loader: &Loader) -> Result<&ExtendedDynamicState> {
+    loader
+        .extended_dynamic_state
+        .as_ref()
+        .ok_or_else(|| anyhow!("extended dynamic state set without the EXTENDED_DYNAMIC_STATE device feature"))
+}
+
+/// Sets the cull mode for pipelines with a `CULL_MODE` dynamic state
+pub fn cmd_set_cull_mode(
+    loader: &Loader,
+    command_buffer: vk::CommandBuffer,
+    cull_mode: vk::CullModeFlags,
+) -> Result<()> {
+    unsafe { extended_dynamic_state(loader)?.cmd_set_cull_mode(command_buffer, cull_mode) };
+    Ok(())
+}
+
+/// Sets the front face for pipelines with a `FRONT_FACE` dynamic state
+pub fn cmd_set_front_face(
+    loader: &Loader,
+    command_buffer: vk::CommandBuffer,
+    front_face: vk::FrontFace,
+) -> Result<()> {
+    unsafe { extended_dynamic_state(loader)?.cmd_set_front_face(command_buffer, front_face) };
+    Ok(())
+}
+
+/// Sets the topology for pipelines with a `PRIMITIVE_TOPOLOGY` dynamic state, which has to be
+/// of the same class, e.g. lines, as the pipeline's static topology
+pub fn cmd_set_primitive_topology(
+    loader: &Loader,
+    command_buffer: vk::CommandBuffer,
+    topology: vk::PrimitiveTopology,
+) -> Result<()> {
+    unsafe {
+        extended_dynamic_state(loader)?.cmd_set_primitive_topology(command_buffer, topology)
+    };
+    Ok(())
+}
+
+/// Dispatches `group_count` task shader workgroups, or mesh shader workgroups without a task stage,
+/// for pipelines built from mesh stages
+pub fn cmd_draw_mesh_tasks(
+    loader: &Loader,
+    command_buffer: vk::CommandBuffer,
+    group_count: [u32; 3],
+) -> Result<()> {
+    let mesh_shader = loader
+        .mesh_shader
+        .as_ref()
+        .ok_or_else(|| anyhow!("mesh tasks drawn without the MESH_SHADER device feature"))?;
+
+    let [x, y, z] = group_count;
+    unsafe { mesh_shader.cmd_draw_mesh_tasks(command_buffer, x, y, z) };
+    Ok(())
+}
+
+/// Whether a pipeline takes part in `VK_PIPELINE_CREATE_DERIVATIVE_BIT` derivation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PipelineDerivation {
+    #[default]
+    Independent,
+    /// Allows other pipelines to derive from this one
+    Base,
+    /// Derives from a pipeline created as [`PipelineDerivation::Base`]
+    Derived(vk::Pipeline),
+}
+
+impl PipelineDerivation {
+    pub fn flags(&self) -> vk::PipelineCreateFlags {
+        match self {
+            Self::Independent => vk::PipelineCreateFlags::empty(),
+            Self::Base => vk::PipelineCreateFlags::ALLOW_DERIVATIVES,
+            Self::Derived(_) => vk::PipelineCreateFlags::DERIVATIVE,
+        }
+    }
+
+    pub fn base(&self) -> vk::Pipeline {
+        match self {
+            Self::Derived(base) => *base,
+            _ => vk::Pipeline::null(),
+        }
+    }
+}
+
+/// Creation timings of derived and independent pipeline variants, to find out whether derivation
+/// pays off on the current driver. Variants alternate between the two until both have
+/// [`DerivationProbe::SAMPLES`] timings, then the medians are compared
+#[derive(Debug, Clone, Default)]
+pub struct DerivationProbe {
+    derived: Vec<std::time::Duration>,
+    independent: Vec<std::time::Duration>,
+}
+
+impl DerivationProbe {
+    pub const SAMPLES: usize = 4;
+
+    /// Derivation to time next, `base` having been created as [`PipelineDerivation::Base`]
+    pub fn next(&self, base: vk::Pipeline) -> PipelineDerivation {
+        match self.derived.len() <= self.independent.len() {
+            true => PipelineDerivation::Derived(base),
+            false => PipelineDerivation::Independent,
+        }
+    }
+
+    pub fn record(&mut self, derivation: PipelineDerivation, elapsed: std::time::Duration) {
+        match derivation {
+            PipelineDerivation::Derived(_) => self.derived.push(elapsed),
+            PipelineDerivation::Independent => self.independent.push(elapsed),
+            PipelineDerivation::Base => (),
+        }
+    }
+
+    /// Whether derived variants were created faster, `None` until both have enough timings
+    pub fn verdict(&self) -> Option<bool> {
+        if self.derived.len() < Self::SAMPLES || self.independent.len() < Self::SAMPLES {
+            return None;
+        }
+
+        let median = |timings: &[std::time::Duration]| {
+            let mut sorted = timings.to_vec();
+            sorted.sort();
+            sorted[sorted.len() / 2]
+        };
+
+        Some(median(&self.derived) < median(&self.independent))
+    }
+}
+
+/// Fixed function state that can't be derived from an effect's resources
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineState {
+    /// Ignored for tessellated effects, which always use `PATCH_LIST`. `POINT_LIST`
+    /// requires the vertex shader to write the point size
+    pub topology: vk::PrimitiveTopology,
+    /// Only used when the effect has tessellation stages, defaults to triangle patches
+    pub patch_control_points: Option<u32>,
+    pub raster: RasterState,
+    /// One mode per color attachment, in the order the attachments were declared.
+    /// A single mode applies to every attachment, and no modes leaves them all opaque
+    pub blend_modes: Vec<BlendMode>,
+    /// At most one specialization per stage
+    pub specializations: Vec<Specialization>,
+    /// Requires a depth attachment with a stencil format, see [`crate::resources::get_depth_stencil_format`]
+    pub stencil: Option<StencilState>,
+    /// Ignored without a depth attachment
+    pub depth: DepthConfig,
+    /// Must match the render pass, see [`build_render_pass`](crate::pipeline::build_render_pass)
+    pub multiview: Option<Multiview>,
+    /// Set while recording instead of baked into the pipeline, on top of the always dynamic
+    /// viewport and scissor. Overrides the matching static state, so one pipeline serves every
+    /// value. The states of `VK_EXT_extended_dynamic_state`, e.g. `CULL_MODE`, need
+    /// [`DeviceFeatures::EXTENDED_DYNAMIC_STATE`]
+    pub dynamic_states: Vec<vk::DynamicState>,
+}
+
+impl Default for PipelineState {
+    fn default() -> Self {
+        Self {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            patch_control_points: None,
+            raster: Default::default(),
+            blend_modes: vec![],
+            specializations: vec![],
+            stencil: None,
+            depth: Default::default(),
+            multiview: None,
+            dynamic_states: vec![],
+        }
+    }
+}
+
+impl PipelineState {
+    /// Depth tested bounding volumes that write nothing, drawn inside occlusion queries,
+    /// see [`crate::culling::OcclusionCuller`]
+    pub fn occlusion_proxy() -> Self {
+        Self {
+            // Back faces keep an object visible while the camera is inside its bounds
+            raster: RasterState {
+                cull_mode: vk::CullModeFlags::NONE,
+                ..Default::default()
+            },
+            blend_modes: vec![BlendMode::NoColorWrite],
+            depth: DepthConfig {
+                write: false,
+                compare_op: vk::CompareOp::LESS_OR_EQUAL,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Line list state for debug rendering, see [`crate::debug::DebugLines`]
+    pub fn lines(line_width: f32) -> Self {
+        Self {
+            topology: vk::PrimitiveTopology::LINE_LIST,
+            raster: RasterState {
+                cull_mode: vk::CullModeFlags::NONE,
+                line_width,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Alpha blended screen space quads drawn over the scene, e.g. the `overlay` feature's HUD
+    pub fn overlay() -> Self {
+        Self {
+            raster: RasterState {
+                cull_mode: vk::CullModeFlags::NONE,
+                ..Default::default()
+            },
+            blend_modes: vec![BlendMode::AlphaBlend],
+            depth: DepthConfig::disabled(),
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 struct PipelineResourceState {
     vertex_state: Option<VertexInputDescription>,
     multisample_state: Option<vk::SampleCountFlags>,
-    depth_stencil_state: Option<()>,
+    /// Whether the depth attachment has a stencil aspect
+    depth_stencil_state: Option<bool>,
+    color_attachments: usize,
 }
 
 pub fn build_pipeline<'a, R, T, S>(
@@ -23,20 +631,122 @@ pub fn build_pipeline<'a, R, T, S>(
     layout: &PipelineLayout,
     resources: R,
     shaders: S,
+    state: &PipelineState,
 ) -> Result<vk::Pipeline>
 where
     R: IntoIterator<Item = T> + Clone,
     T: Deref<Target = ResourceDescription>,
     S: IntoIterator<Item = &'a ShaderModule> + 'a,
 {
-    let shader_stages = shaders
-        .into_iter()
+    build_derived_pipeline(
+        loader,
+        render_pass,
+        layout,
+        resources,
+        shaders,
+        state,
+        PipelineDerivation::Independent,
+    )
+}
+
+/// [`build_pipeline`], flagged for derivation so the driver can share work between
+/// pipelines that only differ in fixed function state
+pub fn build_derived_pipeline<'a, R, T, S>(
+    loader: &Loader,
+    render_pass: vk::RenderPass,
+    layout: &PipelineLayout,
+    resources: R,
+    shaders: S,
+    state: &PipelineState,
+    derivation: PipelineDerivation,
+) -> Result<vk::Pipeline>
+where
+    R: IntoIterator<Item = T> + Clone,
+    T: Deref<Target = ResourceDescription>,
+    S: IntoIterator<Item = &'a ShaderModule> + 'a,
+{
+    let shaders = shaders.into_iter().collect_vec();
+    let tessellated = shaders.iter().any(|module| {
+        module
+            .stage_flags
+            .intersects(vk::ShaderStageFlags::TESSELLATION_CONTROL | vk::ShaderStageFlags::TESSELLATION_EVALUATION)
+    });
+    let meshed = shaders.iter().any(|module| {
+        module
+            .stage_flags
+            .intersects(vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT)
+    });
+
+    if meshed && !loader.features.contains(DeviceFeatures::MESH_SHADER) {
+        return Err(anyhow!(
+            "mesh shader stages require the MESH_SHADER device feature"
+        ));
+    }
+
+    if meshed
+        && shaders.iter().any(|module| {
+            module.stage_flags.intersects(
+                vk::ShaderStageFlags::VERTEX
+                    | vk::ShaderStageFlags::TESSELLATION_CONTROL
+                    | vk::ShaderStageFlags::TESSELLATION_EVALUATION
+                    | vk::ShaderStageFlags::GEOMETRY,
+            )
+        })
+    {
+        return Err(anyhow!(
+            "mesh pipelines only take task, mesh and fragment stages"
+        ));
+    }
+
+    if state.multiview.is_some() {
+        if !loader.features.contains(DeviceFeatures::MULTIVIEW) {
+            return Err(anyhow!("multiview pipelines require the MULTIVIEW device feature"));
+        }
+
+        // Only the base multiview feature is enabled, not its geometry and tessellation variants
+        if tessellated || shaders.iter().any(|module| module.stage_flags == vk::ShaderStageFlags::GEOMETRY) {
+            return Err(anyhow!(
+                "multiview pipelines can't have geometry or tessellation stages"
+            ));
+        }
+    }
+
+    let specialization_layouts = shaders
+        .iter()
         .map(|module| {
-            vk::PipelineShaderStageCreateInfo::builder()
+            state
+                .specializations
+                .iter()
+                .find(|specialization| specialization.stage == module.stage_flags)
+                .map(Specialization::layout)
+        })
+        .collect_vec();
+
+    let specialization_infos = specialization_layouts
+        .iter()
+        .map(|layout| {
+            layout.as_ref().map(|(entries, data)| {
+                vk::SpecializationInfo::builder()
+                    .map_entries(entries)
+                    .data(data)
+                    .build()
+            })
+        })
+        .collect_vec();
+
+    let shader_stages = shaders
+        .iter()
+        .zip(&specialization_infos)
+        .map(|(module, specialization_info)| {
+            let stage = vk::PipelineShaderStageCreateInfo::builder()
                 .stage(module.stage_flags)
                 .module(module.module)
-                .name(unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") })
-                .build()
+                .name(&module.entry_point);
+
+            match specialization_info {
+                Some(info) => stage.specialization_info(info).build(),
+                None => stage.build(),
+            }
         })
         .collect_vec();
 
@@ -56,7 +766,16 @@ where
                     ResourceDescription::Attachment(attachment)
                         if attachment.ty == AttachmentType::DepthStencil =>
                     {
-                        acc.depth_stencil_state = Some(());
+                        acc.depth_stencil_state = Some(attachment.use_stencil);
+                        // Depth only passes have no resolve attachment to take the sample count from
+                        if attachment.samples != vk::SampleCountFlags::TYPE_1 {
+                            acc.multisample_state = Some(attachment.samples);
+                        }
+                    }
+                    ResourceDescription::Attachment(attachment)
+                        if attachment.ty == AttachmentType::Color =>
+                    {
+                        acc.color_attachments += 1;
                     }
                     _ => (),
                 }
@@ -64,52 +783,128 @@ where
                 acc
             });
 
-    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let color_blend_attachment_states = match state.blend_modes.as_slice() {
+        [] => vec![BlendMode::Opaque.attachment_state(); resource_state.color_attachments],
+        [mode] => vec![mode.attachment_state(); resource_state.color_attachments],
+        modes if modes.len() == resource_state.color_attachments => {
+            modes.iter().map(BlendMode::attachment_state).collect_vec()
+        }
+        modes => {
+            return Err(anyhow!(
+                "{} blend modes provided for {} color attachments",
+                modes.len(),
+                resource_state.color_attachments
+            ))
+        }
+    };
+
+    // Outputs without an attachment are discarded and attachments without an output are left
+    // undefined, either way the effect's attachments likely don't match its shader
+    if let Some(fragment) = shaders
+        .iter()
+        .find(|module| module.stage_flags == vk::ShaderStageFlags::FRAGMENT)
+    {
+        let expected = (0..resource_state.color_attachments as u32).collect_vec();
+        if fragment.output_locations != expected {
+            return Err(anyhow!(
+                "fragment shader {:?} writes output locations {:?}, but {} color attachments are bound",
+                fragment.entry_point,
+                fragment.output_locations,
+                resource_state.color_attachments
+            ));
+        }
+    }
+
+    if state.depth.write && !state.depth.test {
+        return Err(anyhow!(
+            "depth writes are discarded with the depth test disabled, test with CompareOp::ALWAYS instead"
+        ));
+    }
+
+    if state.stencil.is_some() && resource_state.depth_stencil_state != Some(true) {
+        return Err(anyhow!(
+            "stencil state provided without a depth attachment with a stencil format"
+        ));
+    }
+
+    let dynamic_states = get_dynamic_states(loader, state)?;
     let dynamic_state =
         vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
 
+    let (bindings, attributes): (&[_], &[_]) = match (&resource_state.vertex_state, meshed) {
+        (Some(vertex), false) => (&vertex.bindings, &vertex.attributes),
+        (None, true) => (&[], &[]),
+        (None, false) => return Err(anyhow!("pipeline has no vertex input description")),
+        (Some(_), true) => {
+            return Err(anyhow!(
+                "mesh pipelines fetch their own vertices and can't have a vertex input description"
+            ))
+        }
+    };
+
     let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
-        .vertex_binding_descriptions(&resource_state.vertex_state.as_ref().unwrap().bindings)
-        .vertex_attribute_descriptions(&resource_state.vertex_state.as_ref().unwrap().attributes);
+        .vertex_binding_descriptions(bindings)
+        .vertex_attribute_descriptions(attributes);
 
     let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
-        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .topology(if tessellated {
+            vk::PrimitiveTopology::PATCH_LIST
+        } else {
+            state.topology
+        })
         .primitive_restart_enable(false);
 
+    let tessellation_state = vk::PipelineTessellationStateCreateInfo::builder()
+        .patch_control_points(state.patch_control_points.unwrap_or(3));
+
     let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
         .viewport_count(1)
         .scissor_count(1);
 
+    let raster = &state.raster;
+    let depth_bias = raster.depth_bias.unwrap_or(DepthBias {
+        constant_factor: 0.,
+        clamp: 0.,
+        slope_factor: 0.,
+    });
+
     let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
         .depth_clamp_enable(false)
         .rasterizer_discard_enable(false)
-        .polygon_mode(vk::PolygonMode::FILL)
-        .line_width(1.)
-        .cull_mode(vk::CullModeFlags::BACK)
-        .front_face(vk::FrontFace::CLOCKWISE)
-        .depth_bias_enable(false);
+        .polygon_mode(raster.polygon_mode)
+        .line_width(raster.line_width)
+        .cull_mode(raster.cull_mode)
+        .front_face(raster.front_face)
+        .depth_bias_enable(raster.depth_bias.is_some())
+        .depth_bias_constant_factor(depth_bias.constant_factor)
+        .depth_bias_clamp(depth_bias.clamp)
+        .depth_bias_slope_factor(depth_bias.slope_factor);
 
     let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
         .sample_shading_enable(resource_state.multisample_state.is_some())
         .rasterization_samples(resource_state.multisample_state.unwrap_or(vk::SampleCountFlags::TYPE_1));
 
-    let color_blend_attachment_state = vk::PipelineColorBlendAttachmentState::builder()
-        .color_write_mask(vk::ColorComponentFlags::RGBA)
-        .blend_enable(false);
-
     let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
         .logic_op_enable(false)
-        .attachments(std::slice::from_ref(&color_blend_attachment_state));
+        .attachments(&color_blend_attachment_states);
 
-    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-        .depth_test_enable(resource_state.depth_stencil_state.is_some())
-        .depth_write_enable(resource_state.depth_stencil_state.is_some())
-        .depth_compare_op(vk::CompareOp::LESS);
+    let mut depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(resource_state.depth_stencil_state.is_some() && state.depth.test)
+        .depth_write_enable(resource_state.depth_stencil_state.is_some() && state.depth.write)
+        .depth_compare_op(state.depth.compare_op);
 
-    let pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+    if let Some(stencil) = state.stencil {
+        depth_stencil_state = depth_stencil_state
+            .stencil_test_enable(true)
+            .front(stencil.front)
+            .back(stencil.back);
+    }
+
+    let mut pipeline_create_info = vk::GraphicsPipelineCreateInfo::builder()
+        .flags(derivation.flags())
+        .base_pipeline_handle(derivation.base())
+        .base_pipeline_index(-1)
         .stages(&shader_stages)
-        .vertex_input_state(&vertex_input_state)
-        .input_assembly_state(&input_assembly_state)
         .viewport_state(&viewport_state)
         .rasterization_state(&rasterization_state)
         .multisample_state(&multisample_state)
@@ -120,6 +915,17 @@ where
         .render_pass(render_pass)
         .subpass(0);
 
+    // Mesh pipelines have no vertex input or input assembly stage
+    if !meshed {
+        pipeline_create_info = pipeline_create_info
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state);
+    }
+
+    if tessellated {
+        pipeline_create_info = pipeline_create_info.tessellation_state(&tessellation_state);
+    }
+
     let pipeline = unsafe {
         loader
             .device
@@ -134,6 +940,60 @@ where
     Ok(pipeline)
 }
 
+/// Compute counterpart of [`build_pipeline`]. Compute pipelines have no fixed function state,
+/// so only the compute specialization is taken from `state`
+pub fn build_compute_pipeline(
+    loader: &Loader,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    state: &PipelineState,
+) -> Result<vk::Pipeline> {
+    if shader.stage_flags != vk::ShaderStageFlags::COMPUTE {
+        return Err(anyhow!(
+            "compute pipelines take a compute stage, not {:?}",
+            shader.stage_flags
+        ));
+    }
+
+    let specialization_layout = state
+        .specializations
+        .iter()
+        .find(|specialization| specialization.stage == vk::ShaderStageFlags::COMPUTE)
+        .map(Specialization::layout);
+    let specialization_info = specialization_layout.as_ref().map(|(entries, data)| {
+        vk::SpecializationInfo::builder()
+            .map_entries(entries)
+            .data(data)
+            .build()
+    });
+
+    let mut stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader.module)
+        .name(&shader.entry_point);
+
+    if let Some(info) = &specialization_info {
+        stage = stage.specialization_info(info);
+    }
+
+    let pipeline_create_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(*stage)
+        .layout(layout.pipeline);
+
+    let pipeline = unsafe {
+        loader
+            .device
+            .create_compute_pipelines(
+                vk::PipelineCache::null(),
+                std::slice::from_ref(&pipeline_create_info),
+                None,
+            )
+            .map_err(|e| e.1)?[0]
+    };
+
+    Ok(pipeline)
+}
+
 pub unsafe fn get_present_pipeline(
     loader: &Loader,
     pdevice: vk::PhysicalDevice,
@@ -145,12 +1005,12 @@ pub unsafe fn get_present_pipeline(
     let vertex_stage_create_info = vk::PipelineShaderStageCreateInfo::builder()
         .stage(shaders.vertex.shader_flags())
         .module(shaders.vertex.shader)
-        .name(CStr::from_bytes_with_nul_unchecked(b"main\0"));
+        .name(&shaders.vertex.entry_point);
 
     let fragment_stage_create_info = vk::PipelineShaderStageCreateInfo::builder()
         .stage(shaders.fragment.shader_flags())
         .module(shaders.fragment.shader)
-        .name(CStr::from_bytes_with_nul_unchecked(b"main\0"));
+        .name(&shaders.fragment.entry_point);
 
     let shader_stages = [*vertex_stage_create_info, *fragment_stage_create_info];
 
@@ -224,3 +1084,53 @@ pub unsafe fn get_present_pipeline(
 
     Ok(pipeline)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn probe(derived: u64, independent: u64) -> DerivationProbe {
+        let mut probe = DerivationProbe::default();
+        for _ in 0..DerivationProbe::SAMPLES {
+            probe.record(
+                PipelineDerivation::Derived(vk::Pipeline::null()),
+                Duration::from_millis(derived),
+            );
+            probe.record(PipelineDerivation::Independent, Duration::from_millis(independent));
+        }
+        probe
+    }
+
+    #[test]
+    fn probe_alternates_derivations() {
+        let mut probe = DerivationProbe::default();
+        let first = probe.next(vk::Pipeline::null());
+        assert_eq!(first, PipelineDerivation::Derived(vk::Pipeline::null()));
+
+        probe.record(first, Duration::from_millis(1));
+        assert_eq!(probe.next(vk::Pipeline::null()), PipelineDerivation::Independent);
+        assert_eq!(probe.verdict(), None);
+    }
+
+    #[test]
+    fn probe_keeps_faster_derivatives() {
+        assert_eq!(probe(1, 2).verdict(), Some(true));
+    }
+
+    #[test]
+    fn probe_falls_back_when_derivatives_are_not_faster() {
+        assert_eq!(probe(2, 1).verdict(), Some(false));
+        assert_eq!(probe(1, 1).verdict(), Some(false));
+    }
+
+    #[test]
+    fn probe_ignores_outliers() {
+        let mut probe = probe(1, 2);
+        probe.record(
+            PipelineDerivation::Derived(vk::Pipeline::null()),
+            Duration::from_secs(1),
+        );
+        assert_eq!(probe.verdict(), Some(true));
+    }
+}
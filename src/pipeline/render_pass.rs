@@ -1,11 +1,13 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use itertools::Itertools;
 use std::ops::Deref;
 
 use crate::loader::Loader;
 use crate::prelude::*;
-use crate::properties::get_sample_counts;
-use crate::resources::{get_depth_format, get_surface_format, AttachmentType, ResourceDescription};
+use crate::properties::{get_sample_counts, DeviceFeatures};
+use crate::resources::{
+    get_depth_format, get_surface_format, AttachmentType, Image, ResourceDescription,
+};
 
 pub unsafe fn get_present_pass(
     loader: &Loader,
@@ -14,9 +16,28 @@ pub unsafe fn get_present_pass(
 ) -> vk::RenderPass {
     let surface_format = get_surface_format(loader, surface, pdevice);
     let msaa_samples = get_sample_counts(loader, pdevice);
+    let depth_format = get_depth_format(&loader.instance, pdevice).unwrap();
 
+    build_present_pass(
+        loader,
+        surface_format.format,
+        Some(depth_format),
+        msaa_samples,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+    )
+}
+
+/// Multisampled color, then depth if given, resolved into a single sampled target left in
+/// `resolve_layout`. Passes differing only in `resolve_layout` are compatible
+pub(crate) unsafe fn build_present_pass(
+    loader: &Loader,
+    format: vk::Format,
+    depth_format: Option<vk::Format>,
+    msaa_samples: vk::SampleCountFlags,
+    resolve_layout: vk::ImageLayout,
+) -> vk::RenderPass {
     let color_attachment = vk::AttachmentDescription::builder()
-        .format(surface_format.format)
+        .format(format)
         .samples(msaa_samples)
         .load_op(vk::AttachmentLoadOp::CLEAR)
         .store_op(vk::AttachmentStoreOp::STORE)
@@ -25,31 +46,33 @@ pub unsafe fn get_present_pass(
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
-    let depth_attachment = vk::AttachmentDescription::builder()
-        .format(get_depth_format(&loader.instance, pdevice).unwrap())
-        .samples(msaa_samples)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::STORE)
-        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+    let depth_attachment = depth_format.map(|depth_format| {
+        vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(msaa_samples)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build()
+    });
 
     let color_resolve_attachment = vk::AttachmentDescription::builder()
-        .format(surface_format.format)
+        .format(format)
         .samples(vk::SampleCountFlags::TYPE_1)
         .load_op(vk::AttachmentLoadOp::DONT_CARE)
         .store_op(vk::AttachmentStoreOp::STORE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
         .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        .final_layout(resolve_layout);
 
-    let attachments = [
-        color_attachment.build(),
-        depth_attachment.build(),
-        color_resolve_attachment.build(),
-    ];
+    let attachments = std::iter::once(color_attachment.build())
+        .chain(depth_attachment)
+        .chain(std::iter::once(color_resolve_attachment.build()))
+        .collect_vec();
 
     let color_attachment_reference = vk::AttachmentReference {
         attachment: 0,
@@ -62,15 +85,18 @@ pub unsafe fn get_present_pass(
     };
 
     let color_resolve_attachment_reference = vk::AttachmentReference {
-        attachment: 2,
+        attachment: attachments.len() as u32 - 1,
         layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
     };
 
     let subpass = vk::SubpassDescription::builder()
         .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
         .color_attachments(std::slice::from_ref(&color_attachment_reference))
-        .resolve_attachments(std::slice::from_ref(&color_resolve_attachment_reference))
-        .depth_stencil_attachment(&depth_attachment_reference);
+        .resolve_attachments(std::slice::from_ref(&color_resolve_attachment_reference));
+    let subpass = match depth_attachment {
+        Some(_) => subpass.depth_stencil_attachment(&depth_attachment_reference),
+        None => subpass,
+    };
 
     let subpass_dependency = vk::SubpassDependency::builder()
         .src_subpass(vk::SUBPASS_EXTERNAL)
@@ -89,10 +115,27 @@ pub unsafe fn get_present_pass(
                 | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
         );
 
+    // Makes attachment writes visible to later passes sampling or copying from them
+    let output_dependency = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        )
+        .src_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ);
+
+    let dependencies = [subpass_dependency.build(), output_dependency.build()];
+
     let render_pass_create_info = vk::RenderPassCreateInfo::builder()
         .attachments(&attachments)
         .subpasses(std::slice::from_ref(&subpass))
-        .dependencies(std::slice::from_ref(&subpass_dependency));
+        .dependencies(&dependencies);
 
     let render_pass = loader
         .device
@@ -107,11 +150,88 @@ pub struct RenderPass {
     pub framebuffer: vk::Framebuffer,
 }
 
-pub fn build_render_pass<I, T>(loader: &Loader, resources: I) -> Result<vk::RenderPass>
+/// Broadcasts every draw of a render pass to each view in `view_mask`, written to the matching
+/// layer of layered attachments and told apart in shaders by `gl_ViewIndex`. Requires
+/// [`DeviceFeatures::MULTIVIEW`], see [`PipelineState::multiview`](crate::pipeline::PipelineState::multiview)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Multiview {
+    pub view_mask: u32,
+    /// Views likely to be spatially coherent, which the driver may render concurrently
+    pub correlation_mask: u32,
+}
+
+impl Multiview {
+    /// All six faces of a cube map, e.g. for point light shadows
+    pub fn cube() -> Self {
+        Self {
+            view_mask: 0b111111,
+            correlation_mask: 0b111111,
+        }
+    }
+
+    /// Left and right eye
+    pub fn stereo() -> Self {
+        Self {
+            view_mask: 0b11,
+            correlation_mask: 0b11,
+        }
+    }
+
+    /// Layers every attachment needs to cover the highest view
+    pub fn layers(&self) -> u32 {
+        u32::BITS - self.view_mask.leading_zeros()
+    }
+
+    fn validate(&self, loader: &Loader) -> Result<()> {
+        if !loader.features.contains(DeviceFeatures::MULTIVIEW) {
+            return Err(anyhow!("multiview render passes require the MULTIVIEW device feature"));
+        }
+
+        if self.view_mask == 0 {
+            return Err(anyhow!("multiview view mask must not be empty"));
+        }
+
+        if self.correlation_mask & !self.view_mask != 0 {
+            return Err(anyhow!(
+                "correlation mask {:#b} has views outside of view mask {:#b}",
+                self.correlation_mask,
+                self.view_mask
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Clear values for a pass built by [`build_render_pass`] from the same resources, in attachment order
+pub fn get_clear_values<I, T>(resources: I) -> Vec<vk::ClearValue>
 where
     I: IntoIterator<Item = T>,
     T: Deref<Target = ResourceDescription>,
 {
+    resources
+        .into_iter()
+        .filter_map(|resource| match resource.deref() {
+            ResourceDescription::Attachment(attachment) => Some(attachment.clear_value()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A single subpass over the attachments of `resources`, broadcast to every view of `multiview` if given
+pub fn build_render_pass<I, T>(
+    loader: &Loader,
+    resources: I,
+    multiview: Option<Multiview>,
+) -> Result<vk::RenderPass>
+where
+    I: IntoIterator<Item = T>,
+    T: Deref<Target = ResourceDescription>,
+{
+    if let Some(multiview) = &multiview {
+        multiview.validate(loader)?;
+    }
+
     let (attachments, attachment_types) = resources
         .into_iter()
         .filter_map(|resource| match resource.deref() {
@@ -122,33 +242,39 @@ where
             let attachment_description = vk::AttachmentDescription::builder()
                 .format(attachment.format)
                 .samples(attachment.samples)
-                .load_op(attachment.ty.load_op())
+                .load_op(attachment.ty.load_op(attachment.clear))
                 .store_op(attachment.ty.store_op())
-                .stencil_load_op(attachment.ty.stencil_load_op(attachment.use_stencil))
+                .stencil_load_op(
+                    attachment
+                        .ty
+                        .stencil_load_op(attachment.use_stencil, attachment.clear),
+                )
                 .stencil_store_op(attachment.ty.stencil_store_op(attachment.use_stencil))
-                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .initial_layout(attachment.initial_layout())
                 .final_layout(attachment.final_layout)
                 .build();
 
-            (
-                attachment_description,
-                (attachment.ty, attachment.final_layout),
-            )
+            (attachment_description, attachment.ty)
         })
         .unzip::<_, _, Vec<_>, Vec<_>>();
 
     let attachment_references = attachment_types
         .into_iter()
         .enumerate()
-        .map(|(idx, (ty, layout))| {
+        .map(|(idx, ty)| {
             let key = match ty {
                 AttachmentType::Input(_) => AttachmentType::Input(Default::default()),
                 ty => ty,
             };
 
-            let layout = match layout {
-                vk::ImageLayout::PRESENT_SRC_KHR => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                layout => layout,
+            // The final layout is only applied after the subpass, so targets that end up
+            // sampled or copied from are still referenced in their attachment layout
+            let layout = match ty {
+                AttachmentType::Color | AttachmentType::Resolve => {
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+                }
+                AttachmentType::DepthStencil => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                AttachmentType::Input(_) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             };
 
             let reference = vk::AttachmentReference::builder()
@@ -176,6 +302,14 @@ where
         .map(Vec::as_slice)
         .unwrap_or_default();
 
+    if !resolve_attachments.is_empty() && resolve_attachments.len() != color_attachments.len() {
+        return Err(anyhow!(
+            "{} resolve attachments for {} color attachments, resolving needs one per color attachment",
+            resolve_attachments.len(),
+            color_attachments.len()
+        ));
+    }
+
     let mut subpass = vk::SubpassDescription::builder()
         .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
         .color_attachments(color_attachments)
@@ -203,10 +337,37 @@ where
                 | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
         );
 
-    let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+    // Makes attachment writes visible to later passes sampling or copying from them
+    let output_dependency = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        )
+        .src_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ);
+
+    let dependencies = [subpass_dependency.build(), output_dependency.build()];
+
+    let view_masks = multiview.map(|multiview| [multiview.view_mask]);
+    let correlation_masks = multiview.map(|multiview| [multiview.correlation_mask]);
+    let mut multiview_create_info = vk::RenderPassMultiviewCreateInfo::builder()
+        .view_masks(view_masks.as_ref().map_or(&[], |masks| masks.as_slice()))
+        .correlation_masks(correlation_masks.as_ref().map_or(&[], |masks| masks.as_slice()));
+
+    let mut render_pass_create_info = vk::RenderPassCreateInfo::builder()
         .attachments(&attachments)
         .subpasses(std::slice::from_ref(&subpass))
-        .dependencies(std::slice::from_ref(&subpass_dependency));
+        .dependencies(&dependencies);
+
+    if multiview.is_some() {
+        render_pass_create_info = render_pass_create_info.push_next(&mut multiview_create_info);
+    }
 
     let render_pass = unsafe {
         loader
@@ -216,3 +377,60 @@ where
 
     Ok(render_pass)
 }
+
+/// Framebuffer over `attachments`, in the order of the render pass' attachments. Multiview passes
+/// need every attachment to cover [`Multiview::layers`], other passes render to as many layers as
+/// every attachment has, selected with `gl_Layer`
+pub fn build_framebuffer(
+    loader: &Loader,
+    render_pass: vk::RenderPass,
+    attachments: &[&Image],
+    multiview: Option<Multiview>,
+) -> Result<vk::Framebuffer> {
+    let extent = attachments
+        .first()
+        .ok_or_else(|| anyhow!("framebuffers need at least one attachment"))?
+        .size;
+
+    if let Some(image) = attachments
+        .iter()
+        .find(|image| image.size.width != extent.width || image.size.height != extent.height)
+    {
+        return Err(anyhow!(
+            "attachment extents differ: {}x{} and {}x{}",
+            extent.width,
+            extent.height,
+            image.size.width,
+            image.size.height
+        ));
+    }
+
+    let min_layers = attachments.iter().map(|image| image.layers).min().unwrap();
+    let layers = match multiview {
+        Some(multiview) if min_layers < multiview.layers() => {
+            return Err(anyhow!(
+                "multiview with {} views needs attachments of at least {} layers, found {}",
+                multiview.view_mask.count_ones(),
+                multiview.layers(),
+                min_layers
+            ));
+        }
+        // Multiview framebuffers are single layered, the view mask selects the layers
+        Some(_) => 1,
+        None => min_layers,
+    };
+
+    let views = attachments.iter().map(|image| image.view).collect_vec();
+    let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+        .render_pass(render_pass)
+        .attachments(&views)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(layers);
+
+    Ok(unsafe {
+        loader
+            .device
+            .create_framebuffer(&framebuffer_create_info, None)?
+    })
+}
@@ -2,10 +2,12 @@ use crate::material::ShaderModule;
 use crate::storage::descriptors::{BindableVec, BindingDescription};
 use crate::{loader::Loader, prelude::*};
 use anyhow::Result;
+use std::ffi::CString;
 use std::rc::Rc;
 
 pub struct VertexShader {
     pub shader: vk::ShaderModule,
+    pub entry_point: CString,
     bindings: Rc<dyn BindableVec>,
     vertex: Rc<dyn BindableVertex>,
 }
@@ -26,11 +28,19 @@ impl VertexShader {
 
         Ok(Self {
             shader: unsafe { loader.device.create_shader_module(&shader_ci, None)? },
+            entry_point: CString::new("main").unwrap(),
             bindings: Rc::new(B::default()),
             vertex: Rc::new(V::default()),
         })
     }
 
+    pub fn with_entry_point(self, entry_point: CString) -> Self {
+        Self {
+            entry_point,
+            ..self
+        }
+    }
+
     pub fn vertex_bindings(&self) -> Vec<vk::VertexInputBindingDescription> {
         self.vertex.bindings()
     }
@@ -61,6 +71,7 @@ impl Shader for VertexShader {
 
 pub struct FragmentShader {
     pub shader: vk::ShaderModule,
+    pub entry_point: CString,
     bindings: Rc<dyn BindableVec>,
 }
 
@@ -79,9 +90,17 @@ impl FragmentShader {
 
         Ok(Self {
             shader: unsafe { loader.device.create_shader_module(&shader_ci, None)? },
+            entry_point: CString::new("main").unwrap(),
             bindings: Rc::new(B::default()),
         })
     }
+
+    pub fn with_entry_point(self, entry_point: CString) -> Self {
+        Self {
+            entry_point,
+            ..self
+        }
+    }
 }
 
 impl Shader for FragmentShader {
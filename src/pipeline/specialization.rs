@@ -0,0 +1,427 @@
+use crate::error::{Result, SiltError};
+use crate::prelude::*;
+
+use std::collections::HashMap;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const DECORATION_SPEC_ID: u32 = 1;
+const DECORATION_LOCATION: u32 = 30;
+const STORAGE_CLASS_OUTPUT: u32 = 3;
+const SPIRV_HEADER_WORDS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecConstant {
+    Bool(bool),
+    U32(u32),
+    I32(i32),
+    F32(f32),
+}
+
+impl SpecConstant {
+    fn bytes(&self) -> [u8; 4] {
+        match *self {
+            SpecConstant::Bool(value) => (value as vk::Bool32).to_ne_bytes(),
+            SpecConstant::U32(value) => value.to_ne_bytes(),
+            SpecConstant::I32(value) => value.to_ne_bytes(),
+            SpecConstant::F32(value) => value.to_ne_bytes(),
+        }
+    }
+}
+
+/// Constant values for a single shader stage, see [`crate::material::ShaderModule::specialize`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Specialization {
+    pub stage: vk::ShaderStageFlags,
+    pub constants: Vec<(u32, SpecConstant)>,
+}
+
+impl Specialization {
+    /// Map entries and packed data backing a `vk::SpecializationInfo`
+    pub fn layout(&self) -> (Vec<vk::SpecializationMapEntry>, Vec<u8>) {
+        self.constants
+            .iter()
+            .enumerate()
+            .map(|(idx, (id, constant))| {
+                let entry = vk::SpecializationMapEntry {
+                    constant_id: *id,
+                    offset: (idx * 4) as u32,
+                    size: 4,
+                };
+
+                (entry, constant.bytes())
+            })
+            .fold((vec![], vec![]), |(mut entries, mut data), (entry, bytes)| {
+                entries.push(entry);
+                data.extend_from_slice(&bytes);
+                (entries, data)
+            })
+    }
+}
+
+/// Opcode and operands of every instruction after the header. Fails on modules shorter than the
+/// header and on instructions running past the end of `code`, e.g. from a truncated .spv file
+pub(crate) fn spirv_instructions(code: &[u32]) -> Result<Vec<(u32, &[u32])>> {
+    if code.len() < SPIRV_HEADER_WORDS {
+        return Err(SiltError::InvalidUsage(format!(
+            "SPIR-V module of {} words is shorter than its header",
+            code.len()
+        )));
+    }
+
+    let mut instructions = vec![];
+    let mut idx = SPIRV_HEADER_WORDS;
+
+    while idx < code.len() {
+        let word_count = (code[idx] >> 16) as usize;
+        let opcode = code[idx] & 0xffff;
+
+        if word_count == 0 || idx + word_count > code.len() {
+            return Err(SiltError::InvalidUsage(format!(
+                "malformed SPIR-V instruction at word {}, {} words long in a module of {}",
+                idx,
+                word_count,
+                code.len()
+            )));
+        }
+
+        instructions.push((opcode, &code[idx + 1..idx + word_count]));
+        idx += word_count;
+    }
+
+    Ok(instructions)
+}
+
+/// Nul terminated literal packed little endian into `words`
+fn literal_string(words: &[u32]) -> Vec<u8> {
+    words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect()
+}
+
+/// Entry points declared by a SPIR-V module, with the stage each one runs in
+pub fn declared_entry_points(code: &[u32]) -> Result<Vec<(vk::ShaderStageFlags, String)>> {
+    Ok(spirv_instructions(code)?
+        .into_iter()
+        .filter(|(opcode, operands)| *opcode == OP_ENTRY_POINT && operands.len() >= 3)
+        .map(|(_, operands)| {
+            let stage = match operands[0] {
+                0 => vk::ShaderStageFlags::VERTEX,
+                1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                3 => vk::ShaderStageFlags::GEOMETRY,
+                4 => vk::ShaderStageFlags::FRAGMENT,
+                5 => vk::ShaderStageFlags::COMPUTE,
+                // TaskNV and TaskEXT, MeshNV and MeshEXT
+                5267 | 5364 => vk::ShaderStageFlags::TASK_EXT,
+                5268 | 5365 => vk::ShaderStageFlags::MESH_EXT,
+                _ => vk::ShaderStageFlags::empty(),
+            };
+
+            let name = literal_string(&operands[2..]);
+            (stage, String::from_utf8_lossy(&name).into_owned())
+        })
+        .collect())
+}
+
+/// Constant ids declared with `layout(constant_id = N)` in a SPIR-V module
+pub fn declared_spec_constants(code: &[u32]) -> Result<Vec<u32>> {
+    Ok(spirv_instructions(code)?
+        .into_iter()
+        .filter_map(|(opcode, operands)| match (opcode, operands) {
+            (OP_DECORATE, &[_, DECORATION_SPEC_ID, id, ..]) => Some(id),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Locations of the `Output` variables used by `entry_point`, sorted. For a fragment shader these
+/// are the color attachments it writes, `layout(location = N) out` or `SV_TargetN`. Builtins such as
+/// the fragment depth have no location and aren't included, arrays report one location per element
+pub fn declared_output_locations(code: &[u32], entry_point: &str) -> Result<Vec<u32>> {
+    let mut interface = vec![];
+    let mut outputs = vec![];
+    let mut locations = vec![];
+    let mut pointers = HashMap::new();
+    let mut arrays = HashMap::new();
+    let mut constants = HashMap::new();
+
+    for (opcode, operands) in spirv_instructions(code)? {
+        match opcode {
+            OP_ENTRY_POINT if operands.len() >= 3 => {
+                let name = literal_string(&operands[2..]);
+
+                // The literal is nul terminated and padded to a whole word, the interface ids follow
+                if name == entry_point.as_bytes() {
+                    let name_words = name.len() / 4 + 1;
+                    interface.extend(operands.iter().skip(2 + name_words));
+                }
+            }
+            OP_TYPE_POINTER if operands.len() >= 3 => {
+                pointers.insert(operands[0], operands[2]);
+            }
+            OP_TYPE_ARRAY if operands.len() >= 3 => {
+                arrays.insert(operands[0], (operands[1], operands[2]));
+            }
+            // Array lengths are 32 bit integer constants, wider constants are never a length
+            OP_CONSTANT if operands.len() == 3 => {
+                constants.insert(operands[1], operands[2]);
+            }
+            OP_VARIABLE if operands.get(2) == Some(&STORAGE_CLASS_OUTPUT) => {
+                outputs.push((operands[1], operands[0]));
+            }
+            OP_DECORATE if operands.get(1) == Some(&DECORATION_LOCATION) => {
+                if let Some(&location) = operands.get(2) {
+                    locations.push((operands[0], location));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Each element of an (possibly nested) array of outputs takes the next location
+    let location_count = |mut ty: u32| {
+        let mut count = 1;
+        while let Some((element, length)) = arrays.get(&ty) {
+            count *= constants.get(length).copied().unwrap_or(1);
+            ty = *element;
+        }
+        count
+    };
+
+    let mut declared = locations
+        .into_iter()
+        .filter(|(id, _)| interface.contains(id))
+        .filter_map(|(id, location)| {
+            let (_, pointer) = outputs.iter().find(|(output, _)| *output == id)?;
+            let count = pointers.get(pointer).map_or(1, |&ty| location_count(ty));
+            Some(location..location + count)
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+    declared.sort_unstable();
+    declared.dedup();
+    Ok(declared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OP_TYPE_INT: u32 = 21;
+    const OP_TYPE_FLOAT: u32 = 22;
+    const OP_TYPE_VECTOR: u32 = 23;
+    const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+    const STORAGE_CLASS_INPUT: u32 = 1;
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let word_count = operands.len() as u32 + 1;
+        std::iter::once(word_count << 16 | opcode)
+            .chain(operands.iter().copied())
+            .collect()
+    }
+
+    /// Nul terminated string literal padded to a whole word
+    fn literal(name: &str) -> Vec<u32> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(name.len() / 4 * 4 + 4, 0);
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    fn entry_point(model: u32, function: u32, name: &str, interface: &[u32]) -> Vec<u32> {
+        let operands = [&[model, function][..], &literal(name), interface].concat();
+        instruction(OP_ENTRY_POINT, &operands)
+    }
+
+    fn module(instructions: impl IntoIterator<Item = Vec<u32>>) -> Vec<u32> {
+        // Magic, version 1.0, generator, id bound and reserved schema
+        let header = vec![0x0723_0203, 0x0001_0000, 0, 64, 0];
+        header.into_iter().chain(instructions.into_iter().flatten()).collect()
+    }
+
+    /// Fragment shader `main` with a float type (1), vec4 (2) and uint (3), the declarations passed
+    /// in follow the types
+    fn fragment(interface: &[u32], declarations: impl IntoIterator<Item = Vec<u32>>) -> Vec<u32> {
+        let types = [
+            instruction(OP_TYPE_FLOAT, &[1, 32]),
+            instruction(OP_TYPE_VECTOR, &[2, 1, 4]),
+            instruction(OP_TYPE_INT, &[3, 32, 0]),
+        ];
+
+        module(
+            std::iter::once(entry_point(EXECUTION_MODEL_FRAGMENT, 4, "main", interface))
+                .chain(types)
+                .chain(declarations),
+        )
+    }
+
+    #[test]
+    fn output_locations_of_single_outputs() {
+        let code = fragment(
+            &[10, 11],
+            [
+                instruction(OP_DECORATE, &[10, DECORATION_LOCATION, 1]),
+                instruction(OP_DECORATE, &[11, DECORATION_LOCATION, 0]),
+                instruction(OP_TYPE_POINTER, &[5, STORAGE_CLASS_OUTPUT, 2]),
+                instruction(OP_VARIABLE, &[5, 10, STORAGE_CLASS_OUTPUT]),
+                instruction(OP_VARIABLE, &[5, 11, STORAGE_CLASS_OUTPUT]),
+            ],
+        );
+
+        assert_eq!(declared_output_locations(&code, "main").unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn output_locations_expand_arrays() {
+        // vec4 colors[3] at location 1
+        let code = fragment(
+            &[10],
+            [
+                instruction(OP_DECORATE, &[10, DECORATION_LOCATION, 1]),
+                instruction(OP_CONSTANT, &[3, 6, 3]),
+                instruction(OP_TYPE_ARRAY, &[7, 2, 6]),
+                instruction(OP_TYPE_POINTER, &[5, STORAGE_CLASS_OUTPUT, 7]),
+                instruction(OP_VARIABLE, &[5, 10, STORAGE_CLASS_OUTPUT]),
+            ],
+        );
+
+        assert_eq!(declared_output_locations(&code, "main").unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn output_locations_expand_nested_arrays() {
+        // vec4 colors[2][2] at location 0
+        let code = fragment(
+            &[10],
+            [
+                instruction(OP_DECORATE, &[10, DECORATION_LOCATION, 0]),
+                instruction(OP_CONSTANT, &[3, 6, 2]),
+                instruction(OP_TYPE_ARRAY, &[7, 2, 6]),
+                instruction(OP_TYPE_ARRAY, &[8, 7, 6]),
+                instruction(OP_TYPE_POINTER, &[5, STORAGE_CLASS_OUTPUT, 8]),
+                instruction(OP_VARIABLE, &[5, 10, STORAGE_CLASS_OUTPUT]),
+            ],
+        );
+
+        assert_eq!(declared_output_locations(&code, "main").unwrap(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn output_locations_skip_inputs_and_other_entry_points() {
+        let mut code = fragment(
+            &[10, 11],
+            [
+                instruction(OP_DECORATE, &[10, DECORATION_LOCATION, 0]),
+                instruction(OP_DECORATE, &[11, DECORATION_LOCATION, 0]),
+                instruction(OP_DECORATE, &[12, DECORATION_LOCATION, 1]),
+                instruction(OP_TYPE_POINTER, &[5, STORAGE_CLASS_OUTPUT, 2]),
+                instruction(OP_TYPE_POINTER, &[6, STORAGE_CLASS_INPUT, 2]),
+                instruction(OP_VARIABLE, &[5, 10, STORAGE_CLASS_OUTPUT]),
+                instruction(OP_VARIABLE, &[6, 11, STORAGE_CLASS_INPUT]),
+                instruction(OP_VARIABLE, &[5, 12, STORAGE_CLASS_OUTPUT]),
+            ],
+        );
+
+        // Output 12 belongs to a second entry point
+        let second = entry_point(EXECUTION_MODEL_FRAGMENT, 9, "other", &[12]);
+        code.splice(SPIRV_HEADER_WORDS..SPIRV_HEADER_WORDS, second);
+
+        assert_eq!(declared_output_locations(&code, "main").unwrap(), vec![0]);
+        assert_eq!(declared_output_locations(&code, "other").unwrap(), vec![1]);
+        assert!(declared_output_locations(&code, "missing").unwrap().is_empty());
+    }
+
+    #[test]
+    fn entry_points_and_spec_constants() {
+        let code = module([
+            entry_point(EXECUTION_MODEL_FRAGMENT, 4, "PSMain", &[]),
+            entry_point(0, 5, "VSMain", &[]),
+            instruction(OP_DECORATE, &[6, DECORATION_SPEC_ID, 3]),
+            instruction(OP_DECORATE, &[7, DECORATION_LOCATION, 0]),
+        ]);
+
+        assert_eq!(
+            declared_entry_points(&code).unwrap(),
+            vec![
+                (vk::ShaderStageFlags::FRAGMENT, "PSMain".to_string()),
+                (vk::ShaderStageFlags::VERTEX, "VSMain".to_string()),
+            ]
+        );
+        assert_eq!(declared_spec_constants(&code).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn entry_point_names_fill_whole_words() {
+        // Four characters take a second word for the nul terminator
+        let code = module([entry_point(5, 4, "main", &[]), entry_point(5, 5, "cs", &[])]);
+
+        assert_eq!(
+            declared_entry_points(&code).unwrap(),
+            vec![
+                (vk::ShaderStageFlags::COMPUTE, "main".to_string()),
+                (vk::ShaderStageFlags::COMPUTE, "cs".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn entry_points_of_mesh_stages() {
+        let code = module([entry_point(5364, 4, "task", &[]), entry_point(5365, 5, "mesh", &[])]);
+
+        assert_eq!(
+            declared_entry_points(&code).unwrap(),
+            vec![
+                (vk::ShaderStageFlags::TASK_EXT, "task".to_string()),
+                (vk::ShaderStageFlags::MESH_EXT, "mesh".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn spec_constants_skip_other_decorations() {
+        let code = module([
+            instruction(OP_DECORATE, &[6, DECORATION_SPEC_ID, 0]),
+            instruction(OP_DECORATE, &[7, DECORATION_LOCATION, 2]),
+            instruction(OP_DECORATE, &[8, DECORATION_SPEC_ID, 5]),
+            // Missing its literal
+            instruction(OP_DECORATE, &[9, DECORATION_SPEC_ID]),
+        ]);
+
+        assert_eq!(declared_spec_constants(&code).unwrap(), vec![0, 5]);
+        assert!(declared_spec_constants(&module([])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncated_modules_are_rejected() {
+        let code = module([
+            entry_point(EXECUTION_MODEL_FRAGMENT, 4, "main", &[10]),
+            instruction(OP_DECORATE, &[6, DECORATION_SPEC_ID, 3]),
+        ]);
+
+        // Cut inside each instruction, and inside the header
+        for len in [SPIRV_HEADER_WORDS + 2, code.len() - 1, SPIRV_HEADER_WORDS - 1, 0] {
+            let truncated = &code[..len];
+            assert!(declared_entry_points(truncated).is_err());
+            assert!(declared_spec_constants(truncated).is_err());
+            assert!(declared_output_locations(truncated, "main").is_err());
+        }
+    }
+
+    #[test]
+    fn zero_length_instructions_are_rejected() {
+        let mut code = module([instruction(OP_DECORATE, &[6, DECORATION_SPEC_ID, 3])]);
+        code.push(OP_DECORATE);
+
+        assert!(declared_entry_points(&code).is_err());
+        assert!(declared_spec_constants(&code).is_err());
+    }
+}
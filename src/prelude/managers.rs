@@ -4,32 +4,42 @@ use std::{
     cell::{Ref, RefCell},
     collections::{HashMap, VecDeque},
     ffi::c_void,
+    hash::Hash,
     num::NonZeroU64,
-    ptr::NonNull, sync::{Arc, atomic::{AtomicUsize, Ordering}},
+    ops::DerefMut,
+    rc::Rc,
+    ptr::NonNull, sync::{Arc, Mutex, MutexGuard, atomic::{AtomicUsize, Ordering}},
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 use winit::{
     event::Event,
     event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    platform::run_return::EventLoopExtRunReturn,
 };
 
-use crate::{vk, loader};
+use crate::{collections::Parity, error::SiltError, vk, loader};
 
 use super::{Loader, Destructible, IterDestructible};
 
-/// Subset of gpu_allocator::vulkan::Allocator with managed allocation handles
+/// Subset of gpu_allocator::vulkan::Allocator with managed allocation handles. Internally
+/// locked, so it can be shared with upload threads through [`crate::sync::GpuUploadHandle`]
 pub struct Allocator {
-    inner: RefCell<gpu_allocator::vulkan::Allocator>,
-    allocations: RefCell<HashMap<vk::Allocation, gpu_allocator::vulkan::Allocation>>,
+    inner: Mutex<gpu_allocator::vulkan::Allocator>,
+    allocations: Mutex<HashMap<vk::Allocation, gpu_allocator::vulkan::Allocation>>,
 }
 
 impl Allocator {
+    fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+        mutex.lock().map_err(|_| anyhow!("allocator poisoned by a panicking thread"))
+    }
+
     fn with<T>(
         &self,
         allocation: vk::Allocation,
         f: impl FnOnce(&gpu_allocator::vulkan::Allocation) -> T,
     ) -> Result<T> {
-        Ok(f(self.allocations.borrow().get(&allocation).ok_or(
+        Ok(f(Self::lock(&self.allocations)?.get(&allocation).ok_or(
             anyhow!(
                 "Allocation {} not found, possible use after free",
                 Uuid::from(allocation).as_urn()
@@ -42,7 +52,7 @@ impl Allocator {
         allocation: vk::Allocation,
         f: impl FnOnce(&gpu_allocator::vulkan::Allocation) -> Result<T>,
     ) -> Result<T> {
-        f(self.allocations.borrow().get(&allocation).ok_or(anyhow!(
+        f(Self::lock(&self.allocations)?.get(&allocation).ok_or(anyhow!(
             "Allocation {} not found, possible use after free",
             Uuid::from(allocation).as_urn()
         ))?)
@@ -50,17 +60,17 @@ impl Allocator {
 
     pub fn new(desc: &vk::AllocatorCreateInfo) -> Result<Self> {
         Ok(Self {
-            inner: RefCell::new(gpu_allocator::vulkan::Allocator::new(desc)?),
+            inner: Mutex::new(gpu_allocator::vulkan::Allocator::new(desc)?),
             allocations: Default::default(),
         })
     }
 
     pub fn allocate(&self, desc: &vk::AllocationCreateInfo<'_>) -> Result<vk::Allocation> {
-        let allocation = self.inner.borrow_mut().allocate(desc)?;
+        let allocation = Self::lock(&self.inner)?
+            .allocate(desc)
+            .map_err(SiltError::from)?;
         let uuid = Uuid::new_v4();
-        if self
-            .allocations
-            .borrow_mut()
+        if Self::lock(&self.allocations)?
             .insert(uuid.into(), allocation)
             .is_some()
         {
@@ -70,15 +80,13 @@ impl Allocator {
     }
 
     pub fn free(&self, allocation: vk::Allocation) -> Result<()> {
-        let allocation = self
-            .allocations
-            .borrow_mut()
+        let allocation = Self::lock(&self.allocations)?
             .remove(&allocation)
             .ok_or(anyhow!(
                 "Could not find allocation {}, possible double free error",
                 Uuid::from(allocation).as_urn()
             ))?;
-        self.inner.borrow_mut().free(allocation)?;
+        Self::lock(&self.inner)?.free(allocation)?;
         Ok(())
     }
 
@@ -119,6 +127,23 @@ pub struct Context {
     inner: RefCell<Option<EventLoop<()>>>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedrawMode {
+    /// Redraw as fast as possible with `ControlFlow::Poll`, draw on `Event::RedrawEventsCleared`
+    #[default]
+    Continuous,
+    /// Sleep until the next event with `ControlFlow::Wait`, draw on `Event::RedrawRequested`
+    /// and call `window.request_redraw()` whenever the state changes
+    OnRequest,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunConfig {
+    pub redraw: RedrawMode,
+    /// Sleeps after each drawn frame to cap the frame rate
+    pub target_fps: Option<u32>,
+}
+
 impl Context {
     fn runner<S>(
         event: Event<'_, ()>,
@@ -154,6 +179,68 @@ impl Context {
         self.inner.into_inner().unwrap().run(event_handler);
     }
 
+    /// Like [`Context::run`], but sets the control flow from `config` before each event.
+    /// The handler can still override it, e.g. to exit
+    pub fn run_with<F>(self, config: RunConfig, mut event_handler: F)
+    where
+        F: 'static + FnMut(Event<'_, ()>, &EventLoopWindowTarget<()>, &mut ControlFlow),
+    {
+        let frame_time = config
+            .target_fps
+            .filter(|&fps| fps > 0)
+            .map(|fps| Duration::from_secs_f64(1. / fps as f64));
+        let mut next_frame = Instant::now();
+        let mut drew = false;
+
+        self.inner
+            .into_inner()
+            .unwrap()
+            .run(move |event, target, control_flow| {
+                *control_flow = match config.redraw {
+                    RedrawMode::Continuous => ControlFlow::Poll,
+                    RedrawMode::OnRequest => ControlFlow::Wait,
+                };
+
+                let frame_end = match (&event, config.redraw) {
+                    (Event::RedrawRequested(_), RedrawMode::OnRequest) => {
+                        drew = true;
+                        false
+                    }
+                    (Event::RedrawEventsCleared, RedrawMode::Continuous) => true,
+                    (Event::RedrawEventsCleared, RedrawMode::OnRequest) => drew,
+                    _ => false,
+                };
+
+                event_handler(event, target, control_flow);
+
+                if let (true, Some(frame_time)) = (frame_end, frame_time) {
+                    drew = false;
+                    next_frame += frame_time;
+
+                    let now = Instant::now();
+                    if next_frame > now {
+                        std::thread::sleep(next_frame - now);
+                    } else {
+                        next_frame = now;
+                    }
+                }
+            });
+    }
+
+    /// Like [`Context::run`], but returns once the handler sets `ControlFlow::Exit`, so the handler
+    /// can borrow from the caller and everything can be destroyed afterwards. Only desktop
+    /// platforms support returning from the event loop
+    pub fn run_return<F>(&self, event_handler: F) -> i32
+    where
+        F: FnMut(Event<'_, ()>, &EventLoopWindowTarget<()>, &mut ControlFlow),
+    {
+        self.inner
+            .borrow_mut()
+            .as_mut()
+            .unwrap()
+            .run_return(event_handler)
+    }
+
     pub fn as_ref(&self) -> Ref<'_, Option<EventLoop<()>>> {
         self.inner.borrow()
     }
@@ -215,11 +302,11 @@ impl DescriptorPool {
         };
 
         let sets = match sets {
-            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) => {
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
                 self.bump_pool(loader)?;
                 self.allocate(loader, set_layouts)?
             },
-            Err(_) => return Err(anyhow!("Unexpected pool allocation failure")),
+            Err(err) => return Err(SiltError::from(err).into()),
             Ok(sets) => {
                 self.current.allocations.fetch_add(sets.len(), Ordering::SeqCst);
                 sets.into_iter().map(|set| ManagedDescriptorSet{set, allocation: self.current.allocations.clone()}).collect()
@@ -243,7 +330,8 @@ impl DescriptorPool {
 
     fn bump_pool(&mut self, loader: &Loader) -> Result<()> {
         if self.exhausted.len() >= 64 {
-            return Err(anyhow!("Extreme descriptor pool fragmentation, likely a recursion error"));
+            // Extreme descriptor pool fragmentation, likely a recursion error
+            return Err(SiltError::DescriptorExhausted.into());
         }
 
         self.sweep(loader);
@@ -265,15 +353,27 @@ impl DescriptorPool {
         });
     }
 
-    const POOL_SIZES: [vk::DescriptorPoolSize; 4] = [
+    const POOL_SIZES: [vk::DescriptorPoolSize; 7] = [
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
                 descriptor_count: 32,
             },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: 32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: 8,
+            },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::UNIFORM_BUFFER,
                 descriptor_count: 32,
             },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                descriptor_count: 8,
+            },
             vk::DescriptorPoolSize {
                 ty: vk::DescriptorType::STORAGE_BUFFER,
                 descriptor_count: 8,
@@ -284,3 +384,114 @@ impl DescriptorPool {
             },
     ];
 }
+
+/// Destroys a resource whose type has been erased
+pub(crate) type DeferredDestroy<'a> = Box<dyn FnOnce(&Loader) + 'a>;
+
+/// Defers destruction of resources until the frame that last used them has finished.
+/// Push resources tagged with the current frame, then flush that frame once its fence has signaled
+pub struct DeletionQueue<K = Parity> {
+    pending: RefCell<HashMap<K, Vec<DeferredDestroy<'static>>>>,
+}
+
+impl<K> Default for DeletionQueue<K> {
+    fn default() -> Self {
+        Self {
+            pending: Default::default(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> DeletionQueue<K> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn push(&self, frame: K, resource: impl Destructible + 'static) {
+        self.pending
+            .borrow_mut()
+            .entry(frame)
+            .or_default()
+            .push(Box::new(move |loader| resource.destroy(loader)));
+    }
+
+    /// Destroys everything pushed for `frame`, in the order it was pushed
+    pub fn flush(&self, loader: &Loader, frame: &K) {
+        let pending = self.pending.borrow_mut().remove(frame);
+        pending
+            .into_iter()
+            .flatten()
+            .for_each(|destroy| destroy(loader));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.borrow().values().all(Vec::is_empty)
+    }
+}
+
+/// Flushes every frame, the device must be idle
+impl<K> Destructible for DeletionQueue<K> {
+    fn destroy(self, loader: &Loader) {
+        self.pending
+            .into_inner()
+            .into_values()
+            .flatten()
+            .for_each(|destroy| destroy(loader));
+    }
+}
+
+/// Destroys `T` once dropped, for RAII semantics instead of calling [`Destructible::destroy`].
+/// Keeps the loader alive, so every `Owned` must be dropped before unwrapping the loader for
+/// [`Loader::destroy`]. The device must be done with the value when it drops, push it onto a
+/// [`DeletionQueue`] to defer that until its frame has finished
+pub struct Owned<T: Destructible> {
+    value: Option<T>,
+    loader: Rc<Loader>,
+}
+
+impl<T: Destructible> Owned<T> {
+    pub fn new(loader: &Rc<Loader>, value: T) -> Self {
+        Self {
+            value: Some(value),
+            loader: loader.clone(),
+        }
+    }
+
+    /// Gives up ownership without destroying the value
+    pub fn into_inner(mut self) -> T {
+        self.value.take().unwrap()
+    }
+
+    pub fn loader(&self) -> &Rc<Loader> {
+        &self.loader
+    }
+}
+
+impl<T: Destructible> std::ops::Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().unwrap()
+    }
+}
+
+impl<T: Destructible> DerefMut for Owned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().unwrap()
+    }
+}
+
+impl<T: Destructible> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            value.destroy(&self.loader);
+        }
+    }
+}
+
+/// Destroys with the loader the value was created with
+impl<T: Destructible> Destructible for Owned<T> {
+    fn destroy(self, _: &Loader) {
+        drop(self);
+    }
+}
@@ -10,6 +10,7 @@ pub use winit::window::Window;
 
 pub use crate::loader::Loader;
 pub use crate::vk;
+pub use crate::resources::{record_layout_transition, Layout, SubresourceRange};
 
 mod managers;
 pub use managers::*;
@@ -21,4 +22,7 @@ mod traits;
 pub use traits::*;
 
 mod identifier;
-pub use identifier::*;
\ No newline at end of file
+pub use identifier::*;
+
+mod registry;
+pub use registry::*;
\ No newline at end of file
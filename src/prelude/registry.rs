@@ -0,0 +1,164 @@
+use std::any::{type_name, Any};
+use std::collections::HashMap;
+
+use crate::error::{Result, SiltError};
+
+use super::Identifier;
+
+struct Entry {
+    value: Box<dyn Any>,
+    type_name: &'static str,
+}
+
+/// Values of any type keyed by [`Identifier`], for resources without a dedicated store, e.g.
+/// those added by plugins. Identifiers are shared between all types, so registering one id
+/// as two different types is an error
+///
+/// ```ignore
+/// registry.insert(id!("Bloom Settings"), BloomConfig::default())?;
+/// let bloom = registry.get::<BloomConfig>(&id!("Bloom Settings"));
+/// ```
+#[derive(Default)]
+pub struct Registry {
+    entries: HashMap<Identifier, Entry>,
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.entries.iter().map(|(id, entry)| (id, entry.type_name)))
+            .finish()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails if `id` is already registered, with [`SiltError::MismatchedIdentifier`] if under another type
+    pub fn insert<T: Any>(&mut self, id: Identifier, value: T) -> Result<Identifier> {
+        if let Some(entry) = self.entries.get(&id) {
+            return Err(match entry.value.is::<T>() {
+                true => SiltError::DuplicateIdentifier(id),
+                false => SiltError::MismatchedIdentifier {
+                    id,
+                    expected: type_name::<T>(),
+                    found: entry.type_name,
+                },
+            });
+        }
+
+        self.entries.insert(
+            id.clone(),
+            Entry {
+                value: Box::new(value),
+                type_name: type_name::<T>(),
+            },
+        );
+
+        Ok(id)
+    }
+
+    /// `None` if `id` is missing or holds another type, see [`Registry::try_get`] to tell them apart
+    pub fn get<T: Any>(&self, id: &Identifier) -> Option<&T> {
+        self.entries.get(id)?.value.downcast_ref()
+    }
+
+    pub fn get_mut<T: Any>(&mut self, id: &Identifier) -> Option<&mut T> {
+        self.entries.get_mut(id)?.value.downcast_mut()
+    }
+
+    pub fn try_get<T: Any>(&self, id: &Identifier) -> Result<&T> {
+        let entry = self
+            .entries
+            .get(id)
+            .ok_or_else(|| SiltError::MissingIdentifier(id.clone()))?;
+
+        entry
+            .value
+            .downcast_ref()
+            .ok_or_else(|| SiltError::MismatchedIdentifier {
+                id: id.clone(),
+                expected: type_name::<T>(),
+                found: entry.type_name,
+            })
+    }
+
+    /// Leaves the entry in place if it holds another type
+    pub fn remove<T: Any>(&mut self, id: &Identifier) -> Option<T> {
+        if !self.entries.get(id)?.value.is::<T>() {
+            return None;
+        }
+
+        let entry = self.entries.remove(id)?;
+        entry.value.downcast().ok().map(|value| *value)
+    }
+
+    pub fn contains(&self, id: &Identifier) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    /// Every entry holding a `T`, in no particular order
+    pub fn iter<T: Any>(&self) -> impl Iterator<Item = (&Identifier, &T)> {
+        self.entries
+            .iter()
+            .filter_map(|(id, entry)| Some((id, entry.value.downcast_ref()?)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id;
+
+    #[test]
+    fn typed_lookup() {
+        let mut registry = Registry::new();
+        registry.insert(id!("count"), 3u32).unwrap();
+        registry.insert(id!("name"), String::from("bloom")).unwrap();
+
+        assert_eq!(registry.get::<u32>(&id!("count")), Some(&3));
+        assert_eq!(registry.get::<String>(&id!("count")), None);
+        assert_eq!(registry.get::<u32>(&id!("missing")), None);
+
+        *registry.get_mut::<u32>(&id!("count")).unwrap() += 1;
+        assert_eq!(registry.iter::<u32>().collect::<Vec<_>>(), vec![(&id!("count"), &4)]);
+
+        assert_eq!(registry.remove::<String>(&id!("count")), None);
+        assert_eq!(registry.remove::<u32>(&id!("count")), Some(4));
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn collisions_are_reported() {
+        let mut registry = Registry::new();
+        registry.insert(id!("settings"), 1.0f32).unwrap();
+
+        assert!(matches!(
+            registry.insert(id!("settings"), 2.0f32),
+            Err(SiltError::DuplicateIdentifier(_))
+        ));
+        assert!(matches!(
+            registry.insert(id!("settings"), 2u32),
+            Err(SiltError::MismatchedIdentifier { expected: "u32", found: "f32", .. })
+        ));
+        assert!(matches!(
+            registry.try_get::<u32>(&id!("settings")),
+            Err(SiltError::MismatchedIdentifier { .. })
+        ));
+        assert!(matches!(
+            registry.try_get::<u32>(&id!("missing")),
+            Err(SiltError::MissingIdentifier(_))
+        ));
+        assert_eq!(registry.get::<f32>(&id!("settings")), Some(&1.0));
+    }
+}
@@ -1,9 +1,19 @@
 use crate::vk;
 
-use super::Loader;
+use std::rc::Rc;
+
+use super::{Loader, Owned};
 
 pub trait Destructible {
     fn destroy(self, loader: &Loader);
+
+    /// Wraps the value to be destroyed when dropped, see [`Owned`]
+    fn owned(self, loader: &Rc<Loader>) -> Owned<Self>
+    where
+        Self: Sized,
+    {
+        Owned::new(loader, self)
+    }
 }
 
 impl Destructible for vk::Buffer {
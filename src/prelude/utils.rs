@@ -1,5 +1,9 @@
+use ash::util::read_spv;
 use shaderc::ShaderKind;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
 
+use crate::error::{Result, SiltError};
 use crate::vk;
 
 pub fn shader_kind_to_shader_stage_flags(kind: ShaderKind) -> vk::ShaderStageFlags {
@@ -42,5 +46,93 @@ pub trait Typed {
 
 pub struct ShaderCode {
     pub code: Vec<u32>,
-    pub kind: ShaderKind
-}
\ No newline at end of file
+    pub kind: ShaderKind,
+    /// Files pulled in through `#include`, empty for pre-compiled SPIR-V
+    pub includes: Vec<PathBuf>,
+}
+
+/// First word of every SPIR-V module
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+impl ShaderCode {
+    /// Pre-compiled SPIR-V, e.g. from `glslc` in a build script, so no source is compiled at runtime
+    pub fn from_spirv(bytes: &[u8], kind: ShaderKind) -> Result<Self> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(SiltError::InvalidUsage(format!(
+                "SPIR-V of {} bytes is not a whole number of words",
+                bytes.len()
+            )));
+        }
+
+        // Modules written on a big endian machine start with the swapped magic number
+        let magic = bytes.get(..4).map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+        if magic != Some(SPIRV_MAGIC) && magic != Some(SPIRV_MAGIC.swap_bytes()) {
+            return Err(SiltError::InvalidUsage(format!(
+                "SPIR-V magic number missing, found {:x?}",
+                magic
+            )));
+        }
+
+        // Checks the header and that no instruction runs past the end, e.g. of a truncated file
+        let code = read_spv(&mut Cursor::new(bytes))?;
+        crate::pipeline::spirv_instructions(&code)?;
+
+        Ok(Self {
+            code,
+            kind,
+            includes: vec![],
+        })
+    }
+
+    /// Reads a SPIR-V file named after its stage, e.g. `shader.frag.spv`
+    pub fn from_spirv_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let kind = path
+            .file_stem()
+            .and_then(|stem| crate::macros::get_kind(stem.to_str()?))
+            .ok_or_else(|| {
+                SiltError::InvalidUsage(format!(
+                    "failed to determine shader type of {}, expected e.g. shader.vert.spv",
+                    path.display()
+                ))
+            })?;
+
+        Self::from_spirv(&std::fs::read(path)?, kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header followed by a single `OpCapability Shader`
+    fn module() -> Vec<u8> {
+        [SPIRV_MAGIC, 0x0001_0000, 0, 1, 0, 2 << 16 | 17, 1]
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect()
+    }
+
+    #[test]
+    fn loads_whole_modules() {
+        let code = ShaderCode::from_spirv(&module(), ShaderKind::Vertex).unwrap();
+        assert_eq!(code.code.len(), 7);
+    }
+
+    #[test]
+    fn rejects_truncated_modules() {
+        let bytes = module();
+
+        // Inside the last instruction, inside the header and right after the magic number
+        for len in [24, 16, 4] {
+            assert!(ShaderCode::from_spirv(&bytes[..len], ShaderKind::Vertex).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_partial_words_and_missing_magic() {
+        let bytes = module();
+        assert!(ShaderCode::from_spirv(&bytes[..bytes.len() - 1], ShaderKind::Vertex).is_err());
+        assert!(ShaderCode::from_spirv(&bytes[4..], ShaderKind::Vertex).is_err());
+    }
+}
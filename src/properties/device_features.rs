@@ -9,6 +9,28 @@ bitflags! {
         const SAMPLER_ANISOTROPY    = 0b1 << 0;
         const SPARSE_BINDING        = 0b1 << 1;
         const IMAGE_CUBE_ARRAY      = 0b1 << 2;
+        const GEOMETRY_SHADER       = 0b1 << 3;
+        const TESSELLATION_SHADER   = 0b1 << 4;
+        const FILL_MODE_NON_SOLID   = 0b1 << 5;
+        const WIDE_LINES            = 0b1 << 6;
+        const SPARSE_RESIDENCY_IMAGE_2D = 0b1 << 7;
+        const STORAGE_IMAGE_WRITE_WITHOUT_FORMAT = 0b1 << 8;
+        /// Backed by `VK_KHR_buffer_device_address` rather than a core feature, see [`crate::resources::Buffer::device_address`]
+        const BUFFER_DEVICE_ADDRESS = 0b1 << 9;
+        /// Backed by `VK_EXT_mesh_shader`, which needs a 1.1 device. Task shaders are enabled when supported
+        const MESH_SHADER           = 0b1 << 10;
+        /// Backed by `VK_KHR_present_id` and `VK_KHR_present_wait`, see [`crate::swapchain::Swapchain::wait_present`]
+        const PRESENT_WAIT          = 0b1 << 11;
+        /// Backed by `VK_EXT_sampler_filter_minmax`, see [`crate::resources::SamplerConfig::reduction_mode`]
+        const SAMPLER_FILTER_MINMAX = 0b1 << 12;
+        /// Indirect draws with a non zero `first_instance`, see [`crate::resources::DrawDataBuffer`]
+        const DRAW_INDIRECT_FIRST_INSTANCE = 0b1 << 13;
+        /// More than one draw per indirect draw call
+        const MULTI_DRAW_INDIRECT   = 0b1 << 14;
+        /// Core in 1.1, rendering every view of a [`crate::pipeline::Multiview`] render pass in one draw
+        const MULTIVIEW             = 0b1 << 15;
+        /// Backed by `VK_EXT_extended_dynamic_state`, see [`crate::pipeline::PipelineState::dynamic_states`]
+        const EXTENDED_DYNAMIC_STATE = 0b1 << 16;
     }
 }
 
@@ -57,6 +79,30 @@ impl Into<DeviceFeatures> for vk::PhysicalDeviceFeatures {
         }).bitor(match self.image_cube_array {
             vk::TRUE => DeviceFeatures::IMAGE_CUBE_ARRAY,
             _ => DeviceFeatures::empty()
+        }).bitor(match self.geometry_shader {
+            vk::TRUE => DeviceFeatures::GEOMETRY_SHADER,
+            _ => DeviceFeatures::empty()
+        }).bitor(match self.tessellation_shader {
+            vk::TRUE => DeviceFeatures::TESSELLATION_SHADER,
+            _ => DeviceFeatures::empty()
+        }).bitor(match self.fill_mode_non_solid {
+            vk::TRUE => DeviceFeatures::FILL_MODE_NON_SOLID,
+            _ => DeviceFeatures::empty()
+        }).bitor(match self.wide_lines {
+            vk::TRUE => DeviceFeatures::WIDE_LINES,
+            _ => DeviceFeatures::empty()
+        }).bitor(match self.sparse_residency_image2_d {
+            vk::TRUE => DeviceFeatures::SPARSE_RESIDENCY_IMAGE_2D,
+            _ => DeviceFeatures::empty()
+        }).bitor(match self.shader_storage_image_write_without_format {
+            vk::TRUE => DeviceFeatures::STORAGE_IMAGE_WRITE_WITHOUT_FORMAT,
+            _ => DeviceFeatures::empty()
+        }).bitor(match self.draw_indirect_first_instance {
+            vk::TRUE => DeviceFeatures::DRAW_INDIRECT_FIRST_INSTANCE,
+            _ => DeviceFeatures::empty()
+        }).bitor(match self.multi_draw_indirect {
+            vk::TRUE => DeviceFeatures::MULTI_DRAW_INDIRECT,
+            _ => DeviceFeatures::empty()
         })
     }
 }
@@ -67,6 +113,16 @@ impl Into<vk::PhysicalDeviceFeatures> for DeviceFeatures {
             .sampler_anisotropy(self.contains(DeviceFeatures::SAMPLER_ANISOTROPY))
             .sparse_binding(self.contains(DeviceFeatures::SPARSE_BINDING))
             .image_cube_array(self.contains(DeviceFeatures::IMAGE_CUBE_ARRAY))
+            .geometry_shader(self.contains(DeviceFeatures::GEOMETRY_SHADER))
+            .tessellation_shader(self.contains(DeviceFeatures::TESSELLATION_SHADER))
+            .fill_mode_non_solid(self.contains(DeviceFeatures::FILL_MODE_NON_SOLID))
+            .wide_lines(self.contains(DeviceFeatures::WIDE_LINES))
+            .sparse_residency_image2_d(self.contains(DeviceFeatures::SPARSE_RESIDENCY_IMAGE_2D))
+            .shader_storage_image_write_without_format(
+                self.contains(DeviceFeatures::STORAGE_IMAGE_WRITE_WITHOUT_FORMAT),
+            )
+            .draw_indirect_first_instance(self.contains(DeviceFeatures::DRAW_INDIRECT_FIRST_INSTANCE))
+            .multi_draw_indirect(self.contains(DeviceFeatures::MULTI_DRAW_INDIRECT))
             .build()
     }
 }
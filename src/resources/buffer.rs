@@ -1,11 +1,13 @@
-use anyhow::{anyhow, Result};
+use crate::error::{Result, SiltError};
 use std::{
     cell::{Cell, RefCell},
     ops::Deref,
+    rc::{Rc, Weak},
 };
 
 use super::{ResourceDescription, TypedResourceDescription, UniformDescription};
 use crate::collections::{ParitySet, Parity};
+use crate::properties::DeviceFeatures;
 use crate::{id, prelude::*, resources::Image, sync::CommandPool};
 
 #[derive(Clone, Debug)]
@@ -13,6 +15,7 @@ pub struct Buffer {
     pub buffer: vk::Buffer,
     pub allocation: vk::Allocation,
     pub size: vk::DeviceSize,
+    pub usage: vk::BufferUsageFlags,
 }
 
 impl Destructible for Buffer {
@@ -22,38 +25,166 @@ impl Destructible for Buffer {
     }
 }
 
+/// How a resource's memory is allocated, resolved to a [`vk::AllocationScheme`] once the handle exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AllocationScheme {
+    /// Sub-allocated from a shared memory block
+    #[default]
+    Managed,
+    /// A memory block of its own, for very large resources or those shared through external memory
+    Dedicated,
+}
+
+impl AllocationScheme {
+    pub fn for_buffer(self, buffer: vk::Buffer) -> vk::AllocationScheme {
+        match self {
+            Self::Managed => vk::AllocationScheme::GpuAllocatorManaged,
+            Self::Dedicated => vk::AllocationScheme::DedicatedBuffer(buffer),
+        }
+    }
+
+    pub fn for_image(self, image: vk::Image) -> vk::AllocationScheme {
+        match self {
+            Self::Managed => vk::AllocationScheme::GpuAllocatorManaged,
+            Self::Dedicated => vk::AllocationScheme::DedicatedImage(image),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BufferCreateInfo {
     pub size: vk::DeviceSize,
     pub name: Identifier,
     pub usage: vk::BufferUsageFlags,
     pub location: vk::MemoryLocation,
+    pub allocation_scheme: AllocationScheme,
+}
+
+impl Default for BufferCreateInfo {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            name: NULL_ID.clone(),
+            usage: vk::BufferUsageFlags::empty(),
+            location: vk::MemoryLocation::GpuOnly,
+            allocation_scheme: AllocationScheme::Managed,
+        }
+    }
+}
+
+impl BufferCreateInfo {
+    pub fn builder() -> BufferCreateInfoBuilder {
+        Default::default()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BufferCreateInfoBuilder {
+    info: BufferCreateInfo,
+}
+
+impl BufferCreateInfoBuilder {
+    pub fn size(mut self, size: vk::DeviceSize) -> Self {
+        self.info.size = size;
+        self
+    }
+
+    pub fn name(mut self, name: Identifier) -> Self {
+        self.info.name = name;
+        self
+    }
+
+    /// Adds to the usage instead of replacing it
+    pub fn usage(mut self, usage: vk::BufferUsageFlags) -> Self {
+        self.info.usage |= usage;
+        self
+    }
+
+    pub fn location(mut self, location: vk::MemoryLocation) -> Self {
+        self.info.location = location;
+        self
+    }
+
+    pub fn vertex(self) -> Self {
+        self.usage(vk::BufferUsageFlags::VERTEX_BUFFER)
+    }
+
+    pub fn index(self) -> Self {
+        self.usage(vk::BufferUsageFlags::INDEX_BUFFER)
+    }
+
+    pub fn uniform(self) -> Self {
+        self.usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+    }
+
+    pub fn storage(self) -> Self {
+        self.usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+    }
+
+    pub fn transfer_src(self) -> Self {
+        self.usage(vk::BufferUsageFlags::TRANSFER_SRC)
+    }
+
+    pub fn transfer_dst(self) -> Self {
+        self.usage(vk::BufferUsageFlags::TRANSFER_DST)
+    }
+
+    /// Host visible transfer source for uploads
+    pub fn staging(self) -> Self {
+        self.transfer_src().location(vk::MemoryLocation::CpuToGpu)
+    }
+
+    pub fn dedicated(mut self) -> Self {
+        self.info.allocation_scheme = AllocationScheme::Dedicated;
+        self
+    }
+
+    pub fn build(self) -> Result<BufferCreateInfo> {
+        if self.info.size == 0 {
+            return Err(SiltError::InvalidUsage("buffer size must be non zero".into()));
+        }
+
+        if self.info.usage.is_empty() {
+            return Err(SiltError::InvalidUsage("buffer usage must not be empty".into()));
+        }
+
+        Ok(self.info)
+    }
 }
 
 impl Buffer {
     pub fn new(loader: &Loader, create_info: BufferCreateInfo) -> Result<Self> {
+        Self::create(&loader.device, &loader.allocator, create_info)
+    }
+
+    /// Shared with [`crate::sync::GpuUploadHandle`], which has no [`Loader`] to create from
+    pub(crate) fn create(
+        device: &Device,
+        allocator: &Allocator,
+        create_info: BufferCreateInfo,
+    ) -> Result<Self> {
         let buffer_create_info = vk::BufferCreateInfo::builder()
             .size(create_info.size)
             .usage(create_info.usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-        let buffer = unsafe { loader.device.create_buffer(&buffer_create_info, None)? };
-        let requirements = unsafe { loader.device.get_buffer_memory_requirements(buffer) };
+        let buffer = unsafe { device.create_buffer(&buffer_create_info, None)? };
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
 
         let allocation_create_info = vk::AllocationCreateInfo {
             name: create_info.name.as_str(),
             requirements,
             location: create_info.location,
             linear: true,
-            allocation_scheme: vk::AllocationScheme::GpuAllocatorManaged,
+            allocation_scheme: create_info.allocation_scheme.for_buffer(buffer),
         };
 
-        let allocation = loader.allocator.allocate(&allocation_create_info)?;
+        let allocation = allocator.allocate(&allocation_create_info)?;
         unsafe {
-            loader.device.bind_buffer_memory(
+            device.bind_buffer_memory(
                 buffer,
-                loader.allocator.get_memory(allocation)?,
-                loader.allocator.get_offset(allocation)?,
+                allocator.get_memory(allocation)?,
+                allocator.get_offset(allocation)?,
             )?
         };
 
@@ -61,9 +192,22 @@ impl Buffer {
             buffer,
             allocation,
             size: requirements.size,
+            usage: create_info.usage,
         })
     }
 
+    /// Requires [`DeviceFeatures::BUFFER_DEVICE_ADDRESS`], and the buffer to be created
+    /// with `SHADER_DEVICE_ADDRESS` usage
+    pub fn device_address(&self, loader: &Loader) -> Result<vk::DeviceAddress> {
+        let buffer_device_address = loader
+            .buffer_device_address
+            .as_ref()
+            .ok_or(SiltError::MissingFeatures(DeviceFeatures::BUFFER_DEVICE_ADDRESS))?;
+
+        let info = vk::BufferDeviceAddressInfo::builder().buffer(self.buffer);
+        Ok(unsafe { buffer_device_address.get_buffer_device_address(&info) })
+    }
+
     pub fn copy_to_buffer(
         &self,
         loader: &Loader,
@@ -72,19 +216,17 @@ impl Buffer {
         region: vk::BufferCopy,
     ) -> Result<()> {
         if region.size + region.src_offset > self.size {
-            return Err(anyhow!(
+            return Err(SiltError::InvalidUsage(format!(
                 "region exceeded src bounds: [{:?}] [{:?}]",
-                region,
-                self
-            ));
+                region, self
+            )));
         }
 
         if region.size + region.dst_offset > dst.size {
-            return Err(anyhow!(
+            return Err(SiltError::InvalidUsage(format!(
                 "region exceeded dst bounds: [{:?}] [{:?}]",
-                region,
-                dst
-            ));
+                region, dst
+            )));
         }
 
         pool.execute_one_time_commands(loader, |loader, cmd| unsafe {
@@ -94,7 +236,9 @@ impl Buffer {
                 dst.buffer,
                 std::slice::from_ref(&region),
             );
-        })
+        })?;
+
+        Ok(())
     }
 
     pub fn copy_to_entire_buffer(
@@ -104,7 +248,10 @@ impl Buffer {
         dst: &Buffer,
     ) -> Result<()> {
         if self.size != dst.size {
-            return Err(anyhow!("src and dst mismatch: [{:?}] [{:?}]", self, dst));
+            return Err(SiltError::InvalidUsage(format!(
+                "src and dst mismatch: [{:?}] [{:?}]",
+                self, dst
+            )));
         }
 
         let region = vk::BufferCopy::builder().size(self.size).build();
@@ -117,25 +264,39 @@ impl Buffer {
         pool: &CommandPool,
         dst: &Image,
         region: vk::BufferImageCopy,
+    ) -> Result<()> {
+        pool.execute_one_time_commands(loader, |loader, cmd| {
+            self.record_copy_to_image(loader, cmd, dst, region)
+        })?
+    }
+
+    /// Records the copy into an existing command buffer, `dst` must be in `TransferDst`
+    pub fn record_copy_to_image(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        dst: &Image,
+        region: vk::BufferImageCopy,
     ) -> Result<()> {
         let region_volume = vk::Volume3D::from(region.image_extent).offset_by(region.image_offset);
         if !vk::Volume3D::from(dst.size).contains(&region_volume) {
-            return Err(anyhow!(
+            return Err(SiltError::InvalidUsage(format!(
                 "Region extents past image bounds: [{:?}] [{:?}]",
-                region,
-                dst
-            ));
+                region, dst
+            )));
         }
 
-        pool.execute_one_time_commands(loader, |loader, cmd| unsafe {
+        unsafe {
             loader.device.cmd_copy_buffer_to_image(
-                cmd,
+                command_buffer,
                 self.buffer,
                 dst.image,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 std::slice::from_ref(&region),
             );
-        })
+        }
+
+        Ok(())
     }
 
     pub fn copy_to_entire_image(
@@ -174,6 +335,7 @@ impl Buffer {
             name: NULL_ID.clone(),
             usage: usage | vk::BufferUsageFlags::TRANSFER_SRC,
             location: vk::MemoryLocation::CpuToGpu,
+            ..Default::default()
         };
 
         let staging = Self::new(loader, staging_ci)?;
@@ -184,6 +346,7 @@ impl Buffer {
             name,
             usage: usage | vk::BufferUsageFlags::TRANSFER_DST,
             location: vk::MemoryLocation::GpuOnly,
+            ..Default::default()
         };
 
         let buffer = Self::new(loader, buffer_ci)?;
@@ -194,12 +357,108 @@ impl Buffer {
         Ok(buffer)
     }
 
-    /// DOES NOT DO ANY BOUNDS CHECKING
-    pub unsafe fn copy_data<T: Copy>(&self, loader: &Loader, data: &[T]) {
-        get_align(loader, self).copy_from_slice(data);
+    /// Copies `data` to the start of the buffer. Host visible buffers are written
+    /// directly, device local buffers go through a staging buffer, which requires
+    /// a `pool` and the buffer to have been created with `TRANSFER_DST` usage
+    pub fn copy_data<T: Copy>(
+        &self,
+        loader: &Loader,
+        pool: Option<&CommandPool>,
+        data: &[T],
+    ) -> Result<()> {
+        let size = std::mem::size_of_val(data) as u64;
+        if size > self.size {
+            return Err(SiltError::InvalidUsage(format!(
+                "data of size {} exceeds buffer bounds: [{:?}]",
+                size, self
+            )));
+        }
+
+        if loader.allocator.get_mapped_ptr(self.allocation).is_ok() {
+            unsafe { get_align(loader, self).copy_from_slice(data) };
+            return Ok(());
+        }
+
+        let pool = pool.ok_or_else(|| {
+            SiltError::InvalidUsage(format!(
+                "buffer is not host visible and no command pool was provided for a staging copy: [{:?}]",
+                self
+            ))
+        })?;
+
+        let staging_ci = BufferCreateInfo {
+            size,
+            name: NULL_ID.clone(),
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            location: vk::MemoryLocation::CpuToGpu,
+            ..Default::default()
+        };
+
+        let staging = Self::new(loader, staging_ci)?;
+        unsafe { get_align(loader, &staging).copy_from_slice(data) };
+
+        let region = vk::BufferCopy::builder().size(size).build();
+        let result = staging.copy_to_buffer(loader, pool, self, region);
+        staging.destroy(loader);
+
+        result
+    }
+
+    /// Reads the whole buffer back as `T`s. Host visible buffers are read directly, device
+    /// local buffers are copied into a staging buffer first, which requires the buffer to
+    /// have been created with `TRANSFER_SRC` usage. Waits for the copy to complete
+    ///
+    /// Nothing is waited on before reading, every submission writing the buffer has to have
+    /// completed, e.g. by waiting on its fence. Otherwise the read may observe partial writes
+    pub fn read_back<T: Copy>(&self, loader: &Loader, pool: &CommandPool) -> Result<Vec<T>> {
+        let stride = std::mem::size_of::<T>() as u64;
+        if stride == 0 || !self.size.is_multiple_of(stride) {
+            return Err(SiltError::InvalidUsage(format!(
+                "buffer size is not a multiple of {} bytes: [{:?}]",
+                stride, self
+            )));
+        }
+
+        let len = (self.size / stride) as usize;
+        if loader.allocator.get_mapped_ptr(self.allocation).is_ok() {
+            return Ok(unsafe { read_mapped(loader, self, len) });
+        }
+
+        if !self.usage.contains(vk::BufferUsageFlags::TRANSFER_SRC) {
+            return Err(SiltError::InvalidUsage(format!(
+                "device local buffers need TRANSFER_SRC usage to be read back: [{:?}]",
+                self
+            )));
+        }
+
+        let staging_ci = BufferCreateInfo {
+            size: self.size,
+            name: NULL_ID.clone(),
+            usage: vk::BufferUsageFlags::TRANSFER_DST,
+            location: vk::MemoryLocation::GpuToCpu,
+            ..Default::default()
+        };
+
+        let staging = Self::new(loader, staging_ci)?;
+        let region = vk::BufferCopy::builder().size(self.size).build();
+        let result = self
+            .copy_to_buffer(loader, pool, &staging, region)
+            .and_then(|_| {
+                loader.allocator.get_mapped_ptr(staging.allocation)?;
+                Ok(unsafe { read_mapped(loader, &staging, len) })
+            });
+        staging.destroy(loader);
+
+        result
     }
 }
 
+/// The buffer must be persistently mapped and hold at least `len` elements
+unsafe fn read_mapped<T: Copy>(loader: &Loader, buffer: &Buffer, len: usize) -> Vec<T> {
+    let ptr = loader.allocator.get_mapped_ptr(buffer.allocation).unwrap().as_ptr() as *const T;
+    (0..len).map(|index| ptr.add(index).read_unaligned()).collect()
+}
+
 unsafe fn get_align<T: Copy>(loader: &Loader, buffer: &Buffer) -> Align<T> {
     let ptr = match loader.allocator.get_mapped_ptr(buffer.allocation) {
         Ok(mut ptr) => ptr.as_mut(),
@@ -254,6 +513,12 @@ pub struct UniformBuffer<T: Copy> {
     value: Cell<T>,
 }
 
+impl<T: Copy> Destructible for UniformBuffer<T> {
+    fn destroy(self, loader: &Loader) {
+        self.buffers.into_iter().destroy(loader);
+    }
+}
+
 impl<T: Copy> UniformBuffer<T> {
     pub fn new(
         loader: &Loader,
@@ -276,12 +541,13 @@ impl<T: Copy> UniformBuffer<T> {
                 } else {
                     vk::MemoryLocation::GpuOnly
                 },
+                ..Default::default()
             },
             _ => {
-                return Err(anyhow!(
+                return Err(SiltError::InvalidUsage(format!(
                     "Resource description [{:?}] not a uniform buffer description",
                     description.deref()
-                ))
+                )))
             }
         };
 
@@ -312,3 +578,82 @@ impl<T: Copy> UniformBuffer<T> {
         self.copy(parity, value);
     }
 }
+
+/// Propagates pending writes to the buffer for `parity`, called by
+/// [`crate::material::MaterialSystem::advance_frame`]
+pub(crate) trait FrameSync {
+    fn sync(&self, parity: Parity);
+}
+
+struct FrameUniformState<T: Copy> {
+    buffer: UniformBuffer<T>,
+    dirty: ParitySet<Cell<bool>>,
+    frame: Rc<Cell<Parity>>,
+}
+
+impl<T: Copy> FrameSync for FrameUniformState<T> {
+    fn sync(&self, parity: Parity) {
+        if self.dirty.get(parity).replace(false) {
+            self.buffer.copy(parity, self.buffer.value.get());
+        }
+    }
+}
+
+/// Uniform buffer double buffered behind a single value. Created through
+/// [`crate::material::MaterialSystem::frame_uniform`], which writes each frame's
+/// buffer as it advances so parity never has to be threaded through by hand
+pub struct FrameUniform<T: Copy> {
+    state: Rc<FrameUniformState<T>>,
+}
+
+impl<T: Copy> Destructible for FrameUniform<T> {
+    fn destroy(self, loader: &Loader) {
+        if let Ok(state) = Rc::try_unwrap(self.state) {
+            state.buffer.buffers.destroy(loader);
+        }
+    }
+}
+
+impl<T: Copy + 'static> FrameUniform<T> {
+    pub(crate) fn new(
+        loader: &Loader,
+        description: &TypedResourceDescription<T>,
+        value: T,
+        name: Option<Identifier>,
+        frame: Rc<Cell<Parity>>,
+    ) -> Result<(Self, Weak<dyn FrameSync>)> {
+        let state = Rc::new(FrameUniformState {
+            buffer: UniformBuffer::new(loader, description, value, name)?,
+            dirty: ParitySet::new(Cell::new(true), Cell::new(true)),
+            frame,
+        });
+        state.sync(state.frame.get());
+
+        let sync = Rc::downgrade(&state) as Weak<dyn FrameSync>;
+        Ok((Self { state }, sync))
+    }
+}
+
+impl<T: Copy> FrameUniform<T> {
+    pub fn get(&self) -> T {
+        self.state.buffer.value.get()
+    }
+
+    /// Writes the current frame's buffer immediately, the other once the frame advances.
+    /// Call after waiting on the current frame's fence
+    pub fn set(&self, value: T) {
+        self.state.buffer.value.set(value);
+        self.state.dirty.iter().for_each(|dirty| dirty.set(true));
+        self.state.sync(self.state.frame.get());
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        let mut value = self.get();
+        f(&mut value);
+        self.set(value);
+    }
+
+    pub fn get_buffers(&self) -> ParitySet<&Buffer> {
+        self.state.buffer.get_buffers()
+    }
+}
@@ -1,5 +1,10 @@
-use derive_more::{Constructor, Deref, From, IsVariant, Unwrap};
-use std::{marker::PhantomData, rc::Rc};
+use derive_more::{Deref, From, IsVariant, Unwrap};
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use crate::{
     prelude::*
@@ -12,6 +17,8 @@ pub struct PartialBindingDescription {
     pub frequency: vk::DescriptorFrequency,
     pub binding: u32,
     pub count: u32,
+    /// Set index declared by the shader, when it differs from the one the frequency maps to
+    pub set: Option<u32>,
 }
 
 impl PartialBindingDescription {
@@ -28,6 +35,7 @@ impl PartialBindingDescription {
             frequency: self.frequency,
             binding: self.binding,
             count: self.count,
+            set: self.set,
         }
     }
 }
@@ -38,6 +46,7 @@ pub struct BindingDescription {
     pub frequency: vk::DescriptorFrequency,
     pub binding: u32,
     pub count: u32,
+    pub set: Option<u32>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -47,6 +56,8 @@ pub struct UniformDescription {
     pub stride: vk::DeviceSize,
     pub elements: usize,
     pub host_visible: bool,
+    /// Bound as `UNIFORM_BUFFER_DYNAMIC`, one element per draw, see [`super::ObjectUniforms`]
+    pub dynamic: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -55,12 +66,30 @@ pub struct SampledImageDescription {
     pub binding: PartialBindingDescription,
 }
 
+/// A `SAMPLER` binding, combined with [`ResourceDescription::SeparateImage`] bindings in the shader
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplerDescription {
+    pub id: Identifier,
+    pub binding: PartialBindingDescription,
+}
+
 /// Allows for easy construction of ```VertexInputDescription``` objects
 pub trait VertexInput {
     fn bindings() -> Vec<vk::VertexInputBindingDescription>;
     fn attributes() -> Vec<vk::VertexInputAttributeDescription>;
 }
 
+/// Combines per vertex and per instance inputs, each half must use distinct bindings and locations
+impl<V: VertexInput, I: VertexInput> VertexInput for (V, I) {
+    fn bindings() -> Vec<vk::VertexInputBindingDescription> {
+        V::bindings().into_iter().chain(I::bindings()).collect()
+    }
+
+    fn attributes() -> Vec<vk::VertexInputAttributeDescription> {
+        V::attributes().into_iter().chain(I::attributes()).collect()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VertexInputDescription {
     pub id: Identifier,
@@ -92,10 +121,9 @@ pub enum AttachmentType {
 }
 
 impl AttachmentType {
-    pub fn load_op(&self) -> vk::AttachmentLoadOp {
+    pub fn load_op(&self, clear: ClearValue) -> vk::AttachmentLoadOp {
         match self {
-            AttachmentType::Color => vk::AttachmentLoadOp::CLEAR,
-            AttachmentType::DepthStencil => vk::AttachmentLoadOp::CLEAR,
+            AttachmentType::Color | AttachmentType::DepthStencil => clear.load_op(),
             AttachmentType::Input(_) => vk::AttachmentLoadOp::LOAD,
             AttachmentType::Resolve => vk::AttachmentLoadOp::DONT_CARE,
         }
@@ -110,13 +138,22 @@ impl AttachmentType {
         }
     }
 
-    pub fn stencil_load_op(&self, use_stencil: bool) -> vk::AttachmentLoadOp {
+    pub fn stencil_load_op(&self, use_stencil: bool, clear: ClearValue) -> vk::AttachmentLoadOp {
         match self {
-            AttachmentType::DepthStencil if use_stencil => vk::AttachmentLoadOp::CLEAR,
+            AttachmentType::DepthStencil if use_stencil => clear.load_op(),
             _ => vk::AttachmentLoadOp::DONT_CARE
         }
     }
 
+    /// Black for color targets, a depth of 1 and stencil of 0 for depth targets
+    pub fn default_clear(&self) -> ClearValue {
+        match self {
+            AttachmentType::Color => ClearValue::Color([0., 0., 0., 1.]),
+            AttachmentType::DepthStencil => ClearValue::DepthStencil(1., 0),
+            AttachmentType::Input(_) | AttachmentType::Resolve => ClearValue::Load,
+        }
+    }
+
     pub fn stencil_store_op(&self, use_stencil: bool) -> vk::AttachmentStoreOp {
         match self {
             AttachmentType::DepthStencil if use_stencil => vk::AttachmentStoreOp::STORE,
@@ -125,6 +162,26 @@ impl AttachmentType {
     }
 }
 
+/// How an attachment's contents are initialized at the start of a render pass
+#[derive(Debug, Clone, Copy, PartialEq, IsVariant)]
+pub enum ClearValue {
+    Color([f32; 4]),
+    /// Clears the stencil to 0 as well, if the format has one
+    Depth(f32),
+    DepthStencil(f32, u32),
+    /// Keeps the previous contents, which must be in the attachment's final layout
+    Load,
+}
+
+impl ClearValue {
+    pub fn load_op(&self) -> vk::AttachmentLoadOp {
+        match self {
+            ClearValue::Load => vk::AttachmentLoadOp::LOAD,
+            _ => vk::AttachmentLoadOp::CLEAR,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct AttachmentDescription {
     pub id: Identifier,
@@ -133,12 +190,82 @@ pub struct AttachmentDescription {
     pub format: vk::Format,
     pub samples: vk::SampleCountFlags,
     pub final_layout: vk::ImageLayout,
+    pub clear: ClearValue,
+}
+
+impl AttachmentDescription {
+    /// Colors are converted to match the numeric type of color formats. Attachments that
+    /// aren't cleared still take up a slot in the clear values, which is ignored
+    pub fn clear_value(&self) -> vk::ClearValue {
+        match self.clear {
+            ClearValue::Color(color) => vk::ClearValue {
+                color: clear_color(self.format, color),
+            },
+            ClearValue::Depth(depth) => vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil: 0 },
+            },
+            ClearValue::DepthStencil(depth, stencil) => vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+            },
+            ClearValue::Load => vk::ClearValue::default(),
+        }
+    }
+
+    /// Loaded attachments start in their final layout, as left by the previous pass
+    pub fn initial_layout(&self) -> vk::ImageLayout {
+        match self.ty.load_op(self.clear) {
+            vk::AttachmentLoadOp::LOAD => self.final_layout,
+            _ => vk::ImageLayout::UNDEFINED,
+        }
+    }
+}
+
+/// Integer formats are cleared through the `int32`/`uint32` members, everything else through `float32`
+pub fn clear_color(format: vk::Format, color: [f32; 4]) -> vk::ClearColorValue {
+    match format {
+        vk::Format::R8_UINT
+        | vk::Format::R8G8_UINT
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::B8G8R8A8_UINT
+        | vk::Format::A2B10G10R10_UINT_PACK32
+        | vk::Format::R16_UINT
+        | vk::Format::R16G16_UINT
+        | vk::Format::R16G16B16A16_UINT
+        | vk::Format::R32_UINT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32B32A32_UINT => vk::ClearColorValue {
+            uint32: color.map(|c| c as u32),
+        },
+        vk::Format::R8_SINT
+        | vk::Format::R8G8_SINT
+        | vk::Format::R8G8B8A8_SINT
+        | vk::Format::B8G8R8A8_SINT
+        | vk::Format::R16_SINT
+        | vk::Format::R16G16_SINT
+        | vk::Format::R16G16B16A16_SINT
+        | vk::Format::R32_SINT
+        | vk::Format::R32G32_SINT
+        | vk::Format::R32G32B32A32_SINT => vk::ClearColorValue {
+            int32: color.map(|c| c as i32),
+        },
+        _ => vk::ClearColorValue { float32: color },
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, IsVariant, Unwrap, From)]
 pub enum ResourceDescription {
     Uniform(UniformDescription),
     SampledImage(SampledImageDescription),
+    /// A `SAMPLED_IMAGE` binding without a sampler
+    #[from(ignore)]
+    SeparateImage(SampledImageDescription),
+    /// A `STORAGE_IMAGE` binding, read and written by compute shaders in `GENERAL` layout
+    #[from(ignore)]
+    StorageImage(SampledImageDescription),
+    Sampler(SamplerDescription),
+    /// A `STORAGE_BUFFER` binding of `elements` values, read by index in the shader
+    #[from(ignore)]
+    StorageBuffer(UniformDescription),
     VertexInput(VertexInputDescription),
     Attachment(AttachmentDescription),
 }
@@ -148,6 +275,10 @@ impl Identified for ResourceDescription {
         match self {
             Self::Uniform(desc) => &desc.id,
             Self::SampledImage(desc) => &desc.id,
+            Self::SeparateImage(desc) => &desc.id,
+            Self::StorageImage(desc) => &desc.id,
+            Self::Sampler(desc) => &desc.id,
+            Self::StorageBuffer(desc) => &desc.id,
             Self::VertexInput(desc) => &desc.id,
             Self::Attachment(desc) => &desc.id,
         }
@@ -166,20 +297,19 @@ impl<T> TypedResourceDescription<T> {
     where
         F: FnOnce(&Self) -> R,
     {
-        Resource {
-            resource: f(self),
-            description: self.inner.clone(),
-        }
+        Resource::new(f(self), self.inner.clone())
     }
 
     pub fn bind_result<F, R, E>(&self, f: F) -> Result<Resource<R>, E>
     where
         F: FnOnce(&Self) -> Result<R, E>,
     {
-        Ok(Resource {
-            resource: f(self)?,
-            description: self.inner.clone(),
-        })
+        Ok(Resource::new(f(self)?, self.inner.clone()))
+    }
+
+    /// See [`ResourceDescription::with_set`]
+    pub fn with_set(&self, set: u32) -> Self {
+        self.inner.with_set(set).into()
     }
 }
 
@@ -207,7 +337,10 @@ impl ResourceDescription {
         Some(match self {
             Self::Uniform(desc) => {
                 BindingDescription {
-                    ty: vk::DescriptorType::UNIFORM_BUFFER,
+                    ty: match desc.dynamic {
+                        true => vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                        false => vk::DescriptorType::UNIFORM_BUFFER,
+                    },
                     ..desc.binding.as_binding()
                 }
             },
@@ -217,6 +350,30 @@ impl ResourceDescription {
                     ..desc.binding.as_binding()
                 }
             },
+            Self::SeparateImage(desc) => {
+                BindingDescription {
+                    ty: vk::DescriptorType::SAMPLED_IMAGE,
+                    ..desc.binding.as_binding()
+                }
+            },
+            Self::StorageImage(desc) => {
+                BindingDescription {
+                    ty: vk::DescriptorType::STORAGE_IMAGE,
+                    ..desc.binding.as_binding()
+                }
+            },
+            Self::StorageBuffer(desc) => {
+                BindingDescription {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    ..desc.binding.as_binding()
+                }
+            },
+            Self::Sampler(desc) => {
+                BindingDescription {
+                    ty: vk::DescriptorType::SAMPLER,
+                    ..desc.binding.as_binding()
+                }
+            },
             Self::Attachment(desc) if desc.ty.is_input() => {
                 BindingDescription {
                     ty: vk::DescriptorType::INPUT_ATTACHMENT,
@@ -227,24 +384,41 @@ impl ResourceDescription {
         })
     }
 
+    /// Copy of the description declared at descriptor set `set` rather than the set its
+    /// frequency maps to, e.g. for third party shaders placing everything at set 0. All bindings
+    /// of a frequency share one set, so overriding one moves the whole frequency. Descriptions
+    /// without a shader binding are returned unchanged
+    pub fn with_set(self: &Rc<Self>, set: u32) -> Rc<Self> {
+        let mut description = self.as_ref().clone();
+        match &mut description {
+            Self::Uniform(UniformDescription { binding, .. })
+            | Self::SampledImage(SampledImageDescription { binding, .. })
+            | Self::SeparateImage(SampledImageDescription { binding, .. })
+            | Self::StorageImage(SampledImageDescription { binding, .. })
+            | Self::Sampler(SamplerDescription { binding, .. })
+            | Self::StorageBuffer(UniformDescription { binding, .. })
+            | Self::Attachment(AttachmentDescription {
+                ty: AttachmentType::Input(binding),
+                ..
+            }) => binding.set = Some(set),
+            _ => {}
+        }
+
+        Rc::new(description)
+    }
+
     pub fn bind<F, R>(self: &Rc<Self>, f: F) -> Resource<R>
     where
         F: FnOnce(&Rc<Self>) -> R,
     {
-        Resource {
-            resource: f(self),
-            description: self.clone(),
-        }
+        Resource::new(f(self), self.clone())
     }
 
     pub fn bind_result<F, R, E>(self: &Rc<Self>, f: F) -> Result<Resource<R>, E>
     where
         F: FnOnce(&Rc<Self>) -> Result<R, E>,
     {
-        Ok(Resource {
-            resource: f(self)?,
-            description: self.clone(),
-        })
+        Ok(Resource::new(f(self)?, self.clone()))
     }
 
     pub fn uniform<T>(
@@ -258,14 +432,64 @@ impl ResourceDescription {
                 frequency,
                 binding,
                 count: 1,
+                set: None,
             },
             stride: std::mem::size_of::<T>() as u64,
             elements: 1,
             host_visible: true,
+            dynamic: false,
+        }))
+        .into()
+    }
+
+    /// Array of `capacity` uniforms at [`vk::DescriptorFrequency::Object`], each draw selecting
+    /// its element with a dynamic offset, see [`super::ObjectUniforms`]
+    pub fn object_uniform<T>(id: Identifier, binding: u32, capacity: usize) -> TypedResourceDescription<T> {
+        Rc::new(Self::from(UniformDescription {
+            id,
+            binding: PartialBindingDescription {
+                frequency: vk::DescriptorFrequency::Object,
+                binding,
+                count: 1,
+                set: None,
+            },
+            stride: std::mem::size_of::<T>() as u64,
+            elements: capacity,
+            host_visible: true,
+            dynamic: true,
+        }))
+        .into()
+    }
+
+    /// Storage buffer of `elements` values of `T`, laid out with std430 rules
+    pub fn storage_buffer<T>(
+        id: Identifier,
+        binding: u32,
+        frequency: vk::DescriptorFrequency,
+        elements: usize,
+    ) -> TypedResourceDescription<T> {
+        Rc::new(Self::StorageBuffer(UniformDescription {
+            id,
+            binding: PartialBindingDescription {
+                frequency,
+                binding,
+                count: 1,
+                set: None,
+            },
+            stride: std::mem::size_of::<T>() as u64,
+            elements,
+            host_visible: true,
+            dynamic: false,
         }))
         .into()
     }
 
+    /// Per draw [`super::DrawData`] for `capacity` draws at [`vk::DescriptorFrequency::Object`],
+    /// see [`super::DrawDataBuffer`]
+    pub fn draw_data(id: Identifier, binding: u32, capacity: usize) -> TypedResourceDescription<super::DrawData> {
+        Self::storage_buffer(id, binding, vk::DescriptorFrequency::Object, capacity)
+    }
+
     pub fn sampled_image(id: Identifier, binding: u32, frequency: vk::DescriptorFrequency) -> Rc<Self> {
         Rc::new(
             SampledImageDescription {
@@ -274,12 +498,98 @@ impl ResourceDescription {
                     frequency,
                     binding,
                     count: 1,
+                    set: None,
+                },
+            }
+            .into(),
+        )
+    }
+
+    /// Image bound without a sampler, sampled in the shader through a [`ResourceDescription::sampler`],
+    /// e.g. `texture(sampler2D(tex, samp), uv)`
+    pub fn separate_image(id: Identifier, binding: u32, frequency: vk::DescriptorFrequency) -> Rc<Self> {
+        Rc::new(Self::SeparateImage(SampledImageDescription {
+            id,
+            binding: PartialBindingDescription {
+                frequency,
+                binding,
+                count: 1,
+                set: None,
+            },
+        }))
+    }
+
+    /// Image written by compute shaders, e.g. `RWTexture2D<float4>`. Bind a [`super::Image`] with
+    /// `STORAGE` usage, it's always accessed in `GENERAL` layout, see [`super::Layout::ComputeReadWrite`]
+    pub fn storage_image(id: Identifier, binding: u32, frequency: vk::DescriptorFrequency) -> Rc<Self> {
+        Rc::new(Self::StorageImage(SampledImageDescription {
+            id,
+            binding: PartialBindingDescription {
+                frequency,
+                binding,
+                count: 1,
+                set: None,
+            },
+        }))
+    }
+
+    pub fn sampler(id: Identifier, binding: u32, frequency: vk::DescriptorFrequency) -> Rc<Self> {
+        Rc::new(
+            SamplerDescription {
+                id,
+                binding: PartialBindingDescription {
+                    frequency,
+                    binding,
+                    count: 1,
+                    set: None,
                 },
             }
             .into(),
         )
     }
 
+    /// Color targets can use any renderable format, e.g. `R16G16B16A16_SFLOAT` for HDR intermediates.
+    /// Cleared with [`AttachmentType::default_clear`]
+    pub fn attachment(
+        id: Identifier,
+        ty: AttachmentType,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        final_layout: vk::ImageLayout,
+    ) -> Rc<Self> {
+        Self::attachment_with_clear(id, ty, format, samples, final_layout, ty.default_clear())
+    }
+
+    pub fn attachment_with_clear(
+        id: Identifier,
+        ty: AttachmentType,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        final_layout: vk::ImageLayout,
+        clear: ClearValue,
+    ) -> Rc<Self> {
+        let use_stencil = matches!(
+            format,
+            vk::Format::D16_UNORM_S8_UINT
+                | vk::Format::D24_UNORM_S8_UINT
+                | vk::Format::D32_SFLOAT_S8_UINT
+                | vk::Format::S8_UINT
+        );
+
+        Rc::new(
+            AttachmentDescription {
+                id,
+                ty,
+                use_stencil,
+                format,
+                samples,
+                final_layout,
+                clear,
+            }
+            .into(),
+        )
+    }
+
     pub fn vertex_input<T: VertexInput>(id: Identifier) -> Rc<Self> {
         Rc::new(
             VertexInputDescription {
@@ -299,8 +609,60 @@ macro_rules! resources {
     };
 }
 
-#[derive(Debug, Clone, Constructor)]
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn next_generation() -> u64 {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone)]
 pub struct Resource<T> {
     pub resource: T,
     pub description: Rc<ResourceDescription>,
-}
\ No newline at end of file
+    /// Replaced by [`Resource::mark_dirty`], descriptors are only rewritten when it changes.
+    /// Unique across every resource, so a new resource replacing one with the same id is rewritten too
+    pub generation: Cell<u64>,
+}
+
+impl<T> Resource<T> {
+    pub fn new(resource: T, description: Rc<ResourceDescription>) -> Self {
+        Self {
+            resource,
+            description,
+            generation: Cell::new(next_generation()),
+        }
+    }
+
+    /// Call after replacing the underlying buffer or image, so descriptors referencing it are rewritten
+    pub fn mark_dirty(&self) {
+        self.generation.set(next_generation());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id;
+
+    fn description() -> Rc<ResourceDescription> {
+        ResourceDescription::sampler(id!("Sampler"), 0, vk::DescriptorFrequency::Global)
+    }
+
+    #[test]
+    fn replacing_a_resource_changes_its_generation() {
+        let old = Resource::new((), description());
+        let new = Resource::new((), description());
+        assert_ne!(old.generation.get(), new.generation.get());
+    }
+
+    #[test]
+    fn marking_dirty_never_repeats_a_generation() {
+        let old = Resource::new((), description());
+        let new = Resource::new((), description());
+
+        old.mark_dirty();
+        assert_ne!(old.generation.get(), new.generation.get());
+        new.mark_dirty();
+        assert_ne!(old.generation.get(), new.generation.get());
+    }
+}
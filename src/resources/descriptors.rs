@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Result};
+use crate::error::{Result, SiltError};
 use derive_more::{Deref, IsVariant, Unwrap};
 use itertools::Itertools;
 use std::collections::HashSet;
@@ -8,7 +8,8 @@ use std::sync::Arc;
 use std::{collections::HashMap, rc::Rc};
 
 use super::{
-    BindingDescription, Buffer, Resource, ResourceDescription, SampledImage, UniformBuffer,
+    BindingDescription, Buffer, FrameUniform, Image, Layout, Resource, ResourceDescription,
+    SampledImage, Sampler, UniformBuffer,
 };
 use crate::collections::{ParitySet, PartialFrequencySet, Redundancy, RedundantSet};
 use crate::{collections::FrequencySet, material::ShaderModule, prelude::*};
@@ -21,6 +22,8 @@ use crate::{collections::FrequencySet, material::ShaderModule, prelude::*};
 pub struct PipelineLayout {
     pub pipeline: vk::PipelineLayout,
     pub descriptors: FrequencySet<Option<vk::DescriptorSetLayout>>,
+    /// Bindings each set layout was created from, to compare layouts across pipelines
+    signatures: FrequencySet<Option<SetSignature>>,
 }
 
 impl Destructible for PipelineLayout {
@@ -29,6 +32,49 @@ impl Destructible for PipelineLayout {
     }
 }
 
+impl PipelineLayout {
+    /// Number of leading sets laid out identically in both layouts. Sets bound with one stay
+    /// valid after binding a pipeline with the other, up to this prefix
+    pub fn compatible_with(&self, other: &PipelineLayout) -> CompatiblePrefix {
+        compatible_prefix(&self.signatures, &other.signatures)
+    }
+}
+
+/// Leading descriptor sets shared by two pipeline layouts, see [`PipelineLayout::compatible_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deref)]
+pub struct CompatiblePrefix(pub usize);
+
+/// Binding, type, count and stages of every binding in a set layout, sorted by binding
+type SetSignature = Vec<(u32, vk::DescriptorType, u32, vk::ShaderStageFlags)>;
+
+fn set_signature(bindings: &[vk::DescriptorSetLayoutBinding]) -> SetSignature {
+    bindings
+        .iter()
+        .map(|binding| {
+            (
+                binding.binding,
+                binding.descriptor_type,
+                binding.descriptor_count,
+                binding.stage_flags,
+            )
+        })
+        .collect()
+}
+
+fn compatible_prefix<T: PartialEq>(
+    lhs: &FrequencySet<Option<T>>,
+    rhs: &FrequencySet<Option<T>>,
+) -> CompatiblePrefix {
+    // Unused frequencies take no set index, so compare the sets as bound
+    CompatiblePrefix(
+        lhs.values()
+            .flatten()
+            .zip(rhs.values().flatten())
+            .take_while(|(lhs, rhs)| lhs == rhs)
+            .count(),
+    )
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Discriminant {
     Global,
@@ -60,11 +106,10 @@ fn consolidate_bindings<I: IntoIterator<Item = vk::DescriptorSetLayoutBinding>>(
 
                     *acc.last_mut().unwrap() = back;
                 } else if back.binding == binding.binding {
-                    return Err(anyhow!(
+                    return Err(SiltError::InvalidUsage(format!(
                         "Bindings match, but other elements not equivalent: {:?}, {:?}",
-                        back,
-                        binding
-                    ));
+                        back, binding
+                    )));
                 } else {
                     acc.push(binding)
                 }
@@ -76,6 +121,26 @@ fn consolidate_bindings<I: IntoIterator<Item = vk::DescriptorSetLayoutBinding>>(
         })
 }
 
+fn set_index(frequency: vk::DescriptorFrequency) -> usize {
+    vk::DescriptorFrequency::ELEMENTS
+        .iter()
+        .position(|&freq| freq == frequency)
+        .unwrap()
+}
+
+/// First used frequency whose declared set index differs from the index it is bound at,
+/// returned with both indices. Sets are bound contiguously from 0 in frequency order, so a gap
+/// or out of order override would shift sets away from the index the shader declares
+fn find_misplaced_set(
+    sets: &FrequencySet<Option<u32>>,
+) -> Option<(vk::DescriptorFrequency, u32, u32)> {
+    sets.iter()
+        .filter_map(|(freq, set)| set.map(|set| (freq, set)))
+        .zip(0..)
+        .find(|((_, declared), bound)| declared != bound)
+        .map(|((freq, declared), bound)| (freq, declared, bound))
+}
+
 #[derive(Debug, Clone, Deref)]
 pub struct Layouts {
     descriptors_flat: Vec<vk::DescriptorSetLayout>,
@@ -102,6 +167,29 @@ impl Layouts {
     where
         S: IntoIterator<Item = (&'a Identifier, &'a ShaderModule)> + Clone,
     {
+        let set_overrides = shaders
+            .clone()
+            .into_iter()
+            .flat_map(|(id, module)| {
+                module
+                    .resources
+                    .iter()
+                    .filter_map(|resource| resource.get_shader_binding())
+                    .filter_map(move |desc| Some(((id.clone(), desc.frequency), desc.set?)))
+            })
+            .into_group_map()
+            .into_iter()
+            .map(|((id, freq), sets)| match sets.iter().all_equal() {
+                true => Ok(((id, freq), sets[0])),
+                false => Err(SiltError::InvalidUsage(format!(
+                    "pipeline {} declares {:?} descriptors at differing sets {:?}",
+                    id,
+                    freq,
+                    sets.into_iter().unique().collect_vec()
+                ))),
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
         let descriptor_layouts = shaders
             .clone()
             .into_iter()
@@ -143,11 +231,11 @@ impl Layouts {
                         .create_descriptor_set_layout(&create_info, None)?
                 };
 
-                Ok((discriminant, layout))
+                Ok((discriminant, (layout, set_signature(&bindings))))
             })
             .collect::<Result<HashMap<_, _>>>()?;
 
-        let layouts = shaders
+        let descriptors = shaders
             .into_iter()
             .map(|(id, _)| id)
             .collect::<HashSet<_>>()
@@ -166,8 +254,46 @@ impl Layouts {
                         layout.map(|layout| (freq, layout))
                     })
                     .collect::<FrequencySet<Vec<_>>>()
-                    .map(|layouts| layouts.get(0).copied());
+                    .map(|layouts| layouts.into_iter().next());
+
+                let sets = descriptors
+                    .iter()
+                    .filter(|(_, layout)| layout.is_some())
+                    .map(|(freq, _)| {
+                        let set = set_overrides.get(&(id.clone(), freq)).copied();
+                        (freq, set.unwrap_or(set_index(freq) as u32))
+                    })
+                    .collect::<FrequencySet<Vec<_>>>()
+                    .map(|sets| sets.into_iter().next());
+
+                (
+                    id,
+                    descriptors.ref_map(|layout| layout.as_ref().map(|(layout, _)| *layout)),
+                    descriptors.map(|layout| layout.map(|(_, signature)| signature)),
+                    sets,
+                )
+            })
+            .collect_vec();
 
+        if let Some((id, (freq, declared, bound))) = descriptors
+            .iter()
+            .find_map(|(id, _, _, sets)| find_misplaced_set(sets).map(|set| (id, set)))
+        {
+            descriptor_layouts
+                .into_values()
+                .map(|(layout, _)| layout)
+                .destroy(loader);
+            return Err(SiltError::InvalidUsage(format!(
+                "pipeline {} declares {:?} descriptors at set {}, but they are bound at set {}. \
+                Sets are bound contiguously in frequency order, so every lower frequency needs at \
+                least one binding, and overridden sets must keep that order",
+                id, freq, declared, bound
+            )));
+        }
+
+        let layouts = descriptors
+            .into_iter()
+            .map(|(id, descriptors, signatures, _)| {
                 let flattened = descriptors.values().copied().flatten().collect_vec();
                 let create_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&flattened);
 
@@ -180,14 +306,20 @@ impl Layouts {
                         PipelineLayout {
                             pipeline,
                             descriptors,
+                            signatures,
                         },
                     )
                 })
             })
             .collect::<std::result::Result<HashMap<_, _>, _>>()?;
 
-        let global_layout = descriptor_layouts.get(&Discriminant::Global).copied();
-        let descriptors_flat = descriptor_layouts.into_values().collect::<Vec<_>>();
+        let global_layout = descriptor_layouts
+            .get(&Discriminant::Global)
+            .map(|(layout, _)| *layout);
+        let descriptors_flat = descriptor_layouts
+            .into_values()
+            .map(|(layout, _)| layout)
+            .collect::<Vec<_>>();
 
         Ok(Layouts {
             descriptors_flat,
@@ -200,7 +332,15 @@ impl Layouts {
 #[derive(Debug, Clone, Copy, IsVariant, Unwrap)]
 pub enum ResourceReference<'a> {
     Buffer(&'a Buffer),
+    /// Buffer bound one element of the given size at a time, for `UNIFORM_BUFFER_DYNAMIC` bindings
+    DynamicBuffer(&'a Buffer, vk::DeviceSize),
     Image(&'a SampledImage),
+    /// View only, for `SAMPLED_IMAGE` bindings
+    SampledImageSeparate(&'a Image),
+    /// View only in `GENERAL` layout, for `STORAGE_IMAGE` bindings
+    StorageImage(&'a Image),
+    /// Sampler only, for `SAMPLER` bindings
+    Sampler(&'a Sampler),
 }
 
 impl ResourceReference<'_> {
@@ -210,46 +350,112 @@ impl ResourceReference<'_> {
         loader: &Loader,
         set: vk::DescriptorSet,
     ) {
-        let buffer_info = match self {
-            Self::Buffer(buffer) => Some(
+        let mut writer = DescriptorWriter::default();
+        writer.push(self, binding, set);
+        writer.submit(loader);
+    }
+}
+
+/// Collects descriptor writes so they can be submitted in a single `update_descriptor_sets` call
+#[derive(Debug, Default)]
+pub struct DescriptorWriter {
+    buffers: Vec<(vk::DescriptorSet, BindingDescription, vk::DescriptorBufferInfo)>,
+    images: Vec<(vk::DescriptorSet, BindingDescription, vk::DescriptorImageInfo)>,
+}
+
+impl DescriptorWriter {
+    pub fn push(
+        &mut self,
+        reference: &ResourceReference,
+        binding: &BindingDescription,
+        set: vk::DescriptorSet,
+    ) {
+        match reference {
+            ResourceReference::Buffer(buffer) => self.buffers.push((
+                set,
+                *binding,
                 vk::DescriptorBufferInfo::builder()
                     .buffer(buffer.buffer)
                     .range(buffer.size)
-                    .offset(0),
-            ),
-            _ => None,
-        };
-
-        let image_info = match self {
-            Self::Image(image) => {
-                Some(
-                    vk::DescriptorImageInfo::builder()
-                        .image_view(image.image.view)
-                        .sampler(image.sampler)
-                        .image_layout(image.image.layout.get().get_layout()), // TODO this could easily be wrong??
-                )
-            }
-            _ => None,
-        };
+                    .offset(0)
+                    .build(),
+            )),
+            ResourceReference::DynamicBuffer(buffer, range) => self.buffers.push((
+                set,
+                *binding,
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer.buffer)
+                    .range(*range)
+                    .offset(0)
+                    .build(),
+            )),
+            ResourceReference::Image(image) => self.images.push((
+                set,
+                *binding,
+                vk::DescriptorImageInfo::builder()
+                    .image_view(image.image.view)
+                    .sampler(image.sampler)
+                    .image_layout(image.image.layout.get().get_layout()) // TODO this could easily be wrong??
+                    .build(),
+            )),
+            ResourceReference::SampledImageSeparate(image) => self.images.push((
+                set,
+                *binding,
+                vk::DescriptorImageInfo::builder()
+                    .image_view(image.view)
+                    .image_layout(image.layout.get().get_layout())
+                    .build(),
+            )),
+            ResourceReference::StorageImage(image) => self.images.push((
+                set,
+                *binding,
+                vk::DescriptorImageInfo::builder()
+                    .image_view(image.view)
+                    .image_layout(Layout::ComputeReadWrite.get_layout())
+                    .build(),
+            )),
+            ResourceReference::Sampler(sampler) => self.images.push((
+                set,
+                *binding,
+                vk::DescriptorImageInfo::builder()
+                    .sampler(sampler.sampler)
+                    .build(),
+            )),
+        }
+    }
 
-        let mut descriptor_write = vk::WriteDescriptorSet::builder()
-            .descriptor_type(binding.ty)
-            .dst_binding(binding.binding)
-            .dst_set(set);
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty() && self.images.is_empty()
+    }
 
-        if let Some(buffer_info) = &buffer_info {
-            descriptor_write = descriptor_write.buffer_info(std::slice::from_ref(buffer_info));
+    pub fn submit(self, loader: &Loader) {
+        if self.is_empty() {
+            return;
         }
 
-        if let Some(image_info) = &image_info {
-            descriptor_write = descriptor_write.image_info(std::slice::from_ref(image_info));
-        }
+        let write = |set: vk::DescriptorSet, binding: &BindingDescription| {
+            vk::WriteDescriptorSet::builder()
+                .descriptor_type(binding.ty)
+                .dst_binding(binding.binding)
+                .dst_set(set)
+        };
 
-        unsafe {
-            loader
-                .device
-                .update_descriptor_sets(std::slice::from_ref(&descriptor_write), &[]);
-        }
+        let writes = self
+            .buffers
+            .iter()
+            .map(|(set, binding, info)| {
+                write(*set, binding)
+                    .buffer_info(std::slice::from_ref(info))
+                    .build()
+            })
+            .chain(self.images.iter().map(|(set, binding, info)| {
+                write(*set, binding)
+                    .image_info(std::slice::from_ref(info))
+                    .build()
+            }))
+            .collect_vec();
+
+        unsafe { loader.device.update_descriptor_sets(&writes, &[]) };
     }
 }
 
@@ -257,6 +463,8 @@ impl ResourceReference<'_> {
 pub struct ResourceBinding<'a> {
     pub description: Rc<ResourceDescription>,
     pub reference: RedundantSet<ResourceReference<'a>>,
+    /// Generation of the bound [`Resource`], see [`Resource::mark_dirty`]
+    pub generation: u64,
 }
 
 impl<'a> AsRef<ResourceBinding<'a>> for ResourceBinding<'a> {
@@ -270,7 +478,7 @@ impl ResourceBinding<'_> {
         Ok(self
                 .reference
                 .as_type(Redundancy::Parity, None)
-                .map_err(|_| anyhow!("Resources corresponding to a descriptor set must be either single or a parity set"))?
+                .map_err(|_| SiltError::InvalidUsage("Resources corresponding to a descriptor set must be either single or a parity set".into()))?
                 .unwrap_parity()
         )
     }
@@ -298,13 +506,24 @@ impl ResourceBinding<'_> {
         &self,
         loader: &Loader,
         sets: FrequencySet<Option<ParitySet<vk::DescriptorSet>>>,
+    ) -> Result<()> {
+        let mut writer = DescriptorWriter::default();
+        self.queue_partial(&mut writer, sets)?;
+        writer.submit(loader);
+        Ok(())
+    }
+
+    pub fn queue_partial(
+        &self,
+        writer: &mut DescriptorWriter,
+        sets: FrequencySet<Option<ParitySet<vk::DescriptorSet>>>,
     ) -> Result<()> {
         if let Some(binding) = self.description.get_shader_binding() {
             let references = self.get_references()?;
 
             if let Some(set) = sets.get(binding.frequency) {
                 for (reference, set) in std::iter::zip(references, set) {
-                    reference.write_descriptor(&binding, loader, *set);
+                    writer.push(reference, &binding, *set);
                 }
             }
         }
@@ -313,12 +532,23 @@ impl ResourceBinding<'_> {
     }
 
     pub fn write_global(&self, loader: &Loader, sets: ParitySet<vk::DescriptorSet>) -> Result<()> {
+        let mut writer = DescriptorWriter::default();
+        self.queue_global(&mut writer, sets)?;
+        writer.submit(loader);
+        Ok(())
+    }
+
+    pub fn queue_global(
+        &self,
+        writer: &mut DescriptorWriter,
+        sets: ParitySet<vk::DescriptorSet>,
+    ) -> Result<()> {
         match self.description.get_shader_binding() {
             Some(binding) if binding.frequency == vk::DescriptorFrequency::Global => {
                 let references = self.get_references()?;
 
                 for (reference, set) in std::iter::zip(references, sets) {
-                    reference.write_descriptor(&binding, loader, set);
+                    writer.push(reference, &binding, set);
                 }
             }
             _ => (),
@@ -329,11 +559,28 @@ impl ResourceBinding<'_> {
 }
 
 pub trait BindableResource {
-    fn bind(&self) -> ResourceBinding;
+    fn bind(&self) -> ResourceBinding<'_>;
 }
 
 impl<T: Copy> BindableResource for Resource<UniformBuffer<T>> {
-    fn bind(&self) -> ResourceBinding {
+    fn bind(&self) -> ResourceBinding<'_> {
+        let description = self.description.clone();
+        let reference = self
+            .resource
+            .get_buffers()
+            .ref_map(|&buffer| ResourceReference::Buffer(buffer))
+            .into();
+
+        ResourceBinding {
+            description,
+            reference,
+            generation: self.generation.get(),
+        }
+    }
+}
+
+impl<T: Copy> BindableResource for Resource<FrameUniform<T>> {
+    fn bind(&self) -> ResourceBinding<'_> {
         let description = self.description.clone();
         let reference = self
             .resource
@@ -344,18 +591,65 @@ impl<T: Copy> BindableResource for Resource<UniformBuffer<T>> {
         ResourceBinding {
             description,
             reference,
+            generation: self.generation.get(),
+        }
+    }
+}
+
+/// Shared by both frames, e.g. a storage buffer written by compute shaders
+impl BindableResource for Resource<Buffer> {
+    fn bind(&self) -> ResourceBinding<'_> {
+        let description = self.description.clone();
+        let reference = ResourceReference::Buffer(&self.resource).into();
+
+        ResourceBinding {
+            description,
+            reference,
+            generation: self.generation.get(),
         }
     }
 }
 
 impl BindableResource for Resource<SampledImage> {
-    fn bind(&self) -> ResourceBinding {
+    fn bind(&self) -> ResourceBinding<'_> {
         let description = self.description.clone();
         let reference = ResourceReference::Image(&self.resource).into();
 
         ResourceBinding {
             description,
             reference,
+            generation: self.generation.get(),
+        }
+    }
+}
+
+/// Bound as a storage image for [`ResourceDescription::StorageImage`] descriptions, sampled otherwise
+impl BindableResource for Resource<Image> {
+    fn bind(&self) -> ResourceBinding<'_> {
+        let description = self.description.clone();
+        let reference = match description.as_ref() {
+            ResourceDescription::StorageImage(_) => ResourceReference::StorageImage(&self.resource),
+            _ => ResourceReference::SampledImageSeparate(&self.resource),
+        }
+        .into();
+
+        ResourceBinding {
+            description,
+            reference,
+            generation: self.generation.get(),
+        }
+    }
+}
+
+impl BindableResource for Resource<Sampler> {
+    fn bind(&self) -> ResourceBinding<'_> {
+        let description = self.description.clone();
+        let reference = ResourceReference::Sampler(&self.resource).into();
+
+        ResourceBinding {
+            description,
+            reference,
+            generation: self.generation.get(),
         }
     }
 }
@@ -413,10 +707,12 @@ impl DescriptorSets {
         R: IntoIterator<Item = I>,
     {
         let sets = self.get_unmanaged_sets().into_frequency_set(None);
+        let mut writer = DescriptorWriter::default();
         for resource in resources {
-            resource.as_ref().write_partial(loader, sets)?;
+            resource.as_ref().queue_partial(&mut writer, sets)?;
         }
 
+        writer.submit(loader);
         Ok(())
     }
 }
@@ -436,9 +732,277 @@ where
     I: AsRef<ResourceBinding<'a>>,
     R: IntoIterator<Item = I>,
 {
+    let mut writer = DescriptorWriter::default();
     for resource in resources {
-        resource.as_ref().write_global(loader, sets)?;
+        resource.as_ref().queue_global(&mut writer, sets)?;
     }
 
+    writer.submit(loader);
     Ok(())
 }
+
+impl ResourceReference<'_> {
+    /// Raw handles written by [`DescriptorWriter::push`], identifying the descriptor contents
+    fn handles(&self) -> [u64; 2] {
+        use vk::Handle;
+
+        match self {
+            ResourceReference::Buffer(buffer) => [buffer.buffer.as_raw(), buffer.size],
+            ResourceReference::DynamicBuffer(buffer, range) => [buffer.buffer.as_raw(), *range],
+            ResourceReference::Image(image) => [image.image.view.as_raw(), image.sampler.as_raw()],
+            ResourceReference::SampledImageSeparate(image) | ResourceReference::StorageImage(image) => {
+                [image.view.as_raw(), 0]
+            }
+            ResourceReference::Sampler(sampler) => [0, sampler.sampler.as_raw()],
+        }
+    }
+}
+
+/// Sets are keyed on the bindings of their layout rather than the layout handle, since sets
+/// allocated with one layout can be bound with any identically defined layout of another effect
+type DescriptorSetKey = (SetSignature, Vec<(BindingDescription, [[u64; 2]; 2])>);
+
+#[derive(Debug)]
+struct CachedDescriptorSets {
+    sets: ParitySet<ManagedDescriptorSet>,
+    /// Id and generation of each bound resource when the sets were written
+    resources: Vec<(Identifier, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DescriptorCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+}
+
+/// Shares descriptor sets between identical combinations of set bindings and bound resources,
+/// e.g. materials that only differ in their pipeline but bind the same textures and uniforms.
+/// Entries are retired when a bound resource's generation changes or it's invalidated,
+/// see [`DescriptorSetCache::release_retired`]
+#[derive(Debug, Default)]
+pub struct DescriptorSetCache {
+    sets: HashMap<DescriptorSetKey, CachedDescriptorSets>,
+    retired: Vec<ParitySet<ManagedDescriptorSet>>,
+    stats: DescriptorCacheStats,
+}
+
+impl Destructible for DescriptorSetCache {
+    fn destroy(self, loader: &Loader) {
+        self.sets
+            .into_values()
+            .flat_map(|cached| cached.sets)
+            .destroy(loader);
+        self.retired.into_iter().flatten().destroy(loader);
+    }
+}
+
+impl DescriptorSetCache {
+    /// Sets for the `frequency` set of `layout` holding the `frequency` bindings of `resources`,
+    /// allocated from `pool` and written on a miss. Hits are shared by every layout declaring
+    /// the same bindings at that frequency. Resources bound at other frequencies are ignored
+    pub fn get_or_write<'a, R, I>(
+        &mut self,
+        loader: &Loader,
+        pool: &mut DescriptorPool,
+        layout: &PipelineLayout,
+        frequency: vk::DescriptorFrequency,
+        resources: R,
+    ) -> Result<ParitySet<vk::DescriptorSet>>
+    where
+        I: AsRef<ResourceBinding<'a>>,
+        R: IntoIterator<Item = I>,
+    {
+        let (set_layout, signature) = match (
+            layout.descriptors.get(frequency),
+            layout.signatures.get(frequency),
+        ) {
+            (Some(set_layout), Some(signature)) => (*set_layout, signature.clone()),
+            _ => {
+                return Err(SiltError::InvalidUsage(format!(
+                    "pipeline layout has no descriptor set at frequency {:?}",
+                    frequency
+                )))
+            }
+        };
+
+        let resources = resources
+            .into_iter()
+            .filter_map(|resource| {
+                let binding = resource.as_ref().description.get_shader_binding()?;
+                (binding.frequency == frequency).then_some((resource, binding))
+            })
+            .sorted_by_key(|(_, binding)| binding.binding)
+            .collect_vec();
+
+        let mut bindings = vec![];
+        let mut generations = vec![];
+        let mut writes = vec![];
+        for (resource, binding) in &resources {
+            let resource = resource.as_ref();
+            let references = resource.get_references()?;
+            bindings.push((*binding, [references.even.handles(), references.odd.handles()]));
+            generations.push((resource.description.id().clone(), resource.generation));
+            writes.push((references, binding));
+        }
+
+        let key = (signature, bindings);
+        match self.sets.remove(&key) {
+            Some(cached) if cached.resources == generations => {
+                let sets = cached.sets.ref_map(|set| **set);
+                self.sets.insert(key, cached);
+                self.stats.hits += 1;
+                return Ok(sets);
+            }
+            Some(stale) => {
+                self.retired.push(stale.sets);
+                self.stats.invalidations += 1;
+            }
+            None => (),
+        }
+
+        self.stats.misses += 1;
+        let even = pool.allocate(loader, &[set_layout])?.remove(0);
+        let odd = match pool.allocate(loader, &[set_layout]) {
+            Ok(mut sets) => sets.remove(0),
+            Err(err) => {
+                even.destroy(loader);
+                return Err(err.into());
+            }
+        };
+        let sets = ParitySet::new(even, odd);
+
+        // References were resolved before allocating, so nothing past here can fail
+        let mut writer = DescriptorWriter::default();
+        for (references, binding) in writes {
+            for (reference, set) in std::iter::zip(references, sets.iter()) {
+                writer.push(reference, binding, **set);
+            }
+        }
+        writer.submit(loader);
+
+        let unmanaged = sets.ref_map(|set| **set);
+        self.sets.insert(
+            key,
+            CachedDescriptorSets {
+                sets,
+                resources: generations,
+            },
+        );
+
+        Ok(unmanaged)
+    }
+
+    /// Retires every entry binding the resource `id`, so its next lookup writes fresh sets
+    pub fn invalidate(&mut self, id: &Identifier) {
+        let stale = self
+            .sets
+            .iter()
+            .filter(|(_, cached)| cached.resources.iter().any(|(resource, _)| resource == id))
+            .map(|(key, _)| key.clone())
+            .collect_vec();
+
+        for key in stale {
+            let cached = self.sets.remove(&key).unwrap();
+            self.retired.push(cached.sets);
+            self.stats.invalidations += 1;
+        }
+    }
+
+    /// Frees retired sets, call once no frame in flight can still be using them
+    pub fn release_retired(&mut self, loader: &Loader) {
+        self.retired.drain(..).flatten().destroy(loader);
+    }
+
+    pub fn stats(&self) -> DescriptorCacheStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signatures(sets: [Option<u32>; 4]) -> FrequencySet<Option<u32>> {
+        unsafe { FrequencySet::from_iter_unsafe(sets) }
+    }
+
+    /// Frequency derived set indices of the used frequencies
+    fn layouts(used: [bool; 4]) -> FrequencySet<Option<u32>> {
+        signatures([0, 1, 2, 3].map(Some)).map(|set| set.filter(|&set| used[set as usize]))
+    }
+
+    #[test]
+    fn contiguous_sets_have_no_gap() {
+        assert_eq!(find_misplaced_set(&layouts([false; 4])), None);
+        assert_eq!(find_misplaced_set(&layouts([true, false, false, false])), None);
+        assert_eq!(find_misplaced_set(&layouts([true, true, true, false])), None);
+        assert_eq!(find_misplaced_set(&layouts([true; 4])), None);
+    }
+
+    #[test]
+    fn gapped_sets_report_missing_set() {
+        // Sets 0 and 2 without set 1
+        assert_eq!(
+            find_misplaced_set(&layouts([true, false, true, false])),
+            Some((vk::DescriptorFrequency::Material, 2, 1))
+        );
+
+        assert_eq!(
+            find_misplaced_set(&layouts([false, false, false, true])),
+            Some((vk::DescriptorFrequency::Object, 3, 0))
+        );
+        assert_eq!(set_index(vk::DescriptorFrequency::Object), 3);
+    }
+
+    #[test]
+    fn overridden_sets_replace_frequency_order() {
+        // Material only shader declaring everything at set 0
+        assert_eq!(find_misplaced_set(&signatures([None, None, Some(0), None])), None);
+        assert_eq!(
+            find_misplaced_set(&signatures([Some(0), None, Some(1), Some(2)])),
+            None
+        );
+
+        // Overrides still bind in frequency order
+        assert_eq!(
+            find_misplaced_set(&signatures([Some(1), None, Some(0), None])),
+            Some((vk::DescriptorFrequency::Global, 1, 0))
+        );
+    }
+
+    #[test]
+    fn identical_layouts_are_fully_compatible() {
+        let layout = signatures([Some(0), Some(1), Some(2), None]);
+        assert_eq!(compatible_prefix(&layout, &layout), CompatiblePrefix(3));
+    }
+
+    #[test]
+    fn layouts_compatible_up_to_first_difference() {
+        let lhs = signatures([Some(0), Some(1), Some(2), None]);
+        let rhs = signatures([Some(0), Some(1), Some(3), Some(4)]);
+        assert_eq!(compatible_prefix(&lhs, &rhs), CompatiblePrefix(2));
+
+        // A set only one side uses ends the prefix
+        let shorter = signatures([Some(0), None, None, None]);
+        assert_eq!(compatible_prefix(&lhs, &shorter), CompatiblePrefix(1));
+    }
+
+    #[test]
+    fn incompatible_layouts_share_no_sets() {
+        let lhs = signatures([Some(0), Some(1), None, None]);
+        let rhs = signatures([Some(5), Some(1), None, None]);
+        assert_eq!(compatible_prefix(&lhs, &rhs), CompatiblePrefix(0));
+
+        let empty = signatures([None; 4]);
+        assert_eq!(compatible_prefix(&empty, &empty), CompatiblePrefix(0));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::collections::{Parity, ParitySet};
+use crate::error::{Result, SiltError};
+use crate::properties::DeviceFeatures;
+use crate::sync::FrameContext;
+use crate::{id, prelude::*};
+use std::cell::Cell;
+use std::ops::Deref;
+
+use super::{
+    BindableResource, Buffer, BufferCreateInfo, Resource, ResourceBinding, ResourceDescription,
+    ResourceReference, TypedResourceDescription, UniformDescription,
+};
+
+/// Per draw object data, matching the std430 layout of
+///
+/// ```glsl
+/// struct DrawData {
+///     mat4 transform;
+///     uint material;
+/// };
+///
+/// layout(std430, set = 3, binding = 0) readonly buffer Draws {
+///     DrawData draws[];
+/// };
+/// ```
+///
+/// Indexed with `gl_InstanceIndex`, as each indirect draw built by [`DrawDataBuilder::push_indexed`]
+/// starts at its entry's index as `first_instance`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawData {
+    pub transform: glam::Mat4,
+    pub material: u32,
+    /// std430 rounds the struct up to the 16 byte alignment of the matrix
+    _padding: [u32; 3],
+}
+
+impl DrawData {
+    pub fn new(transform: glam::Mat4, material: u32) -> Self {
+        Self {
+            transform,
+            material,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Accumulates the draw data of a frame, handing out the index each entry is read at
+#[derive(Debug, Clone, Default)]
+pub struct DrawDataBuilder {
+    entries: Vec<DrawData>,
+    commands: Vec<vk::DrawIndexedIndirectCommand>,
+}
+
+impl DrawDataBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entry without a draw, for draws recorded by hand
+    pub fn push(&mut self, data: DrawData) -> u32 {
+        self.entries.push(data);
+        self.entries.len() as u32 - 1
+    }
+
+    /// Adds an entry along with a single instance indexed draw reading it
+    pub fn push_indexed(
+        &mut self,
+        data: DrawData,
+        index_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+    ) -> u32 {
+        let index = self.push(data);
+        self.commands.push(vk::DrawIndexedIndirectCommand {
+            index_count,
+            instance_count: 1,
+            first_index,
+            vertex_offset,
+            first_instance: index,
+        });
+
+        index
+    }
+
+    pub fn entries(&self) -> &[DrawData] {
+        &self.entries
+    }
+
+    pub fn commands(&self) -> &[vk::DrawIndexedIndirectCommand] {
+        &self.commands
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.commands.clear();
+    }
+}
+
+/// Storage buffer of [`DrawData`] and the indirect draws reading it, one of each per frame.
+/// Described by [`ResourceDescription::draw_data`], a frame's draws are recorded in one call
+/// with [`DrawDataBuffer::record_draws`]
+#[derive(Debug)]
+pub struct DrawDataBuffer {
+    data: ParitySet<Buffer>,
+    commands: ParitySet<Buffer>,
+    capacity: usize,
+    /// Commands uploaded for each frame
+    draw_counts: ParitySet<Cell<u32>>,
+}
+
+impl Destructible for DrawDataBuffer {
+    fn destroy(self, loader: &Loader) {
+        self.data.into_iter().destroy(loader);
+        self.commands.into_iter().destroy(loader);
+    }
+}
+
+impl DrawDataBuffer {
+    pub fn new(loader: &Loader, description: &TypedResourceDescription<DrawData>) -> Result<Self> {
+        let capacity = match description.deref() {
+            ResourceDescription::StorageBuffer(UniformDescription { elements, .. }) => *elements,
+            _ => {
+                return Err(SiltError::InvalidUsage(format!(
+                    "Resource description [{:?}] not a draw data description",
+                    description.deref()
+                )))
+            }
+        };
+
+        if capacity == 0 {
+            return Err(SiltError::InvalidUsage("draw data capacity must be non zero".into()));
+        }
+
+        let data_info = BufferCreateInfo::builder()
+            .size((std::mem::size_of::<DrawData>() * capacity) as u64)
+            .name(id!("Draw Data"))
+            .storage()
+            .location(vk::MemoryLocation::CpuToGpu)
+            .build()?;
+
+        let commands_info = BufferCreateInfo::builder()
+            .size((std::mem::size_of::<vk::DrawIndexedIndirectCommand>() * capacity) as u64)
+            .name(id!("Draw Data Commands"))
+            .usage(vk::BufferUsageFlags::INDIRECT_BUFFER)
+            .location(vk::MemoryLocation::CpuToGpu)
+            .build()?;
+
+        let mut buffers: Vec<Buffer> = vec![];
+        for create_info in [&data_info, &data_info, &commands_info, &commands_info] {
+            match Buffer::new(loader, create_info.clone()) {
+                Ok(buffer) => buffers.push(buffer),
+                Err(err) => {
+                    buffers.destroy(loader);
+                    return Err(err);
+                }
+            }
+        }
+
+        let mut buffers = buffers.into_iter();
+        let mut next = || buffers.next().unwrap();
+
+        Ok(Self {
+            data: ParitySet::new(next(), next()),
+            commands: ParitySet::new(next(), next()),
+            capacity,
+            draw_counts: ParitySet::from_fn(|| Cell::new(0)),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Writes the entries and draws of `builder` for the frame `parity`, which must not be in flight.
+    /// Draws reading any entry but the first need [`DeviceFeatures::DRAW_INDIRECT_FIRST_INSTANCE`]
+    pub fn upload(&self, loader: &Loader, parity: Parity, builder: &DrawDataBuilder) -> Result<()> {
+        if builder.len() > self.capacity {
+            return Err(SiltError::InvalidUsage(format!(
+                "{} draws exceed capacity of {}",
+                builder.len(),
+                self.capacity
+            )));
+        }
+
+        if builder.commands().iter().any(|command| command.first_instance != 0)
+            && !loader.features.contains(DeviceFeatures::DRAW_INDIRECT_FIRST_INSTANCE)
+        {
+            return Err(SiltError::MissingFeatures(DeviceFeatures::DRAW_INDIRECT_FIRST_INSTANCE));
+        }
+
+        unsafe {
+            write_slice(loader, self.data.get(parity), builder.entries())?;
+            write_slice(loader, self.commands.get(parity), builder.commands())?;
+        }
+
+        self.draw_counts
+            .get(parity)
+            .set(builder.commands().len() as u32);
+        Ok(())
+    }
+
+    /// Binds `set`, holding the draw data, right after the sets of `frame`
+    pub fn record_bind(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        frame: &FrameContext,
+        set: vk::DescriptorSet,
+    ) {
+        unsafe {
+            loader.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                frame.layout,
+                frame.descriptor_sets.len() as u32,
+                &[set],
+                &[],
+            )
+        }
+    }
+
+    /// Records every draw uploaded for `parity`, with the vertex and index buffers already bound.
+    /// Falls back to one call per draw without [`DeviceFeatures::MULTI_DRAW_INDIRECT`]
+    pub fn record_draws(&self, loader: &Loader, command_buffer: vk::CommandBuffer, parity: Parity) {
+        let draw_count = self.draw_counts.get(parity).get();
+        let buffer = self.commands.get(parity).buffer;
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+        unsafe {
+            if loader.features.contains(DeviceFeatures::MULTI_DRAW_INDIRECT) {
+                loader
+                    .device
+                    .cmd_draw_indexed_indirect(command_buffer, buffer, 0, draw_count, stride);
+            } else {
+                for draw in 0..draw_count {
+                    loader.device.cmd_draw_indexed_indirect(
+                        command_buffer,
+                        buffer,
+                        (draw * stride) as u64,
+                        1,
+                        stride,
+                    );
+                }
+            }
+        }
+    }
+}
+
+unsafe fn write_slice<T: Copy>(loader: &Loader, buffer: &Buffer, values: &[T]) -> Result<()> {
+    let ptr = loader.allocator.get_mapped_ptr(buffer.allocation)?.as_ptr() as *mut T;
+    std::ptr::copy_nonoverlapping(values.as_ptr(), ptr, values.len());
+    Ok(())
+}
+
+impl BindableResource for Resource<DrawDataBuffer> {
+    fn bind(&self) -> ResourceBinding<'_> {
+        let description = self.description.clone();
+        let reference = self
+            .resource
+            .data
+            .as_ref()
+            .ref_map(|&buffer| ResourceReference::Buffer(buffer))
+            .into();
+
+        ResourceBinding {
+            description,
+            reference,
+            generation: self.generation.get(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_data_matches_std430_layout() {
+        assert_eq!(std::mem::size_of::<DrawData>(), 80);
+        assert_eq!(memoffset::offset_of!(DrawData, material), 64);
+    }
+
+    #[test]
+    fn indexed_draws_read_their_own_entry() {
+        let mut builder = DrawDataBuilder::new();
+        let unused = builder.push(DrawData::new(glam::Mat4::IDENTITY, 7));
+        let first = builder.push_indexed(DrawData::new(glam::Mat4::IDENTITY, 1), 36, 0, 0);
+        let second = builder.push_indexed(DrawData::new(glam::Mat4::IDENTITY, 2), 6, 36, 24);
+
+        assert_eq!((unused, first, second), (0, 1, 2));
+        assert_eq!(builder.len(), 3);
+        assert_eq!(
+            builder
+                .commands()
+                .iter()
+                .map(|command| (command.first_instance, command.instance_count, command.first_index))
+                .collect::<Vec<_>>(),
+            vec![(1, 1, 0), (2, 1, 36)]
+        );
+        assert_eq!(builder.entries()[second as usize].material, 2);
+
+        builder.clear();
+        assert!(builder.is_empty() && builder.commands().is_empty());
+    }
+}
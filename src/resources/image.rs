@@ -1,16 +1,25 @@
-use crate::{prelude::*, properties::ProvidedFeatures, sync::CommandPool, id};
-use anyhow::Result;
-use cached::proc_macro::once;
+use crate::{prelude::*, properties::{DeviceFeatures, ProvidedFeatures}, sync::{CommandPool, CommandRecorder}, id};
+use crate::error::{Result, SiltError};
+use cached::proc_macro::{cached, once};
 use itertools::Itertools;
 use std::cell::Cell;
 
-use super::{Buffer, BufferCreateInfo};
+use super::{
+    AllocationScheme, AttachmentDescription, AttachmentType, Buffer, BufferCreateInfo, Resource,
+};
 
 #[derive(Debug, Clone)]
 pub struct ImageCreateInfo {
     pub width: u32,
     pub height: u32,
     pub mip_levels: u32,
+    /// Images with more than one layer are viewed as 2D arrays, e.g. for layered rendering
+    pub array_layers: u32,
+    /// Overrides the view type derived from `array_layers`, e.g. `TYPE_2D_ARRAY` for an array
+    /// texture that may hold a single layer
+    pub view_type: Option<vk::ImageViewType>,
+    /// Allows [`Image::create_cube_view`], needs square images with a multiple of six layers
+    pub cube_compatible: bool,
     pub format: vk::Format,
     pub tiling: vk::ImageTiling,
     pub usage: vk::ImageUsageFlags,
@@ -18,6 +27,7 @@ pub struct ImageCreateInfo {
     pub samples: vk::SampleCountFlags,
     pub view_aspect: vk::ImageAspectFlags,
     pub name: Identifier,
+    pub allocation_scheme: AllocationScheme,
 }
 
 impl Default for ImageCreateInfo {
@@ -26,6 +36,9 @@ impl Default for ImageCreateInfo {
             width: 0,
             height: 0,
             mip_levels: 1,
+            array_layers: 1,
+            view_type: None,
+            cube_compatible: false,
             format: vk::Format::R8G8B8A8_SRGB,
             tiling: vk::ImageTiling::OPTIMAL,
             usage: vk::ImageUsageFlags::empty(),
@@ -33,10 +46,215 @@ impl Default for ImageCreateInfo {
             samples: vk::SampleCountFlags::from_raw(1),
             view_aspect: vk::ImageAspectFlags::COLOR,
             name: NULL_ID.clone(),
+            allocation_scheme: AllocationScheme::Managed,
         }
     }
 }
 
+impl ImageCreateInfo {
+    pub fn builder() -> ImageCreateInfoBuilder {
+        Default::default()
+    }
+}
+
+/// Fluent alternative to filling out [`ImageCreateInfo`] by hand. The view aspect is
+/// derived from the format unless set explicitly, see [`format_aspect`]. Depth stencil
+/// formats only view the depth aspect when sampled or read as input attachments
+#[derive(Debug, Clone, Default)]
+pub struct ImageCreateInfoBuilder {
+    info: ImageCreateInfo,
+    view_aspect: Option<vk::ImageAspectFlags>,
+    mips_from_size: bool,
+}
+
+impl ImageCreateInfoBuilder {
+    pub fn width(mut self, width: u32) -> Self {
+        self.info.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: u32) -> Self {
+        self.info.height = height;
+        self
+    }
+
+    pub fn extent(self, extent: vk::Extent2D) -> Self {
+        self.width(extent.width).height(extent.height)
+    }
+
+    pub fn mip_levels(mut self, mip_levels: u32) -> Self {
+        self.info.mip_levels = mip_levels;
+        self.mips_from_size = false;
+        self
+    }
+
+    /// Full mip chain down to 1x1, computed from the final size in [`ImageCreateInfoBuilder::build`]
+    pub fn mips_from_size(mut self) -> Self {
+        self.mips_from_size = true;
+        self
+    }
+
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.info.array_layers = array_layers;
+        self
+    }
+
+    pub fn view_type(mut self, view_type: vk::ImageViewType) -> Self {
+        self.info.view_type = Some(view_type);
+        self
+    }
+
+    /// Six cube compatible layers, one per face
+    pub fn cube(mut self) -> Self {
+        self.info.array_layers = 6;
+        self.info.cube_compatible = true;
+        self
+    }
+
+    pub fn format(mut self, format: vk::Format) -> Self {
+        self.info.format = format;
+        self
+    }
+
+    pub fn tiling(mut self, tiling: vk::ImageTiling) -> Self {
+        self.info.tiling = tiling;
+        self
+    }
+
+    /// Adds to the usage instead of replacing it
+    pub fn usage(mut self, usage: vk::ImageUsageFlags) -> Self {
+        self.info.usage |= usage;
+        self
+    }
+
+    pub fn location(mut self, location: vk::MemoryLocation) -> Self {
+        self.info.location = location;
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.info.samples = samples;
+        self
+    }
+
+    pub fn view_aspect(mut self, view_aspect: vk::ImageAspectFlags) -> Self {
+        self.view_aspect = Some(view_aspect);
+        self
+    }
+
+    pub fn name(mut self, name: Identifier) -> Self {
+        self.info.name = name;
+        self
+    }
+
+    pub fn dedicated(mut self) -> Self {
+        self.info.allocation_scheme = AllocationScheme::Dedicated;
+        self
+    }
+
+    pub fn color_attachment(self) -> Self {
+        self.usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+    }
+
+    pub fn depth_attachment(self) -> Self {
+        self.usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+    }
+
+    pub fn transient(self) -> Self {
+        self.usage(vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+    }
+
+    pub fn sampled(self) -> Self {
+        self.usage(vk::ImageUsageFlags::SAMPLED)
+    }
+
+    pub fn storage(self) -> Self {
+        self.usage(vk::ImageUsageFlags::STORAGE)
+    }
+
+    pub fn transfer_src(self) -> Self {
+        self.usage(vk::ImageUsageFlags::TRANSFER_SRC)
+    }
+
+    pub fn transfer_dst(self) -> Self {
+        self.usage(vk::ImageUsageFlags::TRANSFER_DST)
+    }
+
+    pub fn build(self) -> Result<ImageCreateInfo> {
+        let mut info = self.info;
+
+        if info.width == 0 || info.height == 0 {
+            return Err(SiltError::InvalidUsage(format!(
+                "image extent must be non zero: {}x{}",
+                info.width, info.height
+            )));
+        }
+
+        if info.usage.is_empty() {
+            return Err(SiltError::InvalidUsage("image usage must not be empty".into()));
+        }
+
+        if info.array_layers == 0 {
+            return Err(SiltError::InvalidUsage("image must have at least one layer".into()));
+        }
+
+        if info.cube_compatible && (info.width != info.height || !info.array_layers.is_multiple_of(6)) {
+            return Err(SiltError::InvalidUsage(format!(
+                "cube images must be square with a multiple of six layers: {}x{} with {} layers",
+                info.width, info.height, info.array_layers
+            )));
+        }
+
+        if self.mips_from_size {
+            info.mip_levels = info.width.max(info.height).ilog2() + 1;
+        }
+
+        if info.tiling == vk::ImageTiling::LINEAR
+            && (info.mip_levels > 1 || info.samples != vk::SampleCountFlags::TYPE_1)
+        {
+            return Err(SiltError::InvalidUsage(
+                "linear images can only have a single mip level and sample".into(),
+            ));
+        }
+
+        if info.samples != vk::SampleCountFlags::TYPE_1 && info.mip_levels > 1 {
+            return Err(SiltError::InvalidUsage(
+                "multisampled images can only have a single mip level".into(),
+            ));
+        }
+
+        let attachment_usage = vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+            | vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+            | vk::ImageUsageFlags::INPUT_ATTACHMENT;
+
+        if info.usage.contains(vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+            && !attachment_usage.contains(info.usage)
+        {
+            return Err(SiltError::InvalidUsage(format!(
+                "transient images can only be used as attachments: {:?}",
+                info.usage
+            )));
+        }
+
+        let depth_stencil = vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL;
+        let read_usage = vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::INPUT_ATTACHMENT;
+
+        // Descriptors can only view one aspect of a depth stencil image
+        info.view_aspect = match self.view_aspect {
+            Some(view_aspect) => view_aspect,
+            None if format_aspect(info.format) == depth_stencil
+                && info.usage.intersects(read_usage) =>
+            {
+                vk::ImageAspectFlags::DEPTH
+            }
+            None => format_aspect(info.format),
+        };
+
+        Ok(info)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Layout {
     Initial,
@@ -47,6 +265,10 @@ pub enum Layout {
     DepthAttachment,
     DepthStencilAttachment,
     Present,
+    /// Read and written by compute shaders as a storage image
+    ComputeReadWrite,
+    /// Sampled by compute shaders, in the same layout as [`Layout::FragmentRead`]
+    ComputeRead,
 }
 
 impl Layout {
@@ -60,6 +282,8 @@ impl Layout {
             Layout::DepthAttachment => vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
             Layout::DepthStencilAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
             Layout::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+            Layout::ComputeReadWrite => vk::ImageLayout::GENERAL,
+            Layout::ComputeRead => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
         }
     }
 
@@ -75,6 +299,7 @@ impl Layout {
                     | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
             }
             Layout::Present => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            Layout::ComputeReadWrite | Layout::ComputeRead => vk::PipelineStageFlags::COMPUTE_SHADER,
         }
     }
 
@@ -91,8 +316,107 @@ impl Layout {
                 vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
                     | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
             }
-            Layout::Present => vk::AccessFlags::SHADER_WRITE,
+            // Presentation is synchronized by the semaphore, not the barrier
+            Layout::Present => vk::AccessFlags::NONE,
+            Layout::ComputeReadWrite => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            Layout::ComputeRead => vk::AccessFlags::SHADER_READ,
+        }
+    }
+}
+
+/// Mips and layers touched by a transition, the whole image by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubresourceRange {
+    pub base_mip: u32,
+    pub mip_count: u32,
+    pub base_layer: u32,
+    pub layer_count: u32,
+}
+
+impl Default for SubresourceRange {
+    fn default() -> Self {
+        Self {
+            base_mip: 0,
+            mip_count: vk::REMAINING_MIP_LEVELS,
+            base_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        }
+    }
+}
+
+impl SubresourceRange {
+    /// A single mip of the first layer
+    pub fn mip(level: u32) -> Self {
+        Self {
+            base_mip: level,
+            mip_count: 1,
+            base_layer: 0,
+            layer_count: 1,
+        }
+    }
+
+    pub fn is_whole(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn with_aspect(&self, aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: self.base_mip,
+            level_count: self.mip_count,
+            base_array_layer: self.base_layer,
+            layer_count: self.layer_count,
+        }
+    }
+}
+
+/// Transitions an image not wrapped in an [`Image`], e.g. a swapchain image, with the stages and
+/// access of the [`Layout`] table. Nothing tracks the layout, `old_layout` has to be known
+pub fn record_layout_transition(
+    loader: &Loader,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    aspect: vk::ImageAspectFlags,
+    old_layout: Layout,
+    new_layout: Layout,
+    range: SubresourceRange,
+) {
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout.get_layout())
+        .new_layout(new_layout.get_layout())
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .src_access_mask(old_layout.get_access())
+        .dst_access_mask(new_layout.get_access())
+        .subresource_range(range.with_aspect(aspect));
+
+    unsafe {
+        loader.device.cmd_pipeline_barrier(
+            command_buffer,
+            old_layout.get_pipeline_stage(),
+            new_layout.get_pipeline_stage(),
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            std::slice::from_ref(&barrier),
+        )
+    };
+}
+
+/// Every aspect held by `format`, as needed by barriers on images of that format
+pub fn format_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
         }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
     }
 }
 
@@ -103,6 +427,8 @@ pub struct Image {
     pub allocation: vk::Allocation,
     pub size: vk::Extent3D,
     pub mips: u32,
+    pub layers: u32,
+    pub cube_compatible: bool,
     pub samples: vk::SampleCountFlags,
     pub format: vk::Format,
     pub layout: Cell<Layout>,
@@ -118,17 +444,32 @@ impl Destructible for Image {
 
 impl Image {
     pub fn new(loader: &Loader, create_info: ImageCreateInfo) -> Result<Self> {
+        Self::create(&loader.device, &loader.allocator, create_info)
+    }
+
+    /// Shared with [`crate::sync::GpuUploadHandle`], which has no [`Loader`] to create from
+    pub(crate) fn create(
+        device: &Device,
+        allocator: &Allocator,
+        create_info: ImageCreateInfo,
+    ) -> Result<Self> {
         let size = vk::Extent3D {
             width: create_info.width,
             height: create_info.height,
             depth: 1,
         };
 
+        let flags = match create_info.cube_compatible {
+            true => vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            false => vk::ImageCreateFlags::empty(),
+        };
+
         let image_ci = vk::ImageCreateInfo::builder()
+            .flags(flags)
             .image_type(vk::ImageType::TYPE_2D)
             .extent(size)
             .mip_levels(create_info.mip_levels)
-            .array_layers(1)
+            .array_layers(create_info.array_layers)
             .format(create_info.format)
             .tiling(create_info.tiling)
             .initial_layout(vk::ImageLayout::UNDEFINED)
@@ -136,44 +477,49 @@ impl Image {
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .samples(create_info.samples);
 
-        let image = unsafe { loader.device.create_image(&image_ci, None)? };
-        let requirements = unsafe { loader.device.get_image_memory_requirements(image) };
+        let image = unsafe { device.create_image(&image_ci, None)? };
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
 
         let allocation_create_info = vk::AllocationCreateInfo {
             name: create_info.name.as_str(),
             requirements,
             location: create_info.location,
             linear: create_info.tiling == vk::ImageTiling::LINEAR,
-            allocation_scheme: vk::AllocationScheme::GpuAllocatorManaged,
+            allocation_scheme: create_info.allocation_scheme.for_image(image),
         };
 
-        let allocation = loader.allocator.allocate(&allocation_create_info)?;
+        let allocation = allocator.allocate(&allocation_create_info)?;
         unsafe {
-            loader
-                .device
+            device
                 .bind_image_memory(
                     image,
-                    loader.allocator.get_memory(allocation)?,
-                    loader.allocator.get_offset(allocation)?,
+                    allocator.get_memory(allocation)?,
+                    allocator.get_offset(allocation)?,
                 )
                 .unwrap();
         }
 
+        let view_type = match (create_info.view_type, create_info.array_layers) {
+            (Some(view_type), _) => view_type,
+            (None, 1) => vk::ImageViewType::TYPE_2D,
+            (None, _) => vk::ImageViewType::TYPE_2D_ARRAY,
+        };
+
         let view_ci = vk::ImageViewCreateInfo::builder()
             .image(image)
             .format(create_info.format)
-            .view_type(vk::ImageViewType::TYPE_2D)
+            .view_type(view_type)
             .subresource_range(
                 vk::ImageSubresourceRange::builder()
                     .aspect_mask(create_info.view_aspect)
                     .base_mip_level(0)
                     .level_count(create_info.mip_levels)
                     .base_array_layer(0)
-                    .layer_count(1)
+                    .layer_count(create_info.array_layers)
                     .build(),
             );
 
-        let view = unsafe { loader.device.create_image_view(&view_ci, None)? };
+        let view = unsafe { device.create_image_view(&view_ci, None)? };
 
         Ok(Image {
             image,
@@ -181,68 +527,215 @@ impl Image {
             allocation,
             size,
             mips: create_info.mip_levels,
+            layers: create_info.array_layers,
+            cube_compatible: create_info.cube_compatible,
             samples: create_info.samples,
             format: create_info.format,
             layout: Cell::new(Layout::Initial),
         })
     }
 
+    /// Cube view over the first six layers, for sampling with a `samplerCube`. The image's own
+    /// view stays a 2D array, which is what framebuffers need. Destroyed by the caller
+    pub fn create_cube_view(&self, loader: &Loader) -> Result<vk::ImageView> {
+        if !self.cube_compatible {
+            return Err(SiltError::InvalidUsage(format!(
+                "image wasn't created cube compatible: [{:?}]",
+                self
+            )));
+        }
+
+        let view_ci = vk::ImageViewCreateInfo::builder()
+            .image(self.image)
+            .format(self.format)
+            .view_type(vk::ImageViewType::CUBE)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(self.aspect() & !vk::ImageAspectFlags::STENCIL)
+                    .base_mip_level(0)
+                    .level_count(self.mips)
+                    .base_array_layer(0)
+                    .layer_count(6)
+                    .build(),
+            );
+
+        Ok(unsafe { loader.device.create_image_view(&view_ci, None)? })
+    }
+
+    /// Creates a render target matching `description`, color targets can also be sampled and copied from
+    pub fn new_attachment(
+        loader: &Loader,
+        description: &AttachmentDescription,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        Self::new_layered_attachment(loader, description, extent, 1)
+    }
+
+    /// Like [`Image::new_attachment`] with `layers` layers, rendered to in one pass with a
+    /// [`crate::pipeline::Multiview`] render pass or by writing `gl_Layer`
+    pub fn new_layered_attachment(
+        loader: &Loader,
+        description: &AttachmentDescription,
+        extent: vk::Extent2D,
+        layers: u32,
+    ) -> Result<Self> {
+        if layers == 0 {
+            return Err(SiltError::InvalidUsage("image must have at least one layer".into()));
+        }
+
+        let (usage, view_aspect) = match description.ty {
+            AttachmentType::Color | AttachmentType::Resolve => (
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                vk::ImageAspectFlags::COLOR,
+            ),
+            // Views of both aspects can't be sampled, so stencil targets need their own depth view to be read
+            AttachmentType::DepthStencil => (
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                format_aspect(description.format),
+            ),
+            AttachmentType::Input(_) => (
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT,
+                vk::ImageAspectFlags::COLOR,
+            ),
+        };
+
+        let create_info = ImageCreateInfo {
+            width: extent.width,
+            height: extent.height,
+            format: description.format,
+            samples: description.samples,
+            usage,
+            view_aspect,
+            array_layers: layers,
+            name: description.id.clone(),
+            ..Default::default()
+        };
+
+        Self::new(loader, create_info)
+    }
+
+    pub fn aspect(&self) -> vk::ImageAspectFlags {
+        format_aspect(self.format)
+    }
+
     pub fn transition_layout(
         &self,
         loader: &Loader,
         pool: &CommandPool,
         new_layout: Layout,
     ) -> Result<()> {
-        let old_layout = self.layout.get();
-        let old_stage = old_layout.get_pipeline_stage();
-        let new_stage = new_layout.get_pipeline_stage();
-        let src_access = old_layout.get_access();
-        let dst_access = new_layout.get_access();
-
-        self.layout.set(new_layout);
-
-        pool.execute_one_time_commands(loader, |_, command_buffer| {
-            let barrier = vk::ImageMemoryBarrier::builder()
-                .old_layout(old_layout.get_layout())
-                .new_layout(new_layout.get_layout())
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .image(self.image)
-                .src_access_mask(src_access)
-                .dst_access_mask(dst_access)
-                .subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .base_mip_level(0)
-                        .level_count(self.mips)
-                        .base_array_layer(0)
-                        .layer_count(1)
-                        .build(),
-                );
+        pool.execute_one_time_commands(loader, |loader, command_buffer| {
+            self.record_transition(loader, command_buffer, new_layout)
+        })?;
 
-            unsafe {
-                loader.device.cmd_pipeline_barrier(
-                    command_buffer,
-                    old_stage,
-                    new_stage,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    std::slice::from_ref(&barrier),
-                )
-            };
-        })
+        Ok(())
+    }
+
+    /// See [`Image::record_transition_range`]
+    pub fn transition_layout_range(
+        &self,
+        loader: &Loader,
+        pool: &CommandPool,
+        old_layout: Layout,
+        new_layout: Layout,
+        range: SubresourceRange,
+    ) -> Result<()> {
+        pool.execute_one_time_commands(loader, |loader, command_buffer| {
+            self.record_transition_range(loader, command_buffer, old_layout, new_layout, range)
+        })?;
+
+        Ok(())
+    }
+
+    /// Records a layout transition into an existing command buffer, tracking the new layout
+    pub fn record_transition(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        new_layout: Layout,
+    ) {
+        self.record_transition_range(
+            loader,
+            command_buffer,
+            self.layout.get(),
+            new_layout,
+            SubresourceRange::default(),
+        )
+    }
+
+    /// Transitions part of the image. The tracked layout only follows transitions of the
+    /// whole image, so partial ranges should be returned to it before the next whole transition
+    pub fn record_transition_range(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        old_layout: Layout,
+        new_layout: Layout,
+        range: SubresourceRange,
+    ) {
+        if range.is_whole() {
+            self.layout.set(new_layout);
+        }
+
+        record_layout_transition(
+            loader,
+            command_buffer,
+            self.image,
+            self.aspect(),
+            old_layout,
+            new_layout,
+            range,
+        )
+    }
+
+    /// Fails if the image format can't be linearly blitted, see [`supports_linear_blit`]
+    pub fn generate_mipmaps(
+        &self,
+        loader: &Loader,
+        pool: &CommandPool,
+        features: ProvidedFeatures,
+    ) -> Result<()> {
+        self.check_blit_mipmaps(loader, features)?;
+        pool.execute_one_time_commands(loader, |loader, command_buffer| {
+            self.record_mipmaps(loader, command_buffer)
+        })?;
+
+        Ok(())
     }
 
-    pub fn generate_mipmaps(&self, loader: &Loader, pool: &CommandPool) {
+    /// Records [`Image::generate_mipmaps`] into a batch, see [`CommandPool::execute_one_time_commands_batched`]
+    pub fn record_generate_mipmaps(
+        &self,
+        recorder: &CommandRecorder,
+        features: ProvidedFeatures,
+    ) -> Result<()> {
+        self.check_blit_mipmaps(recorder.loader(), features)?;
+        recorder.record(|loader, command_buffer| self.record_mipmaps(loader, command_buffer));
+
+        Ok(())
+    }
+
+    fn check_blit_mipmaps(&self, loader: &Loader, features: ProvidedFeatures) -> Result<()> {
+        if !supports_linear_blit(&loader.instance, features.pdevice, self.format) {
+            return Err(SiltError::UnsupportedFormat(format!(
+                "{:?} does not support linear blits for mipmap generation",
+                self.format
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn record_mipmaps(&self, loader: &Loader, command_buffer: vk::CommandBuffer) {
         if self.layout.get() != Layout::TransferDst {
-            self.transition_layout(loader, pool, Layout::TransferDst)
-                .unwrap();
+            self.record_transition(loader, command_buffer, Layout::TransferDst);
         }
 
         self.layout.set(Layout::FragmentRead);
 
-        pool.execute_one_time_commands(loader, |_, command_buffer| {
+        {
             let mut barrier = vk::ImageMemoryBarrier::builder()
                 .image(self.image)
                 .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
@@ -251,12 +744,13 @@ impl Image {
                     vk::ImageSubresourceRange::builder()
                         .aspect_mask(vk::ImageAspectFlags::COLOR)
                         .base_array_layer(0)
-                        .layer_count(1)
+                        .layer_count(self.layers)
                         .level_count(1)
                         .build(),
                 )
                 .build();
 
+            // Every layer is downsampled by the same blits
             for mip_level in 0..self.mips - 1 {
                 let mip_width = (self.size.width >> mip_level).max(1);
                 let mip_height = (self.size.height >> mip_level).max(1);
@@ -291,7 +785,7 @@ impl Image {
                     .src_subresource(
                         vk::ImageSubresourceLayers::builder()
                             .base_array_layer(0)
-                            .layer_count(1)
+                            .layer_count(self.layers)
                             .mip_level(mip_level)
                             .aspect_mask(vk::ImageAspectFlags::COLOR)
                             .build(),
@@ -307,7 +801,7 @@ impl Image {
                     .dst_subresource(
                         vk::ImageSubresourceLayers::builder()
                             .base_array_layer(0)
-                            .layer_count(1)
+                            .layer_count(self.layers)
                             .mip_level(mip_level + 1)
                             .aspect_mask(vk::ImageAspectFlags::COLOR)
                             .build(),
@@ -360,8 +854,7 @@ impl Image {
                     &[barrier],
                 )
             };
-        })
-        .unwrap();
+        }
     }
 }
 
@@ -374,11 +867,289 @@ pub struct SwapchainImage {
     pub samples: vk::SampleCountFlags,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerConfig {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// Clamped to the device limit, ignored if anisotropy isn't enabled on the device
+    pub max_anisotropy: Option<f32>,
+    pub border_color: vk::BorderColor,
+    /// Enables depth comparison, e.g. for hardware PCF on shadow maps
+    pub compare_op: Option<vk::CompareOp>,
+    /// Returns the min or max of the filtered texels instead of their weighted average, e.g. for
+    /// HiZ pyramids. Needs [`DeviceFeatures::SAMPLER_FILTER_MINMAX`] and can't be combined with
+    /// `compare_op`. Sampled images also need a format passing [`supports_filter_minmax`]
+    pub reduction_mode: Option<vk::SamplerReductionMode>,
+    /// Added to the computed lod, negative values sharpen. Clamped to the device's `maxSamplerLodBias`
+    pub mip_lod_bias: f32,
+    pub min_lod: f32,
+    /// Clamped to the mip count, `None` samples every mip
+    pub max_lod: Option<f32>,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            max_anisotropy: Some(f32::MAX),
+            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+            compare_op: None,
+            reduction_mode: None,
+            mip_lod_bias: 0.,
+            min_lod: 0.,
+            max_lod: None,
+        }
+    }
+}
+
+impl SamplerConfig {
+    pub fn with_address_mode(self, mode: vk::SamplerAddressMode) -> Self {
+        Self {
+            address_mode_u: mode,
+            address_mode_v: mode,
+            address_mode_w: mode,
+            ..self
+        }
+    }
+
+    pub fn with_filter(self, filter: vk::Filter) -> Self {
+        Self {
+            mag_filter: filter,
+            min_filter: filter,
+            ..self
+        }
+    }
+
+    pub fn with_lod_bias(self, mip_lod_bias: f32) -> Self {
+        Self {
+            mip_lod_bias,
+            ..self
+        }
+    }
+
+    pub fn with_lod_range(self, min_lod: f32, max_lod: Option<f32>) -> Self {
+        Self {
+            min_lod,
+            max_lod,
+            ..self
+        }
+    }
+
+    /// Clamp to edge addressing, for skyboxes and screen space textures
+    pub fn clamp_to_edge() -> Self {
+        Self::default().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+    }
+
+    /// Unfiltered sampling without anisotropy, for pixel art
+    pub fn nearest() -> Self {
+        Self {
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            max_anisotropy: None,
+            ..Self::default().with_filter(vk::Filter::NEAREST)
+        }
+    }
+
+    /// Depth comparison sampler for PCF shadow maps
+    pub fn shadow_compare() -> Self {
+        Self {
+            max_anisotropy: None,
+            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+            compare_op: Some(vk::CompareOp::LESS_OR_EQUAL),
+            ..Self::default().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        }
+    }
+
+    /// Min or max filtering over clamped edges, for downsampling depth into HiZ pyramids
+    pub fn reduction(mode: vk::SamplerReductionMode) -> Self {
+        Self {
+            max_anisotropy: None,
+            reduction_mode: Some(mode),
+            ..Self::clamp_to_edge()
+        }
+    }
+
+    /// `quality` can only lower what the config asks for, see [`Loader::set_texture_quality`]
+    pub fn create_info(
+        &self,
+        features: ProvidedFeatures,
+        mips: u32,
+        quality: TextureQuality,
+    ) -> vk::SamplerCreateInfo {
+        let max_anisotropy = features
+            .sampler_anisotropy()
+            .zip(self.max_anisotropy)
+            .zip(quality.max_anisotropy)
+            .map(|((limit, requested), cap)| requested.min(cap).clamp(1., limit));
+        let mipmap_mode = match quality.trilinear {
+            true => self.mipmap_mode,
+            false => vk::SamplerMipmapMode::NEAREST,
+        };
+        let max_bias = features.limits.max_sampler_lod_bias;
+        let mip_lod_bias = self.mip_lod_bias.clamp(-max_bias, max_bias);
+        let min_lod = self.min_lod.clamp(0., mips as f32);
+        let max_lod = [self.max_lod, quality.max_lod]
+            .into_iter()
+            .flatten()
+            .fold(mips as f32, f32::min)
+            .max(min_lod);
+
+        vk::SamplerCreateInfo::builder()
+            .mag_filter(self.mag_filter)
+            .min_filter(self.min_filter)
+            .address_mode_u(self.address_mode_u)
+            .address_mode_v(self.address_mode_v)
+            .address_mode_w(self.address_mode_w)
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.))
+            .border_color(self.border_color)
+            .compare_enable(self.compare_op.is_some())
+            .compare_op(self.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .mipmap_mode(mipmap_mode)
+            .mip_lod_bias(mip_lod_bias)
+            .min_lod(min_lod)
+            .max_lod(max_lod)
+            .build()
+    }
+
+    /// Creates a sampler from `create_info`, chaining the reduction mode of the config
+    fn create_sampler(
+        &self,
+        loader: &Loader,
+        create_info: &vk::SamplerCreateInfo,
+    ) -> Result<vk::Sampler> {
+        if let Some(mode) = self.reduction_mode {
+            if !loader.features.contains(DeviceFeatures::SAMPLER_FILTER_MINMAX) {
+                return Err(SiltError::MissingFeatures(DeviceFeatures::SAMPLER_FILTER_MINMAX));
+            }
+
+            if self.compare_op.is_some() && mode != vk::SamplerReductionMode::WEIGHTED_AVERAGE {
+                return Err(SiltError::InvalidUsage(format!(
+                    "depth comparison can't be combined with {:?} reduction: [{:?}]",
+                    mode, self
+                )));
+            }
+        }
+
+        let reduction = self
+            .reduction_mode
+            .map(|mode| vk::SamplerReductionModeCreateInfo::builder().reduction_mode(mode).build());
+        let create_info = vk::SamplerCreateInfo {
+            p_next: reduction
+                .as_ref()
+                .map_or(std::ptr::null(), |reduction| reduction as *const _ as *const _),
+            ..*create_info
+        };
+
+        Ok(unsafe { loader.device.create_sampler(&create_info, None)? })
+    }
+}
+
+/// Global downgrade applied on top of every [`SamplerConfig`], e.g. for a graphics settings menu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureQuality {
+    /// Caps the anisotropy of every sampler, `None` disables anisotropic filtering
+    pub max_anisotropy: Option<f32>,
+    /// Falls back to nearest mip selection when disabled
+    pub trilinear: bool,
+    /// Caps the lod samplers can reach
+    pub max_lod: Option<f32>,
+}
+
+impl Default for TextureQuality {
+    fn default() -> Self {
+        Self {
+            max_anisotropy: Some(f32::MAX),
+            trilinear: true,
+            max_lod: None,
+        }
+    }
+}
+
+impl TextureQuality {
+    /// Bilinear filtering without anisotropy
+    pub fn low() -> Self {
+        Self {
+            max_anisotropy: None,
+            trilinear: false,
+            max_lod: None,
+        }
+    }
+}
+
+/// Standalone sampler, shared between any number of [`super::ResourceDescription::SeparateImage`] bindings
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    pub sampler: vk::Sampler,
+    pub config: SamplerConfig,
+    pub properties: vk::SamplerCreateInfo,
+    pub mips: u32,
+}
+
+impl Destructible for Sampler {
+    fn destroy(self, loader: &Loader) {
+        self.sampler.destroy(loader);
+    }
+}
+
+impl Sampler {
+    /// `mips` bounds the sampled lod, pass the largest mip count of the images it samples.
+    /// With a reduction mode, their formats aren't checked here, see [`supports_filter_minmax`]
+    pub fn new(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        config: SamplerConfig,
+        mips: u32,
+    ) -> Result<Self> {
+        let create_info = config.create_info(features, mips, loader.texture_quality());
+        let sampler = config.create_sampler(loader, &create_info)?;
+
+        Ok(Self {
+            sampler,
+            config,
+            properties: create_info,
+            mips,
+        })
+    }
+
+    /// Recreates the sampler with the loader's current [`TextureQuality`]. Returns the
+    /// replaced handle, which must outlive any frame still using it
+    pub fn rebuild(&mut self, loader: &Loader, features: ProvidedFeatures) -> Result<vk::Sampler> {
+        let rebuilt = Self::new(loader, features, self.config, self.mips)?;
+        Ok(std::mem::replace(self, rebuilt).sampler)
+    }
+}
+
+impl Resource<Sampler> {
+    /// Rebuilds the sampler and marks the resource dirty, so descriptors pick up the new handle
+    pub fn apply_texture_quality(
+        &mut self,
+        loader: &Loader,
+        features: ProvidedFeatures,
+    ) -> Result<vk::Sampler> {
+        let old = self.resource.rebuild(loader, features)?;
+        self.mark_dirty();
+        Ok(old)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SampledImage {
     pub image: Image,
     pub sampler: vk::Sampler,
+    pub config: SamplerConfig,
     pub properties: vk::SamplerCreateInfo,
+    /// Most detailed mip holding data, the sampler's `min_lod` is raised to it.
+    /// Zero unless uploaded with [`ImageFile::upload_streamed`]
+    pub resident_mip: u32,
 }
 
 impl Destructible for SampledImage {
@@ -393,56 +1164,366 @@ impl SampledImage {
         loader: &Loader,
         image: Image,
         features: ProvidedFeatures,
+        config: SamplerConfig,
     ) -> Result<Self> {
-        let create_info = vk::SamplerCreateInfo::builder()
-            .mag_filter(vk::Filter::LINEAR)
-            .min_filter(vk::Filter::LINEAR)
-            .address_mode_u(vk::SamplerAddressMode::REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::REPEAT)
-            .address_mode_w(vk::SamplerAddressMode::REPEAT)
-            .anisotropy_enable(features.sampler_anisotropy().is_some())
-            .max_anisotropy(features.sampler_anisotropy().unwrap_or_default())
-            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
-            .compare_op(vk::CompareOp::ALWAYS)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .min_lod(0.)
-            .max_lod(image.mips as f32)
-            .build();
-
-        let sampler = unsafe { loader.device.create_sampler(&create_info, None)? };
+        if config.reduction_mode.is_some()
+            && !supports_filter_minmax(&loader.instance, features.pdevice, image.format)
+        {
+            return Err(SiltError::UnsupportedFormat(format!(
+                "{:?} does not support min max reduction sampling",
+                image.format
+            )));
+        }
+
+        let create_info = config.create_info(features, image.mips, loader.texture_quality());
+        let sampler = config.create_sampler(loader, &create_info)?;
 
         Ok(Self {
             image,
             sampler,
+            config,
             properties: create_info,
+            resident_mip: 0,
         })
     }
+
+    /// Replaces the contents of every mip with `file`, e.g. a new video frame or a procedurally
+    /// generated lookup table. Mips are regenerated on the gpu when the format allows it, and the
+    /// image is left in `FragmentRead`, so it must not be in use by frames in flight
+    pub fn update(
+        &self,
+        loader: &Loader,
+        features: ProvidedFeatures,
+        pool: &CommandPool,
+        file: &ImageFile,
+    ) -> Result<()> {
+        let image = &self.image;
+        if (file.width, file.height) != (image.size.width, image.size.height) {
+            return Err(SiltError::InvalidUsage(format!(
+                "update of size {:?} doesn't match image size {:?}",
+                (file.width, file.height),
+                (image.size.width, image.size.height)
+            )));
+        }
+
+        // Narrow images may have fallen back to rgba on upload, but never the other way around
+        let channels = ChannelLayout::from_format(image.format);
+        if channels != ChannelLayout::Rgba && channels != file.channels {
+            return Err(SiltError::InvalidUsage(format!(
+                "update with {:?} channels doesn't match image format {:?}",
+                file.channels, image.format
+            )));
+        }
+
+        if self.resident_mip != 0 {
+            return Err(SiltError::InvalidUsage(
+                "streamed images are updated one mip at a time with stream_mip".into(),
+            ));
+        }
+
+        let blit =
+            image.mips > 1 && supports_linear_blit(&loader.instance, features.pdevice, image.format);
+        pool.execute_one_time_commands_batched(loader, |recorder| {
+            recorder
+                .record(|loader, cmd| image.record_transition(loader, cmd, Layout::TransferDst));
+
+            if blit {
+                copy_levels(recorder, image, [(0, &file.pixels)])?;
+                image.record_generate_mipmaps(recorder, features)
+            } else {
+                let levels = file.mip_chain();
+                copy_levels(recorder, image, (0..image.mips).zip(&levels))?;
+                recorder.record(|loader, cmd| {
+                    image.record_transition(loader, cmd, Layout::FragmentRead)
+                });
+                Ok(())
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Uploads `level`, the mip directly above the most detailed resident one, and unlocks
+    /// it in the sampler. Returns the replaced sampler, which must outlive any frame still
+    /// using it. The image is briefly transitioned out of `FragmentRead`, so it must not be
+    /// in use by frames in flight
+    pub fn stream_mip(
+        &mut self,
+        loader: &Loader,
+        pool: &CommandPool,
+        level: u32,
+        data: &image::RgbaImage,
+    ) -> Result<vk::Sampler> {
+        if level + 1 != self.resident_mip {
+            return Err(SiltError::InvalidUsage(format!(
+                "mip {} can't be streamed while mip {} is the most detailed resident mip",
+                level, self.resident_mip
+            )));
+        }
+
+        let extent = (
+            (self.image.size.width >> level).max(1),
+            (self.image.size.height >> level).max(1),
+        );
+        if data.dimensions() != extent {
+            return Err(SiltError::InvalidUsage(format!(
+                "mip {} of size {:?} doesn't match expected size {:?}",
+                level,
+                data.dimensions(),
+                extent
+            )));
+        }
+
+        let image = &self.image;
+        pool.execute_one_time_commands_batched(loader, |recorder| {
+            let final_layout = image.layout.get();
+            recorder
+                .record(|loader, cmd| image.record_transition(loader, cmd, Layout::TransferDst));
+            copy_levels(recorder, image, [(level, data)])?;
+            recorder.record(|loader, cmd| image.record_transition(loader, cmd, final_layout));
+            Ok(())
+        })?;
+
+        self.resident_mip = level;
+        self.replace_sampler(loader, self.properties)
+    }
+
+    fn replace_sampler(
+        &mut self,
+        loader: &Loader,
+        create_info: vk::SamplerCreateInfo,
+    ) -> Result<vk::Sampler> {
+        let create_info = vk::SamplerCreateInfo {
+            min_lod: self.config.min_lod.max(self.resident_mip as f32),
+            max_lod: create_info.max_lod.max(self.resident_mip as f32),
+            ..create_info
+        };
+        let sampler = self.config.create_sampler(loader, &create_info)?;
+        self.properties = create_info;
+
+        Ok(std::mem::replace(&mut self.sampler, sampler))
+    }
+
+    /// Recreates the sampler with the loader's current [`TextureQuality`]. Returns the
+    /// replaced handle, which must outlive any frame still using it
+    pub fn rebuild_sampler(
+        &mut self,
+        loader: &Loader,
+        features: ProvidedFeatures,
+    ) -> Result<vk::Sampler> {
+        let create_info = self
+            .config
+            .create_info(features, self.image.mips, loader.texture_quality());
+        self.replace_sampler(loader, create_info)
+    }
+
+    /// Creates a color target that can be rendered into with [`SampledImage::render`]
+    /// and then sampled, e.g. for shadow maps, reflection probes or post processing
+    pub fn new_render_target(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        description: &AttachmentDescription,
+        extent: vk::Extent2D,
+        config: SamplerConfig,
+    ) -> Result<Self> {
+        if !description.ty.is_color() {
+            return Err(SiltError::InvalidUsage(format!(
+                "render targets must be color attachments: [{:?}]",
+                description
+            )));
+        }
+
+        let image = Image::new_attachment(loader, description, extent)?;
+        Self::new(loader, image, features, config)
+    }
+
+    /// Transitions the image to `ColorAttachment` before `record` and back to `FragmentRead`
+    /// afterwards. Render passes recorded by `record` should use `COLOR_ATTACHMENT_OPTIMAL`
+    /// as the final layout of this image
+    pub fn render(
+        &self,
+        loader: &Loader,
+        pool: &CommandPool,
+        record: impl FnOnce(&Loader, vk::CommandBuffer),
+    ) -> Result<()> {
+        pool.execute_one_time_commands(loader, |loader, command_buffer| {
+            self.image
+                .record_transition(loader, command_buffer, Layout::ColorAttachment);
+            record(loader, command_buffer);
+            self.image
+                .record_transition(loader, command_buffer, Layout::FragmentRead);
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Resource<SampledImage> {
+    /// Rebuilds the sampler and marks the resource dirty, so descriptors pick up the new handle
+    pub fn apply_texture_quality(
+        &mut self,
+        loader: &Loader,
+        features: ProvidedFeatures,
+    ) -> Result<vk::Sampler> {
+        let old = self.resource.rebuild_sampler(loader, features)?;
+        self.mark_dirty();
+        Ok(old)
+    }
+
+    /// See [`SampledImage::stream_mip`], marks the resource dirty so descriptors pick up the new sampler
+    pub fn stream_mip(
+        &mut self,
+        loader: &Loader,
+        pool: &CommandPool,
+        level: u32,
+        data: &image::RgbaImage,
+    ) -> Result<vk::Sampler> {
+        let old = self.resource.stream_mip(loader, pool, level, data)?;
+        self.mark_dirty();
+        Ok(old)
+    }
+}
+
+/// Channels kept on the gpu when uploading an [`ImageFile`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelLayout {
+    #[default]
+    Rgba,
+    /// Red channel only, which is the luminance of grayscale files. Uploaded as `R8_UNORM` or
+    /// `R8_SRGB`, depending on the [`ColorSpace`]
+    R,
+    /// Red and green channels, e.g. two component normal maps. Uploaded as `R8G8_UNORM` or `R8G8_SRGB`
+    Rg,
+}
+
+/// How texels are interpreted when sampled. The format family follows it on every fallback, so
+/// a texture samples the same whichever format the device supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// Color, decoded from sRGB to linear when sampled
+    #[default]
+    Srgb,
+    /// Data sampled as is, e.g. normal, roughness or occlusion maps
+    Linear,
+}
+
+impl ColorSpace {
+    fn rgba_formats(&self) -> [vk::Format; 2] {
+        match self {
+            Self::Srgb => [vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB],
+            Self::Linear => [vk::Format::R8G8B8A8_UNORM, vk::Format::B8G8R8A8_UNORM],
+        }
+    }
+}
+
+impl ChannelLayout {
+    pub fn channels(&self) -> usize {
+        match self {
+            Self::Rgba => 4,
+            Self::R => 1,
+            Self::Rg => 2,
+        }
+    }
+
+    fn format(&self, color_space: ColorSpace) -> Option<vk::Format> {
+        match (self, color_space) {
+            (Self::Rgba, _) => None,
+            (Self::R, ColorSpace::Srgb) => Some(vk::Format::R8_SRGB),
+            (Self::R, ColorSpace::Linear) => Some(vk::Format::R8_UNORM),
+            (Self::Rg, ColorSpace::Srgb) => Some(vk::Format::R8G8_SRGB),
+            (Self::Rg, ColorSpace::Linear) => Some(vk::Format::R8G8_UNORM),
+        }
+    }
+
+    fn from_format(format: vk::Format) -> Self {
+        match format {
+            vk::Format::R8_UNORM | vk::Format::R8_SRGB => Self::R,
+            vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB => Self::Rg,
+            _ => Self::Rgba,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ImageFile {
+    /// Always rgba on the cpu, narrowed to `channels` when uploaded
     pub pixels: image::RgbaImage,
     pub width: u32,
     pub height: u32,
     pub size: u64,
     pub max_mips: u32,
+    pub channels: ChannelLayout,
+    pub color_space: ColorSpace,
 }
 
 impl ImageFile {
     pub fn new(path: impl AsRef<std::path::Path>) -> Result<Self> {
-        let pixels = image::open(path)?.into_rgba8();
+        Ok(Self::from_pixels(image::open(path)?.into_rgba8()))
+    }
+
+    /// Uploads only the channels in `channels`, e.g. [`ChannelLayout::R`] for roughness or height maps.
+    /// Narrow layouts hold data rather than color, so they default to [`ColorSpace::Linear`].
+    /// Falls back to rgba on devices that can't sample the narrow format
+    pub fn new_with_channels(
+        path: impl AsRef<std::path::Path>,
+        channels: ChannelLayout,
+    ) -> Result<Self> {
+        let color_space = match channels {
+            ChannelLayout::Rgba => ColorSpace::Srgb,
+            ChannelLayout::R | ChannelLayout::Rg => ColorSpace::Linear,
+        };
+
+        Ok(Self {
+            channels,
+            color_space,
+            ..Self::new(path)?
+        })
+    }
+
+    /// Samples the file as data rather than color, see [`ColorSpace::Linear`]
+    pub fn linear(self) -> Self {
+        Self {
+            color_space: ColorSpace::Linear,
+            ..self
+        }
+    }
+
+    /// Single texel image, e.g. as a fallback for untextured materials
+    pub fn solid(color: [u8; 4]) -> Self {
+        Self::from_pixels(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)))
+    }
+
+    pub fn from_pixels(pixels: image::RgbaImage) -> Self {
         let width = pixels.width();
         let height = pixels.height();
         let size = pixels.as_flat_samples().min_length().unwrap() as u64;
         let max_mips = (width.max(height) as f32).log2().floor() as u32 + 1;
 
-        Ok(Self {
+        Self {
             pixels,
             width,
             height,
             size,
             max_mips,
+            channels: ChannelLayout::Rgba,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+
+    /// Downsamples each mip level on the cpu, used when the format can't be blitted
+    pub fn mip_chain(&self) -> Vec<image::RgbaImage> {
+        std::iter::successors(Some(self.pixels.clone()), |level| {
+            (level.width() > 1 || level.height() > 1).then(|| {
+                image::imageops::resize(
+                    level,
+                    (level.width() >> 1).max(1),
+                    (level.height() >> 1).max(1),
+                    image::imageops::FilterType::Triangle,
+                )
+            })
         })
+        .take(self.max_mips as usize)
+        .collect()
     }
 
     pub fn upload_to_gpu(
@@ -450,36 +1531,376 @@ impl ImageFile {
         loader: &Loader,
         features: ProvidedFeatures,
         pool: &CommandPool,
+        sampler: SamplerConfig,
     ) -> Result<SampledImage> {
-        let buffer_ci = BufferCreateInfo {
-            size: self.size,
-            name: NULL_ID.clone(),
-            usage: vk::BufferUsageFlags::TRANSFER_SRC,
-            location: vk::MemoryLocation::CpuToGpu,
+        let usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        let format = negotiate_channel_format(
+            &loader.instance,
+            features.pdevice,
+            usage,
+            self.channels,
+            self.color_space,
+        )?;
+        let blit = supports_linear_blit(&loader.instance, features.pdevice, format);
+
+        let image_ci = ImageCreateInfo {
+            width: self.width,
+            height: self.height,
+            mip_levels: self.max_mips,
+            format,
+            usage: if blit {
+                usage | vk::ImageUsageFlags::TRANSFER_SRC
+            } else {
+                usage
+            },
+            view_aspect: vk::ImageAspectFlags::COLOR,
+            name: id!("Texture Image"),
+            ..Default::default()
         };
 
-        let src_buffer = Buffer::new(loader, buffer_ci)?;
-        unsafe { src_buffer.copy_data(loader, &self.pixels) };
+        let image = Image::new(loader, image_ci)?;
+        pool.execute_one_time_commands_batched(loader, |recorder| {
+            recorder
+                .record(|loader, cmd| image.record_transition(loader, cmd, Layout::TransferDst));
+
+            if blit {
+                copy_levels(recorder, &image, [(0, &self.pixels)])?;
+                image.record_generate_mipmaps(recorder, features)
+            } else {
+                let levels = self.mip_chain();
+                copy_levels(recorder, &image, (0..).zip(&levels))?;
+                recorder.record(|loader, cmd| {
+                    image.record_transition(loader, cmd, Layout::FragmentRead)
+                });
+                Ok(())
+            }
+        })?;
+
+        SampledImage::new(loader, image, features, sampler)
+    }
+
+    /// Uploads only the `resident` least detailed mips, the rest are streamed in later with
+    /// [`SampledImage::stream_mip`], e.g. from [`ImageFile::mip_chain`]. Memory for the full
+    /// chain is still allocated up front
+    pub fn upload_streamed(
+        &self,
+        loader: &Loader,
+        features: ProvidedFeatures,
+        pool: &CommandPool,
+        sampler: SamplerConfig,
+        resident: u32,
+    ) -> Result<SampledImage> {
+        let usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        let format = negotiate_channel_format(
+            &loader.instance,
+            features.pdevice,
+            usage,
+            self.channels,
+            self.color_space,
+        )?;
+        let resident_mip = self.max_mips - resident.clamp(1, self.max_mips);
 
         let image_ci = ImageCreateInfo {
             width: self.width,
             height: self.height,
             mip_levels: self.max_mips,
-            usage: vk::ImageUsageFlags::TRANSFER_DST
-                | vk::ImageUsageFlags::TRANSFER_SRC
-                | vk::ImageUsageFlags::SAMPLED,
+            format,
+            usage,
             view_aspect: vk::ImageAspectFlags::COLOR,
-            name: id!("Texture Image"),
+            name: id!("Streamed Texture Image"),
             ..Default::default()
         };
 
         let image = Image::new(loader, image_ci)?;
-        image.transition_layout(loader, pool, Layout::TransferDst)?;
-        src_buffer.copy_to_entire_image(loader, pool, &image)?;
-        image.generate_mipmaps(loader, pool);
+        let levels = self.mip_chain();
+        pool.execute_one_time_commands_batched(loader, |recorder| {
+            recorder
+                .record(|loader, cmd| image.record_transition(loader, cmd, Layout::TransferDst));
+            copy_levels(
+                recorder,
+                &image,
+                (0..).zip(&levels).skip(resident_mip as usize),
+            )?;
+            recorder
+                .record(|loader, cmd| image.record_transition(loader, cmd, Layout::FragmentRead));
+            Ok(())
+        })?;
+
+        let mut sampled = SampledImage::new(loader, image, features, sampler)?;
+        sampled.resident_mip = resident_mip;
+        let placeholder = sampled.replace_sampler(loader, sampled.properties)?;
+        placeholder.destroy(loader);
+
+        Ok(sampled)
+    }
+}
+
+/// Uploads equally sized `files` as the layers of one 2D array texture, e.g. sprite sheets
+/// sampled with a `sampler2DArray`. Mips are generated per layer
+pub fn upload_texture_array(
+    loader: &Loader,
+    features: ProvidedFeatures,
+    pool: &CommandPool,
+    files: &[ImageFile],
+    sampler: SamplerConfig,
+) -> Result<SampledImage> {
+    let first = files
+        .first()
+        .ok_or_else(|| SiltError::InvalidUsage("texture arrays need at least one layer".into()))?;
+
+    let layout = |file: &ImageFile| (file.width, file.height, file.channels, file.color_space);
+    if let Some(file) = files.iter().find(|file| layout(file) != layout(first)) {
+        return Err(SiltError::InvalidUsage(format!(
+            "texture array layers differ: {:?} and {:?}",
+            layout(first),
+            layout(file)
+        )));
+    }
+
+    if files.len() as u32 > features.limits.max_image_array_layers {
+        return Err(SiltError::InvalidUsage(format!(
+            "{} layers exceed the device limit of {}",
+            files.len(),
+            features.limits.max_image_array_layers
+        )));
+    }
+
+    let usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+    let format = negotiate_channel_format(
+        &loader.instance,
+        features.pdevice,
+        usage,
+        first.channels,
+        first.color_space,
+    )?;
+    let blit = supports_linear_blit(&loader.instance, features.pdevice, format);
+
+    let image_ci = ImageCreateInfo {
+        width: first.width,
+        height: first.height,
+        mip_levels: first.max_mips,
+        array_layers: files.len() as u32,
+        // Stays an array for a single file, shaders sample it with a `sampler2DArray`
+        view_type: Some(vk::ImageViewType::TYPE_2D_ARRAY),
+        format,
+        usage: if blit {
+            usage | vk::ImageUsageFlags::TRANSFER_SRC
+        } else {
+            usage
+        },
+        view_aspect: vk::ImageAspectFlags::COLOR,
+        name: id!("Texture Array Image"),
+        ..Default::default()
+    };
+
+    let image = Image::new(loader, image_ci)?;
+    let result = pool.execute_one_time_commands_batched(loader, |recorder| {
+        recorder.record(|loader, cmd| image.record_transition(loader, cmd, Layout::TransferDst));
+
+        if blit {
+            copy_layers(
+                recorder,
+                &image,
+                (0..).zip(files).map(|(layer, file)| (layer, 0, &file.pixels)),
+            )?;
+            image.record_generate_mipmaps(recorder, features)
+        } else {
+            let chains = files.iter().map(ImageFile::mip_chain).collect_vec();
+            copy_layers(
+                recorder,
+                &image,
+                (0..).zip(&chains).flat_map(|(layer, chain)| {
+                    (0..).zip(chain).map(move |(mip_level, level)| (layer, mip_level, level))
+                }),
+            )?;
+            recorder.record(|loader, cmd| image.record_transition(loader, cmd, Layout::FragmentRead));
+            Ok(())
+        }
+    });
+
+    if let Err(err) = result {
+        image.destroy(loader);
+        return Err(err.into());
+    }
+
+    SampledImage::new(loader, image, features, sampler)
+}
+
+/// Records copies of whole mip levels of the first layer, see [`copy_layers`]
+fn copy_levels<'a>(
+    recorder: &mut CommandRecorder,
+    image: &Image,
+    levels: impl IntoIterator<Item = (u32, &'a image::RgbaImage)>,
+) -> Result<()> {
+    copy_layers(
+        recorder,
+        image,
+        levels.into_iter().map(|(mip_level, level)| (0, mip_level, level)),
+    )
+}
+
+/// Records copies of whole `(layer, mip level)` subresources from a single staging buffer, which
+/// is destroyed once the batch completes. The image must be in `TransferDst` when the copies
+/// execute. Levels are narrowed to the channels of the image format
+fn copy_layers<'a>(
+    recorder: &mut CommandRecorder,
+    image: &Image,
+    levels: impl IntoIterator<Item = (u32, u32, &'a image::RgbaImage)>,
+) -> Result<()> {
+    let levels = levels.into_iter().collect_vec();
+    let channels = ChannelLayout::from_format(image.format).channels();
+    let mut data = levels
+        .iter()
+        .flat_map(|(_, _, level)| level.as_raw().chunks_exact(4))
+        .flat_map(|pixel| &pixel[..channels])
+        .copied()
+        .collect_vec();
+
+    if matches!(image.format, vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM) {
+        data.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+    }
+
+    let buffer_ci = BufferCreateInfo {
+        size: data.len() as u64,
+        name: NULL_ID.clone(),
+        usage: vk::BufferUsageFlags::TRANSFER_SRC,
+        location: vk::MemoryLocation::CpuToGpu,
+        ..Default::default()
+    };
+
+    let loader = recorder.loader();
+    let src_buffer = Buffer::new(loader, buffer_ci)?;
+    if let Err(err) = src_buffer.copy_data(loader, None, &data) {
         src_buffer.destroy(loader);
+        return Err(err);
+    }
 
-        SampledImage::new(loader, image, features)
+    let result = levels.iter().try_fold(0, |offset, (layer, mip_level, level)| {
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(offset)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(*mip_level)
+                    .base_array_layer(*layer)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_extent(vk::Extent3D {
+                width: level.width(),
+                height: level.height(),
+                depth: 1,
+            });
+
+        src_buffer.record_copy_to_image(loader, recorder.command_buffer(), image, *region)?;
+        Ok::<_, SiltError>(offset + (level.width() * level.height()) as u64 * channels as u64)
+    });
+
+    recorder.defer_destroy(src_buffer);
+    result.map(|_| ())
+}
+
+#[cached(key = "(vk::PhysicalDevice, vk::Format)", convert = "{ (pdevice, format) }")]
+pub fn get_format_properties(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    format: vk::Format,
+) -> vk::FormatProperties {
+    unsafe { instance.get_physical_device_format_properties(pdevice, format) }
+}
+
+/// Whether an optimally tiled image of `format` can generate mipmaps with linear blits
+pub fn supports_linear_blit(instance: &Instance, pdevice: vk::PhysicalDevice, format: vk::Format) -> bool {
+    get_format_properties(instance, pdevice, format)
+        .optimal_tiling_features
+        .contains(
+            vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+}
+
+/// Whether an optimally tiled image of `format` can be sampled with a min or max reduction mode,
+/// see [`SamplerConfig::reduction_mode`]
+pub fn supports_filter_minmax(instance: &Instance, pdevice: vk::PhysicalDevice, format: vk::Format) -> bool {
+    get_format_properties(instance, pdevice, format)
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_MINMAX)
+}
+
+/// Format features an optimally tiled image needs for `usage`, sampling assumes linear filtering
+pub fn format_features_for_usage(usage: vk::ImageUsageFlags) -> vk::FormatFeatureFlags {
+    [
+        (
+            vk::ImageUsageFlags::SAMPLED,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        ),
+        (vk::ImageUsageFlags::TRANSFER_SRC, vk::FormatFeatureFlags::TRANSFER_SRC),
+        (vk::ImageUsageFlags::TRANSFER_DST, vk::FormatFeatureFlags::TRANSFER_DST),
+        (vk::ImageUsageFlags::STORAGE, vk::FormatFeatureFlags::STORAGE_IMAGE),
+        (vk::ImageUsageFlags::COLOR_ATTACHMENT, vk::FormatFeatureFlags::COLOR_ATTACHMENT),
+        (
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        ),
+    ]
+    .into_iter()
+    .filter(|(image_usage, _)| usage.contains(*image_usage))
+    .fold(vk::FormatFeatureFlags::empty(), |acc, (_, features)| acc | features)
+}
+
+/// Picks the first 8 bit rgba format in `color_space` supporting `usage`. Bgra formats need
+/// their pixel data swizzled before upload
+pub fn negotiate_texture_format(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    usage: vk::ImageUsageFlags,
+    color_space: ColorSpace,
+) -> Result<vk::Format> {
+    let features = format_features_for_usage(usage);
+
+    find_supported_format(
+        instance,
+        pdevice,
+        color_space.rgba_formats(),
+        vk::ImageTiling::OPTIMAL,
+        features,
+    )
+    .ok_or_else(|| {
+        SiltError::UnsupportedFormat(format!(
+            "no 8 bit {:?} rgba texture format supports {:?}",
+            color_space, features
+        ))
+    })
+}
+
+/// [`negotiate_texture_format`] for textures narrowed to `channels`, falling back to rgba of the
+/// same color space when no narrow format supports `usage`
+pub fn negotiate_channel_format(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    usage: vk::ImageUsageFlags,
+    channels: ChannelLayout,
+    color_space: ColorSpace,
+) -> Result<vk::Format> {
+    let narrow = channels.format(color_space).and_then(|format| {
+        find_supported_format(
+            instance,
+            pdevice,
+            [format],
+            vk::ImageTiling::OPTIMAL,
+            format_features_for_usage(usage),
+        )
+    });
+
+    match narrow {
+        Some(format) => Ok(format),
+        None => {
+            if channels != ChannelLayout::Rgba {
+                log::warn!("{:?} textures unsupported, uploading as rgba", channels);
+            }
+            negotiate_texture_format(instance, pdevice, usage, color_space)
+        }
     }
 }
 
@@ -517,6 +1938,22 @@ pub fn get_depth_format(instance: &Instance, pdevice: vk::PhysicalDevice) -> Opt
     )
 }
 
+/// Like [`get_depth_format`], but only considers formats with a stencil aspect
+#[once]
+pub fn get_depth_stencil_format(instance: &Instance, pdevice: vk::PhysicalDevice) -> Option<vk::Format> {
+    find_supported_format(
+        instance,
+        pdevice,
+        [
+            vk::Format::D24_UNORM_S8_UINT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+            vk::Format::D16_UNORM_S8_UINT,
+        ],
+        vk::ImageTiling::OPTIMAL,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+    )
+}
+
 pub fn get_surface_format(
     loader: &Loader,
     surface: vk::SurfaceKHR,
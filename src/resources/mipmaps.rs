@@ -0,0 +1,327 @@
+use crate::error::{Result, SiltError};
+use crate::material::ShaderOptions;
+use crate::properties::{DeviceFeatures, ProvidedFeatures};
+use crate::sync::CommandPool;
+use crate::{compile, prelude::*};
+
+use super::{get_format_properties, Image, Layout};
+
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MipmapStrategy {
+    /// Linear blits between levels, see [`Image::generate_mipmaps`]
+    #[default]
+    Blit,
+    /// Box filtered downsampling in a compute shader, see [`Image::generate_mipmaps_compute`]
+    Compute,
+}
+
+impl Image {
+    pub fn generate_mipmaps_with(
+        &self,
+        loader: &Loader,
+        pool: &CommandPool,
+        features: ProvidedFeatures,
+        strategy: MipmapStrategy,
+    ) -> Result<()> {
+        match strategy {
+            MipmapStrategy::Blit => self.generate_mipmaps(loader, pool, features),
+            MipmapStrategy::Compute => self.generate_mipmaps_compute(loader, pool, features),
+        }
+    }
+
+    /// Downsamples each level into the next with the bundled `downsample` compute shader.
+    /// Works for formats without blit support, e.g. HDR targets, but the image needs
+    /// `SAMPLED` and `STORAGE` usage, and the device `STORAGE_IMAGE_WRITE_WITHOUT_FORMAT`.
+    /// The pool's queue must support compute
+    pub fn generate_mipmaps_compute(
+        &self,
+        loader: &Loader,
+        pool: &CommandPool,
+        features: ProvidedFeatures,
+    ) -> Result<()> {
+        if !loader
+            .features
+            .contains(DeviceFeatures::STORAGE_IMAGE_WRITE_WITHOUT_FORMAT)
+        {
+            return Err(SiltError::MissingFeatures(
+                DeviceFeatures::STORAGE_IMAGE_WRITE_WITHOUT_FORMAT,
+            ));
+        }
+
+        let format_features = get_format_properties(&loader.instance, features.pdevice, self.format)
+            .optimal_tiling_features;
+        if !format_features
+            .contains(vk::FormatFeatureFlags::STORAGE_IMAGE | vk::FormatFeatureFlags::SAMPLED_IMAGE)
+        {
+            return Err(SiltError::UnsupportedFormat(format!(
+                "{:?} does not support storage images for mipmap generation",
+                self.format
+            )));
+        }
+
+        if self.layers > 1 {
+            return Err(SiltError::InvalidUsage(format!(
+                "compute mipmap generation only supports single layer images, found {} layers",
+                self.layers
+            )));
+        }
+
+        if self.mips <= 1 {
+            return Ok(());
+        }
+
+        let code = compile!("../../assets/shaders/downsample.comp", ShaderOptions::HLSL)?;
+        let downsample = unsafe { DownsamplePipeline::new(loader, &code.code, self)? };
+
+        let result = pool.execute_one_time_commands(loader, |loader, command_buffer| {
+            self.record_transition(loader, command_buffer, Layout::ComputeReadWrite);
+            unsafe { downsample.record(loader, command_buffer, self) };
+            self.record_transition(loader, command_buffer, Layout::FragmentRead);
+        });
+
+        downsample.destroy(loader);
+        result?;
+
+        Ok(())
+    }
+}
+
+/// Transient objects for a single [`Image::generate_mipmaps_compute`] call
+struct DownsamplePipeline {
+    module: vk::ShaderModule,
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    pool: vk::DescriptorPool,
+    /// One set per destination level, reading from the level above it
+    sets: Vec<vk::DescriptorSet>,
+    /// One single level view per mip
+    views: Vec<vk::ImageView>,
+}
+
+impl Destructible for DownsamplePipeline {
+    fn destroy(self, loader: &Loader) {
+        self.views.destroy(loader);
+        self.pool.destroy(loader);
+        self.pipeline.destroy(loader);
+        self.layout.destroy(loader);
+        self.set_layout.destroy(loader);
+        self.module.destroy(loader);
+    }
+}
+
+impl DownsamplePipeline {
+    unsafe fn new(loader: &Loader, code: &[u32], image: &Image) -> Result<Self> {
+        let module = loader
+            .device
+            .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(code), None)?;
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+        ];
+        let set_layout = loader.device.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings),
+            None,
+        )?;
+
+        let push_constant = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<[u32; 2]>() as u32,
+        };
+        let layout = loader.device.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(std::slice::from_ref(&set_layout))
+                .push_constant_ranges(std::slice::from_ref(&push_constant)),
+            None,
+        )?;
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(std::ffi::CStr::from_bytes_with_nul_unchecked(b"main\0"));
+        let pipeline_ci = vk::ComputePipelineCreateInfo::builder()
+            .stage(*stage)
+            .layout(layout);
+        let pipeline = loader
+            .device
+            .create_compute_pipelines(
+                vk::PipelineCache::null(),
+                std::slice::from_ref(&pipeline_ci),
+                None,
+            )
+            .map_err(|(_, err)| err)?[0];
+
+        let views = (0..image.mips)
+            .map(|mip_level| {
+                let view_ci = vk::ImageViewCreateInfo::builder()
+                    .image(image.image)
+                    .format(image.format)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: mip_level,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    });
+
+                loader.device.create_image_view(&view_ci, None)
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let levels = image.mips - 1;
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: levels,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: levels,
+            },
+        ];
+        let pool = loader.device.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(levels)
+                .pool_sizes(&pool_sizes),
+            None,
+        )?;
+
+        let set_layouts = vec![set_layout; levels as usize];
+        let sets = loader.device.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&set_layouts),
+        )?;
+
+        let image_infos = views
+            .windows(2)
+            .map(|views| {
+                [
+                    vk::DescriptorImageInfo {
+                        sampler: vk::Sampler::null(),
+                        image_view: views[0],
+                        image_layout: vk::ImageLayout::GENERAL,
+                    },
+                    vk::DescriptorImageInfo {
+                        sampler: vk::Sampler::null(),
+                        image_view: views[1],
+                        image_layout: vk::ImageLayout::GENERAL,
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let writes = sets
+            .iter()
+            .zip(&image_infos)
+            .flat_map(|(&set, infos)| {
+                [
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(&infos[..1])
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&infos[1..])
+                        .build(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        loader.device.update_descriptor_sets(&writes, &[]);
+
+        Ok(Self {
+            module,
+            set_layout,
+            layout,
+            pipeline,
+            pool,
+            sets,
+            views,
+        })
+    }
+
+    /// The image must already be in [`Layout::ComputeReadWrite`]
+    unsafe fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, image: &Image) {
+        loader.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline,
+        );
+
+        for (idx, &set) in self.sets.iter().enumerate() {
+            let mip_level = idx as u32 + 1;
+            let size = [
+                (image.size.width >> mip_level).max(1),
+                (image.size.height >> mip_level).max(1),
+            ];
+
+            loader.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[set],
+                &[],
+            );
+            loader.device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                std::slice::from_raw_parts(size.as_ptr() as *const u8, 8),
+            );
+            loader.device.cmd_dispatch(
+                command_buffer,
+                size[0].div_ceil(WORKGROUP_SIZE),
+                size[1].div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+
+            // The level just written is read by the next dispatch
+            let barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::GENERAL)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .image(image.image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: mip_level,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                });
+
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&barrier),
+            );
+        }
+    }
+}
@@ -8,4 +8,22 @@ mod image;
 pub use self::image::*;
 
 mod descriptors;
-pub use descriptors::*;
\ No newline at end of file
+pub use descriptors::*;
+
+mod sparse;
+pub use sparse::*;
+
+mod mipmaps;
+pub use mipmaps::*;
+
+mod transient;
+pub use transient::*;
+
+mod object_uniforms;
+pub use object_uniforms::*;
+
+mod draw_data;
+pub use draw_data::*;
+
+mod postprocess;
+pub use postprocess::*;
@@ -0,0 +1,183 @@
+use crate::collections::{Parity, ParitySet};
+use crate::error::{Result, SiltError};
+use crate::properties::ProvidedFeatures;
+use crate::sync::{FrameContext, Recordable};
+use crate::{id, prelude::*};
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use super::{
+    BindableResource, Buffer, BufferCreateInfo, Resource, ResourceBinding, ResourceDescription,
+    ResourceReference, TypedResourceDescription, UniformDescription,
+};
+
+/// One `T` per object, packed into a single buffer per frame and bound through a single
+/// `UNIFORM_BUFFER_DYNAMIC` descriptor. Each draw selects its element with a dynamic offset,
+/// see [`ObjectUniforms::record_bind`]. Described by [`ResourceDescription::object_uniform`]
+#[derive(Debug)]
+pub struct ObjectUniforms<T: Copy> {
+    buffers: ParitySet<Buffer>,
+    /// Element size rounded up to `min_uniform_buffer_offset_alignment`
+    stride: vk::DeviceSize,
+    capacity: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Copy> Destructible for ObjectUniforms<T> {
+    fn destroy(self, loader: &Loader) {
+        self.buffers.into_iter().destroy(loader);
+    }
+}
+
+impl<T: Copy> ObjectUniforms<T> {
+    pub fn new(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        description: &TypedResourceDescription<T>,
+    ) -> Result<Self> {
+        let capacity = match description.deref() {
+            ResourceDescription::Uniform(UniformDescription {
+                elements,
+                dynamic: true,
+                ..
+            }) => *elements,
+            _ => {
+                return Err(SiltError::InvalidUsage(format!(
+                    "Resource description [{:?}] not an object uniform description",
+                    description.deref()
+                )))
+            }
+        };
+
+        let alignment = features.limits.min_uniform_buffer_offset_alignment.max(1);
+        let size = std::mem::size_of::<T>() as u64;
+        let stride = size.div_ceil(alignment) * alignment;
+
+        let create_info = BufferCreateInfo {
+            size: stride * capacity as u64,
+            name: id!("Object Uniforms"),
+            usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
+            location: vk::MemoryLocation::CpuToGpu,
+            ..Default::default()
+        };
+
+        let even = Buffer::new(loader, create_info.clone())?;
+        let odd = match Buffer::new(loader, create_info) {
+            Ok(odd) => odd,
+            Err(err) => {
+                even.destroy(loader);
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            buffers: ParitySet::new(even, odd),
+            stride,
+            capacity,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Dynamic offset selecting the element at `index`
+    pub fn offset(&self, index: usize) -> u32 {
+        (index as u64 * self.stride) as u32
+    }
+
+    /// Writes object `index` of the frame `parity`, which must not be in flight
+    pub fn write(&self, loader: &Loader, parity: Parity, index: usize, value: T) -> Result<()> {
+        self.write_range(loader, parity, index, std::slice::from_ref(&value))
+    }
+
+    /// Writes consecutive objects starting at `first`
+    pub fn write_range(
+        &self,
+        loader: &Loader,
+        parity: Parity,
+        first: usize,
+        values: &[T],
+    ) -> Result<()> {
+        if first + values.len() > self.capacity {
+            return Err(SiltError::InvalidUsage(format!(
+                "objects {}..{} exceed capacity of {}",
+                first,
+                first + values.len(),
+                self.capacity
+            )));
+        }
+
+        let buffer = self.buffers.get(parity);
+        let ptr = loader.allocator.get_mapped_ptr(buffer.allocation)?.as_ptr() as *mut u8;
+
+        for (index, value) in (first..).zip(values) {
+            unsafe {
+                let dst = ptr.add(self.offset(index) as usize) as *mut T;
+                dst.write_unaligned(*value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Binds `set`, holding these uniforms, right after the sets of `frame` with the offset of
+    /// object `index`. Rebinding only the object set per draw leaves the lower sets bound
+    pub fn record_bind(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        frame: &FrameContext,
+        set: vk::DescriptorSet,
+        index: usize,
+    ) {
+        unsafe {
+            loader.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                frame.layout,
+                frame.descriptor_sets.len() as u32,
+                &[set],
+                &[self.offset(index)],
+            )
+        }
+    }
+}
+
+impl<T: Copy> BindableResource for Resource<ObjectUniforms<T>> {
+    fn bind(&self) -> ResourceBinding<'_> {
+        let description = self.description.clone();
+        let stride = self.resource.stride;
+        let reference = self
+            .resource
+            .buffers
+            .as_ref()
+            .ref_map(|&buffer| ResourceReference::DynamicBuffer(buffer, stride))
+            .into();
+
+        ResourceBinding {
+            description,
+            reference,
+            generation: self.generation.get(),
+        }
+    }
+}
+
+/// Draws `inner` with object `index` of `uniforms` bound, e.g. pushed once per object into a
+/// [`crate::sync::RecordList`] sharing one object set
+pub struct ObjectDraw<'a, T: Copy> {
+    pub uniforms: &'a ObjectUniforms<T>,
+    /// Object frequency set for the current frame, see [`crate::material::MaterialSystem::object_set`]
+    pub set: vk::DescriptorSet,
+    pub index: usize,
+    pub inner: &'a dyn Recordable,
+}
+
+impl<T: Copy> Recordable for ObjectDraw<'_, T> {
+    fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, frame: &FrameContext) {
+        self.uniforms
+            .record_bind(loader, command_buffer, frame, self.set, self.index);
+        self.inner.record(loader, command_buffer, frame);
+    }
+}
@@ -0,0 +1,634 @@
+use std::rc::Rc;
+
+use crate::error::{Result, SiltError};
+use crate::material::{MaterialSystemBuilder, ShaderOptions};
+use crate::pipeline::{PipelineState, SpecConstant};
+use crate::properties::{DeviceFeatures, ProvidedFeatures};
+use crate::sync::FrameContext;
+use crate::{compile, id, prelude::*};
+
+use super::{
+    get_format_properties, BindableResource, Image, ImageCreateInfo, Layout, Resource,
+    ResourceBinding, ResourceDescription, ResourceReference, SampledImage, Sampler, SamplerConfig,
+};
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Width of the gaussian kernel of a [`PostProcess`], sent as push constants so it can be
+/// changed between recordings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlurConfig {
+    /// Texels sampled on each side of the center, per axis
+    pub radius: u32,
+    pub sigma: f32,
+}
+
+impl Default for BlurConfig {
+    fn default() -> Self {
+        Self {
+            radius: 4,
+            sigma: 2.,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BlurPushConstants {
+    size: [u32; 2],
+    direction: [i32; 2],
+    radius: i32,
+    sigma: f32,
+}
+
+/// Separable gaussian blur of a sampled image into a storage image, with the bundled `blur`
+/// compute shader. The horizontal pass writes an intermediate image owned by the post process,
+/// the vertical pass reads it back into the output. A starting point for bloom or depth of field
+pub struct PostProcess {
+    pub config: BlurConfig,
+    pipeline: BlurPipeline,
+    intermediate: Image,
+}
+
+impl Destructible for PostProcess {
+    fn destroy(self, loader: &Loader) {
+        self.pipeline.destroy(loader);
+        self.intermediate.destroy(loader);
+    }
+}
+
+struct BlurPipeline {
+    compute: ComputePipeline,
+    pool: vk::DescriptorPool,
+    /// Horizontal then vertical pass
+    sets: [vk::DescriptorSet; 2],
+}
+
+impl Destructible for BlurPipeline {
+    fn destroy(self, loader: &Loader) {
+        self.pool.destroy(loader);
+        self.compute.destroy(loader);
+    }
+}
+
+/// Single set compute pipeline with push constants, for post processes outside the material system
+struct ComputePipeline {
+    module: vk::ShaderModule,
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl Destructible for ComputePipeline {
+    fn destroy(self, loader: &Loader) {
+        self.pipeline.destroy(loader);
+        self.layout.destroy(loader);
+        self.set_layout.destroy(loader);
+        self.module.destroy(loader);
+    }
+}
+
+impl PostProcess {
+    /// Blurs `input` into `output`, which must have the same extent, a single mip and
+    /// `STORAGE` usage. Needs the device `STORAGE_IMAGE_WRITE_WITHOUT_FORMAT`
+    pub fn new(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        input: &SampledImage,
+        output: &Image,
+        config: BlurConfig,
+    ) -> Result<Self> {
+        validate_output(loader, features, input, output)?;
+
+        let (width, height) = (output.size.width, output.size.height);
+        let intermediate = Image::new(
+            loader,
+            ImageCreateInfo {
+                width,
+                height,
+                format: output.format,
+                usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                name: id!("Post Process Intermediate"),
+                ..Default::default()
+            },
+        )?;
+
+        let code = compile!("../../assets/shaders/blur.comp", ShaderOptions::HLSL)?;
+        match unsafe { BlurPipeline::new(loader, &code.code, input, output, &intermediate) } {
+            Ok(pipeline) => Ok(Self {
+                config,
+                pipeline,
+                intermediate,
+            }),
+            Err(err) => {
+                intermediate.destroy(loader);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Output images need to be storage images matching the input, written without a declared format
+fn validate_output(
+    loader: &Loader,
+    features: ProvidedFeatures,
+    input: &SampledImage,
+    output: &Image,
+) -> Result<()> {
+    if !loader
+        .features
+        .contains(DeviceFeatures::STORAGE_IMAGE_WRITE_WITHOUT_FORMAT)
+    {
+        return Err(SiltError::MissingFeatures(
+            DeviceFeatures::STORAGE_IMAGE_WRITE_WITHOUT_FORMAT,
+        ));
+    }
+
+    let format_features = get_format_properties(&loader.instance, features.pdevice, output.format)
+        .optimal_tiling_features;
+    if !format_features
+        .contains(vk::FormatFeatureFlags::STORAGE_IMAGE | vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    {
+        return Err(SiltError::UnsupportedFormat(format!(
+            "{:?} does not support storage images for post processing",
+            output.format
+        )));
+    }
+
+    let output_size = (output.size.width, output.size.height);
+    let input_size = (input.image.size.width, input.image.size.height);
+    if input_size != output_size || output.mips != 1 {
+        return Err(SiltError::InvalidUsage(format!(
+            "post process output of size {:?} with {} mips doesn't match input of size {:?}",
+            output_size, output.mips, input_size
+        )));
+    }
+
+    Ok(())
+}
+
+impl ComputePipeline {
+    /// Every binding is a single descriptor visible to the compute stage
+    ///
+    /// # Safety
+    ///
+    /// `code` must be valid SPIR-V whose push constants are a `P` and whose set 0 matches `bindings`
+    unsafe fn new<P>(loader: &Loader, code: &[u32], bindings: &[vk::DescriptorType]) -> Result<Self> {
+        let module = loader
+            .device
+            .create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(code), None)?;
+
+        let bindings = (0..)
+            .zip(bindings)
+            .map(|(binding, &ty)| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(ty)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let set_layout = loader
+            .device
+            .create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings),
+                None,
+            )
+            .inspect_err(|_| module.destroy(loader))?;
+
+        let push_constant = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<P>() as u32,
+        };
+        let layout = loader
+            .device
+            .create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::builder()
+                    .set_layouts(std::slice::from_ref(&set_layout))
+                    .push_constant_ranges(std::slice::from_ref(&push_constant)),
+                None,
+            )
+            .inspect_err(|_| {
+                set_layout.destroy(loader);
+                module.destroy(loader);
+            })?;
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(std::ffi::CStr::from_bytes_with_nul_unchecked(b"main\0"));
+        let pipeline_ci = vk::ComputePipelineCreateInfo::builder()
+            .stage(*stage)
+            .layout(layout);
+        let pipeline = loader
+            .device
+            .create_compute_pipelines(
+                vk::PipelineCache::null(),
+                std::slice::from_ref(&pipeline_ci),
+                None,
+            )
+            .map_err(|(_, err)| {
+                layout.destroy(loader);
+                set_layout.destroy(loader);
+                module.destroy(loader);
+                err
+            })?[0];
+
+        Ok(Self {
+            module,
+            set_layout,
+            layout,
+            pipeline,
+        })
+    }
+
+    /// Dispatches one thread per pixel of `size` with `set` bound
+    ///
+    /// # Safety
+    ///
+    /// `P` must be the push constant type the pipeline was created with, and `set` must be
+    /// allocated from its `set_layout`. The compute pipeline must already be bound
+    unsafe fn record_dispatch<P>(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        set: vk::DescriptorSet,
+        push_constants: &P,
+        size: [u32; 2],
+    ) {
+        loader.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.layout,
+            0,
+            &[set],
+            &[],
+        );
+        loader.device.cmd_push_constants(
+            command_buffer,
+            self.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            std::slice::from_raw_parts(
+                push_constants as *const P as *const u8,
+                std::mem::size_of::<P>(),
+            ),
+        );
+        loader.device.cmd_dispatch(
+            command_buffer,
+            size[0].div_ceil(WORKGROUP_SIZE),
+            size[1].div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+    }
+}
+
+impl BlurPipeline {
+    /// # Safety
+    ///
+    /// `code` must be valid SPIR-V for the blur shader. The images must outlive the pipeline,
+    /// whose descriptor sets keep their views
+    unsafe fn new(
+        loader: &Loader,
+        code: &[u32],
+        input: &SampledImage,
+        output: &Image,
+        intermediate: &Image,
+    ) -> Result<Self> {
+        let compute = ComputePipeline::new::<BlurPushConstants>(
+            loader,
+            code,
+            &[vk::DescriptorType::SAMPLED_IMAGE, vk::DescriptorType::STORAGE_IMAGE],
+        )?;
+        let set_layout = compute.set_layout;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: 2,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 2,
+            },
+        ];
+        let pool = match loader.device.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(2)
+                .pool_sizes(&pool_sizes),
+            None,
+        ) {
+            Ok(pool) => pool,
+            Err(err) => {
+                compute.destroy(loader);
+                return Err(err.into());
+            }
+        };
+
+        let sets = match loader.device.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(&[set_layout; 2]),
+        ) {
+            Ok(sets) => sets,
+            Err(err) => {
+                pool.destroy(loader);
+                compute.destroy(loader);
+                return Err(err.into());
+            }
+        };
+        let sets = [sets[0], sets[1]];
+
+        let image_info = |view| vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: view,
+            image_layout: vk::ImageLayout::GENERAL,
+        };
+        let image_infos = [
+            [image_info(input.image.view), image_info(intermediate.view)],
+            [image_info(intermediate.view), image_info(output.view)],
+        ];
+
+        let writes = sets
+            .iter()
+            .zip(&image_infos)
+            .flat_map(|(&set, infos)| {
+                [
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(&infos[..1])
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&infos[1..])
+                        .build(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        loader.device.update_descriptor_sets(&writes, &[]);
+
+        Ok(Self {
+            compute,
+            pool,
+            sets,
+        })
+    }
+}
+
+impl PostProcess {
+    /// Records both passes. `input` and `output` must be the images the post process was
+    /// created with, and end up in `FragmentRead`
+    pub fn record(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        input: &SampledImage,
+        output: &Image,
+    ) {
+        input
+            .image
+            .record_transition(loader, command_buffer, Layout::ComputeReadWrite);
+        self.intermediate
+            .record_transition(loader, command_buffer, Layout::ComputeReadWrite);
+        output.record_transition(loader, command_buffer, Layout::ComputeReadWrite);
+
+        let size = [output.size.width, output.size.height];
+        unsafe {
+            loader.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.compute.pipeline,
+            );
+
+            for (&set, direction) in self.pipeline.sets.iter().zip([[1, 0], [0, 1]]) {
+                let push_constants = BlurPushConstants {
+                    size,
+                    direction,
+                    radius: self.config.radius as i32,
+                    sigma: self.config.sigma.max(f32::EPSILON),
+                };
+
+                self.pipeline.compute.record_dispatch(
+                    loader,
+                    command_buffer,
+                    set,
+                    &push_constants,
+                    size,
+                );
+
+                // The horizontal pass is read by the vertical one
+                let barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ);
+                loader.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    std::slice::from_ref(&barrier),
+                    &[],
+                    &[],
+                );
+            }
+        }
+
+        input
+            .image
+            .record_transition(loader, command_buffer, Layout::FragmentRead);
+        output.record_transition(loader, command_buffer, Layout::FragmentRead);
+    }
+}
+
+/// Edge detection thresholds of an [`Fxaa`] pass, defaults match the FXAA 3.11 quality preset.
+/// Baked into the effect's pipeline as specialization constants 0 through 2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FxaaConfig {
+    /// Local contrast, relative to the brightest neighbour, needed to treat a pixel as an edge
+    pub edge_threshold: f32,
+    /// Contrast below which dark pixels are skipped regardless of `edge_threshold`
+    pub edge_threshold_min: f32,
+    /// How much subpixel aliasing is removed, lower keeps fine detail sharper
+    pub subpixel: f32,
+}
+
+impl Default for FxaaConfig {
+    fn default() -> Self {
+        Self {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel: 0.75,
+        }
+    }
+}
+
+impl FxaaConfig {
+    fn specialization(&self) -> [(u32, SpecConstant); 3] {
+        [
+            (0, SpecConstant::F32(self.edge_threshold)),
+            (1, SpecConstant::F32(self.edge_threshold_min)),
+            (2, SpecConstant::F32(self.subpixel)),
+        ]
+    }
+}
+
+/// Bindings of the bundled `fxaa` compute shader, all at [`vk::DescriptorFrequency::Material`]
+/// in set 0: the input image at 0, its sampler at 1 and the output storage image at 2
+#[derive(Debug, Clone)]
+pub struct FxaaDescriptions {
+    pub input: Rc<ResourceDescription>,
+    pub sampler: Rc<ResourceDescription>,
+    pub output: Rc<ResourceDescription>,
+}
+
+impl Default for FxaaDescriptions {
+    fn default() -> Self {
+        let frequency = vk::DescriptorFrequency::Material;
+        Self {
+            input: ResourceDescription::separate_image(id!("FXAA Input"), 0, frequency).with_set(0),
+            sampler: ResourceDescription::sampler(id!("FXAA Sampler"), 1, frequency).with_set(0),
+            output: ResourceDescription::storage_image(id!("FXAA Output"), 2, frequency).with_set(0),
+        }
+    }
+}
+
+impl FxaaDescriptions {
+    pub fn all(&self) -> [Rc<ResourceDescription>; 3] {
+        [self.input.clone(), self.sampler.clone(), self.output.clone()]
+    }
+}
+
+/// Fast approximate antialiasing of a resolved color image into a storage image, as a compute
+/// effect of the material system running the bundled `fxaa` shader. Edges are found from luma, so
+/// the input should already be tonemapped. Cheaper than MSAA and also smooths shading and alpha
+/// tested edges
+///
+/// ```ignore
+/// let fxaa = Fxaa::register(&mut builder, &loader, features, id!("FXAA"), FxaaConfig::default())?;
+/// let mut materials = builder.build_static()?;
+///
+/// materials.get_local_resources_mut(&fxaa.effect)?.material =
+///     fxaa.bindings(&loader, &resolved, &output)?.into();
+/// materials.write_local_sets(&loader, &fxaa.effect, vk::PartialDescriptorFrequency::Material)?;
+///
+/// // Each frame, outside of a render pass
+/// let frame = materials.get_frame_context(&loader, &fxaa.effect)?;
+/// fxaa.record(&loader, command_buffer, &frame, &resolved.resource, &output.resource);
+/// ```
+pub struct Fxaa {
+    pub effect: Identifier,
+    pub descriptions: FxaaDescriptions,
+    sampler: Resource<Sampler>,
+    features: ProvidedFeatures,
+}
+
+impl Destructible for Fxaa {
+    fn destroy(self, loader: &Loader) {
+        self.sampler.resource.destroy(loader);
+    }
+}
+
+impl Fxaa {
+    /// Registers the shader and the compute effect `id` with `builder`. Needs the device
+    /// `STORAGE_IMAGE_WRITE_WITHOUT_FORMAT`
+    pub fn register(
+        builder: &mut MaterialSystemBuilder,
+        loader: &Loader,
+        features: ProvidedFeatures,
+        id: Identifier,
+        config: FxaaConfig,
+    ) -> Result<Self> {
+        if !loader
+            .features
+            .contains(DeviceFeatures::STORAGE_IMAGE_WRITE_WITHOUT_FORMAT)
+        {
+            return Err(SiltError::MissingFeatures(
+                DeviceFeatures::STORAGE_IMAGE_WRITE_WITHOUT_FORMAT,
+            ));
+        }
+
+        let descriptions = FxaaDescriptions::default();
+        let shader = builder.add_shader(
+            Identifier::new(format!("{} Shader", id)),
+            compile!("../../assets/shaders/fxaa.comp", ShaderOptions::HLSL)?,
+            descriptions.all(),
+        )?;
+
+        let state = PipelineState {
+            specializations: vec![builder.specialize(&shader, &config.specialization())?],
+            ..Default::default()
+        };
+        let effect = builder.register_effect_with_state(id, [shader], state)?;
+
+        // Created last, so a failed registration has nothing to clean up
+        let sampler_config = SamplerConfig {
+            max_anisotropy: None,
+            ..SamplerConfig::clamp_to_edge()
+        };
+        let sampler = descriptions
+            .sampler
+            .bind_result(|_| Sampler::new(loader, features, sampler_config, 1))?;
+
+        Ok(Self {
+            effect,
+            descriptions,
+            sampler,
+            features,
+        })
+    }
+
+    /// Material resources of the effect, reading `input` into `output`. `output` has the same
+    /// requirements as for [`PostProcess::new`]. The input descriptor takes the layout `input` is
+    /// in when the sets are written, which should be [`Layout::FragmentRead`]
+    pub fn bindings<'a>(
+        &'a self,
+        loader: &Loader,
+        input: &'a Resource<SampledImage>,
+        output: &'a Resource<Image>,
+    ) -> Result<Vec<ResourceBinding<'a>>> {
+        validate_output(loader, self.features, &input.resource, &output.resource)?;
+
+        Ok(vec![
+            ResourceBinding {
+                description: self.descriptions.input.clone(),
+                reference: ResourceReference::SampledImageSeparate(&input.resource.image).into(),
+                generation: input.generation.get(),
+            },
+            self.sampler.bind(),
+            ResourceBinding {
+                description: self.descriptions.output.clone(),
+                reference: ResourceReference::StorageImage(&output.resource).into(),
+                generation: output.generation.get(),
+            },
+        ])
+    }
+
+    /// Records the pass with the sets of `frame`, see [`crate::material::MaterialSystem::get_frame_context`].
+    /// `input` and `output` must be the images bound with [`Fxaa::bindings`], and end up in `FragmentRead`
+    pub fn record(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        frame: &FrameContext,
+        input: &SampledImage,
+        output: &Image,
+    ) {
+        input
+            .image
+            .record_transition(loader, command_buffer, Layout::ComputeRead);
+        output.record_transition(loader, command_buffer, Layout::ComputeReadWrite);
+
+        let groups = [output.size.width, output.size.height].map(|size| size.div_ceil(WORKGROUP_SIZE));
+        frame.record_dispatch(loader, command_buffer, [groups[0], groups[1], 1], None);
+
+        input
+            .image
+            .record_transition(loader, command_buffer, Layout::FragmentRead);
+        output.record_transition(loader, command_buffer, Layout::FragmentRead);
+    }
+}
@@ -0,0 +1,419 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use crate::error::{Result, SiltError};
+use crate::prelude::*;
+use crate::properties::DeviceFeatures;
+use crate::sync::QueueHandle;
+
+use super::{ImageCreateInfo, Layout};
+
+/// A single tile of a sparse image, in units of the image's page granularity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SparsePage {
+    pub mip_level: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Partially resident image for virtual texturing. Only pages bound with
+/// [`SparseImage::bind_pages`] are backed by memory, reads from unbound
+/// pages return undefined values. Levels past the mip tail are always resident
+#[derive(Debug)]
+pub struct SparseImage {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub size: vk::Extent3D,
+    pub mips: u32,
+    pub format: vk::Format,
+    pub granularity: vk::Extent3D,
+    pub layout: Cell<Layout>,
+    queue: vk::Queue,
+    page_requirements: vk::MemoryRequirements,
+    pages: RefCell<HashMap<SparsePage, vk::Allocation>>,
+    mip_tail: Option<vk::Allocation>,
+    mip_tail_first_lod: u32,
+}
+
+impl Destructible for SparseImage {
+    fn destroy(self, loader: &Loader) {
+        self.view.destroy(loader);
+        self.image.destroy(loader);
+        self.pages.into_inner().into_values().destroy(loader);
+        self.mip_tail.destroy(loader);
+    }
+}
+
+impl SparseImage {
+    /// `queue` must support sparse binding, all page binds are submitted to it
+    pub fn new(loader: &Loader, queue: &QueueHandle, create_info: ImageCreateInfo) -> Result<Self> {
+        let required = DeviceFeatures::SPARSE_BINDING | DeviceFeatures::SPARSE_RESIDENCY_IMAGE_2D;
+        if !loader.features.contains(required) {
+            return Err(SiltError::MissingFeatures(required - loader.features));
+        }
+
+        let size = vk::Extent3D {
+            width: create_info.width,
+            height: create_info.height,
+            depth: 1,
+        };
+
+        let image_ci = vk::ImageCreateInfo::builder()
+            .flags(vk::ImageCreateFlags::SPARSE_BINDING | vk::ImageCreateFlags::SPARSE_RESIDENCY)
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(size)
+            .mip_levels(create_info.mip_levels)
+            .array_layers(1)
+            .format(create_info.format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(create_info.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(vk::SampleCountFlags::TYPE_1);
+
+        let image = unsafe { loader.device.create_image(&image_ci, None)? };
+        let page_requirements = unsafe { loader.device.get_image_memory_requirements(image) };
+        let sparse_requirements = unsafe {
+            loader
+                .device
+                .get_image_sparse_memory_requirements(image)
+        }
+        .into_iter()
+        .find(|requirements| {
+            requirements
+                .format_properties
+                .aspect_mask
+                .contains(create_info.view_aspect)
+        });
+
+        let Some(sparse_requirements) = sparse_requirements else {
+            image.destroy(loader);
+            return Err(SiltError::UnsupportedFormat(format!(
+                "{:?} does not support sparse residency",
+                create_info.format
+            )));
+        };
+
+        let view_ci = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .format(create_info.format)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(create_info.view_aspect)
+                    .base_mip_level(0)
+                    .level_count(create_info.mip_levels)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            );
+
+        let view = unsafe { loader.device.create_image_view(&view_ci, None)? };
+
+        let mut sparse_image = Self {
+            image,
+            view,
+            size,
+            mips: create_info.mip_levels,
+            format: create_info.format,
+            granularity: sparse_requirements.format_properties.image_granularity,
+            layout: Cell::new(Layout::Initial),
+            queue: queue.queues[0],
+            page_requirements,
+            pages: Default::default(),
+            mip_tail: None,
+            mip_tail_first_lod: sparse_requirements.image_mip_tail_first_lod,
+        };
+
+        if sparse_requirements.image_mip_tail_first_lod < create_info.mip_levels {
+            let requirements = vk::MemoryRequirements {
+                size: sparse_requirements.image_mip_tail_size,
+                ..page_requirements
+            };
+            let allocation = sparse_image.allocate(loader, requirements)?;
+
+            let bind = vk::SparseMemoryBind::builder()
+                .resource_offset(sparse_requirements.image_mip_tail_offset)
+                .size(sparse_requirements.image_mip_tail_size)
+                .memory(loader.allocator.get_memory(allocation)?)
+                .memory_offset(loader.allocator.get_offset(allocation)?)
+                .build();
+
+            let opaque_bind = vk::SparseImageOpaqueMemoryBindInfo::builder()
+                .image(image)
+                .binds(std::slice::from_ref(&bind))
+                .build();
+
+            let bind_info = vk::BindSparseInfo::builder()
+                .image_opaque_binds(std::slice::from_ref(&opaque_bind));
+
+            sparse_image.submit(loader, *bind_info)?;
+            sparse_image.mip_tail = Some(allocation);
+        }
+
+        Ok(sparse_image)
+    }
+
+    /// Number of pages along each axis of a mip level
+    pub fn page_count(&self, mip_level: u32) -> (u32, u32) {
+        page_count(self.size, self.granularity, mip_level)
+    }
+
+    pub fn is_resident(&self, page: &SparsePage) -> bool {
+        page.mip_level >= self.mip_tail_first_lod || self.pages.borrow().contains_key(page)
+    }
+
+    /// Backs `pages` with memory. Pages that are already resident or inside the mip tail are skipped.
+    /// Blocks until the bind has completed on the sparse queue
+    pub fn bind_pages(&self, loader: &Loader, pages: &[SparsePage]) -> Result<()> {
+        if let Some(page) = pages.iter().find(|page| {
+            page.mip_level >= self.mips || page_region(self.size, self.granularity, page).is_none()
+        }) {
+            return Err(SiltError::InvalidUsage(format!(
+                "page {:?} lies outside of the image, which has {} mips of {:?} pages at mip 0",
+                page,
+                self.mips,
+                self.page_count(0)
+            )));
+        }
+
+        let requirements = vk::MemoryRequirements {
+            size: page_size(
+                self.granularity,
+                texel_size(self.format),
+                self.page_requirements.alignment,
+            ),
+            ..self.page_requirements
+        };
+
+        let pages = pages
+            .iter()
+            .filter(|page| !self.is_resident(page))
+            .copied()
+            .collect::<Vec<_>>();
+
+        let allocations = pages
+            .iter()
+            .map(|_| self.allocate(loader, requirements))
+            .collect::<Result<Vec<_>>>()?;
+
+        let binds = pages
+            .iter()
+            .zip(&allocations)
+            .map(|(page, &allocation)| {
+                Ok(self
+                    .page_bind(page)
+                    .memory(loader.allocator.get_memory(allocation)?)
+                    .memory_offset(loader.allocator.get_offset(allocation)?)
+                    .build())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.submit_image_binds(loader, &binds)?;
+        self.pages.borrow_mut().extend(pages.into_iter().zip(allocations));
+
+        Ok(())
+    }
+
+    /// Releases the memory behind `pages`. The pages must not be in use by any pending command buffers
+    pub fn unbind_pages(&self, loader: &Loader, pages: &[SparsePage]) -> Result<()> {
+        let resident = pages
+            .iter()
+            .filter(|page| self.pages.borrow().contains_key(page))
+            .copied()
+            .collect::<Vec<_>>();
+
+        let binds = resident
+            .iter()
+            .map(|page| self.page_bind(page).memory(vk::DeviceMemory::null()).build())
+            .collect::<Vec<_>>();
+
+        self.submit_image_binds(loader, &binds)?;
+
+        let mut allocations = self.pages.borrow_mut();
+        resident
+            .iter()
+            .filter_map(|page| allocations.remove(page))
+            .destroy(loader);
+
+        Ok(())
+    }
+
+    /// Only called with pages validated by [`SparseImage::bind_pages`]
+    fn page_bind(&self, page: &SparsePage) -> vk::SparseImageMemoryBindBuilder<'static> {
+        let (offset, extent) = page_region(self.size, self.granularity, page).unwrap();
+
+        vk::SparseImageMemoryBind::builder()
+            .subresource(vk::ImageSubresource {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: page.mip_level,
+                array_layer: 0,
+            })
+            .offset(offset)
+            .extent(extent)
+    }
+
+    fn allocate(&self, loader: &Loader, requirements: vk::MemoryRequirements) -> Result<vk::Allocation> {
+        let allocation_create_info = vk::AllocationCreateInfo {
+            name: "Sparse Page",
+            requirements,
+            location: vk::MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme: vk::AllocationScheme::GpuAllocatorManaged,
+        };
+
+        Ok(loader.allocator.allocate(&allocation_create_info)?)
+    }
+
+    fn submit_image_binds(&self, loader: &Loader, binds: &[vk::SparseImageMemoryBind]) -> Result<()> {
+        if binds.is_empty() {
+            return Ok(());
+        }
+
+        let image_bind = vk::SparseImageMemoryBindInfo::builder()
+            .image(self.image)
+            .binds(binds)
+            .build();
+
+        let bind_info = vk::BindSparseInfo::builder().image_binds(std::slice::from_ref(&image_bind));
+        self.submit(loader, *bind_info)
+    }
+
+    fn submit(&self, loader: &Loader, bind_info: vk::BindSparseInfo) -> Result<()> {
+        unsafe {
+            let fence = loader
+                .device
+                .create_fence(&vk::FenceCreateInfo::default(), None)?;
+
+            let result = loader
+                .device
+                .queue_bind_sparse(self.queue, std::slice::from_ref(&bind_info), fence)
+                .and_then(|_| loader.device.wait_for_fences(&[fence], true, u64::MAX));
+
+            fence.destroy(loader);
+            Ok(result?)
+        }
+    }
+}
+
+fn page_count(size: vk::Extent3D, granularity: vk::Extent3D, mip_level: u32) -> (u32, u32) {
+    let width = (size.width >> mip_level).max(1);
+    let height = (size.height >> mip_level).max(1);
+
+    (
+        width.div_ceil(granularity.width),
+        height.div_ceil(granularity.height),
+    )
+}
+
+/// Texels covered by `page`, clamped to the edge of its mip level. `None` for pages outside of it
+fn page_region(
+    size: vk::Extent3D,
+    granularity: vk::Extent3D,
+    page: &SparsePage,
+) -> Option<(vk::Offset3D, vk::Extent3D)> {
+    let (columns, rows) = page_count(size, granularity, page.mip_level);
+    if page.x >= columns || page.y >= rows {
+        return None;
+    }
+
+    let mip_width = (size.width >> page.mip_level).max(1);
+    let mip_height = (size.height >> page.mip_level).max(1);
+    let x = page.x * granularity.width;
+    let y = page.y * granularity.height;
+
+    Some((
+        vk::Offset3D {
+            x: x as i32,
+            y: y as i32,
+            z: 0,
+        },
+        vk::Extent3D {
+            width: granularity.width.min(mip_width - x),
+            height: granularity.height.min(mip_height - y),
+            depth: 1,
+        },
+    ))
+}
+
+/// Memory backing a single page, one sparse block (the image's alignment) when the texel size of
+/// the format isn't known
+fn page_size(granularity: vk::Extent3D, texel_size: Option<u64>, alignment: u64) -> u64 {
+    let texels = granularity.width as u64 * granularity.height as u64 * granularity.depth as u64;
+    match texel_size {
+        Some(texel_size) => (texels * texel_size).div_ceil(alignment) * alignment,
+        None => alignment,
+    }
+}
+
+/// Bytes per texel of uncompressed color formats
+fn texel_size(format: vk::Format) -> Option<u64> {
+    match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => Some(1),
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB | vk::Format::R16_SFLOAT => Some(2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: vk::Extent3D = vk::Extent3D {
+        width: 300,
+        height: 200,
+        depth: 1,
+    };
+    const GRANULARITY: vk::Extent3D = vk::Extent3D {
+        width: 128,
+        height: 128,
+        depth: 1,
+    };
+
+    fn page(mip_level: u32, x: u32, y: u32) -> SparsePage {
+        SparsePage { mip_level, x, y }
+    }
+
+    #[test]
+    fn interior_pages_cover_the_granularity() {
+        let (offset, extent) = page_region(SIZE, GRANULARITY, &page(0, 1, 0)).unwrap();
+        assert_eq!((offset.x, offset.y), (128, 0));
+        assert_eq!((extent.width, extent.height), (128, 128));
+    }
+
+    #[test]
+    fn edge_pages_are_clamped_to_the_mip() {
+        let (offset, extent) = page_region(SIZE, GRANULARITY, &page(0, 2, 1)).unwrap();
+        assert_eq!((offset.x, offset.y), (256, 128));
+        assert_eq!((extent.width, extent.height), (44, 72));
+
+        let (_, extent) = page_region(SIZE, GRANULARITY, &page(2, 0, 0)).unwrap();
+        assert_eq!((extent.width, extent.height), (75, 50));
+    }
+
+    #[test]
+    fn pages_outside_the_mip_are_rejected() {
+        assert_eq!(page_count(SIZE, GRANULARITY, 0), (3, 2));
+        assert!(page_region(SIZE, GRANULARITY, &page(0, 3, 0)).is_none());
+        assert!(page_region(SIZE, GRANULARITY, &page(0, 0, 2)).is_none());
+        assert!(page_region(SIZE, GRANULARITY, &page(1, 2, 0)).is_none());
+    }
+
+    #[test]
+    fn page_size_is_one_rounded_block() {
+        assert_eq!(page_size(GRANULARITY, Some(4), 0x10000), 0x10000);
+        assert_eq!(page_size(GRANULARITY, Some(8), 0x10000), 0x20000);
+        assert_eq!(page_size(GRANULARITY, Some(1), 0x10000), 0x10000);
+        assert_eq!(page_size(GRANULARITY, None, 0x10000), 0x10000);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::collections::{Parity, ParitySet};
+use crate::error::{Result, SiltError};
+use crate::{id, prelude::*};
+use std::cell::Cell;
+
+use super::{Buffer, BufferCreateInfo};
+
+#[derive(Debug, Clone)]
+pub struct TransientBufferCreateInfo {
+    /// Bytes available to each frame in flight
+    pub size: vk::DeviceSize,
+    pub name: Identifier,
+    pub usage: vk::BufferUsageFlags,
+    /// Minimum offset alignment of every allocation, e.g. `min_uniform_buffer_offset_alignment`
+    /// when the data is bound as a dynamic uniform buffer
+    pub alignment: vk::DeviceSize,
+}
+
+impl Default for TransientBufferCreateInfo {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            name: id!("Transient Buffer"),
+            usage: vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER,
+            alignment: 4,
+        }
+    }
+}
+
+/// Per frame scratch memory for immediate mode data, e.g. UI or debug geometry. Each parity
+/// has its own persistently mapped `CpuToGpu` buffer, bump allocated by [`TransientBuffer::push`]
+/// and rewound by [`TransientBuffer::reset`], so writing one frame never clobbers the other
+#[derive(Debug)]
+pub struct TransientBuffer {
+    buffers: ParitySet<Buffer>,
+    heads: ParitySet<Cell<vk::DeviceSize>>,
+    alignment: vk::DeviceSize,
+}
+
+impl Destructible for TransientBuffer {
+    fn destroy(self, loader: &Loader) {
+        self.buffers.into_iter().destroy(loader);
+    }
+}
+
+impl TransientBuffer {
+    pub fn new(loader: &Loader, create_info: TransientBufferCreateInfo) -> Result<Self> {
+        if create_info.size == 0 {
+            return Err(SiltError::InvalidUsage(
+                "transient buffer size must be non zero".into(),
+            ));
+        }
+
+        if !create_info.alignment.is_power_of_two() {
+            return Err(SiltError::InvalidUsage(format!(
+                "transient buffer alignment {} is not a power of two",
+                create_info.alignment
+            )));
+        }
+
+        let buffer_ci = BufferCreateInfo {
+            size: create_info.size,
+            name: create_info.name,
+            usage: create_info.usage,
+            location: vk::MemoryLocation::CpuToGpu,
+            ..Default::default()
+        };
+
+        let even = Buffer::new(loader, buffer_ci.clone())?;
+        let odd = match Buffer::new(loader, buffer_ci) {
+            Ok(odd) => odd,
+            Err(err) => {
+                even.destroy(loader);
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            buffers: ParitySet::new(even, odd),
+            heads: ParitySet::from_fn(Default::default),
+            alignment: create_info.alignment,
+        })
+    }
+
+    /// Frees every allocation made for `parity`. Call after waiting on that frame's fence
+    pub fn reset(&self, parity: Parity) {
+        self.heads.get(parity).set(0);
+    }
+
+    /// Copies `data` into the frame's region, returning the buffer and offset to bind it at,
+    /// e.g. with `cmd_bind_vertex_buffers`. Valid until the next [`TransientBuffer::reset`] of `parity`
+    pub fn push<T: Copy>(
+        &self,
+        loader: &Loader,
+        parity: Parity,
+        data: &[T],
+    ) -> Result<(vk::Buffer, vk::DeviceSize)> {
+        let buffer = self.buffers.get(parity);
+        let head = self.heads.get(parity);
+
+        let alignment = self.alignment.max(std::mem::align_of::<T>() as u64);
+        let offset = (head.get() + alignment - 1) & !(alignment - 1);
+        let size = std::mem::size_of_val(data) as u64;
+
+        if offset + size > buffer.size {
+            return Err(SiltError::InvalidUsage(format!(
+                "transient allocation of {} bytes exceeds the {} bytes left this frame",
+                size,
+                buffer.size.saturating_sub(offset)
+            )));
+        }
+
+        let ptr = loader.allocator.get_mapped_ptr(buffer.allocation)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                (ptr.as_ptr() as *mut u8).add(offset as usize),
+                size as usize,
+            );
+        }
+
+        head.set(offset + size);
+        Ok((buffer.buffer, offset))
+    }
+
+    /// Bytes allocated for `parity` since its last reset
+    pub fn used(&self, parity: Parity) -> vk::DeviceSize {
+        self.heads.get(parity).get()
+    }
+
+    pub fn capacity(&self) -> vk::DeviceSize {
+        self.buffers.even.size
+    }
+}
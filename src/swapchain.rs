@@ -1,36 +1,168 @@
-use anyhow::{Result};
+use anyhow::{anyhow, Result};
 use itertools::{izip, Itertools};
+use std::cell::Cell;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
 
+use crate::error::SiltError;
 use crate::loader::Loader;
+use crate::pipeline::{build_present_pass, ViewportConvention};
 use crate::prelude::*;
 use crate::properties::get_sample_counts;
-use crate::resources::{Image, get_surface_format, ImageCreateInfo, get_depth_format};
+use crate::resources::{
+    format_aspect, format_features_for_usage, get_depth_format, get_depth_stencil_format,
+    get_format_properties, get_surface_format, supports_linear_blit, Buffer, BufferCreateInfo,
+    Image, ImageCreateInfo,
+};
+use crate::sync::{CommandPool, RecordList};
 
+#[derive(Debug, Clone, Copy)]
+pub struct SwapchainCreateInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Creates a depth attachment alongside the swapchain images, recreated
+    /// with the swapchain. Disable when the render pass manages its own depth
+    pub manage_depth: bool,
+    /// Picks a managed depth format with a stencil aspect, see [`get_depth_stencil_format`].
+    /// The render pass must use the same format
+    pub depth_stencil: bool,
+    /// Fraction of the window resolution to render at. Anything other than 1 renders
+    /// into an intermediate target, upscaled with [`Swapchain::record_upscale`]
+    pub render_scale: f32,
+    /// Used by [`Swapchain::record_viewport`], validated on creation
+    pub viewport_convention: ViewportConvention,
+    /// `PRE_MULTIPLIED` or `POST_MULTIPLIED` let the compositor blend a transparent window.
+    /// Must be in the surface's `supported_composite_alpha`
+    pub composite_alpha: vk::CompositeAlphaFlagsKHR,
+    /// Usage of the swapchain images, e.g. `STORAGE` for compute shaders writing them directly,
+    /// see [`Swapchain::record_compute_target`]. `COLOR_ATTACHMENT` is needed to render to them
+    /// with a present pass unless render scaling. Must be in the surface's `supported_usage_flags`
+    pub swapchain_usage: vk::ImageUsageFlags,
+}
+
+impl Default for SwapchainCreateInfo {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            manage_depth: true,
+            depth_stencil: false,
+            render_scale: 1.,
+            viewport_convention: Default::default(),
+            composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            swapchain_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        }
+    }
+}
+
+/// Identifies a present of one swapchain for [`Swapchain::wait_present`], counting up from 1
+pub type PresentId = u64;
+
+/// Index of a swapchain image, returned by [`Swapchain::acquire`]. Selects the per image
+/// resources, the [`SwapFrame`]s, while per frame in flight resources such as command buffers,
+/// fences and descriptor sets are selected by a [`crate::collections::FrameIndex`]. The two
+/// advance independently, and there are usually more images than frames in flight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ImageIndex(u32);
+
+impl ImageIndex {
+    /// For indices from calling `acquire_next_image` directly, prefer [`Swapchain::acquire`]
+    pub fn from_raw(index: u32) -> Self {
+        Self(index)
+    }
+
+    pub fn as_raw(self) -> u32 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for ImageIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Result of [`Swapchain::acquire`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapchainStatus {
+    /// Index of the acquired image, and whether the swapchain is suboptimal
+    Acquired(ImageIndex, bool),
+    /// The swapchain no longer matches the surface, recreate it before acquiring again
+    OutOfDate,
+    /// The window has no area. Nothing was acquired, and nothing should be recorded or
+    /// recreated until it's restored, at which point acquiring reports [`SwapchainStatus::OutOfDate`]
+    Minimized,
+}
+
+/// Resources owned by a single swapchain image. Indexed by the acquired image index,
+/// not by the frame in flight
 #[derive(Debug, Clone)]
 pub struct SwapFrame {
     pub image: vk::Image,
     pub view: vk::ImageView,
+    pub depth_view: Option<vk::ImageView>,
+    /// Null when the swapchain was created without a present pass
     pub framebuffer: vk::Framebuffer,
+    /// Signaled by the submission rendering to this image and waited on by [`Swapchain::present`].
+    /// This has to be per image, a presentation can still be waiting on the semaphore after the
+    /// frame in flight that signaled it has been reused
+    pub render_finished: vk::Semaphore,
 }
 
 #[derive(Debug, Clone)]
 pub struct Swapchain {
     pub swapchain: vk::SwapchainKHR,
     pub extent: vk::Extent2D,
-    pub depth: Image,
+    /// Extent of the framebuffers, `extent` scaled by the render scale
+    pub render_extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+    pub depth: Option<Image>,
     pub color: Image,
-    pub frames: Vec<SwapFrame>,
+    /// Resolve target at `render_extent`, blitted to the swapchain image when scaling.
+    /// Left in `TRANSFER_SRC_OPTIMAL` by the present pass
+    pub scaled: Option<Image>,
+    /// Indexed by [`ImageIndex`], see [`Swapchain::frame`]
+    frames: Vec<SwapFrame>,
+    /// Swapchain replaced by [`Swapchain::recreate`], kept alive until this one has presented.
+    /// Resizing again before that chains the older ones through its own `retired`
+    retired: Option<Box<Swapchain>>,
+    /// Id of the latest present, 0 before the first
+    presents: Cell<PresentId>,
+    /// When the latest present was queued, and the smoothed interval between presents.
+    /// Used to estimate display times without `VK_KHR_present_wait`
+    present_timing: Cell<Option<(Instant, Duration)>>,
+    surface: vk::SurfaceKHR,
+    pdevice: vk::PhysicalDevice,
+    present_pass: vk::RenderPass,
+    /// Compatible with `present_pass`, but leaving `scaled` in `TRANSFER_SRC_OPTIMAL` rather than
+    /// `PRESENT_SRC_KHR`. Only created when scaling with a present pass
+    scaled_pass: Option<vk::RenderPass>,
+    create_info: SwapchainCreateInfo,
 }
 
 impl Swapchain {
+    /// `present_pass` may be null when nothing renders to the swapchain with a render pass, e.g. when
+    /// compute shaders write it directly. No framebuffers are created then
     pub unsafe fn new(
         loader: &Loader,
         surface: vk::SurfaceKHR,
         pdevice: vk::PhysicalDevice,
         present_pass: vk::RenderPass,
-        width: u32,
-        height: u32,
+        create_info: SwapchainCreateInfo,
+    ) -> Result<Self> {
+        Self::create(loader, surface, pdevice, present_pass, create_info, vk::SwapchainKHR::null())
+    }
+
+    unsafe fn create(
+        loader: &Loader,
+        surface: vk::SurfaceKHR,
+        pdevice: vk::PhysicalDevice,
+        present_pass: vk::RenderPass,
+        create_info: SwapchainCreateInfo,
+        old_swapchain: vk::SwapchainKHR,
     ) -> Result<Self> {
+        let SwapchainCreateInfo { width, height, .. } = create_info;
         let msaa_samples = get_sample_counts(loader, pdevice);
         let surface_capabilities = loader
             .surface
@@ -63,6 +195,90 @@ impl Swapchain {
             surface_capabilities.current_transform
         };
 
+        create_info.viewport_convention.validate(loader)?;
+
+        if create_info.composite_alpha.as_raw().count_ones() != 1
+            || !surface_capabilities
+                .supported_composite_alpha
+                .contains(create_info.composite_alpha)
+        {
+            return Err(anyhow!(
+                "composite alpha {:?} unsupported by the surface, supported modes are {:?}",
+                create_info.composite_alpha,
+                surface_capabilities.supported_composite_alpha
+            ));
+        }
+
+        let scaling = create_info.render_scale != 1.;
+        if !(create_info.render_scale > 0. && create_info.render_scale <= 1.) {
+            return Err(anyhow!(
+                "render scale must be in (0, 1], got {}",
+                create_info.render_scale
+            ));
+        }
+
+        if scaling
+            && (!surface_capabilities
+                .supported_usage_flags
+                .contains(vk::ImageUsageFlags::TRANSFER_DST)
+                || !supports_linear_blit(&loader.instance, pdevice, surface_format.format))
+        {
+            return Err(anyhow!(
+                "swapchain format {:?} does not support linear blits, render scaling is unavailable",
+                surface_format.format
+            ));
+        }
+
+        let render_extent = vk::Extent2D {
+            width: ((extent.width as f32 * create_info.render_scale) as u32).max(1),
+            height: ((extent.height as f32 * create_info.render_scale) as u32).max(1),
+        };
+
+        if !surface_capabilities
+            .supported_usage_flags
+            .contains(create_info.swapchain_usage)
+        {
+            return Err(anyhow!(
+                "swapchain usage {:?} unsupported by the surface, supported usage is {:?}",
+                create_info.swapchain_usage,
+                surface_capabilities.supported_usage_flags
+            ));
+        }
+
+        let required_features = format_features_for_usage(create_info.swapchain_usage);
+        if !get_format_properties(&loader.instance, pdevice, surface_format.format)
+            .optimal_tiling_features
+            .contains(required_features)
+        {
+            return Err(anyhow!(
+                "swapchain format {:?} does not support usage {:?}",
+                surface_format.format,
+                create_info.swapchain_usage
+            ));
+        }
+
+        // Without scaling the present pass resolves straight into the swapchain image
+        if present_pass != vk::RenderPass::null()
+            && !scaling
+            && !create_info
+                .swapchain_usage
+                .contains(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        {
+            return Err(anyhow!(
+                "swapchain usage {:?} lacks COLOR_ATTACHMENT, which the present pass renders to",
+                create_info.swapchain_usage
+            ));
+        }
+
+        // Transfer src allows for frame captures, but isn't guaranteed to be supported
+        let usage = create_info.swapchain_usage
+            | (surface_capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC)
+            | if scaling {
+                vk::ImageUsageFlags::TRANSFER_DST
+            } else {
+                vk::ImageUsageFlags::empty()
+            };
+
         let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(surface)
             .image_extent(extent)
@@ -71,10 +287,11 @@ impl Swapchain {
             .image_format(surface_format.format)
             .present_mode(present_mode)
             .pre_transform(pre_transform)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(usage)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .composite_alpha(create_info.composite_alpha)
             .clipped(true)
+            .old_swapchain(old_swapchain)
             .image_array_layers(1);
 
         let swapchain = loader
@@ -111,8 +328,8 @@ impl Swapchain {
             .collect::<Vec<_>>();
 
         let color_image_ci = ImageCreateInfo {
-            width: extent.width,
-            height: extent.height,
+            width: render_extent.width,
+            height: render_extent.height,
             format: surface_format.format,
             samples: msaa_samples,
             tiling: vk::ImageTiling::OPTIMAL,
@@ -123,35 +340,81 @@ impl Swapchain {
 
         let color = Image::new(loader, color_image_ci)?;
 
-        let depth_format = get_depth_format(
-            &loader.instance,
-            pdevice
-        )
-        .unwrap();
+        let depth_format = if create_info.manage_depth {
+            let depth_format = match create_info.depth_stencil {
+                true => get_depth_stencil_format(&loader.instance, pdevice)
+                    .ok_or(anyhow!("no supported depth stencil format"))?,
+                false => get_depth_format(&loader.instance, pdevice)
+                    .ok_or(anyhow!("no supported depth format"))?,
+            };
+            Some(depth_format)
+        } else {
+            None
+        };
+
+        let depth = if let Some(depth_format) = depth_format {
+            let depth_image_ci = ImageCreateInfo {
+                width: render_extent.width,
+                height: render_extent.height,
+                format: depth_format,
+                samples: msaa_samples,
+                tiling: vk::ImageTiling::OPTIMAL,
+                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                view_aspect: format_aspect(depth_format),
+                ..Default::default()
+            };
 
-        let depth_image_ci = ImageCreateInfo {
-            width: extent.width,
-            height: extent.height,
-            format: depth_format,
-            samples: msaa_samples,
-            tiling: vk::ImageTiling::OPTIMAL,
-            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-            view_aspect: vk::ImageAspectFlags::DEPTH,
-            ..Default::default()
+            Some(Image::new(loader, depth_image_ci)?)
+        } else {
+            None
+        };
+
+        let depth_view = depth.as_ref().map(|depth| depth.view);
+
+        let scaled = if scaling {
+            let scaled_image_ci = ImageCreateInfo {
+                width: render_extent.width,
+                height: render_extent.height,
+                format: surface_format.format,
+                tiling: vk::ImageTiling::OPTIMAL,
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                ..Default::default()
+            };
+
+            Some(Image::new(loader, scaled_image_ci)?)
+        } else {
+            None
         };
 
-        let depth = Image::new(loader, depth_image_ci)?;
+        let scaled_pass = (scaling && present_pass != vk::RenderPass::null()).then(|| {
+            build_present_pass(
+                loader,
+                surface_format.format,
+                depth_format,
+                msaa_samples,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )
+        });
+        let framebuffer_pass = scaled_pass.unwrap_or(present_pass);
 
         let framebuffers = image_views
             .iter()
-            .map(|image_view| {
-                let attachments = [color.view, depth.view, *image_view];
+            .map(|&image_view| {
+                if present_pass == vk::RenderPass::null() {
+                    return vk::Framebuffer::null();
+                }
+
+                let resolve_view = scaled.as_ref().map_or(image_view, |scaled| scaled.view);
+                let attachments = std::iter::once(color.view)
+                    .chain(depth_view)
+                    .chain(std::iter::once(resolve_view))
+                    .collect_vec();
 
                 let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
-                    .render_pass(present_pass)
+                    .render_pass(framebuffer_pass)
                     .attachments(&attachments)
-                    .width(extent.width)
-                    .height(extent.height)
+                    .width(render_extent.width)
+                    .height(render_extent.height)
                     .layers(1);
 
                 loader
@@ -162,33 +425,629 @@ impl Swapchain {
             .collect_vec();
 
         let frames = izip!(images, image_views, framebuffers)
-            .map(|(image, view, framebuffer)| SwapFrame {
-                image,
-                view,
-                framebuffer,
+            .map(|(image, view, framebuffer)| {
+                Ok(SwapFrame {
+                    image,
+                    view,
+                    depth_view,
+                    framebuffer,
+                    render_finished: loader
+                        .device
+                        .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?,
+                })
             })
-            .collect_vec();
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(Self {
             swapchain,
             extent,
+            render_extent,
+            format: surface_format.format,
+            usage,
             depth,
             color,
+            scaled,
             frames,
+            retired: None,
+            presents: Cell::new(0),
+            present_timing: Cell::new(None),
+            surface,
+            pdevice,
+            present_pass,
+            scaled_pass,
+            create_info,
         })
     }
 
+    /// Sets the dynamic viewport to the render extent with the configured [`ViewportConvention`]
+    pub fn record_viewport(&self, loader: &Loader, command_buffer: vk::CommandBuffer) {
+        crate::pipeline::cmd_set_viewport(
+            loader,
+            command_buffer,
+            self.render_extent,
+            self.create_info.viewport_convention,
+        );
+    }
+
+    /// Render pass the framebuffers were created for, null for compute only presentation.
+    /// When scaling this is a pass compatible with the one given on creation, which pipelines can
+    /// still be created against
+    pub fn present_pass(&self) -> vk::RenderPass {
+        self.scaled_pass.unwrap_or(self.present_pass)
+    }
+
+    /// Number of images the presentation engine actually created, which is independent
+    /// of the number of frames in flight
+    pub fn image_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Resources of the swapchain image `index`
+    pub fn frame(&self, index: ImageIndex) -> Result<&SwapFrame> {
+        self.frames
+            .get(index.0 as usize)
+            .ok_or(anyhow!("image index {} out of range", index))
+    }
+
+    /// Every swapchain image, e.g. to create per image resources alongside them
+    pub fn frames(&self) -> impl Iterator<Item = (ImageIndex, &SwapFrame)> {
+        (0..).map(ImageIndex).zip(&self.frames)
+    }
+
+    /// Whether the window currently has no area, e.g. while minimized. A swapchain can't be
+    /// created with a zero extent, so rendering has to wait until it's restored
+    pub fn is_minimized(loader: &Loader) -> bool {
+        let size = loader.window.inner_size();
+        size.width == 0 || size.height == 0
+    }
+
+    /// Acquires the next image, unless the window is minimized.
+    /// `image_available` should belong to the current frame in flight, and is only
+    /// signaled for [`SwapchainStatus::Acquired`]
+    ///
+    /// # Safety
+    ///
+    /// `image_available` must be unsignaled, with no pending signal operation
+    pub unsafe fn acquire(
+        &self,
+        loader: &Loader,
+        image_available: vk::Semaphore,
+    ) -> Result<SwapchainStatus> {
+        if Self::is_minimized(loader) {
+            return Ok(SwapchainStatus::Minimized);
+        }
+
+        let result = loader.swapchain.acquire_next_image(
+            self.swapchain,
+            u64::MAX,
+            image_available,
+            vk::Fence::null(),
+        );
+
+        match Loader::check(result) {
+            Ok((image_index, suboptimal)) => {
+                Ok(SwapchainStatus::Acquired(ImageIndex(image_index), suboptimal))
+            }
+            Err(SiltError::Vk(vk::Result::ERROR_OUT_OF_DATE_KHR)) => Ok(SwapchainStatus::OutOfDate),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Presents `image_index`, waiting on that image's [`SwapFrame::render_finished`].
+    /// Returns the id to pass to [`Swapchain::wait_present`], and whether the swapchain is suboptimal
+    ///
+    /// # Safety
+    ///
+    /// `image_index` must have been acquired, and the submission rendering to it must signal its
+    /// [`SwapFrame::render_finished`]. `queue` must support presenting to the surface
+    pub unsafe fn present(
+        &self,
+        loader: &Loader,
+        queue: vk::Queue,
+        image_index: ImageIndex,
+    ) -> Result<(PresentId, bool)> {
+        let frame = self.frame(image_index)?;
+
+        let id = self.presents.get() + 1;
+        let mut present_id =
+            vk::PresentIdKHR::builder().present_ids(std::slice::from_ref(&id));
+
+        let mut present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(std::slice::from_ref(&frame.render_finished))
+            .swapchains(std::slice::from_ref(&self.swapchain))
+            .image_indices(std::slice::from_ref(&image_index.0));
+
+        if loader.present_wait.is_some() {
+            present_info = present_info.push_next(&mut present_id);
+        }
+
+        let suboptimal = Loader::check(loader.swapchain.queue_present(queue, &present_info))?;
+        self.presents.set(id);
+
+        let now = Instant::now();
+        let timing = match self.present_timing.get() {
+            Some((last, interval)) => (now, (interval * 7 + (now - last)) / 8),
+            None => (now, Duration::ZERO),
+        };
+        self.present_timing.set(Some(timing));
+
+        Ok((id, suboptimal))
+    }
+
+    /// Blocks until present `id` is displayed, or `timeout` passes, returning whether it was displayed.
+    ///
+    /// With [`crate::properties::DeviceFeatures::PRESENT_WAIT`] this is reported by the presentation
+    /// engine. Without it, it's estimated from when the present was queued plus the average interval
+    /// between presents, which can't see compositor latency or dropped frames
+    pub fn wait_present(&self, loader: &Loader, id: PresentId, timeout: Duration) -> Result<bool> {
+        if id == 0 || id > self.presents.get() {
+            return Err(anyhow!("present {} was never queued on this swapchain", id));
+        }
+
+        if let Some(present_wait) = &loader.present_wait {
+            let timeout = timeout.as_nanos().min(u64::MAX as u128) as u64;
+            let result = unsafe { present_wait.wait_for_present(self.swapchain, id, timeout) };
+            return match Loader::check(result) {
+                Ok(()) => Ok(true),
+                Err(SiltError::Vk(vk::Result::TIMEOUT)) => Ok(false),
+                Err(err) => Err(err.into()),
+            };
+        }
+
+        // Older presents are assumed to be on screen already
+        let Some((queued, interval)) = self.present_timing.get() else {
+            return Ok(true);
+        };
+        if id < self.presents.get() {
+            return Ok(true);
+        }
+
+        let remaining = (queued + interval).saturating_duration_since(Instant::now());
+        std::thread::sleep(remaining.min(timeout));
+        Ok(remaining <= timeout)
+    }
+
+    /// Rebuilds the swapchain, and the depth attachment if managed, at a new size.
+    /// The old swapchain is handed to the new one as `old_swapchain` and retired rather
+    /// than destroyed, so no device idle is needed. Retired swapchains may still have
+    /// presents pending, see [`Swapchain::release_retired`] for when they're destroyed
+    /// Fails for a zero extent, wait until [`Swapchain::is_minimized`] is false
+    ///
+    /// # Safety
+    ///
+    /// Frames of the old swapchain must not be recorded into afterwards. Commands already
+    /// submitted may keep using them until [`Swapchain::release_retired`] hands them off
+    pub unsafe fn recreate(&mut self, loader: &Loader, width: u32, height: u32) -> Result<()> {
+        if width == 0 || height == 0 {
+            return Err(anyhow!(
+                "can't recreate the swapchain at {}x{}, the window is minimized",
+                width,
+                height
+            ));
+        }
+
+        let create_info = SwapchainCreateInfo {
+            width,
+            height,
+            ..self.create_info
+        };
+
+        let new = Self::create(
+            loader,
+            self.surface,
+            self.pdevice,
+            self.present_pass,
+            create_info,
+            self.swapchain,
+        )?;
+        let old = std::mem::replace(self, new);
+        self.retired = Some(Box::new(old));
+
+        Ok(())
+    }
+
+    /// Changes the render scale, recreating the swapchain the same way as [`Swapchain::recreate`]
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Swapchain::recreate`]
+    pub unsafe fn set_render_scale(&mut self, loader: &Loader, render_scale: f32) -> Result<()> {
+        self.create_info.render_scale = render_scale;
+        self.recreate(loader, self.create_info.width, self.create_info.height)
+    }
+
+    /// Call after each present. Once the current swapchain has presented, retired swapchains
+    /// are pushed onto `deletion` under `frame`, and destroyed once that frame's fence has signaled
+    pub fn release_retired<K: Hash + Eq>(&mut self, deletion: &DeletionQueue<K>, frame: K) {
+        if self.presents.get() == 0 {
+            return;
+        }
+
+        if let Some(retired) = self.retired.take() {
+            deletion.push(frame, *retired);
+        }
+    }
+
+    /// Upscales the rendered frame into the swapchain image `image_index` with a linear blit.
+    /// Record after the present pass has ended, does nothing when not scaling
+    pub fn record_upscale(&self, loader: &Loader, command_buffer: vk::CommandBuffer, image_index: ImageIndex) {
+        let (Some(scaled), Ok(frame)) = (&self.scaled, self.frame(image_index)) else {
+            return;
+        };
+
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let barrier = |image, old_layout, new_layout, src_access, dst_access| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .image(image)
+                .subresource_range(subresource_range)
+                .build()
+        };
+
+        // The present pass already leaves its resolve target in TRANSFER_SRC_OPTIMAL
+        let to_transfer = [
+            barrier(
+                scaled.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            barrier(
+                frame.image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        ];
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let corner = |extent: vk::Extent2D| vk::Offset3D {
+            x: extent.width as i32,
+            y: extent.height as i32,
+            z: 1,
+        };
+
+        let blit = vk::ImageBlit::builder()
+            .src_subresource(subresource)
+            .src_offsets([vk::Offset3D::default(), corner(self.render_extent)])
+            .dst_subresource(subresource)
+            .dst_offsets([vk::Offset3D::default(), corner(self.extent)]);
+
+        let to_present = barrier(
+            frame.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::empty(),
+        );
+
+        unsafe {
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer,
+            );
+
+            loader.device.cmd_blit_image(
+                command_buffer,
+                scaled.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                frame.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                std::slice::from_ref(&blit),
+                vk::Filter::LINEAR,
+            );
+
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&to_present),
+            );
+        }
+    }
+
+    /// Moves the swapchain image `image_index` to `GENERAL` for compute shaders to write, discarding
+    /// its contents. Requires `STORAGE` in [`SwapchainCreateInfo::swapchain_usage`], follow the
+    /// dispatch with [`Swapchain::record_compute_present`]. The acquire semaphore must be waited on
+    /// at `COLOR_ATTACHMENT_OUTPUT`, as [`crate::sync::FrameScheduler`] does
+    pub fn record_compute_target(&self, loader: &Loader, command_buffer: vk::CommandBuffer, image_index: ImageIndex) {
+        let Ok(frame) = self.frame(image_index) else {
+            return;
+        };
+
+        // Sourced from the stage the acquire semaphore is waited on, so the transition and the
+        // writes after it are ordered after the presentation engine is done reading the image
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(Layout::ComputeReadWrite.get_layout())
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(Layout::ComputeReadWrite.get_access())
+            .image(frame.image)
+            .subresource_range(SubresourceRange::mip(0).with_aspect(vk::ImageAspectFlags::COLOR));
+
+        unsafe {
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                Layout::ComputeReadWrite.get_pipeline_stage(),
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&barrier),
+            )
+        };
+    }
+
+    /// Moves the swapchain image `image_index` from `GENERAL` to `PRESENT_SRC_KHR` once compute
+    /// shaders have written it, in place of the final layout of a present pass
+    pub fn record_compute_present(&self, loader: &Loader, command_buffer: vk::CommandBuffer, image_index: ImageIndex) {
+        let Ok(frame) = self.frame(image_index) else {
+            return;
+        };
+
+        record_layout_transition(
+            loader,
+            command_buffer,
+            frame.image,
+            vk::ImageAspectFlags::COLOR,
+            Layout::ComputeReadWrite,
+            Layout::Present,
+            SubresourceRange::mip(0),
+        );
+    }
+
+    /// Reads back a presented swapchain image. The image must not be in use by
+    /// any pending command buffers, and is returned to `PRESENT_SRC_KHR` afterwards
+    pub fn capture_frame(
+        &self,
+        loader: &Loader,
+        pool: &CommandPool,
+        image_index: ImageIndex,
+    ) -> Result<image::RgbaImage> {
+        let frame = self.frame(image_index)?;
+
+        if !self.usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            return Err(anyhow!("swapchain images do not support transfer src usage"));
+        }
+
+        let swizzle = match self.format {
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => true,
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => false,
+            format => {
+                return Err(anyhow!(
+                    "capturing swapchain format {:?} is not supported, only 8 bit rgba/bgra formats can be captured",
+                    format
+                ))
+            }
+        };
+
+        let size = self.extent.width as u64 * self.extent.height as u64 * 4;
+        let buffer_ci = BufferCreateInfo {
+            size,
+            name: NULL_ID.clone(),
+            usage: vk::BufferUsageFlags::TRANSFER_DST,
+            location: vk::MemoryLocation::GpuToCpu,
+            ..Default::default()
+        };
+
+        let buffer = Buffer::new(loader, buffer_ci)?;
+
+        let result = pool.execute_one_time_commands(loader, |loader, command_buffer| unsafe {
+            record_layout_transition(
+                loader,
+                command_buffer,
+                frame.image,
+                vk::ImageAspectFlags::COLOR,
+                Layout::Present,
+                Layout::TransferSrc,
+                SubresourceRange::mip(0),
+            );
+
+            let region = vk::BufferImageCopy::builder()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image_extent(vk::Extent3D {
+                    width: self.extent.width,
+                    height: self.extent.height,
+                    depth: 1,
+                });
+
+            loader.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                frame.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                std::slice::from_ref(&region),
+            );
+
+            record_layout_transition(
+                loader,
+                command_buffer,
+                frame.image,
+                vk::ImageAspectFlags::COLOR,
+                Layout::TransferSrc,
+                Layout::Present,
+                SubresourceRange::mip(0),
+            );
+        });
+
+        let pixels = result.and_then(|_| {
+            let ptr = loader.allocator.get_mapped_ptr(buffer.allocation)?;
+            let mut pixels = unsafe {
+                std::slice::from_raw_parts(ptr.as_ptr() as *const u8, size as usize)
+            }
+            .to_vec();
+
+            if swizzle {
+                pixels.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+            }
+
+            Ok(pixels)
+        });
+
+        buffer.destroy(loader);
+
+        image::RgbaImage::from_raw(self.extent.width, self.extent.height, pixels?)
+            .ok_or(anyhow!("captured frame did not match the swapchain extent"))
+    }
+
     pub unsafe fn cleanup(self, loader: &Loader) -> Result<()> {
-        loader.device.device_wait_idle()?;
+        loader.wait_idle()?;
         self.destroy(loader);
         Ok(())
     }
 }
 
+/// Brackets the draws of a frame in the swapchain's present pass. [`PresentPass::begin`] starts it
+/// on the acquired image's framebuffer and covers the render extent with the viewport and scissor
+///
+/// ```ignore
+/// let pass = PresentPass::new(&swapchain);
+/// pass.begin(&loader, command_buffer, &swapchain, image_index)?;
+/// list.record(&loader, command_buffer);
+/// pass.end(&loader, command_buffer);
+/// ```
+#[derive(Clone)]
+pub struct PresentPass {
+    pub render_pass: vk::RenderPass,
+    /// One per framebuffer attachment: color, then depth if managed, then the resolve target
+    pub clear_values: Vec<vk::ClearValue>,
+}
+
+impl PresentPass {
+    /// Clears color to transparent black and depth to 1, see [`PresentPass::with_clear_values`]
+    pub fn new(swapchain: &Swapchain) -> Self {
+        let color = vk::ClearValue {
+            color: vk::ClearColorValue { float32: [0.; 4] },
+        };
+        let depth = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.,
+                stencil: 0,
+            },
+        };
+
+        Self {
+            render_pass: swapchain.present_pass(),
+            clear_values: std::iter::once(color)
+                .chain(swapchain.depth.as_ref().map(|_| depth))
+                .chain(std::iter::once(color))
+                .collect(),
+        }
+    }
+
+    /// E.g. from [`crate::material::MaterialSystem::get_effect_clear_values`]
+    pub fn with_clear_values(self, clear_values: Vec<vk::ClearValue>) -> Self {
+        Self {
+            clear_values,
+            ..self
+        }
+    }
+
+    /// Begins the render pass on the framebuffer of `image_index`, and sets the viewport and
+    /// scissor to the swapchain's render extent
+    pub fn begin(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        swapchain: &Swapchain,
+        image_index: ImageIndex,
+    ) -> Result<()> {
+        // The swapchain's pass rather than `render_pass`, which is stale after a render scale change
+        let render_pass = swapchain.present_pass();
+        if render_pass == vk::RenderPass::null() {
+            return Err(anyhow!("swapchain was created without a present pass"));
+        }
+
+        let frame = swapchain.frame(image_index)?;
+
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: swapchain.render_extent,
+        };
+
+        let begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(frame.framebuffer)
+            .render_area(render_area)
+            .clear_values(&self.clear_values);
+
+        unsafe {
+            loader.device.cmd_begin_render_pass(
+                command_buffer,
+                &begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            swapchain.record_viewport(loader, command_buffer);
+            loader
+                .device
+                .cmd_set_scissor(command_buffer, 0, std::slice::from_ref(&render_area));
+        }
+
+        Ok(())
+    }
+
+    pub fn end(&self, loader: &Loader, command_buffer: vk::CommandBuffer) {
+        unsafe { loader.device.cmd_end_render_pass(command_buffer) };
+    }
+
+    /// Records `list` between [`PresentPass::begin`] and [`PresentPass::end`]
+    pub fn record(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        swapchain: &Swapchain,
+        image_index: ImageIndex,
+        list: &RecordList,
+    ) -> Result<()> {
+        self.begin(loader, command_buffer, swapchain, image_index)?;
+        list.record(loader, command_buffer);
+        self.end(loader, command_buffer);
+        Ok(())
+    }
+}
+
 impl Destructible for SwapFrame {
     fn destroy(self, loader: &Loader) {
         self.framebuffer.destroy(loader);
         self.view.destroy(loader);
+        self.render_finished.destroy(loader);
     }
 }
 
@@ -200,6 +1059,11 @@ impl Destructible for Swapchain {
 
         self.color.destroy(loader);
         self.depth.destroy(loader);
+        self.scaled.destroy(loader);
+        self.scaled_pass.destroy(loader);
         self.swapchain.destroy(loader);
+        if let Some(retired) = self.retired {
+            retired.destroy(loader);
+        }
     }
 }
@@ -0,0 +1,527 @@
+use crate::prelude::*;
+use crate::resources::{Buffer, Image, Layout, SubresourceRange};
+
+use super::QueueHandle;
+
+/// Hands a resource from one queue family to another using a release barrier
+/// recorded on the source queue and a matching acquire barrier recorded on the
+/// destination queue. The two submissions must still be ordered with a semaphore.
+///
+/// If both queues belong to the same family no ownership transfer is needed,
+/// the release records a regular pipeline barrier and the acquire is a no-op
+#[derive(Debug, Clone, Copy)]
+pub struct CrossQueueBarrier {
+    pub src_family: u32,
+    pub dst_family: u32,
+    pub src_stage: vk::PipelineStageFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub src_access: vk::AccessFlags,
+    pub dst_access: vk::AccessFlags,
+}
+
+impl CrossQueueBarrier {
+    pub fn new(src: &QueueHandle, dst: &QueueHandle) -> Self {
+        Self {
+            src_family: src.family,
+            dst_family: dst.family,
+            src_stage: vk::PipelineStageFlags::ALL_COMMANDS,
+            dst_stage: vk::PipelineStageFlags::ALL_COMMANDS,
+            src_access: vk::AccessFlags::MEMORY_WRITE,
+            dst_access: vk::AccessFlags::MEMORY_READ,
+        }
+    }
+
+    pub fn stages(self, src: vk::PipelineStageFlags, dst: vk::PipelineStageFlags) -> Self {
+        Self {
+            src_stage: src,
+            dst_stage: dst,
+            ..self
+        }
+    }
+
+    pub fn access(self, src: vk::AccessFlags, dst: vk::AccessFlags) -> Self {
+        Self {
+            src_access: src,
+            dst_access: dst,
+            ..self
+        }
+    }
+
+    /// Swaps source and destination, for handing the resource back
+    pub fn reversed(self) -> Self {
+        Self {
+            src_family: self.dst_family,
+            dst_family: self.src_family,
+            src_stage: self.dst_stage,
+            dst_stage: self.src_stage,
+            src_access: self.dst_access,
+            dst_access: self.src_access,
+        }
+    }
+
+    pub fn is_ownership_transfer(&self) -> bool {
+        self.src_family != self.dst_family
+    }
+
+    fn families(&self) -> (u32, u32) {
+        if self.is_ownership_transfer() {
+            (self.src_family, self.dst_family)
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        }
+    }
+
+    fn buffer_barrier(&self, buffer: &Buffer) -> vk::BufferMemoryBarrierBuilder<'static> {
+        let (src_family, dst_family) = self.families();
+
+        vk::BufferMemoryBarrier::builder()
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .buffer(buffer.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+    }
+
+    fn image_barrier(&self, image: &Image, new_layout: Layout) -> vk::ImageMemoryBarrierBuilder<'static> {
+        let (src_family, dst_family) = self.families();
+
+        vk::ImageMemoryBarrier::builder()
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .old_layout(image.layout.get().get_layout())
+            .new_layout(new_layout.get_layout())
+            .image(image.image)
+            .subresource_range(SubresourceRange::default().with_aspect(image.aspect()))
+    }
+
+    /// Recorded on a command buffer submitted to the source queue
+    pub fn release_buffer(&self, loader: &Loader, command_buffer: vk::CommandBuffer, buffer: &Buffer) {
+        let (dst_stage, dst_access) = if self.is_ownership_transfer() {
+            (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty())
+        } else {
+            (self.dst_stage, self.dst_access)
+        };
+
+        let barrier = self
+            .buffer_barrier(buffer)
+            .src_access_mask(self.src_access)
+            .dst_access_mask(dst_access);
+
+        unsafe {
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                self.src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                std::slice::from_ref(&barrier),
+                &[],
+            )
+        };
+    }
+
+    /// Recorded on a command buffer submitted to the destination queue
+    pub fn acquire_buffer(&self, loader: &Loader, command_buffer: vk::CommandBuffer, buffer: &Buffer) {
+        if !self.is_ownership_transfer() {
+            return;
+        }
+
+        let barrier = self
+            .buffer_barrier(buffer)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(self.dst_access);
+
+        unsafe {
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                std::slice::from_ref(&barrier),
+                &[],
+            )
+        };
+    }
+
+    /// Recorded on a command buffer submitted to the source queue. The layout
+    /// transition must be identical on both sides, so pass the same `new_layout`
+    /// to [`CrossQueueBarrier::acquire_image`]
+    pub fn release_image(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        image: &Image,
+        new_layout: Layout,
+    ) {
+        let (dst_stage, dst_access) = if self.is_ownership_transfer() {
+            (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty())
+        } else {
+            (self.dst_stage, self.dst_access)
+        };
+
+        let barrier = self
+            .image_barrier(image, new_layout)
+            .src_access_mask(self.src_access)
+            .dst_access_mask(dst_access);
+
+        unsafe {
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                self.src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&barrier),
+            )
+        };
+
+        if !self.is_ownership_transfer() {
+            image.layout.set(new_layout);
+        }
+    }
+
+    /// Recorded on a command buffer submitted to the destination queue
+    pub fn acquire_image(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        image: &Image,
+        new_layout: Layout,
+    ) {
+        if !self.is_ownership_transfer() {
+            return;
+        }
+
+        let barrier = self
+            .image_barrier(image, new_layout)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(self.dst_access);
+
+        unsafe {
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                std::slice::from_ref(&barrier),
+            )
+        };
+
+        image.layout.set(new_layout);
+    }
+}
+
+/// Image transition queued in a [`BarrierBatch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ImageTransition {
+    image: vk::Image,
+    aspect: vk::ImageAspectFlags,
+    range: SubresourceRange,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+/// Buffer dependency queued in a [`BarrierBatch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BufferDependency {
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+/// Collects image and buffer barriers and records them with a single `cmd_pipeline_barrier`,
+/// waiting on the union of their source stages. Barriers on the same image range or buffer
+/// region are merged, so only push barriers that may all execute at once, e.g. every gbuffer
+/// attachment moving to `FragmentRead` before a resolve
+#[derive(Debug, Default)]
+pub struct BarrierBatch {
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    images: Vec<ImageTransition>,
+    buffers: Vec<BufferDependency>,
+}
+
+impl BarrierBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transitions the whole image from its tracked layout, tracking the new layout
+    pub fn transition(&mut self, image: &Image, new_layout: Layout) -> &mut Self {
+        self.transition_range(image, image.layout.get(), new_layout, SubresourceRange::default())
+    }
+
+    /// See [`Image::record_transition_range`] for how partial ranges are tracked
+    pub fn transition_range(
+        &mut self,
+        image: &Image,
+        old_layout: Layout,
+        new_layout: Layout,
+        range: SubresourceRange,
+    ) -> &mut Self {
+        if range.is_whole() {
+            image.layout.set(new_layout);
+        }
+
+        self.push_image(
+            ImageTransition {
+                image: image.image,
+                aspect: image.aspect(),
+                range,
+                old_layout: old_layout.get_layout(),
+                new_layout: new_layout.get_layout(),
+                src_access: old_layout.get_access(),
+                dst_access: new_layout.get_access(),
+            },
+            old_layout.get_pipeline_stage(),
+            new_layout.get_pipeline_stage(),
+        );
+
+        self
+    }
+
+    /// Makes `src_access` of the whole buffer in `src_stage` visible to `dst_access` in `dst_stage`
+    pub fn buffer(
+        &mut self,
+        buffer: &Buffer,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) -> &mut Self {
+        self.push_buffer(
+            BufferDependency {
+                buffer: buffer.buffer,
+                offset: 0,
+                size: vk::WHOLE_SIZE,
+                src_access,
+                dst_access,
+            },
+            src_stage,
+            dst_stage,
+        );
+
+        self
+    }
+
+    /// Repeating a transition of the same range merges their access, a transition continuing
+    /// from a queued one replaces its destination layout and access
+    fn push_image(
+        &mut self,
+        transition: ImageTransition,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+    ) {
+        self.src_stage |= src_stage;
+        self.dst_stage |= dst_stage;
+
+        let queued = self.images.iter_mut().find(|queued| {
+            queued.image == transition.image
+                && queued.range == transition.range
+                && queued.aspect == transition.aspect
+        });
+
+        match queued {
+            Some(queued)
+                if queued.old_layout == transition.old_layout
+                    && queued.new_layout == transition.new_layout =>
+            {
+                queued.src_access |= transition.src_access;
+                queued.dst_access |= transition.dst_access;
+            }
+            Some(queued) if queued.new_layout == transition.old_layout => {
+                queued.new_layout = transition.new_layout;
+                queued.dst_access = transition.dst_access;
+            }
+            _ => self.images.push(transition),
+        }
+    }
+
+    fn push_buffer(
+        &mut self,
+        dependency: BufferDependency,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+    ) {
+        self.src_stage |= src_stage;
+        self.dst_stage |= dst_stage;
+
+        let queued = self.buffers.iter_mut().find(|queued| {
+            queued.buffer == dependency.buffer
+                && queued.offset == dependency.offset
+                && queued.size == dependency.size
+        });
+
+        match queued {
+            Some(queued) => {
+                queued.src_access |= dependency.src_access;
+                queued.dst_access |= dependency.dst_access;
+            }
+            None => self.buffers.push(dependency),
+        }
+    }
+
+    /// Number of barriers the batch records, after merging
+    pub fn len(&self) -> usize {
+        self.images.len() + self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty() && self.buffers.is_empty()
+    }
+
+    /// Source and destination stages of the batch. `TOP_OF_PIPE` sources and `BOTTOM_OF_PIPE`
+    /// destinations are dropped when other stages are present, as they add nothing to them
+    pub fn stages(&self) -> (vk::PipelineStageFlags, vk::PipelineStageFlags) {
+        let minimal = |stages: vk::PipelineStageFlags, redundant| match stages == redundant {
+            true => stages,
+            false => stages & !redundant,
+        };
+
+        (
+            minimal(self.src_stage, vk::PipelineStageFlags::TOP_OF_PIPE),
+            minimal(self.dst_stage, vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+        )
+    }
+
+    /// Records every queued barrier and empties the batch. Nothing is recorded if it is empty
+    pub fn record(&mut self, loader: &Loader, command_buffer: vk::CommandBuffer) {
+        if self.is_empty() {
+            return;
+        }
+
+        let (src_stage, dst_stage) = self.stages();
+
+        let images = self
+            .images
+            .drain(..)
+            .map(|transition| {
+                vk::ImageMemoryBarrier::builder()
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .old_layout(transition.old_layout)
+                    .new_layout(transition.new_layout)
+                    .image(transition.image)
+                    .src_access_mask(transition.src_access)
+                    .dst_access_mask(transition.dst_access)
+                    .subresource_range(transition.range.with_aspect(transition.aspect))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let buffers = self
+            .buffers
+            .drain(..)
+            .map(|dependency| {
+                vk::BufferMemoryBarrier::builder()
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(dependency.buffer)
+                    .offset(dependency.offset)
+                    .size(dependency.size)
+                    .src_access_mask(dependency.src_access)
+                    .dst_access_mask(dependency.dst_access)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
+            loader.device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffers,
+                &images,
+            )
+        };
+
+        self.src_stage = vk::PipelineStageFlags::empty();
+        self.dst_stage = vk::PipelineStageFlags::empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn transition(image: u64, old: Layout, new: Layout) -> ImageTransition {
+        ImageTransition {
+            image: vk::Image::from_raw(image),
+            aspect: vk::ImageAspectFlags::COLOR,
+            range: SubresourceRange::default(),
+            old_layout: old.get_layout(),
+            new_layout: new.get_layout(),
+            src_access: old.get_access(),
+            dst_access: new.get_access(),
+        }
+    }
+
+    fn push(batch: &mut BarrierBatch, image: u64, old: Layout, new: Layout) {
+        batch.push_image(
+            transition(image, old, new),
+            old.get_pipeline_stage(),
+            new.get_pipeline_stage(),
+        );
+    }
+
+    #[test]
+    fn distinct_images_share_one_barrier_call() {
+        let mut batch = BarrierBatch::new();
+        push(&mut batch, 1, Layout::ColorAttachment, Layout::FragmentRead);
+        push(&mut batch, 2, Layout::DepthAttachment, Layout::FragmentRead);
+
+        assert_eq!(batch.len(), 2);
+        assert_eq!(
+            batch.stages(),
+            (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::FRAGMENT_SHADER
+            )
+        );
+    }
+
+    #[test]
+    fn chained_transitions_of_one_image_merge() {
+        let mut batch = BarrierBatch::new();
+        push(&mut batch, 1, Layout::Initial, Layout::TransferDst);
+        push(&mut batch, 1, Layout::TransferDst, Layout::FragmentRead);
+
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch.images[0].old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(batch.images[0].new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        // Top of pipe adds nothing once other source stages are waited on
+        assert_eq!(batch.stages().0, vk::PipelineStageFlags::TRANSFER);
+    }
+
+    #[test]
+    fn repeated_transitions_merge() {
+        let mut batch = BarrierBatch::new();
+        push(&mut batch, 1, Layout::ColorAttachment, Layout::FragmentRead);
+        push(&mut batch, 1, Layout::ColorAttachment, Layout::FragmentRead);
+
+        assert_eq!(batch.len(), 1);
+    }
+
+    #[test]
+    fn unrelated_layouts_are_not_merged() {
+        let mut batch = BarrierBatch::new();
+        push(&mut batch, 1, Layout::ColorAttachment, Layout::FragmentRead);
+        push(&mut batch, 1, Layout::TransferSrc, Layout::TransferDst);
+
+        assert_eq!(batch.len(), 2);
+    }
+}
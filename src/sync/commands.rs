@@ -35,6 +35,33 @@ impl CommandPool {
     }
 }
 
+/// Handed out by [`CommandPool::execute_one_time_commands_batched`]
+pub struct CommandRecorder<'a> {
+    loader: &'a Loader,
+    command_buffer: vk::CommandBuffer,
+    deferred: Vec<DeferredDestroy<'a>>,
+}
+
+impl<'a> CommandRecorder<'a> {
+    pub fn loader(&self) -> &'a Loader {
+        self.loader
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    /// Records directly into the batch's command buffer
+    pub fn record(&self, f: impl FnOnce(&Loader, vk::CommandBuffer)) {
+        f(self.loader, self.command_buffer)
+    }
+
+    /// Keeps `resource` alive until the batch has finished executing
+    pub fn defer_destroy<T: Destructible + 'a>(&mut self, resource: T) {
+        self.deferred.push(Box::new(move |loader| resource.destroy(loader)));
+    }
+}
+
 pub fn get_command_pools(
     loader: &Loader,
     queues: &[QueueHandle],
@@ -86,12 +113,12 @@ impl CommandPool {
             let submit_info =
                 vk::SubmitInfo::builder().command_buffers(std::slice::from_ref(&command_buffer));
 
-            loader.device.queue_submit(
+            Loader::check(loader.device.queue_submit(
                 self.queue.queues[0],
                 std::slice::from_ref(&submit_info),
                 vk::Fence::null(),
-            )?;
-            loader.device.queue_wait_idle(self.queue.queues[0])?;
+            ))?;
+            Loader::check(loader.device.queue_wait_idle(self.queue.queues[0]))?;
             loader
                 .device
                 .free_command_buffers(self.pool, &[command_buffer]);
@@ -100,6 +127,70 @@ impl CommandPool {
         }
     }
 
+    /// Records every operation `f` queues on the recorder into a single command buffer,
+    /// submitted once and waited on with a fence. Resources handed to
+    /// [`CommandRecorder::defer_destroy`], e.g. staging buffers, are destroyed after the wait
+    pub fn execute_one_time_commands_batched<'a, F, R>(&self, loader: &'a Loader, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut CommandRecorder<'a>) -> crate::error::Result<R>,
+    {
+        unsafe {
+            let command_buffer_create_info = vk::CommandBufferAllocateInfo::builder()
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_pool(self.pool)
+                .command_buffer_count(1);
+
+            let command_buffer = loader
+                .device
+                .allocate_command_buffers(&command_buffer_create_info)?[0];
+
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            loader
+                .device
+                .begin_command_buffer(command_buffer, &begin_info)?;
+
+            let mut recorder = CommandRecorder {
+                loader,
+                command_buffer,
+                deferred: vec![],
+            };
+
+            let result = f(&mut recorder).map_err(anyhow::Error::from).and_then(|ret| {
+                loader.device.end_command_buffer(command_buffer)?;
+
+                let fence = loader
+                    .device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)?;
+                let submit_info = vk::SubmitInfo::builder()
+                    .command_buffers(std::slice::from_ref(&command_buffer));
+
+                let submitted = Loader::check(loader.device.queue_submit(
+                    self.queue.queues[0],
+                    std::slice::from_ref(&submit_info),
+                    fence,
+                ))
+                .and_then(|_| {
+                    Loader::check(loader.device.wait_for_fences(&[fence], true, u64::MAX))
+                });
+
+                fence.destroy(loader);
+                submitted?;
+                Ok(ret)
+            });
+
+            for destroy in recorder.deferred {
+                destroy(loader);
+            }
+
+            loader
+                .device
+                .free_command_buffers(self.pool, &[command_buffer]);
+
+            result
+        }
+    }
+
     pub fn get_main_command_buffers(
         &self,
         loader: &Loader,
@@ -119,6 +210,247 @@ impl CommandPool {
     }
 }
 
+impl CommandPool {
+    /// One pool per worker thread, as a pool and every buffer allocated from it may only
+    /// be used by one thread at a time
+    pub fn for_threads(
+        loader: &Loader,
+        queue: &QueueHandle,
+        flags: vk::CommandPoolCreateFlags,
+        threads: usize,
+    ) -> Result<Vec<CommandPool>> {
+        let mut pools = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            match CommandPool::new(loader, queue, flags) {
+                Ok(pool) => pools.push(pool),
+                Err(err) => {
+                    pools.destroy(loader);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(pools)
+    }
+
+    pub fn get_secondary_buffers(
+        &self,
+        loader: &Loader,
+        count: u32,
+    ) -> Result<Vec<vk::CommandBuffer>> {
+        let buffer_ci = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.pool)
+            .command_buffer_count(count)
+            .level(vk::CommandBufferLevel::SECONDARY);
+
+        Ok(unsafe { loader.device.allocate_command_buffers(&buffer_ci)? })
+    }
+
+    /// Resets every buffer allocated from this pool, none of which may be pending execution
+    pub fn reset(&self, device: &Device) -> Result<()> {
+        unsafe {
+            device.reset_command_pool(self.pool, vk::CommandPoolResetFlags::empty())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Render pass a secondary command buffer continues. The primary has to begin the render pass
+/// with `SubpassContents::SECONDARY_COMMAND_BUFFERS` before executing it with [`cmd_execute_commands`]
+#[derive(Debug, Clone, Copy)]
+pub struct SecondaryInheritance {
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+    /// May be null when the framebuffer isn't known at record time, at some cost on some drivers
+    pub framebuffer: vk::Framebuffer,
+}
+
+impl SecondaryInheritance {
+    /// Begins recording draws inside the render pass. Takes the device rather than the [`Loader`],
+    /// which can't be shared with worker threads
+    pub fn begin(&self, device: &Device, command_buffer: vk::CommandBuffer) -> Result<()> {
+        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(self.render_pass)
+            .subpass(self.subpass)
+            .framebuffer(self.framebuffer);
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(
+                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                    | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            )
+            .inheritance_info(&inheritance_info);
+
+        unsafe { device.begin_command_buffer(command_buffer, &begin_info)? };
+        Ok(())
+    }
+
+    pub fn end(&self, device: &Device, command_buffer: vk::CommandBuffer) -> Result<()> {
+        unsafe { device.end_command_buffer(command_buffer)? };
+        Ok(())
+    }
+}
+
+/// Executes secondary buffers recorded with a matching [`SecondaryInheritance`], in order
+pub fn cmd_execute_commands(
+    loader: &Loader,
+    command_buffer: vk::CommandBuffer,
+    secondaries: &[vk::CommandBuffer],
+) {
+    if secondaries.is_empty() {
+        return;
+    }
+
+    unsafe {
+        loader
+            .device
+            .cmd_execute_commands(command_buffer, secondaries)
+    }
+}
+
+/// Pipeline state bound before a [`Recordable`] is recorded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameContext {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    /// Bound starting at set 0
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl FrameContext {
+    /// Binds the pipeline and sets at the compute bind point and dispatches `group_count`
+    /// workgroups, followed by `barrier` if given. The pipeline must be a compute pipeline, e.g.
+    /// that of a compute effect, see [`crate::material::MaterialSystem::get_frame_context`]
+    pub fn record_dispatch(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        group_count: [u32; 3],
+        barrier: Option<DispatchBarrier>,
+    ) {
+        let [x, y, z] = group_count;
+
+        unsafe {
+            loader
+                .device
+                .cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+
+            if !self.descriptor_sets.is_empty() {
+                loader.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.layout,
+                    0,
+                    &self.descriptor_sets,
+                    &[],
+                );
+            }
+
+            loader.device.cmd_dispatch(command_buffer, x, y, z);
+
+            if let Some(barrier) = barrier {
+                loader.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    barrier.dst_stage,
+                    vk::DependencyFlags::empty(),
+                    std::slice::from_ref(&barrier.barrier),
+                    &[],
+                    &[],
+                );
+            }
+        }
+    }
+}
+
+/// Memory dependency recorded after a dispatch, see [`FrameContext::record_dispatch`]
+#[derive(Debug, Clone, Copy)]
+pub struct DispatchBarrier {
+    pub barrier: vk::MemoryBarrier,
+    /// Stages waiting on the dispatch, e.g. `VERTEX_INPUT` for a draw reading its output as vertices
+    pub dst_stage: vk::PipelineStageFlags,
+}
+
+impl DispatchBarrier {
+    /// Makes the dispatch's shader writes visible to `dst_access` in `dst_stage`
+    pub fn new(dst_stage: vk::PipelineStageFlags, dst_access: vk::AccessFlags) -> Self {
+        Self {
+            barrier: vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(dst_access)
+                .build(),
+            dst_stage,
+        }
+    }
+}
+
 pub trait Recordable {
-    fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer);
+    fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer, frame: &FrameContext);
+}
+
+/// Records many drawables into one command buffer, only rebinding the pipeline
+/// and descriptor sets when they differ from the previous entry
+#[derive(Default)]
+pub struct RecordList<'a> {
+    entries: Vec<(FrameContext, &'a dyn Recordable)>,
+}
+
+impl<'a> RecordList<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, frame: FrameContext, recordable: &'a dyn Recordable) {
+        self.entries.push((frame, recordable));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Entries are recorded in the order they were pushed, so grouping entries
+    /// by pipeline avoids the most rebinds
+    pub fn record(&self, loader: &Loader, command_buffer: vk::CommandBuffer) {
+        let mut bound_pipeline = vk::Pipeline::null();
+        let mut bound_sets: Option<(vk::PipelineLayout, &[vk::DescriptorSet])> = None;
+
+        for (frame, recordable) in &self.entries {
+            if frame.pipeline != bound_pipeline {
+                unsafe {
+                    loader.device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        frame.pipeline,
+                    )
+                };
+                bound_pipeline = frame.pipeline;
+            }
+
+            let sets = (frame.layout, &frame.descriptor_sets[..]);
+            if !frame.descriptor_sets.is_empty() && bound_sets != Some(sets) {
+                unsafe {
+                    loader.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        frame.layout,
+                        0,
+                        &frame.descriptor_sets,
+                        &[],
+                    )
+                };
+                bound_sets = Some(sets);
+            }
+
+            recordable.record(loader, command_buffer, frame);
+        }
+    }
 }
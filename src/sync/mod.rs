@@ -5,4 +5,16 @@ mod commands;
 pub use commands::*;
 
 mod primitives;
-pub use primitives::*;
\ No newline at end of file
+pub use primitives::*;
+
+mod barrier;
+pub use barrier::*;
+
+mod query;
+pub use query::*;
+
+mod scheduler;
+pub use scheduler::*;
+
+mod upload;
+pub use upload::*;
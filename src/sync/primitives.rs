@@ -1,29 +1,28 @@
 use crate::{prelude::*, collections::ParitySet};
 
+/// Per frame in flight synchronization. The semaphore presentation waits on is per
+/// swapchain image instead, see [`crate::swapchain::SwapFrame::render_finished`]
 pub struct SyncPrimitives {
     pub image_available: vk::Semaphore,
-    pub render_finished: vk::Semaphore,
     pub in_flight: vk::Fence,
 }
 
 impl Destructible for SyncPrimitives {
     fn destroy(self, loader: &Loader) {
         self.image_available.destroy(loader);
-        self.render_finished.destroy(loader);
         self.in_flight.destroy(loader);
     }
 }
 
+/// # Safety
+///
+/// Every semaphore and fence has to be destroyed once no submission uses it anymore
 pub unsafe fn get_sync_primitives(loader: &Loader) -> ParitySet<SyncPrimitives> {
     ParitySet::from_fn(|| {
         let image_available = loader
             .device
             .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
             .unwrap();
-        let render_finished = loader
-            .device
-            .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
-            .unwrap();
 
         let fence_create_info =
             vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
@@ -34,7 +33,6 @@ pub unsafe fn get_sync_primitives(loader: &Loader) -> ParitySet<SyncPrimitives>
 
         SyncPrimitives {
             image_available,
-            render_finished,
             in_flight,
         }
     })
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use crate::error::{Result, SiltError};
+use crate::prelude::*;
+use crate::properties::ProvidedFeatures;
+
+use super::QueueHandle;
+
+/// Wrapper around timestamp and occlusion query pools. Queries must be reset
+/// with [`QueryPool::cmd_reset`] before they are written each frame
+#[derive(Debug, Clone, Copy)]
+pub struct QueryPool {
+    pub pool: vk::QueryPool,
+    pub ty: vk::QueryType,
+    pub count: u32,
+    /// Nanoseconds per timestamp tick
+    timestamp_period: f32,
+    timestamp_mask: u64,
+}
+
+impl Destructible for QueryPool {
+    fn destroy(self, loader: &Loader) {
+        unsafe { loader.device.destroy_query_pool(self.pool, None) };
+    }
+}
+
+impl QueryPool {
+    fn create_pool(loader: &Loader, ty: vk::QueryType, count: u32) -> Result<vk::QueryPool> {
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(ty)
+            .query_count(count);
+
+        Ok(unsafe { loader.device.create_query_pool(&create_info, None)? })
+    }
+
+    /// Timestamp support is per queue family, so the pool is only valid on
+    /// queues from the same family as `queue`. Without `timestampComputeAndGraphics`
+    /// some graphics and compute queues may not support timestamps at all
+    pub fn timestamps(
+        loader: &Loader,
+        features: ProvidedFeatures,
+        queue: &QueueHandle,
+        count: u32,
+    ) -> Result<Self> {
+        let valid_bits = unsafe {
+            loader
+                .instance
+                .get_physical_device_queue_family_properties(features.pdevice)
+        }
+        .get(queue.family as usize)
+        .map(|props| props.timestamp_valid_bits)
+        .unwrap_or(0);
+
+        if valid_bits == 0 {
+            return Err(SiltError::InvalidUsage(format!(
+                "queue family {} does not support timestamp queries",
+                queue.family
+            )));
+        }
+
+        Ok(Self {
+            pool: Self::create_pool(loader, vk::QueryType::TIMESTAMP, count)?,
+            ty: vk::QueryType::TIMESTAMP,
+            count,
+            timestamp_period: features.limits.timestamp_period,
+            timestamp_mask: u64::MAX >> (64 - valid_bits.min(64)),
+        })
+    }
+
+    pub fn occlusion(loader: &Loader, count: u32) -> Result<Self> {
+        Ok(Self {
+            pool: Self::create_pool(loader, vk::QueryType::OCCLUSION, count)?,
+            ty: vk::QueryType::OCCLUSION,
+            count,
+            timestamp_period: 0.,
+            timestamp_mask: 0,
+        })
+    }
+
+    pub fn cmd_reset(&self, loader: &Loader, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            loader
+                .device
+                .cmd_reset_query_pool(command_buffer, self.pool, 0, self.count)
+        };
+    }
+
+    pub fn cmd_write_timestamp(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        stage: vk::PipelineStageFlags,
+        index: u32,
+    ) {
+        debug_assert_eq!(self.ty, vk::QueryType::TIMESTAMP);
+        unsafe {
+            loader
+                .device
+                .cmd_write_timestamp(command_buffer, stage, self.pool, index)
+        };
+    }
+
+    /// `precise` reports exact sample counts instead of a boolean, which
+    /// requires the `occlusionQueryPrecise` feature
+    pub fn cmd_begin_query(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        index: u32,
+        precise: bool,
+    ) {
+        debug_assert_eq!(self.ty, vk::QueryType::OCCLUSION);
+        let flags = if precise {
+            vk::QueryControlFlags::PRECISE
+        } else {
+            vk::QueryControlFlags::empty()
+        };
+
+        unsafe {
+            loader
+                .device
+                .cmd_begin_query(command_buffer, self.pool, index, flags)
+        };
+    }
+
+    pub fn cmd_end_query(&self, loader: &Loader, command_buffer: vk::CommandBuffer, index: u32) {
+        unsafe { loader.device.cmd_end_query(command_buffer, self.pool, index) };
+    }
+
+    /// Blocks until every query in the pool is available
+    pub fn read_raw(&self, loader: &Loader) -> Result<Vec<u64>> {
+        let mut results = vec![0u64; self.count as usize];
+        unsafe {
+            loader.device.get_query_pool_results(
+                self.pool,
+                0,
+                self.count,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )?
+        };
+
+        Ok(results)
+    }
+
+    /// Timestamps scaled to real time. Only differences between timestamps
+    /// written in the same submission are meaningful
+    pub fn read_results(&self, loader: &Loader) -> Result<Vec<Duration>> {
+        if self.ty != vk::QueryType::TIMESTAMP {
+            return Err(SiltError::InvalidUsage(
+                "only timestamp queries can be read as durations".into(),
+            ));
+        }
+
+        Ok(self
+            .read_raw(loader)?
+            .into_iter()
+            .map(|ticks| {
+                let nanos = (ticks & self.timestamp_mask) as f64 * self.timestamp_period as f64;
+                Duration::from_nanos(nanos as u64)
+            })
+            .collect())
+    }
+}
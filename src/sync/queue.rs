@@ -71,7 +71,42 @@ pub struct QueueHandle {
     pub ty: QueueType,
     pub unique: bool,
     pub queues: Vec<vk::Queue>,
+    /// Queue family index, needed for ownership transfer barriers
     pub family: u32,
+    /// Capabilities of the queue family, which may exceed what `ty` requested
+    pub flags: vk::QueueFlags,
+    pdevice: vk::PhysicalDevice,
+}
+
+impl QueueHandle {
+    pub fn shares_family(&self, other: &QueueHandle) -> bool {
+        self.family == other.family
+    }
+
+    pub fn supports(&self, flags: vk::QueueFlags) -> bool {
+        self.flags.contains(flags)
+    }
+
+    pub fn supports_present(&self, loader: &Loader, surface: vk::SurfaceKHR) -> bool {
+        unsafe {
+            loader
+                .surface
+                .get_physical_device_surface_support(self.pdevice, self.family, surface)
+                .unwrap_or(false)
+        }
+    }
+
+    /// Waits for every queue in the handle to go idle
+    pub fn wait_idle(&self, loader: &Loader) -> crate::error::Result<()> {
+        self.queues
+            .iter()
+            .try_for_each(|&queue| Loader::check(unsafe { loader.device.queue_wait_idle(queue) }))
+    }
+}
+
+/// Finds the first queue handle of the given type, e.g. to locate the async compute queue
+pub fn find_queue(queues: &[QueueHandle], ty: QueueType) -> Option<&QueueHandle> {
+    queues.iter().find(|queue| queue.ty == ty)
 }
 
 impl QueueRequest {
@@ -196,6 +231,8 @@ pub fn get_device_queues(
                 unique: queue_allocators.get(&prop.family).unwrap().has_unique(),
                 queues,
                 family: prop.family,
+                flags: prop.props.queue_flags,
+                pdevice,
             }
         })
         .collect_vec();
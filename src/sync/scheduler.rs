@@ -0,0 +1,212 @@
+use crate::collections::{FrameIndex, Parity, ParitySet};
+use crate::error::{Result, SiltError};
+use crate::prelude::*;
+use crate::swapchain::{ImageIndex, PresentId, Swapchain, SwapchainStatus};
+
+use super::{get_sync_primitives, CommandPool, SyncPrimitives};
+
+/// What happened to a frame handed to [`FrameScheduler::draw_frame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOutcome {
+    Presented(PresentId),
+    /// The swapchain was out of date or suboptimal and has been recreated at the window size.
+    /// If this happened on acquire nothing was recorded, on present the frame was submitted but
+    /// may not have been shown. A window minimized in between is only recreated once restored
+    Recreated,
+    /// The window is minimized, nothing was acquired, recorded or presented
+    Skipped,
+}
+
+/// Everything needed to record one frame, handed to the closure of [`FrameScheduler::draw_frame`].
+/// The command buffer is already begun, and is ended and submitted after the closure returns
+#[derive(Debug, Clone, Copy)]
+pub struct ScheduledFrame {
+    /// Frame in flight, selecting the command buffer and any per frame descriptor sets
+    pub parity: FrameIndex,
+    pub command_buffer: vk::CommandBuffer,
+    /// Acquired swapchain image, selecting the framebuffer
+    pub image_index: ImageIndex,
+    pub framebuffer: vk::Framebuffer,
+    pub render_extent: vk::Extent2D,
+}
+
+/// Owns the per frame fences, semaphores and command buffers, and runs the acquire, record,
+/// submit and present cycle of each frame, recreating the swapchain when it goes out of date
+pub struct FrameScheduler {
+    sync: ParitySet<SyncPrimitives>,
+    command_buffers: ParitySet<vk::CommandBuffer>,
+    queue: vk::Queue,
+    frame: Parity,
+    deletion: DeletionQueue,
+}
+
+impl Destructible for FrameScheduler {
+    /// The device must be idle
+    fn destroy(self, loader: &Loader) {
+        for parity in Parity::ELEMENTS {
+            self.deletion.flush(loader, &parity);
+        }
+        self.sync.into_iter().destroy(loader);
+    }
+}
+
+impl FrameScheduler {
+    /// `pool` must allow resetting individual buffers, and its queue must support presenting
+    pub fn new(loader: &Loader, pool: &CommandPool) -> Result<Self> {
+        if !pool
+            .flags
+            .contains(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+        {
+            return Err(SiltError::InvalidUsage(
+                "frame scheduler needs a pool created with RESET_COMMAND_BUFFER".into(),
+            ));
+        }
+
+        let queue = *pool.queue.queues.first().ok_or_else(|| {
+            SiltError::InvalidUsage("frame scheduler pool has no queues".into())
+        })?;
+
+        Ok(Self {
+            sync: unsafe { get_sync_primitives(loader) },
+            command_buffers: pool.get_main_command_buffers(loader)?,
+            queue,
+            frame: Parity::Even,
+            deletion: DeletionQueue::new(),
+        })
+    }
+
+    /// Frame in flight the next call to [`FrameScheduler::draw_frame`] records into
+    pub fn frame(&self) -> Parity {
+        self.frame
+    }
+
+    /// Resources pushed under a frame are destroyed once that frame's fence next signals
+    pub fn deletion_queue(&self) -> &DeletionQueue {
+        &self.deletion
+    }
+
+    /// Waits for the current frame in flight, acquires an image and lets `record` fill the
+    /// frame's command buffer, then submits and presents it. Out of date swapchains, on
+    /// acquire or present, are recreated at the window's current size
+    pub fn draw_frame<F>(
+        &mut self,
+        loader: &Loader,
+        swapchain: &mut Swapchain,
+        record: F,
+    ) -> Result<FrameOutcome>
+    where
+        F: FnOnce(&ScheduledFrame) -> Result<()>,
+    {
+        let parity = self.frame;
+        let sync = self.sync.get(parity);
+
+        unsafe {
+            Loader::check(
+                loader
+                    .device
+                    .wait_for_fences(&[sync.in_flight], true, u64::MAX),
+            )?;
+        }
+        self.deletion.flush(loader, &parity);
+
+        let image_index = match unsafe { swapchain.acquire(loader, sync.image_available)? } {
+            SwapchainStatus::Acquired(image_index, _) => image_index,
+            SwapchainStatus::Minimized => return Ok(FrameOutcome::Skipped),
+            SwapchainStatus::OutOfDate => {
+                let size = loader.window.inner_size();
+                unsafe { swapchain.recreate(loader, size.width, size.height)? };
+                return Ok(FrameOutcome::Recreated);
+            }
+        };
+
+        let command_buffer = *self.command_buffers.get(parity);
+        let swap_frame = swapchain.frame(image_index)?;
+        let render_finished = swap_frame.render_finished;
+        unsafe {
+            Loader::check(
+                loader
+                    .device
+                    .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty()),
+            )?;
+            Loader::check(loader.device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            ))?;
+        }
+
+        let recorded = record(&ScheduledFrame {
+            parity,
+            command_buffer,
+            image_index,
+            framebuffer: swap_frame.framebuffer,
+            render_extent: swapchain.render_extent,
+        });
+
+        if let Err(err) = recorded {
+            unsafe { self.discard_frame(loader, command_buffer, sync)? };
+            return Err(err);
+        }
+
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(std::slice::from_ref(&sync.image_available))
+            .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+            .command_buffers(std::slice::from_ref(&command_buffer))
+            .signal_semaphores(std::slice::from_ref(&render_finished));
+
+        // Only reset right before submitting, an early return would leave the next wait hanging
+        unsafe {
+            Loader::check(loader.device.end_command_buffer(command_buffer))?;
+            Loader::check(loader.device.reset_fences(&[sync.in_flight]))?;
+            Loader::check(loader.device.queue_submit(
+                self.queue,
+                &[submit_info.build()],
+                sync.in_flight,
+            ))?;
+        }
+
+        self.frame.swap();
+        let (presented, recreate) = match unsafe { swapchain.present(loader, self.queue, image_index) } {
+            Ok((id, suboptimal)) => (Some(id), suboptimal),
+            Err(err) => match SiltError::from(err) {
+                SiltError::Vk(vk::Result::ERROR_OUT_OF_DATE_KHR) => (None, true),
+                err => return Err(err),
+            },
+        };
+
+        swapchain.release_retired(&self.deletion, parity);
+
+        // A minimized window is recreated once acquiring reports it out of date
+        if recreate && !Swapchain::is_minimized(loader) {
+            let size = loader.window.inner_size();
+            unsafe { swapchain.recreate(loader, size.width, size.height)? };
+            return Ok(FrameOutcome::Recreated);
+        }
+
+        // The frame was submitted either way, so it's never reported as skipped
+        Ok(presented.map_or(FrameOutcome::Recreated, FrameOutcome::Presented))
+    }
+
+    /// Ends the partially recorded `command_buffer` without submitting it, and submits a batch
+    /// that only waits on the acquire semaphore so it can be signaled again. The fence is signaled
+    /// by that batch, so the next wait on this frame doesn't hang. The acquired image is dropped
+    unsafe fn discard_frame(
+        &self,
+        loader: &Loader,
+        command_buffer: vk::CommandBuffer,
+        sync: &SyncPrimitives,
+    ) -> Result<()> {
+        Loader::check(loader.device.end_command_buffer(command_buffer))?;
+
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(std::slice::from_ref(&sync.image_available))
+            .wait_dst_stage_mask(&[vk::PipelineStageFlags::ALL_COMMANDS]);
+
+        Loader::check(loader.device.reset_fences(&[sync.in_flight]))?;
+        Loader::check(loader.device.queue_submit(
+            self.queue,
+            &[submit_info.build()],
+            sync.in_flight,
+        ))
+    }
+}
@@ -0,0 +1,199 @@
+use crate::error::{Result, SiltError};
+use crate::id;
+use crate::prelude::*;
+use crate::resources::{Buffer, BufferCreateInfo, Image, ImageCreateInfo};
+use std::sync::{Arc, Mutex};
+
+use super::QueueHandle;
+
+/// `Send` subset of the [`Loader`] for creating resources and submitting transfers from asset
+/// loading threads, created with [`Loader::upload_handle`]. Clones share the same queue.
+///
+/// The synchronization contract is:
+/// - The allocator is internally locked, so allocations may happen on any thread at any time
+/// - Submissions through the handle are serialized by a mutex, but nothing else may submit to
+///   its queue. The queue handle passed in must therefore own its `vk::Queue`, in practice a
+///   dedicated [`super::QueueType::Transfer`] request
+/// - Every call records into its own transient command pool and waits for its submission
+///   before returning, so no command buffer or pool outlives a call
+/// - Resources come back owned by the handle's queue family. If the render thread uses another
+///   family, release them in [`GpuUploadHandle::execute`] and acquire them on the render thread
+///   with a [`super::CrossQueueBarrier`]
+/// - Resources may be destroyed on either side, but never while a frame might still use them,
+///   and every handle has to be dropped before [`Loader::destroy`]
+#[derive(Clone)]
+pub struct GpuUploadHandle {
+    device: Device,
+    allocator: Arc<Allocator>,
+    queue: Arc<Mutex<vk::Queue>>,
+    family: u32,
+}
+
+impl GpuUploadHandle {
+    pub(crate) fn new(device: Device, allocator: Arc<Allocator>, queue: &QueueHandle) -> Result<Self> {
+        if !queue.supports(vk::QueueFlags::TRANSFER)
+            && !queue.supports(vk::QueueFlags::GRAPHICS)
+            && !queue.supports(vk::QueueFlags::COMPUTE)
+        {
+            return Err(SiltError::InvalidUsage(format!(
+                "queue [{:?}] can't execute transfers",
+                queue.ty
+            )));
+        }
+
+        if !queue.unique {
+            return Err(SiltError::InvalidUsage(format!(
+                "queue [{:?}] shares its vk::Queue with other requests, request a dedicated one for uploads",
+                queue.ty
+            )));
+        }
+
+        Ok(Self {
+            device,
+            allocator,
+            queue: Arc::new(Mutex::new(queue.queues[0])),
+            family: queue.family,
+        })
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn allocator(&self) -> &Allocator {
+        &self.allocator
+    }
+
+    /// Queue family owning every resource uploaded through the handle
+    pub fn family(&self) -> u32 {
+        self.family
+    }
+
+    pub fn create_buffer(&self, create_info: BufferCreateInfo) -> Result<Buffer> {
+        Buffer::create(&self.device, &self.allocator, create_info)
+    }
+
+    pub fn create_image(&self, create_info: ImageCreateInfo) -> Result<Image> {
+        Image::create(&self.device, &self.allocator, create_info)
+    }
+
+    pub fn destroy_buffer(&self, buffer: Buffer) -> Result<()> {
+        unsafe { self.device.destroy_buffer(buffer.buffer, None) };
+        Ok(self.allocator.free(buffer.allocation)?)
+    }
+
+    pub fn destroy_image(&self, image: Image) -> Result<()> {
+        unsafe {
+            self.device.destroy_image_view(image.view, None);
+            self.device.destroy_image(image.image, None);
+        }
+        Ok(self.allocator.free(image.allocation)?)
+    }
+
+    /// Records `f` into a one time command buffer, submits it and blocks the calling thread until
+    /// it completes. Only the submission itself holds the queue lock
+    pub fn execute<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Device, vk::CommandBuffer) -> R,
+    {
+        let pool_ci = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(self.family);
+        let pool = unsafe { self.device.create_command_pool(&pool_ci, None)? };
+
+        let result = unsafe { self.execute_in(pool, f) };
+        unsafe { self.device.destroy_command_pool(pool, None) };
+        result
+    }
+
+    unsafe fn execute_in<F, R>(&self, pool: vk::CommandPool, f: F) -> Result<R>
+    where
+        F: FnOnce(&Device, vk::CommandBuffer) -> R,
+    {
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_pool(pool)
+            .command_buffer_count(1);
+        let command_buffer = self.device.allocate_command_buffers(&allocate_info)?[0];
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.device.begin_command_buffer(command_buffer, &begin_info)?;
+        let ret = f(&self.device, command_buffer);
+        self.device.end_command_buffer(command_buffer)?;
+
+        let fence = self.device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+        let submit_info =
+            vk::SubmitInfo::builder().command_buffers(std::slice::from_ref(&command_buffer));
+
+        let submitted = match self.queue.lock() {
+            Ok(queue) => Loader::check(self.device.queue_submit(
+                *queue,
+                std::slice::from_ref(&submit_info),
+                fence,
+            )),
+            Err(_) => Err(SiltError::InvalidUsage(
+                "upload queue poisoned by a panicking thread".into(),
+            )),
+        };
+        let waited = submitted
+            .and_then(|_| Loader::check(self.device.wait_for_fences(&[fence], true, u64::MAX)));
+
+        self.device.destroy_fence(fence, None);
+        waited.map(|_| ret)
+    }
+
+    /// Creates a device local buffer from `create_info` holding `data`, copied through a
+    /// temporary staging buffer
+    pub fn upload_buffer<T: Copy>(&self, create_info: BufferCreateInfo, data: &[T]) -> Result<Buffer> {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        if size != create_info.size {
+            return Err(SiltError::InvalidUsage(format!(
+                "{} bytes of data don't match buffer [{:?}]",
+                size, create_info
+            )));
+        }
+
+        let staging_info = BufferCreateInfo::builder()
+            .size(size)
+            .name(id!("Upload Staging"))
+            .staging()
+            .build()?;
+        let staging = self.create_buffer(staging_info)?;
+
+        let buffer = self
+            .write_staging(&staging, data)
+            .and_then(|_| {
+                self.create_buffer(BufferCreateInfo {
+                    usage: create_info.usage | vk::BufferUsageFlags::TRANSFER_DST,
+                    ..create_info
+                })
+            })
+            .and_then(|buffer| {
+                let region = vk::BufferCopy::builder().size(size).build();
+                match self.execute(|device, cmd| unsafe {
+                    device.cmd_copy_buffer(cmd, staging.buffer, buffer.buffer, &[region])
+                }) {
+                    Ok(()) => Ok(buffer),
+                    Err(err) => {
+                        self.destroy_buffer(buffer)?;
+                        Err(err)
+                    }
+                }
+            });
+
+        self.destroy_buffer(staging)?;
+        buffer
+    }
+
+    fn write_staging<T: Copy>(&self, staging: &Buffer, data: &[T]) -> Result<()> {
+        let ptr = self.allocator.get_mapped_ptr(staging.allocation)?.as_ptr() as *mut T;
+        unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len()) };
+        Ok(())
+    }
+}
+
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<GpuUploadHandle>();
+};
@@ -0,0 +1,91 @@
+//! Setup and teardown shared by the GPU tests. They run without the test harness since winit
+//! needs the main thread, and only with `SILT_GPU_TESTS` set since they need a vulkan device
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+use silt::id;
+use silt::loader::{DebugConfig, LoaderCreateInfo, ValidationConfig};
+use silt::prelude::*;
+use silt::properties::{DeviceFeatures, DeviceFeaturesRequest};
+use silt::resources::{Buffer, BufferCreateInfo, Resource, TypedResourceDescription};
+use silt::sync::{QueueHandle, QueueRequest, QueueType};
+
+/// Runs `test` with a validated loader and a single graphics queue, then destroys the loader.
+/// Every object created by `test` has to be destroyed by the time it returns
+pub fn run_gpu_test(
+    name: &str,
+    test: impl FnOnce(&Loader, &[QueueHandle]) -> Result<()>,
+) -> Result<()> {
+    run_gpu_test_with_features(name, DeviceFeatures::empty(), test)
+}
+
+/// Like [`run_gpu_test`], skipping the test on devices without every feature of `required`
+pub fn run_gpu_test_with_features(
+    name: &str,
+    required: DeviceFeatures,
+    test: impl FnOnce(&Loader, &[QueueHandle]) -> Result<()>,
+) -> Result<()> {
+    if std::env::var_os("SILT_GPU_TESTS").is_none() {
+        println!(
+            "{} test ignored, set SILT_GPU_TESTS to run it",
+            name.to_lowercase()
+        );
+        return Ok(());
+    }
+
+    let loader_ci = LoaderCreateInfo {
+        width: 64,
+        height: 64,
+        title: format!("{} Test", name),
+        device_features: DeviceFeaturesRequest {
+            required: DeviceFeatures::empty(),
+            prefered: required,
+        },
+        queue_requests: vec![QueueRequest {
+            ty: QueueType::Graphics,
+            count: 1,
+        }],
+        debug: DebugConfig {
+            panic_on_error: true,
+            ..Default::default()
+        },
+        validation: ValidationConfig::Enabled,
+        device_selection: Default::default(),
+    };
+
+    let (loader, handles) = Loader::new(loader_ci)?;
+    if loader.features.contains(required) {
+        test(&loader, &handles.queues)?;
+    } else {
+        println!(
+            "{} test ignored, the device lacks {:?}",
+            name.to_lowercase(),
+            required - loader.features
+        );
+    }
+
+    loader
+        .destroy(handles)
+        .map_err(|failed| anyhow!("failed to destroy the loader: {}", failed.2))
+}
+
+/// Storage buffer of `count` values for `values`, read back by the compute tests
+pub fn values_buffer(
+    loader: &Loader,
+    values: &TypedResourceDescription<u32>,
+    count: usize,
+    location: vk::MemoryLocation,
+) -> Result<Resource<Buffer>> {
+    Ok(values.bind_result(|_| {
+        Buffer::new(
+            loader,
+            BufferCreateInfo {
+                size: (count * std::mem::size_of::<u32>()) as u64,
+                name: id!("Values"),
+                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
+                location,
+                ..Default::default()
+            },
+        )
+    })?)
+}
@@ -0,0 +1,75 @@
+//! Dispatches a compute effect writing a storage buffer and reads the buffer back
+
+mod common;
+
+use anyhow::Result;
+use silt::material::{MaterialSystemBuilder, ShaderOptions};
+use silt::prelude::*;
+use silt::resources::{BindableResource, ResourceDescription};
+use silt::sync::{CommandPool, DispatchBarrier};
+use silt::{compile, id, resources};
+
+const GROUP_SIZE: u32 = 64;
+const GROUPS: u32 = 4;
+
+fn main() -> Result<()> {
+    common::run_gpu_test("Compute Dispatch", |loader, queues| {
+        let pool = CommandPool::new(loader, &queues[0], vk::CommandPoolCreateFlags::empty())?;
+        let count = (GROUP_SIZE * GROUPS) as usize;
+
+        let mut materials = MaterialSystemBuilder::new(loader);
+        let values = ResourceDescription::storage_buffer::<u32>(
+            id!("Values"),
+            0,
+            vk::DescriptorFrequency::Global,
+            count,
+        );
+
+        let shader = materials.add_shader(
+            id!("Fill Buffer"),
+            compile!("../assets/shaders/fill_buffer.comp", ShaderOptions::HLSL)?,
+            resources!(values),
+        )?;
+        let effect = materials.register_effect(id!("Fill Buffer Effect"), [shader])?;
+
+        let buffer = common::values_buffer(loader, &values, count, vk::MemoryLocation::GpuToCpu)?;
+
+        let mut materials = materials.build_static()?;
+        *materials.get_global_resources_mut() = vec![buffer.bind()].into();
+        materials.write_global_sets(loader)?;
+
+        assert!(materials.get_effect_pipeline(loader, &effect)?.is_compute());
+        let frame = materials.get_frame_context(loader, &effect)?;
+
+        pool.execute_one_time_commands(loader, |loader, cmd| {
+            frame.record_dispatch(
+                loader,
+                cmd,
+                [GROUPS, 1, 1],
+                Some(DispatchBarrier::new(
+                    vk::PipelineStageFlags::HOST,
+                    vk::AccessFlags::HOST_READ,
+                )),
+            );
+        })?;
+
+        let ptr = loader
+            .allocator
+            .get_mapped_ptr(buffer.resource.allocation)?;
+        let read = unsafe { std::slice::from_raw_parts(ptr.as_ptr() as *const u32, count) };
+
+        for (idx, value) in read.iter().enumerate() {
+            assert_eq!(
+                *value,
+                (idx * idx) as u32,
+                "compute shader wrote an unexpected value"
+            );
+        }
+
+        materials.destroy(loader);
+        buffer.resource.destroy(loader);
+        pool.destroy(loader);
+
+        Ok(())
+    })
+}
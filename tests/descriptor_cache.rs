@@ -0,0 +1,61 @@
+//! Looks up cached material sets for two effects declaring the same bindings, which must share
+//! a single pair of descriptor sets
+
+mod common;
+
+use anyhow::Result;
+use silt::material::{MaterialSystemBuilder, ShaderOptions};
+use silt::prelude::*;
+use silt::resources::{BindableResource, DescriptorCacheStats, ResourceDescription};
+use silt::{compile, id, resources};
+
+const COUNT: usize = 256;
+
+fn main() -> Result<()> {
+    common::run_gpu_test("Descriptor Cache", |loader, _| {
+        let mut materials = MaterialSystemBuilder::new(loader);
+        let values = ResourceDescription::storage_buffer::<u32>(
+            id!("Values"),
+            0,
+            vk::DescriptorFrequency::Material,
+            COUNT,
+        )
+        .with_set(0);
+
+        let shader = materials.add_shader(
+            id!("Fill Buffer"),
+            compile!("../assets/shaders/fill_buffer.comp", ShaderOptions::HLSL)?,
+            resources!(values),
+        )?;
+
+        // Each effect gets its own set layouts, identically defined from the same bindings
+        let first = materials.register_effect(id!("First Fill Effect"), [shader.clone()])?;
+        let second = materials.register_effect(id!("Second Fill Effect"), [shader])?;
+
+        let buffer = common::values_buffer(loader, &values, COUNT, vk::MemoryLocation::GpuOnly)?;
+
+        let mut materials = materials.build_static()?;
+        let material = vk::PartialDescriptorFrequency::Material;
+
+        let first_sets = materials.get_cached_sets(loader, &first, material, [buffer.bind()])?;
+        let second_sets = materials.get_cached_sets(loader, &second, material, [buffer.bind()])?;
+
+        assert_eq!(
+            first_sets, second_sets,
+            "identical bindings allocated separate sets"
+        );
+        assert_eq!(
+            materials.descriptor_cache_stats(),
+            DescriptorCacheStats {
+                hits: 1,
+                misses: 1,
+                invalidations: 0,
+            }
+        );
+
+        materials.destroy(loader);
+        buffer.resource.destroy(loader);
+
+        Ok(())
+    })
+}
@@ -0,0 +1,126 @@
+//! Clears an `R16G16B16A16_SFLOAT` attachment through a render pass and reads it back
+
+mod common;
+
+use anyhow::Result;
+use silt::id;
+use silt::pipeline::build_render_pass;
+use silt::prelude::*;
+use silt::resources::{
+    AttachmentType, Buffer, BufferCreateInfo, ClearValue, Image, ResourceDescription,
+};
+use silt::sync::CommandPool;
+
+const CLEAR: [f32; 4] = [4.5, -1.25, 0.125, 1.];
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f => f32::INFINITY,
+        _ => (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn main() -> Result<()> {
+    common::run_gpu_test("HDR Attachment", |loader, queues| {
+        let pool = CommandPool::new(loader, &queues[0], vk::CommandPoolCreateFlags::empty())?;
+        let extent = vk::Extent2D {
+            width: 4,
+            height: 4,
+        };
+
+        let attachment = ResourceDescription::attachment_with_clear(
+            id!("HDR Target"),
+            AttachmentType::Color,
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ClearValue::Color(CLEAR),
+        );
+        let description = attachment.as_ref().clone().unwrap_attachment();
+
+        let image = Image::new_attachment(loader, &description, extent)?;
+        let render_pass = build_render_pass(loader, [attachment.clone()], None)?;
+
+        let framebuffer_ci = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(std::slice::from_ref(&image.view))
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe { loader.device.create_framebuffer(&framebuffer_ci, None)? };
+
+        let readback = Buffer::new(
+            loader,
+            BufferCreateInfo {
+                size: (extent.width * extent.height * 8) as u64,
+                name: id!("HDR Readback"),
+                usage: vk::BufferUsageFlags::TRANSFER_DST,
+                location: vk::MemoryLocation::GpuToCpu,
+                ..Default::default()
+            },
+        )?;
+
+        let clear_value = description.clear_value();
+        pool.execute_one_time_commands(loader, |loader, cmd| unsafe {
+            let begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(render_pass)
+                .framebuffer(framebuffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent,
+                })
+                .clear_values(std::slice::from_ref(&clear_value));
+
+            loader
+                .device
+                .cmd_begin_render_pass(cmd, &begin_info, vk::SubpassContents::INLINE);
+            loader.device.cmd_end_render_pass(cmd);
+
+            let region = vk::BufferImageCopy::builder()
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_extent(image.size);
+
+            loader.device.cmd_copy_image_to_buffer(
+                cmd,
+                image.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback.buffer,
+                std::slice::from_ref(&region),
+            );
+        })?;
+
+        let ptr = loader.allocator.get_mapped_ptr(readback.allocation)?;
+        let texels = unsafe {
+            std::slice::from_raw_parts(
+                ptr.as_ptr() as *const u16,
+                (extent.width * extent.height * 4) as usize,
+            )
+        };
+
+        for texel in texels.chunks_exact(4) {
+            let texel = [0, 1, 2, 3].map(|idx| f16_to_f32(texel[idx]));
+            assert_eq!(
+                texel, CLEAR,
+                "hdr attachment did not clear to the expected float value"
+            );
+        }
+
+        readback.destroy(loader);
+        framebuffer.destroy(loader);
+        render_pass.destroy(loader);
+        image.destroy(loader);
+        pool.destroy(loader);
+
+        Ok(())
+    })
+}
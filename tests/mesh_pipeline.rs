@@ -0,0 +1,153 @@
+//! Builds a mesh shader effect from a `.mesh` and a `.frag` shader, draws a triangle covering the
+//! target with it and reads the target back. Skipped on devices without mesh shaders
+
+mod common;
+
+use anyhow::Result;
+use silt::material::MaterialSystemBuilder;
+use silt::pipeline::cmd_draw_mesh_tasks;
+use silt::prelude::*;
+use silt::properties::DeviceFeatures;
+use silt::resources::{AttachmentType, Buffer, BufferCreateInfo, Image, ResourceDescription};
+use silt::sync::CommandPool;
+use silt::{compile, id, resources};
+
+const COLOR: [u8; 4] = [64, 128, 191, 255];
+
+fn main() -> Result<()> {
+    common::run_gpu_test_with_features(
+        "Mesh Pipeline",
+        DeviceFeatures::MESH_SHADER,
+        |loader, queues| {
+            let pool = CommandPool::new(loader, &queues[0], vk::CommandPoolCreateFlags::empty())?;
+            let extent = vk::Extent2D {
+                width: 4,
+                height: 4,
+            };
+
+            let target = ResourceDescription::attachment(
+                id!("Mesh Target"),
+                AttachmentType::Color,
+                vk::Format::R8G8B8A8_UNORM,
+                vk::SampleCountFlags::TYPE_1,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            );
+
+            let mut materials = MaterialSystemBuilder::new(loader);
+            let mesh = materials.add_shader(
+                id!("Mesh Triangle"),
+                compile!("../assets/shaders/mesh_triangle.mesh", ())?,
+                std::iter::empty(),
+            )?;
+            let fragment = materials.add_shader(
+                id!("Mesh Triangle Fragment"),
+                compile!("../assets/shaders/mesh_triangle.frag", ())?,
+                resources!(target),
+            )?;
+            let effect =
+                materials.register_effect(id!("Mesh Triangle Effect"), [mesh, fragment])?;
+            let mut materials = materials.build_static()?;
+
+            let pipeline = materials.get_effect_pipeline(loader, &effect)?;
+            let (pipeline, render_pass) = (pipeline.pipeline, pipeline.render_pass.unwrap());
+            let clear_values = materials.get_effect_clear_values(&effect)?;
+
+            let description = target.as_ref().clone().unwrap_attachment();
+            let image = Image::new_attachment(loader, &description, extent)?;
+            let framebuffer_ci = vk::FramebufferCreateInfo::builder()
+                .render_pass(render_pass)
+                .attachments(std::slice::from_ref(&image.view))
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            let framebuffer = unsafe { loader.device.create_framebuffer(&framebuffer_ci, None)? };
+
+            let readback = Buffer::new(
+                loader,
+                BufferCreateInfo {
+                    size: (extent.width * extent.height * 4) as u64,
+                    name: id!("Mesh Readback"),
+                    usage: vk::BufferUsageFlags::TRANSFER_DST,
+                    location: vk::MemoryLocation::GpuToCpu,
+                    ..Default::default()
+                },
+            )?;
+
+            let mut draw_result = Ok(());
+            pool.execute_one_time_commands(loader, |loader, cmd| unsafe {
+                let render_area = vk::Rect2D {
+                    offset: vk::Offset2D::default(),
+                    extent,
+                };
+                let begin_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(render_pass)
+                    .framebuffer(framebuffer)
+                    .render_area(render_area)
+                    .clear_values(&clear_values);
+
+                loader
+                    .device
+                    .cmd_begin_render_pass(cmd, &begin_info, vk::SubpassContents::INLINE);
+                loader
+                    .device
+                    .cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+
+                let viewport = vk::Viewport {
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    max_depth: 1.,
+                    ..Default::default()
+                };
+                loader.device.cmd_set_viewport(cmd, 0, &[viewport]);
+                loader.device.cmd_set_scissor(cmd, 0, &[render_area]);
+
+                draw_result = cmd_draw_mesh_tasks(loader, cmd, [1, 1, 1]);
+                loader.device.cmd_end_render_pass(cmd);
+
+                let region = vk::BufferImageCopy::builder()
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image_extent(image.size);
+
+                loader.device.cmd_copy_image_to_buffer(
+                    cmd,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    readback.buffer,
+                    std::slice::from_ref(&region),
+                );
+            })?;
+            draw_result?;
+
+            let ptr = loader.allocator.get_mapped_ptr(readback.allocation)?;
+            let texels = unsafe {
+                std::slice::from_raw_parts(
+                    ptr.as_ptr() as *const u8,
+                    (extent.width * extent.height * 4) as usize,
+                )
+            };
+
+            for texel in texels.chunks_exact(4) {
+                // UNORM conversion may round either way
+                let matches = texel.iter().zip(COLOR).all(|(&a, b)| a.abs_diff(b) <= 1);
+                assert!(
+                    matches,
+                    "mesh pipeline did not cover the target, read {:?}",
+                    texel
+                );
+            }
+
+            readback.destroy(loader);
+            framebuffer.destroy(loader);
+            image.destroy(loader);
+            materials.destroy(loader);
+            pool.destroy(loader);
+
+            Ok(())
+        },
+    )
+}
@@ -0,0 +1,93 @@
+//! Replaces a bound storage buffer with a new resource of the same id, whose descriptors must be
+//! rewritten so the next dispatch fills the new buffer
+
+mod common;
+
+use anyhow::Result;
+use silt::material::{MaterialSystemBuilder, ShaderOptions};
+use silt::prelude::*;
+use silt::resources::{BindableResource, Buffer, Resource, ResourceDescription};
+use silt::sync::{CommandPool, DispatchBarrier, FrameContext};
+use silt::{compile, id, resources};
+
+const GROUP_SIZE: u32 = 64;
+
+fn read_back(loader: &Loader, buffer: &Resource<Buffer>) -> Result<Vec<u32>> {
+    let ptr = loader
+        .allocator
+        .get_mapped_ptr(buffer.resource.allocation)?;
+    let count = GROUP_SIZE as usize;
+    Ok(unsafe { std::slice::from_raw_parts(ptr.as_ptr() as *const u32, count) }.to_vec())
+}
+
+fn dispatch(loader: &Loader, pool: &CommandPool, frame: &FrameContext) -> Result<()> {
+    pool.execute_one_time_commands(loader, |loader, cmd| {
+        frame.record_dispatch(
+            loader,
+            cmd,
+            [1, 1, 1],
+            Some(DispatchBarrier::new(
+                vk::PipelineStageFlags::HOST,
+                vk::AccessFlags::HOST_READ,
+            )),
+        );
+    })?;
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    common::run_gpu_test("Resource Rewrite", |loader, queues| {
+        let pool = CommandPool::new(loader, &queues[0], vk::CommandPoolCreateFlags::empty())?;
+        let count = GROUP_SIZE as usize;
+
+        let mut materials = MaterialSystemBuilder::new(loader);
+        let values = ResourceDescription::storage_buffer::<u32>(
+            id!("Values"),
+            0,
+            vk::DescriptorFrequency::Global,
+            count,
+        );
+
+        let shader = materials.add_shader(
+            id!("Fill Buffer"),
+            compile!("../assets/shaders/fill_buffer.comp", ShaderOptions::HLSL)?,
+            resources!(values),
+        )?;
+        let effect = materials.register_effect(id!("Fill Buffer Effect"), [shader])?;
+        let mut materials = materials.build_static()?;
+
+        let old = common::values_buffer(loader, &values, count, vk::MemoryLocation::GpuToCpu)?;
+        *materials.get_global_resources_mut() = vec![old.bind()].into();
+        materials.write_global_sets(loader)?;
+        dispatch(
+            loader,
+            &pool,
+            &materials.get_frame_context(loader, &effect)?,
+        )?;
+
+        // Same id as the old buffer, only its generation tells the two apart
+        let new = common::values_buffer(loader, &values, count, vk::MemoryLocation::GpuToCpu)?;
+        *materials.get_global_resources_mut() = vec![new.bind()].into();
+        materials.write_global_sets(loader)?;
+        dispatch(
+            loader,
+            &pool,
+            &materials.get_frame_context(loader, &effect)?,
+        )?;
+
+        let expected = (0..GROUP_SIZE).map(|idx| idx * idx).collect::<Vec<_>>();
+        assert_eq!(
+            read_back(loader, &new)?,
+            expected,
+            "replaced buffer was not rewritten"
+        );
+
+        materials.destroy(loader);
+        old.resource.destroy(loader);
+        new.resource.destroy(loader);
+        pool.destroy(loader);
+
+        Ok(())
+    })
+}